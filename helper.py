@@ -66,6 +66,7 @@ def run_test(
     The protocol for tests is as follows:
     //! THIS IS A TEST PROGRAM
     //! STAGE: {0|1}
+    //! FLAGS: <compiler flags> (optional)
     //! {RUNTIME|COMPILER}
     //! {FAILURE|SUCCESS|DIAGNOSTICS}
     //! CODE: <error code> (only if FAILURE)
@@ -126,6 +127,10 @@ def run_test(
         if single_stage is not None and stage != single_stage:
             return TestResult(path, STATE.DONT_TEST)
 
+        flags = []
+        if next(lines, pop=False).startswith("//! FLAGS:"):
+            flags = next(lines).removeprefix("//! FLAGS:").split()
+
         point_of_failure = next(lines).removeprefix("//! ").upper().strip()
         if point_of_failure not in ["RUNTIME", "COMPILER"]:
             print(f"{CORRUPT} {path}", file=sys.stderr)
@@ -176,7 +181,7 @@ def run_test(
             return TestResult(path, STATE.SUCCESS)
 
         filename = "./out/{}.exe".format(path.replace(os.sep, "."))
-        output = call_cmd([compiler_path, path, "-v", "-o", filename])
+        output = call_cmd([compiler_path, path, "-v", "-o", filename, *flags])
         stdout = output.stdout.decode("utf-8").split('\n')
         stderr = output.stderr.decode("utf-8").split('\n')
         if point_of_failure == "RUNTIME":
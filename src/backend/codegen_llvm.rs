@@ -1,10 +1,12 @@
+use std::cell::OnceCell;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::frontend::nodes;
 use crate::frontend::parser::Operation;
-use crate::frontend::tokens::{KEYWORD_BLANK, KEYWORD_NULL};
-use crate::util::flags::Flags;
+use crate::frontend::tokens::{Location, KEYWORD_BLANK, KEYWORD_NULL};
+use crate::util::coverage::{self, CoverageRegion, RegionKind};
+use crate::util::flags::{Flags, Sanitizer};
 use crate::compiler::ERR_STR;
 use crate::internal_panic;
 use crate::util::opt_flags::OptimizationLevel;
@@ -13,12 +15,13 @@ use inkwell::attributes::{Attribute, AttributeLoc};
 use inkwell::builder::BuilderError;
 use inkwell::basic_block::BasicBlock;
 use inkwell::context::Context;
-use inkwell::module::Module;
+use inkwell::intrinsics::Intrinsic;
+use inkwell::module::{Linkage, Module};
 use inkwell::builder::Builder;
-use inkwell::passes::PassManager;
+use inkwell::passes::{PassBuilderOptions, PassManager};
 use inkwell::targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple};
 use inkwell::types::{BasicType, BasicTypeEnum, StructType};
-use inkwell::values::{BasicValue, InstructionValue, IntValue, PointerValue};
+use inkwell::values::{BasicValue, CallSiteValue, FunctionValue, GlobalValue, InstructionValue, IntValue, PointerValue, StructValue};
 use inkwell::AddressSpace;
 use inkwell::values::BasicValueEnum;
 
@@ -64,8 +67,15 @@ macro_rules! codegen_function_header {
         let llvm_func = $codegen.module.get_function(&$name).unwrap();
         let entry = $codegen.context.append_basic_block(llvm_func, "entry");
         $codegen.builder.position_at_end(entry);
+        codegen_function_parameters!($codegen, $function, llvm_func, 0);
+    };
+}
+
+// Parameters become stack variables, `$offset` skips hidden leading parameters like the environment of closures
+macro_rules! codegen_function_parameters {
+    ($codegen:ident, $function:ident, $llvm_func:ident, $offset:expr) => {
         for (i, param) in $function.parameters.iter().enumerate() {
-            let func_param = llvm_func.get_nth_param(i as u32).unwrap();
+            let func_param = $llvm_func.get_nth_param(i as u32 + $offset).unwrap();
             let param_type = $codegen.codegen_type_node(&param.typ);
             if param_type.is_struct_type() {
                 let typ = $codegen.struct_type_for_arg(&param_type);
@@ -95,12 +105,26 @@ macro_rules! codegen_function_header {
 #[derive(Debug, Clone)]
 pub struct StructInfo<'src> {
     pub fields: Vec<(&'src str, Type<'src>)>,
+    pub is_union: bool,
+    pub layout: nodes::Layout,
+    pub field_layouts: Vec<nodes::Layout>,
+    // LLVM indices of every field, union fields have none because they start at the union itself
+    indices: Vec<Vec<u32>>,
+    offsets: Vec<usize>,
+    // Alignment every field is guaranteed to have, lower than its natural one in packed structs
+    alignments: Vec<usize>,
 }
 
 impl<'src> StructInfo<'src> {
-    fn new() -> Self {
+    fn new(strukt: &nodes::StructNode<'src>) -> Self {
         Self {
             fields: Vec::new(),
+            is_union: strukt.is_union,
+            layout: strukt.layout,
+            field_layouts: strukt.fields.iter().map(|f| f.layout).collect(),
+            indices: Vec::new(),
+            offsets: Vec::new(),
+            alignments: Vec::new(),
         }
     }
 
@@ -111,6 +135,26 @@ impl<'src> StructInfo<'src> {
         self.fields.push((field_name, typ.clone()));
     }
 
+    fn has_custom_layout(&self) -> bool {
+        self.is_union || self.layout != nodes::Layout::default() || self.field_layouts.iter().any(|l| *l != nodes::Layout::default())
+    }
+
+    /// Offsets of the fields, size and alignment of the struct, given the natural size and alignment of every field.
+    /// Without any `packed`, `align(N)` or `union` this is the same layout LLVM uses for our structs.
+    pub fn layout(&self, fields: &[(usize, usize)]) -> (Vec<usize>, usize, usize) {
+        let mut offsets = Vec::with_capacity(fields.len());
+        let mut end: usize = 0;
+        let mut align = self.layout.align.unwrap_or(1);
+        for ((size, natural), layout) in fields.iter().zip(&self.field_layouts) {
+            let field_align = layout.apply(if self.layout.packed { 1 } else { *natural });
+            let offset = if self.is_union { 0 } else { end.next_multiple_of(field_align) };
+            offsets.push(offset);
+            end = end.max(offset + size);
+            align = align.max(field_align);
+        }
+        (offsets, end.next_multiple_of(align), align)
+    }
+
     fn get_field_index(&self, field_name: &str) -> usize {
         for (i, (name, _)) in self.fields.iter().enumerate() {
             if name == &field_name {
@@ -121,6 +165,9 @@ impl<'src> StructInfo<'src> {
     }
 }
 
+// Variables by name
+type Scope<'ctx> = HashMap<String, BasicValueEnum<'ctx>>;
+
 pub struct LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     filename: PathBuf,
     exename: PathBuf,
@@ -132,16 +179,27 @@ pub struct LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     comptime_evaluator: Evaluator<'flags, 'src, 'ast>,
 
     global_values: HashSet<String>,
-    stack_scopes: Vec<HashMap<String, BasicValueEnum<'ctx>>>,
-    loop_blocks: Vec<(BasicBlock<'ctx>, BasicBlock<'ctx>)>, // (loop, after_loop)
+    stack_scopes: Vec<Scope<'ctx>>,
+    loop_blocks: Vec<(BasicBlock<'ctx>, BasicBlock<'ctx>, usize)>, // (loop, after_loop, deferred depth)
+    // Statements deferred in each enclosing block, with the number of variable scopes when the block was entered.
+    // Every statement keeps the block's scope as it was at the `defer`, later declarations could shadow its variables.
+    deferred: Vec<(usize, Vec<(nodes::Statement<'src>, Scope<'ctx>)>)>,
     struct_defs: HashMap<&'src str, StructType<'ctx>>,
     struct_info: HashMap<&'src str, StructInfo<'src>>,
+    interfaces: HashMap<&'src str, nodes::InterfaceNode<'src>>,
 
     flags: &'flags Flags,
     link_flags: Vec<String>,
+    // Original contents of every source file, only needed to turn runtime error locations into line:col
+    sources: HashMap<PathBuf, String>,
+    // Byte offsets at which each line starts, by file_id
+    line_starts: HashMap<usize, Vec<usize>>,
+    // `--coverage`: Increments refer to the placeholder until we know how many counters we need
+    coverage_regions: Vec<CoverageRegion>,
+    coverage_placeholder: Option<GlobalValue<'ctx>>,
 }
 impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
-    pub fn new(flags: &'flags Flags, context: &'ctx Context) -> Self {
+    pub fn new(flags: &'flags Flags, context: &'ctx Context, sources: HashMap<PathBuf, String>) -> Self {
         let filename = if let Some(output) = &flags.output {
             let pb = PathBuf::from(output);
             pb.with_extension("bufo")
@@ -169,6 +227,11 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         module.set_triple(&target_triple);
         module.set_data_layout(&target_machine.get_target_data().get_data_layout());
         let builder = context.create_builder();
+        let mut link_flags = Vec::new();
+        if cfg!(unix) && flags.optimizations.is_debug() {
+            // Otherwise backtraces can't name our functions
+            link_flags.push(String::from("-export-dynamic"));
+        }
         Self {
             filename,
             exename,
@@ -180,10 +243,16 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             global_values: HashSet::new(),
             stack_scopes: Vec::new(),
             loop_blocks: Vec::new(),
+            deferred: Vec::new(),
             struct_defs: HashMap::new(),
             struct_info: HashMap::new(),
+            interfaces: HashMap::new(),
             flags,
-            link_flags: Vec::new(),
+            link_flags,
+            sources,
+            line_starts: HashMap::new(),
+            coverage_regions: Vec::new(),
+            coverage_placeholder: None,
         }
     }
 
@@ -292,6 +361,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 break;
             }
         }
+        let target_data = self.target_machine.get_target_data();
         for strukt in &sequence {
             let real_name = strukt.get_full_name();
             let mut fields = Vec::new();
@@ -301,12 +371,24 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 fields.push(field_type);
                 field_types.push((field.name, field.type_def.typ.clone()));
             }
-            let struct_type_def = self.context.struct_type(&fields, false);
-            self.struct_defs.insert(real_name, struct_type_def.into());
-            let mut struct_info = StructInfo::new();
+            let mut struct_info = StructInfo::new(strukt);
             for (name, typ) in field_types {
                 struct_info.add_field(&name, &typ);
             }
+            let struct_type_def = if struct_info.has_custom_layout() {
+                self.codegen_custom_layout(&mut struct_info, &fields)
+            } else {
+                let struct_type_def = self.context.struct_type(&fields, false);
+                struct_info.indices = (0..fields.len() as u32).map(|i| vec![i]).collect();
+                struct_info.offsets = (0..fields.len() as u32)
+                    .map(|i| target_data.offset_of_element(&struct_type_def, i).unwrap() as usize)
+                    .collect();
+                struct_info.alignments = fields.iter()
+                    .map(|f| target_data.get_abi_alignment(f) as usize)
+                    .collect();
+                struct_type_def
+            };
+            self.struct_defs.insert(real_name, struct_type_def.into());
             self.struct_info.insert(real_name, struct_info);
         }
         if self.flags.debug {
@@ -315,6 +397,143 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         Ok(())
     }
 
+    /// Unions and structs with `packed` or `align(N)` are laid out by `StructInfo::layout`.
+    /// The fields are placed in a packed struct with explicit padding, a union is just its bytes.
+    /// If the result needs a higher alignment than 1, it's wrapped in a struct that starts with a zero-sized vector of that alignment.
+    #[trace_call(always)]
+    fn codegen_custom_layout(&self, struct_info: &mut StructInfo<'src>, fields: &[BasicTypeEnum<'ctx>]) -> StructType<'ctx> {
+        let target_data = self.target_machine.get_target_data();
+        let natural: Vec<_> = fields.iter()
+            .map(|f| (target_data.get_abi_size(f) as usize, target_data.get_abi_alignment(f) as usize))
+            .collect();
+        let (offsets, size, align) = struct_info.layout(&natural);
+        let byte_type = self.context.i8_type();
+        let mut elements: Vec<BasicTypeEnum<'ctx>> = Vec::new();
+        let mut indices = Vec::new();
+        if struct_info.is_union {
+            elements.push(byte_type.array_type(size as u32).into());
+            indices.resize(fields.len(), Vec::new());
+        } else {
+            let mut end = 0;
+            for ((field, offset), (field_size, _)) in fields.iter().zip(&offsets).zip(&natural) {
+                if *offset > end {
+                    elements.push(byte_type.array_type((offset - end) as u32).into());
+                }
+                indices.push(vec![elements.len() as u32]);
+                elements.push(*field);
+                end = offset + field_size;
+            }
+            if size > end {
+                elements.push(byte_type.array_type((size - end) as u32).into());
+            }
+        }
+        let packed = self.context.struct_type(&elements, true);
+        // A field is only as aligned as its offset and the struct allow
+        struct_info.alignments = offsets.iter()
+            .zip(&natural)
+            .map(|(offset, (_, field_align))| {
+                let at_offset = if *offset == 0 { align } else { align.min(1 << offset.trailing_zeros()) };
+                at_offset.min(*field_align)
+            })
+            .collect();
+        struct_info.offsets = offsets;
+        if align == 1 {
+            struct_info.indices = indices;
+            return packed;
+        }
+        if !struct_info.is_union {
+            for index in &mut indices {
+                index.insert(0, 1);
+            }
+        }
+        struct_info.indices = indices;
+        let align_type = byte_type.vec_type(align as u32).array_type(0);
+        self.context.struct_type(&[align_type.into(), packed.into()], false)
+    }
+
+    /// Alignment of the field or element `binary` accesses, if a packed struct makes it lower than the natural one.
+    /// Loads and stores of that place have to use it instead of the ABI alignment.
+    #[trace_call(always)]
+    fn access_alignment(&self, binary: &nodes::BinaryNode<'src>) -> Option<u32> {
+        let lhs_type = binary.lhs.get_type();
+        // Structs behind a reference are naturally aligned, taking a reference to a packed field is rejected
+        let base = match binary.lhs.as_ref() {
+            nodes::Expression::Binary(lhs)
+                if !lhs_type.is_reference() && matches!(lhs.operation, Operation::MemberAccess | Operation::IndexedAccess) => {
+                self.access_alignment(lhs)
+            }
+            _ => None,
+        };
+        let struct_type = match &lhs_type {
+            Type::Ref(inner, _) => inner.as_ref(),
+            typ => typ,
+        };
+        let field = match (binary.operation, struct_type, binary.rhs.as_ref()) {
+            (Operation::MemberAccess, Type::Struct(struct_name), nodes::Expression::Name(field)) => {
+                let struct_info = self.struct_info.get(struct_name)?;
+                Some(struct_info.alignments[struct_info.get_field_index(field.name)] as u32)
+            }
+            _ => None,
+        };
+        let align = match (base, field) {
+            (Some(base), Some(field)) => base.min(field),
+            (base, field) => base.or(field)?,
+        };
+        let natural = self.target_machine.get_target_data().get_abi_alignment(&self.codegen_type(&binary.typ));
+        (align < natural).then_some(align)
+    }
+
+    /// Applies `access_alignment` to a load or store of the place `binary` accesses
+    #[trace_call(always)]
+    fn set_access_alignment(&self, binary: &nodes::BinaryNode<'src>, instruction: InstructionValue<'ctx>) {
+        if let Some(align) = self.access_alignment(binary) {
+            if let Err(e) = instruction.set_alignment(align) {
+                internal_panic!("Could not set alignment of packed field access: {e}");
+            }
+        }
+    }
+
+    /// Pointer to a field of the struct at `ptr`
+    #[trace_call(always)]
+    fn build_field_ptr(&self, struct_name: &str, ptr: PointerValue<'ctx>, field_name: &str) -> Result<PointerValue<'ctx>, BuilderError> {
+        let Some(struct_type) = self.get_struct_type(struct_name) else {
+            internal_panic!("Could not find struct {}", struct_name)
+        };
+        let Some(struct_info) = self.struct_info.get(struct_name) else {
+            internal_panic!("Could not find struct {}", struct_name)
+        };
+        match struct_info.indices[struct_info.get_field_index(field_name)].as_slice() {
+            [] => Ok(ptr),
+            [index] => self.builder.build_struct_gep(struct_type, ptr, *index, "field_ptr"),
+            indices => {
+                let i32_type = self.context.i32_type();
+                let mut gep_indices = vec![i32_type.const_zero()];
+                gep_indices.extend(indices.iter().map(|i| i32_type.const_int(*i as u64, false)));
+                unsafe { self.builder.build_in_bounds_gep(struct_type, ptr, &gep_indices, "field_ptr") }
+            }
+        }
+    }
+
+    /// Inserts the value of a field into a struct value, following the LLVM indices of the field
+    #[trace_call(always)]
+    fn build_insert_field(
+        &self,
+        struct_value: StructValue<'ctx>,
+        value: BasicValueEnum<'ctx>,
+        indices: &[u32],
+        name: &str,
+    ) -> Result<StructValue<'ctx>, BuilderError> {
+        match indices {
+            [] => internal_panic!("Union fields can't be inserted into a union value"),
+            [index] => Ok(self.builder.build_insert_value(struct_value, value, *index, name)?.into_struct_value()),
+            [index, rest @ ..] => {
+                let inner = self.builder.build_extract_value(struct_value, *index, name)?.into_struct_value();
+                let inner = self.build_insert_field(inner, value, rest, name)?;
+                Ok(self.builder.build_insert_value(struct_value, inner, *index, name)?.into_struct_value())
+            }
+        }
+    }
+
     #[trace_call(always)]
     fn fill_lookup(&mut self, file: &'ast nodes::FileNode<'src>) -> Result<(), String> {
         for external in &file.externs {
@@ -332,6 +551,9 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 }
             }
         }
+        for interface in &file.interfaces {
+            self.interfaces.insert(interface.name, interface.clone());
+        }
         for function in &file.functions {
             if function.is_comptime {
                 self.comptime_evaluator.add_function(&function);
@@ -408,6 +630,9 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         if let Err(e) = self.codegen_file(file) {
             internal_panic!("Module verification failed:\n{}", e.to_string());
         }
+        if let Err(e) = self.codegen_coverage_dump() {
+            internal_panic!("Module verification failed:\n{}", e.to_string());
+        }
         self.finalize_executable()
     }
 
@@ -465,6 +690,20 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             )?;
             self.builder.build_return(Some(&ret))?;
         }
+        {
+            let panic = self.module.get_function("GLOBAL_PANIC").unwrap();
+            panic.add_attribute(AttributeLoc::Function, self.context.create_enum_attribute(Attribute::get_named_enum_kind_id("noreturn"), 0));
+            let panic_body = self.context.append_basic_block(panic, "entry");
+            self.builder.position_at_end(panic_body);
+            let args: Vec<_> = panic.get_param_iter().map(|p| p.into()).collect();
+            // The user's handler may return, in which case we still terminate
+            if let Some(handler) = self.module.get_function("func.panicHandler") {
+                self.builder.build_call(handler, &args, "intr_user_panic")?;
+            }
+            let default_handler = self.module.get_function("func.defaultPanicHandler").unwrap();
+            self.builder.build_call(default_handler, &args, "intr_default_panic")?;
+            self.builder.build_unreachable()?;
+        }
         Ok(())
     }
 
@@ -507,6 +746,24 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         let entry = self.context.append_basic_block(main, "entry");
         self.builder.position_at_end(entry);
         self.codegen_entrypoint_init()?;
+        if self.flags.coverage {
+            // Body is emitted in codegen_coverage_dump()
+            let dump = self.module.add_function("coverage.dump", self.context.void_type().fn_type(&[], false), None);
+            // Global destructors also run when the program calls exit(), e.g. after a panic
+            let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+            let entry_type = self.context.struct_type(&[
+                self.context.i32_type().into(),
+                ptr_type.into(),
+                ptr_type.into(),
+            ], false);
+            let dtors = self.module.add_global(entry_type.array_type(1), None, "llvm.global_dtors");
+            dtors.set_linkage(Linkage::Appending);
+            dtors.set_initializer(&entry_type.const_array(&[entry_type.const_named_struct(&[
+                self.context.i32_type().const_int(65535, false).into(),
+                dump.as_global_value().as_pointer_value().into(),
+                ptr_type.const_null().into(),
+            ])]));
+        }
         let _ = self.builder.build_call(
             self.module.get_function("func.setupStdHandles").expect("setupStdHandles is part of the prelude and should always be found"),
             &[], "setup_std"
@@ -569,6 +826,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 break;
             }
         }
+        self.instrument_sanitizers()?;
 
         // link urmom.o /NODEFAULTLIB:libcmt /LIBPATH:"./raylib/lib" raylib.lib WinMM.lib Gdi32.lib msvcrt.lib Gdi32.lib User32.lib Shell32.lib /OUT:urmom.exe
 
@@ -583,6 +841,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             println!("[INFO] Created {}", path.to_str().unwrap());
         }
 
+        if self.flags.coverage {
+            let mapname = self.filename.with_extension("covmap");
+            let path = std::path::Path::new(&mapname);
+            coverage::write_map(path, &self.coverage_regions)?;
+            println!("[INFO] Created {}", path.to_str().unwrap());
+        }
+
         if self.flags.emit_asm {
             let objname = self.filename.with_extension("s");
             let path = std::path::Path::new(&objname);
@@ -593,12 +858,35 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         self.link_executable(&path)
     }
 
+    /// `--sanitize=undefined` is handled by our own runtime checks during codegen,
+    /// ASan needs every function to be marked and instrumented by LLVM
+    #[trace_call(always)]
+    fn instrument_sanitizers(&mut self) -> Result<(), String> {
+        if !self.flags.sanitize(Sanitizer::Address) {
+            return Ok(());
+        }
+        let id = Attribute::get_named_enum_kind_id("sanitize_address");
+        if id == 0 {
+            return Err("AddressSanitizer is not supported by this version of LLVM".to_string());
+        }
+        for function in self.module.get_functions() {
+            if function.count_basic_blocks() > 0 {
+                function.add_attribute(AttributeLoc::Function, self.context.create_enum_attribute(id, 0));
+            }
+        }
+        self.module.run_passes("asan", &self.target_machine, PassBuilderOptions::create())
+            .map_err(|e| format!("Could not run AddressSanitizer pass: {}", e))
+    }
+
     #[cfg(windows)]
     fn link_executable(&mut self, objpath: &std::path::Path) -> Result<(), String> {
         let mut link_cmd = std::process::Command::new("link");
         link_cmd.arg(&objpath);
         link_cmd.arg(format!("/OUT:{}", self.exename.as_path().to_str().unwrap()));
         link_cmd.arg("/DEFAULTLIB:libcmt");
+        if self.flags.sanitize(Sanitizer::Address) {
+            link_cmd.arg("/INFERASANLIBS");
+        }
         for flag in &self.link_flags {
             link_cmd.arg(flag);
         }
@@ -631,21 +919,36 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
 
     #[cfg(unix)]
     fn link_executable(&mut self, objpath: &std::path::Path) -> Result<(), String> {
-        let mut link_cmd = std::process::Command::new("ld");
-        link_cmd.arg("-dynamic-linker");
-        link_cmd.arg("/lib64/ld-linux-x86-64.so.2");
-        link_cmd.arg("/lib/x86_64-linux-gnu/crt1.o");
-        link_cmd.arg("/lib/x86_64-linux-gnu/crti.o");
-        link_cmd.arg(&objpath);
-        link_cmd.arg("/lib/x86_64-linux-gnu/crtn.o");
-        link_cmd.arg("-lc");
-        for flag in &self.link_flags {
-            link_cmd.arg(flag);
-        }
+        let mut link_cmd = if self.flags.sanitizers.is_empty() {
+            let mut link_cmd = std::process::Command::new("ld");
+            link_cmd.arg("-dynamic-linker");
+            link_cmd.arg("/lib64/ld-linux-x86-64.so.2");
+            link_cmd.arg("/lib/x86_64-linux-gnu/crt1.o");
+            link_cmd.arg("/lib/x86_64-linux-gnu/crti.o");
+            link_cmd.arg(&objpath);
+            link_cmd.arg("/lib/x86_64-linux-gnu/crtn.o");
+            link_cmd.arg("-lc");
+            for flag in &self.link_flags {
+                link_cmd.arg(flag);
+            }
+            link_cmd
+        } else {
+            // The driver knows where the sanitizer runtimes live and how to link them
+            let mut link_cmd = std::process::Command::new("cc");
+            link_cmd.arg(objpath);
+            // Our object files aren't position independent
+            link_cmd.arg("-no-pie");
+            let sanitizers: Vec<_> = self.flags.sanitizers.iter().map(|s| s.name()).collect();
+            link_cmd.arg(format!("-fsanitize={}", sanitizers.join(",")));
+            for flag in &self.link_flags {
+                link_cmd.arg(format!("-Wl,{}", flag));
+            }
+            link_cmd
+        };
         link_cmd.arg("-o");
         link_cmd.arg(self.exename.as_path().to_str().unwrap());
         if self.flags.verbose {
-            let mut s = String::from("ld");
+            let mut s = link_cmd.get_program().to_str().unwrap().to_string();
             for arg in link_cmd.get_args() {
                 s.push(' ');
                 s.push_str(arg.to_str().unwrap());
@@ -676,6 +979,9 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         for global in &file.globals {
             self.codegen_global(global)?;
         }
+        for assert_node in &file.asserts {
+            self.codegen_comptime_assert(assert_node);
+        }
         // External functions are already added in `fill_lookup`
         // for external in &file.externs {
         //     self.codegen_extern(external)?;
@@ -711,6 +1017,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
 
         let name = method.get_full_name();
         codegen_function_header!(self, method, name);
+        self.codegen_coverage_counter(RegionKind::Function, &method.location, Some(&method.block))?;
 
         self.codegen_block(&method.block)?;
 
@@ -730,6 +1037,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
 
         let name = format!("func.{}", function.name);
         codegen_function_header!(self, function, name);
+        self.codegen_coverage_counter(RegionKind::Function, &function.location, Some(&function.block))?;
 
         self.codegen_block(&function.block)?;
 
@@ -746,13 +1054,36 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     #[trace_call(always)]
     fn codegen_block(&mut self, block: &nodes::BlockNode<'src>) -> Result<(), BuilderError> {
         self.enter_scope();
+        self.deferred.push((self.stack_scopes.len(), Vec::new()));
         for statement in &block.statements {
             self.codegen_statement(statement)?;
         }
+        // Blocks with a terminator already ran their deferred statements before leaving
+        if !block.llvm_has_terminator {
+            self.codegen_deferred(self.deferred.len() - 1)?;
+        }
+        self.deferred.pop();
         self.exit_scope();
         Ok(())
     }
 
+    /// Emits the deferred statements of all blocks from the innermost one down to `depth`, in reverse order
+    #[trace_call(always)]
+    fn codegen_deferred(&mut self, depth: usize) -> Result<(), BuilderError> {
+        for index in (depth..self.deferred.len()).rev() {
+            let (scopes, statements) = self.deferred[index].clone();
+            // Variables declared after the `defer` could shadow the ones the statements refer to
+            let inner_scopes = self.stack_scopes.split_off(scopes - 1);
+            for (statement, scope) in statements.into_iter().rev() {
+                self.stack_scopes.push(scope);
+                self.codegen_statement(&statement)?;
+                self.stack_scopes.pop();
+            }
+            self.stack_scopes.extend(inner_scopes);
+        }
+        Ok(())
+    }
+
     #[trace_call(always)]
     fn codegen_statement(&mut self, statement: &nodes::Statement<'src>) -> Result<(), BuilderError> {
         match statement {
@@ -767,22 +1098,48 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 let _ = self.codegen_expression(expr, false)?;
                 Ok(())
             },
+            nodes::Statement::ComptimeAssert(assert_node) => {
+                self.codegen_comptime_assert(assert_node);
+                Ok(())
+            }
+            nodes::Statement::Destructure(destructure) => self.codegen_stmt_destructure(destructure),
+            nodes::Statement::Defer(defer_node) => {
+                let Some(scope) = self.stack_scopes.last().cloned() else {
+                    internal_panic!("Deferred statement outside of a scope")
+                };
+                let Some((_, statements)) = self.deferred.last_mut() else {
+                    internal_panic!("Deferred statement outside of a block")
+                };
+                statements.push(((*defer_node.statement).clone(), scope));
+                Ok(())
+            }
+        }
+    }
+
+    #[trace_call(always)]
+    fn codegen_comptime_assert(&mut self, assert_node: &nodes::ComptimeAssertNode<'src>) {
+        if let Err(eval_error) = self.comptime_evaluator.check_assertion(assert_node) {
+            eprintln!("{ERR_STR}: {eval_error}");
+            self.comptime_evaluator.print_call_stack(None);
+            std::process::exit(1);
         }
     }
 
     #[trace_call(always)]
     fn codegen_stmt_continue(&mut self, _continue_node: &nodes::ContinueNode) -> Result<(), BuilderError> {
         debug_assert!(!self.loop_blocks.is_empty());
-        let (loop_block, _) = self.loop_blocks.last().unwrap();
-        self.builder.build_unconditional_branch(*loop_block)?;
+        let (loop_block, _, depth) = *self.loop_blocks.last().unwrap();
+        self.codegen_deferred(depth)?;
+        self.builder.build_unconditional_branch(loop_block)?;
         Ok(())
     }
 
     #[trace_call(always)]
     fn codegen_stmt_break(&mut self, _break_node: &nodes::BreakNode) -> Result<(), BuilderError> {
         debug_assert!(!self.loop_blocks.is_empty());
-        let (_, after_block) = self.loop_blocks.last().unwrap();
-        self.builder.build_unconditional_branch(*after_block)?;
+        let (_, after_block, depth) = *self.loop_blocks.last().unwrap();
+        self.codegen_deferred(depth)?;
+        self.builder.build_unconditional_branch(after_block)?;
         Ok(())
     }
 
@@ -793,7 +1150,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         let while_body = self.context.append_basic_block(function, "codegen_stmt_while_body");
         let while_after = self.context.append_basic_block(function, "codegen_stmt_while_after");
         let while_step = self.context.append_basic_block(function, "codegen_stmt_while_step");
-        self.loop_blocks.push((while_step, while_after));
+        self.loop_blocks.push((while_step, while_after, self.deferred.len()));
 
         self.builder.build_unconditional_branch(while_cond)?;
         self.builder.position_at_end(while_cond);
@@ -801,6 +1158,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         self.builder.build_conditional_branch(condition.into_int_value(), while_body, while_after)?;
 
         self.builder.position_at_end(while_body);
+        self.codegen_coverage_counter(RegionKind::Loop, &while_node.location, Some(&while_node.body))?;
         self.codegen_block(&while_node.body)?;
         if !while_node.body.llvm_has_terminator {
             self.builder.build_unconditional_branch(while_step)?;
@@ -818,14 +1176,17 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     }
 
     #[trace_call(always)]
-    fn codegen_stmt_return(&mut self, return_node: &nodes::ReturnNode) -> Result<(), BuilderError> {
+    fn codegen_stmt_return(&mut self, return_node: &nodes::ReturnNode<'src>) -> Result<(), BuilderError> {
         match &return_node.return_value {
             Some(value) => {
+                // The value is computed before deferred statements run, they can't change it anymore
                 let value = self.codegen_expression(value, false)?;
+                self.codegen_deferred(0)?;
                 self.builder.build_return(Some(&value))?;
                 Ok(())
             }
             None => {
+                self.codegen_deferred(0)?;
                 self.builder.build_return(None)?;
                 Ok(())
             }
@@ -883,6 +1244,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             let res = self.comptime_evaluator.add_variable(&let_node);
             if let Err(eval_error) = res {
                 eprintln!("{ERR_STR}: {eval_error}");
+                self.comptime_evaluator.print_call_stack(Some(let_node));
                 std::process::exit(1);
             } else {
                 Ok(())
@@ -907,6 +1269,24 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         }
     }
 
+    #[trace_call(always)]
+    fn codegen_stmt_destructure(&mut self, destructure: &nodes::DestructureNode<'src>) -> Result<(), BuilderError> {
+        let value = self.codegen_expression(&destructure.expression, false)?.into_struct_value();
+        let Type::Tuple(types) = &destructure.typ.typ else {
+            internal_panic!("Expected tuple, found {}", destructure.typ.typ)
+        };
+        for (index, ((_, name), typ)) in destructure.names.iter().zip(types.iter()).enumerate() {
+            if *name == "_" {
+                continue;
+            }
+            let element = self.builder.build_extract_value(value, index as u32, name)?;
+            let alloca = self.allocate(self.codegen_type(typ), name)?;
+            self.add_variable(name, alloca.into());
+            self.store_value_in_ptr(alloca, element)?;
+        }
+        Ok(())
+    }
+
     #[trace_call(always)]
     fn codegen_stmt_if(&mut self, if_node: &nodes::IfNode<'src>) -> Result<(), BuilderError> {
         let condition = self.codegen_expression(&if_node.condition, false)?;
@@ -919,11 +1299,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
 
             // Emit then block.
             self.builder.position_at_end(then_bb);
+            self.codegen_coverage_counter(RegionKind::Then, &if_node.location, Some(&if_node.if_body))?;
             self.codegen_block(&if_node.if_body)?;
             let then_bb = self.builder.get_insert_block().unwrap();
 
             // Emit else block.
             self.builder.position_at_end(else_bb);
+            self.codegen_coverage_counter(RegionKind::Else, &if_node.location, Some(else_body))?;
             self.codegen_block(else_body)?;
             let else_bb = self.builder.get_insert_block().unwrap();
             match (if_node.if_body.llvm_has_terminator, else_body.llvm_has_terminator) {
@@ -954,10 +1336,20 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             let then_bb = self.context.append_basic_block(parent, "codegen_stmt_if_then");
             let cont_bb = self.context.append_basic_block(parent, "codegen_stmt_if_after");
 
-            self.builder.build_conditional_branch(condition.into_int_value(), then_bb, cont_bb)?;
+            if self.flags.coverage {
+                // The implicit else branch needs its own block to count how often it's taken
+                let else_bb = self.context.append_basic_block(parent, "codegen_stmt_if_else");
+                self.builder.build_conditional_branch(condition.into_int_value(), then_bb, else_bb)?;
+                self.builder.position_at_end(else_bb);
+                self.codegen_coverage_counter(RegionKind::Else, &if_node.location, None)?;
+                self.builder.build_unconditional_branch(cont_bb)?;
+            } else {
+                self.builder.build_conditional_branch(condition.into_int_value(), then_bb, cont_bb)?;
+            }
 
             // Emit then block.
             self.builder.position_at_end(then_bb);
+            self.codegen_coverage_counter(RegionKind::Then, &if_node.location, Some(&if_node.if_body))?;
             self.codegen_block(&if_node.if_body)?;
             if !if_node.if_body.llvm_has_terminator {
                 self.builder.build_unconditional_branch(cont_bb)?;
@@ -970,7 +1362,12 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     }
 
     #[trace_call(always)]
-    fn codegen_expression(&mut self, expression: &nodes::Expression, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+    fn codegen_expression(&mut self, expression: &nodes::Expression<'src>, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        if !needs_ptr {
+            if let Some(folded) = self.fold_constant(expression)? {
+                return Ok(folded);
+            }
+        }
         match expression {
             nodes::Expression::Literal(literal) => self.codegen_literal(literal),
             nodes::Expression::Binary(binary) => self.codegen_binary(binary, needs_ptr),
@@ -979,12 +1376,27 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             nodes::Expression::Unary(unary) => self.codegen_unary(unary, needs_ptr),
             nodes::Expression::StructLiteral(struct_literal) => self.codegen_struct_literal(struct_literal, needs_ptr),
             nodes::Expression::ArrayLiteral(array_literal) => self.codegen_array_literal(array_literal, needs_ptr),
+            nodes::Expression::TupleLiteral(tuple_literal) => self.codegen_tuple_literal(tuple_literal, needs_ptr),
             nodes::Expression::Sizeof(typ) => {
                 let t = self.codegen_type_node(&typ);
                 let s = self.get_struct_size(&t);
                 let v = self.context.i64_type().const_int(s, false);
                 Ok(v.into())
             },
+            nodes::Expression::Reflection(reflection) => self.codegen_reflection(reflection),
+            nodes::Expression::Embed(embed) => self.codegen_embed(embed),
+            nodes::Expression::Arithmetic(arithmetic) => self.codegen_arithmetic(arithmetic),
+            nodes::Expression::Lambda(lambda) => self.codegen_lambda(lambda, needs_ptr),
+            nodes::Expression::As(expr, typ) if matches!(typ.typ, Type::Dyn(..)) => {
+                let value = self.codegen_dyn_reference(expr, &typ.typ)?;
+                if needs_ptr {
+                    let alloca = self.allocate(value.get_type(), "dyn_reference")?;
+                    self.store_value_in_ptr(alloca, value)?;
+                    Ok(alloca.into())
+                } else {
+                    Ok(value)
+                }
+            }
             nodes::Expression::As(expr, typ) => {
                 let e = self.codegen_expression(expr, false)?;
                 let t = self.codegen_type(&typ.typ);
@@ -1069,13 +1481,19 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     }
 
     #[trace_call(always)]
-    fn codegen_unary(&mut self, unary_node: &nodes::UnaryNode, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+    fn codegen_unary(&mut self, unary_node: &nodes::UnaryNode<'src>, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
         match unary_node.operation {
             Operation::Negate => {
                 let value = self.codegen_expression(&unary_node.expression, false)?;
                 if unary_node.typ.is_float() {
                     let result = self.builder.build_float_neg(value.into_float_value(), "codegen_unary_negate")?;
                     Ok(result.into())
+                } else if self.needs_overflow_check(&unary_node.typ) {
+                    // -x overflows for the smallest signed value, so it's checked as 0 - x
+                    let value = self.try_into_int_value(&value)?;
+                    let zero = value.get_type().const_zero();
+                    let result = self.codegen_checked_int_op(&unary_node.location, Operation::Sub, &unary_node.typ, zero, value)?;
+                    Ok(result.into())
                 } else {
                     let result = self.builder.build_int_neg(self.try_into_int_value(&value)?, "codegen_unary_negate")?;
                     Ok(result.into())
@@ -1127,24 +1545,40 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     #[trace_call(always)]
     fn codegen_struct_literal(
         &mut self,
-        struct_literal: &nodes::StructLiteralNode,
+        struct_literal: &nodes::StructLiteralNode<'src>,
         needs_ptr: bool
     ) -> Result<BasicValueEnum<'ctx>, BuilderError> {
         let mut expressions = Vec::new();
         for field in &struct_literal.fields {
             expressions.push(self.codegen_expression(&field.1, false)?);
         }
+        // Listed fields are evaluated before the base, then overwrite its fields
+        let base = match &struct_literal.base {
+            Some(base) => Some(self.codegen_expression(base, false)?.into_struct_value()),
+            None => None,
+        };
         let real_name = struct_literal.typ.get_underlying_struct_name();
         let struct_type = self.struct_defs.get(&real_name).unwrap();
-        let mut struct_instance = struct_type.const_zero();
+        let struct_info = self.struct_info.get(&real_name).unwrap();
+        if struct_info.is_union {
+            // The only field starts at the union itself, the remaining bytes are zeroed
+            let union_alloc = self.allocate(BasicTypeEnum::StructType(*struct_type), "codegen_union_literal")?;
+            self.store_value_in_ptr(union_alloc, struct_type.const_zero())?;
+            self.store_value_in_ptr(union_alloc, expressions[0])?;
+            if needs_ptr {
+                return Ok(union_alloc.into());
+            }
+            return self.load_value_from_ptr(*struct_type, union_alloc, "union_literal_load");
+        }
+        let mut struct_instance = base.unwrap_or_else(|| struct_type.const_zero());
         for (i, field) in struct_literal.fields.iter().enumerate() {
-            let offset = self.struct_info.get(&real_name).unwrap().get_field_index(&field.0);
-            struct_instance = self.builder.build_insert_value(
+            let index = struct_info.get_field_index(&field.0);
+            struct_instance = self.build_insert_field(
                 struct_instance,
                 expressions[i],
-                offset as u32,
+                &struct_info.indices[index],
                 "struct_elem",
-            )?.into_struct_value();
+            )?;
         }
         if needs_ptr {
             let struct_alloc = self.allocate(BasicTypeEnum::StructType(*struct_type), "codegen_struct_literal")?;
@@ -1155,6 +1589,32 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         }
     }
 
+    #[trace_call(always)]
+    fn codegen_tuple_literal(
+        &mut self,
+        tuple_literal: &nodes::TupleLiteralNode<'src>,
+        needs_ptr: bool
+    ) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let tuple_type = self.codegen_type(&tuple_literal.typ).into_struct_type();
+        let mut tuple_instance = tuple_type.const_zero();
+        for (i, element) in tuple_literal.elements.iter().enumerate() {
+            let value = self.codegen_expression(element, false)?;
+            tuple_instance = self.builder.build_insert_value(
+                tuple_instance,
+                value,
+                i as u32,
+                "tuple_elem",
+            )?.into_struct_value();
+        }
+        if needs_ptr {
+            let tuple_alloc = self.allocate(tuple_type.into(), "codegen_tuple_literal")?;
+            self.store_value_in_ptr(tuple_alloc, tuple_instance)?;
+            Ok(tuple_alloc.into())
+        } else {
+            Ok(tuple_instance.as_basic_value_enum())
+        }
+    }
+
     #[trace_call(always)]
     fn get_struct_size(&self, typ: &BasicTypeEnum<'ctx>) -> u64 {
         let size_0 = self.target_machine.get_target_data().get_store_size(typ);
@@ -1212,7 +1672,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     #[trace_call(always)]
     fn codegen_array_literal(
         &mut self,
-        array_literal: &nodes::ArrayLiteralNode,
+        array_literal: &nodes::ArrayLiteralNode<'src>,
         needs_ptr: bool
     ) -> Result<BasicValueEnum<'ctx>, BuilderError> {
         let underlying_type = self.codegen_type(&array_literal.typ);
@@ -1282,26 +1742,40 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     }
 
     #[trace_call(always)]
-    fn codegen_function_call(&mut self, function_call: &nodes::CallNode, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
-        let real_name = if function_call.is_extern {
-            function_call.function_name.to_string()
+    fn codegen_function_call(&mut self, function_call: &nodes::CallNode<'src>, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        if !function_call.is_extern && !function_call.is_closure && !function_call.is_indirect && matches!(function_call.function_name, "assert" | "assert_with_msg") {
+            return self.codegen_assert(function_call);
+        }
+        let result = if function_call.is_closure {
+            let variable = self.get_variable(function_call.function_name).into_pointer_value();
+            let closure = self.load_value_from_ptr(self.dyn_type(), variable, "closure")?;
+            self.codegen_closure_call(closure, function_call)?
+        } else if function_call.is_indirect {
+            let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+            let variable = self.get_variable(function_call.function_name).into_pointer_value();
+            let function_ptr = self.load_value_from_ptr(ptr_type, variable, "function_ptr")?.into_pointer_value();
+            self.codegen_indirect_call(function_ptr, None, function_call)?
         } else {
-            format!("func.{}", function_call.function_name)
-        };
-        let Some(function) = self.module.get_function(&real_name) else {
-            internal_panic!("Could not find function {}", real_name);
-        };
-        let mut args = Vec::new();
-        for arg in &function_call.arguments {
-            let expr = self.codegen_expression(arg, false)?;
-            if expr.is_struct_value() {
-                let expr = self.struct_value_for_arg(expr)?;
-                args.push(expr.into());
+            let real_name = if function_call.is_extern {
+                function_call.function_name.to_string()
             } else {
-                args.push(expr.into());
+                format!("func.{}", function_call.function_name)
+            };
+            let Some(function) = self.module.get_function(&real_name) else {
+                internal_panic!("Could not find function {}", real_name);
+            };
+            let mut args = Vec::new();
+            for arg in &function_call.arguments {
+                let expr = self.codegen_expression(arg, false)?;
+                if expr.is_struct_value() {
+                    let expr = self.struct_value_for_arg(expr)?;
+                    args.push(expr.into());
+                } else {
+                    args.push(expr.into());
+                }
             }
-        }
-        let result = self.builder.build_call(function, &args, "codegen_function_call")?;
+            self.builder.build_call(function, &args, "codegen_function_call")?
+        };
         let val = if result.try_as_basic_value().left().is_none() {
             // NOTE: The return value doesn't matter, the function returns None
             //       it's a workaround for `void_type` not being a BasicValueEnum
@@ -1318,6 +1792,195 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         }
     }
 
+    /// Calls through a function pointer, the signature follows from the checked arguments and the result type
+    #[trace_call(always)]
+    fn codegen_indirect_call(
+        &mut self,
+        function_ptr: PointerValue<'ctx>,
+        environment: Option<PointerValue<'ctx>>,
+        function_call: &nodes::CallNode<'src>,
+    ) -> Result<CallSiteValue<'ctx>, BuilderError> {
+        let mut param_types = Vec::new();
+        let mut args = Vec::new();
+        if let Some(environment) = environment {
+            param_types.push(environment.get_type().into());
+            args.push(environment.into());
+        }
+        for arg in &function_call.arguments {
+            let expr = self.codegen_expression(arg, false)?;
+            if expr.is_struct_value() {
+                param_types.push(self.struct_type_for_arg(&expr.get_type()).into());
+                args.push(self.struct_value_for_arg(expr)?.into());
+            } else {
+                param_types.push(expr.get_type().into());
+                args.push(expr.into());
+            }
+        }
+        let function_type = if function_call.typ == Type::None {
+            self.context.void_type().fn_type(&param_types, false)
+        } else {
+            self.codegen_type(&function_call.typ).fn_type(&param_types, false)
+        };
+        self.builder.build_indirect_call(function_type, function_ptr, &args, "indirect_call")
+    }
+
+    /// A closure is called like a function pointer that receives the environment as its first argument
+    #[trace_call(always)]
+    fn codegen_closure_call(&mut self, closure: BasicValueEnum<'ctx>, function_call: &nodes::CallNode<'src>) -> Result<CallSiteValue<'ctx>, BuilderError> {
+        let closure = closure.into_struct_value();
+        let environment = self.builder.build_extract_value(closure, 0, "closure_env")?.into_pointer_value();
+        let function_ptr = self.builder.build_extract_value(closure, 1, "closure_fn")?.into_pointer_value();
+        self.codegen_indirect_call(function_ptr, Some(environment), function_call)
+    }
+
+    /// Lambdas become a function taking the environment as its first parameter, paired with that environment.
+    /// The environment holds copies of captures by value and addresses of captures by reference.
+    #[trace_call(always)]
+    fn codegen_lambda(&mut self, lambda: &nodes::LambdaNode<'src>, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+        let env_fields: Vec<BasicTypeEnum> = lambda.captures.iter().map(|capture| {
+            if capture.is_reference {
+                ptr_type.into()
+            } else {
+                self.codegen_type(&capture.typ)
+            }
+        }).collect();
+        let env_type = self.context.struct_type(&env_fields, false);
+
+        let environment = if lambda.captures.is_empty() {
+            ptr_type.const_null()
+        } else {
+            // The closure may outlive the current function, so the environment lives on the heap
+            let malloc = match self.module.get_function("malloc") {
+                Some(malloc) => malloc,
+                None => {
+                    let malloc_type = ptr_type.fn_type(&[self.context.i64_type().into()], false);
+                    self.module.add_function("malloc", malloc_type, None)
+                }
+            };
+            let env_size = self.context.i64_type().const_int(self.get_struct_size(&env_type.into()), false);
+            let environment = self.builder.build_call(malloc, &[env_size.into()], "closure_env")?
+                .try_as_basic_value().left().unwrap().into_pointer_value();
+            for (index, capture) in lambda.captures.iter().enumerate() {
+                let variable = self.get_variable(capture.name).into_pointer_value();
+                let field_ptr = self.builder.build_struct_gep(env_type, environment, index as u32, capture.name)?;
+                if capture.is_reference {
+                    self.store_value_in_ptr(field_ptr, variable)?;
+                } else {
+                    let value = self.load_value_from_ptr(self.codegen_type(&capture.typ), variable, capture.name)?;
+                    self.store_value_in_ptr(field_ptr, value)?;
+                }
+            }
+            environment
+        };
+
+        let function = self.codegen_lambda_function(lambda, env_type)?;
+        let closure = self.dyn_type().const_zero();
+        let closure = self.builder.build_insert_value(closure, environment, 0, "closure")?;
+        let closure = self.builder.build_insert_value(closure, function.as_global_value().as_pointer_value(), 1, "closure")?;
+        let closure = closure.as_basic_value_enum();
+        if needs_ptr {
+            let alloc = self.allocate(closure.get_type(), "closure")?;
+            self.store_value_in_ptr(alloc, closure)?;
+            Ok(alloc.into())
+        } else {
+            Ok(closure)
+        }
+    }
+
+    /// Generates the body of a lambda as its own function and returns to the current insert point afterwards
+    #[trace_call(always)]
+    fn codegen_lambda_function(&mut self, lambda: &nodes::LambdaNode<'src>, env_type: StructType<'ctx>) -> Result<FunctionValue<'ctx>, BuilderError> {
+        let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+        let mut param_types = vec![ptr_type.into()];
+        for param in &lambda.parameters {
+            let codegened_type = self.codegen_type_node(&param.typ);
+            if codegened_type.is_struct_type() {
+                param_types.push(self.struct_type_for_arg(&codegened_type).into());
+            } else {
+                param_types.push(codegened_type.into());
+            }
+        }
+        let function_type = if lambda.return_type.typ == Type::None {
+            self.context.void_type().fn_type(&param_types, false)
+        } else {
+            self.codegen_type(&lambda.return_type.typ).fn_type(&param_types, false)
+        };
+        // LLVM makes the name unique for every lambda
+        let function = self.module.add_function("closure", function_type, None);
+
+        let insert_block = self.builder.get_insert_block().unwrap();
+        let outer_scopes = std::mem::take(&mut self.stack_scopes);
+        let outer_loops = std::mem::take(&mut self.loop_blocks);
+        let outer_deferred = std::mem::take(&mut self.deferred);
+        self.enter_scope();
+
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+        let environment = function.get_nth_param(0).unwrap().into_pointer_value();
+        environment.set_name("env");
+        for (index, capture) in lambda.captures.iter().enumerate() {
+            let field_ptr = self.builder.build_struct_gep(env_type, environment, index as u32, capture.name)?;
+            if capture.is_reference {
+                let variable = self.load_value_from_ptr(ptr_type, field_ptr, capture.name)?;
+                self.add_variable(capture.name, variable);
+            } else {
+                self.add_variable(capture.name, field_ptr.into());
+            }
+        }
+        codegen_function_parameters!(self, lambda, function, 1);
+        self.codegen_coverage_counter(RegionKind::Function, &lambda.location, Some(&lambda.block))?;
+
+        self.codegen_block(&lambda.block)?;
+
+        if lambda.return_type.typ == Type::None && !lambda.block.llvm_has_terminator {
+            self.builder.build_return(None)?;
+        } else {
+            // Closure is guaranteed to return a value in block codegen
+        }
+
+        self.exit_scope();
+        self.stack_scopes = outer_scopes;
+        self.loop_blocks = outer_loops;
+        self.deferred = outer_deferred;
+        self.builder.position_at_end(insert_block);
+        Ok(function)
+    }
+
+    /// A function used as a value, Bufo functions are called `func.name`, externs keep their name
+    #[trace_call(always)]
+    fn codegen_function_address(&mut self, name: &nodes::NameNode, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let Some(function) = self.module.get_function(&format!("func.{}", name.name))
+            .or_else(|| self.module.get_function(name.name)) else {
+            internal_panic!("Could not find function {}", name.name)
+        };
+        let address = function.as_global_value().as_pointer_value();
+        if needs_ptr {
+            let alloc = self.allocate(address.get_type().as_basic_type_enum(), "function_address")?;
+            self.store_value_in_ptr(alloc, address)?;
+            Ok(alloc.into())
+        } else {
+            Ok(address.into())
+        }
+    }
+
+    #[trace_call(extra)]
+    fn fold_constant(&mut self, expression: &nodes::Expression<'src>) -> Result<Option<BasicValueEnum<'ctx>>, BuilderError> {
+        // Literals, names and friends already codegen to constants
+        if !matches!(expression, nodes::Expression::Unary(_) | nodes::Expression::Binary(_) | nodes::Expression::As(..)) {
+            return Ok(None);
+        }
+        let typ = expression.get_type();
+        if !(typ.is_integer() || matches!(typ, Type::Bool)) || expression.find_non_constant().is_some() {
+            return Ok(None);
+        }
+        // Anything the evaluator rejects (e.g. division by zero) is left for runtime
+        match self.comptime_evaluator.evaluate_constant(expression) {
+            Ok(value) if value.in_type_bounds(&typ) => Ok(Some(self.comptime_value_in_context(&typ, &value)?)),
+            _ => Ok(None),
+        }
+    }
+
     #[trace_call(extra)]
     fn comptime_value_in_context(&self, typ: &Type<'src>, value: &Value) -> Result<BasicValueEnum<'ctx>, BuilderError> {
         match value {
@@ -1327,7 +1990,10 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 debug_assert!(*typ == Type::Bool);
                 Ok(self.context.bool_type().const_int(*b as u64, false).into())
             },
-            Value::Char(_) => todo!(),
+            Value::Char(c) => {
+                debug_assert!(*typ == Type::Char);
+                Ok(self.context.i8_type().const_int(*c as u64, false).into())
+            },
             Value::I128(i) => {
                 debug_assert!(value.in_type_bounds(typ));
                 let value = match typ {
@@ -1345,31 +2011,63 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 Ok(value.into())
             },
             Value::F64(_) => todo!(),
+            Value::Ptr(0) => {
+                debug_assert!(typ.is_reference());
+                Ok(self.context.i8_type().ptr_type(AddressSpace::default()).const_null().into())
+            },
             Value::Ptr(p) => {
-                let memory = self.comptime_evaluator.get_memory_by_ptr(*p);
+                let memory = self.comptime_evaluator.get_string_by_ptr(*p);
                 debug_assert!(matches!(typ, Type::Ref(_, _)));
                 debug_assert!(typ.get_underlying_type() == &Type::Char);
                 let s = String::from_utf8_lossy(memory);
                 let value = self.builder.build_global_string_ptr(&s, "comptime_str")?;
                 Ok(value.as_pointer_value().into())
             },
+            Value::Struct(elements) if matches!(typ, Type::Tuple(_)) => {
+                let Type::Tuple(types) = typ else { unreachable!() };
+                let mut tuple_instance = self.codegen_type(typ).into_struct_type().const_zero();
+                for (i, (element, typ)) in elements.iter().zip(types.iter()).enumerate() {
+                    tuple_instance = self.builder.build_insert_value(
+                        tuple_instance,
+                        self.comptime_value_in_context(typ, element)?,
+                        i as u32,
+                        "tuple_elem",
+                    )?.into_struct_value();
+                }
+                Ok(tuple_instance.into())
+            },
             Value::Struct(s) => {
                 debug_assert!(matches!(typ, Type::Struct(_)));
                 let real_name = typ.get_underlying_struct_name();
                 let struct_type = self.struct_defs.get(&real_name).unwrap();
                 let struct_info = self.struct_info.get(&real_name).unwrap();
                 let mut struct_instance = struct_type.const_zero();
-                for (field_value, (field_name, typ)) in s.iter().zip(&struct_info.fields) {
-                    let offset = self.struct_info.get(&real_name).unwrap().get_field_index(&field_name);
-                    struct_instance = self.builder.build_insert_value(
+                for ((field_value, (field_name, typ)), indices) in s.iter().zip(&struct_info.fields).zip(&struct_info.indices) {
+                    struct_instance = self.build_insert_field(
                         struct_instance,
                         self.comptime_value_in_context(typ, field_value)?,
-                        offset as u32,
+                        indices,
                         field_name,
-                    )?.into_struct_value();
+                    )?;
                 }
                 Ok(struct_instance.into())
             },
+            Value::Array(bytes) if matches!(typ, Type::Struct(_)) => {
+                // The comptime evaluator keeps unions as their raw bytes
+                let struct_type = self.codegen_type(typ).into_struct_type();
+                let byte_type = self.context.i8_type();
+                let bytes: Vec<_> = bytes.iter().map(|b| match b {
+                    Value::I128(b) => byte_type.const_int(*b as u64, false),
+                    v => internal_panic!("Expected byte of union, found {v}"),
+                }).collect();
+                let union_bytes = self.context.const_struct(&[byte_type.const_array(&bytes).into()], true);
+                if struct_type.count_fields() == 1 {
+                    Ok(union_bytes.into())
+                } else {
+                    let align_type = struct_type.get_field_type_at_index(0).unwrap();
+                    Ok(self.context.const_struct(&[align_type.const_zero(), union_bytes.into()], false).into())
+                }
+            }
             Value::Array(elements) => {
                 let underlying_type = self.codegen_type(&typ);
                 let Type::Array(element_type, size) = &typ else {
@@ -1393,7 +2091,9 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
 
     #[trace_call(always)]
     fn codegen_name(&mut self, name: &nodes::NameNode, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
-        if !self.known_variable(&name.name) {
+        if !self.known_variable(name.name) && matches!(name.typ, Type::Function(..)) {
+            self.codegen_function_address(name, needs_ptr)
+        } else if !self.known_variable(&name.name) {
             let value = self.comptime_evaluator.get_variable_value(name);
             if let Err(_eval_error) = value {
                 todo!()
@@ -1424,7 +2124,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
     }
 
     #[trace_call(always)]
-    fn codegen_binary(&mut self, binary: &nodes::BinaryNode, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+    fn codegen_binary(&mut self, binary: &nodes::BinaryNode<'src>, needs_ptr: bool) -> Result<BasicValueEnum<'ctx>, BuilderError> {
         match &binary.operation {
             Operation::Add => {
                 let lhs = self.codegen_expression(&binary.lhs, false)?;
@@ -1454,11 +2154,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                             Ok(i2p.into())
                         }
                     } else {
-                        let result = self.builder.build_int_add(
-                            self.try_into_int_value(&lhs)?,
-                            self.try_into_int_value(&rhs)?,
-                            "codegen_binary_add",
-                        )?;
+                        let lhs = self.try_into_int_value(&lhs)?;
+                        let rhs = self.try_into_int_value(&rhs)?;
+                        if self.needs_overflow_check(&binary.typ) {
+                            let result = self.codegen_checked_int_op(&binary.location, Operation::Add, &binary.typ, lhs, rhs)?;
+                            return Ok(result.into());
+                        }
+                        let result = self.builder.build_int_add(lhs, rhs, "codegen_binary_add")?;
                         Ok(result.into())
                     }
                 }
@@ -1488,11 +2190,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                         };
                         Ok(gep.into())
                     } else {
-                        let result = self.builder.build_int_sub(
-                            self.try_into_int_value(&lhs)?,
-                            self.try_into_int_value(&rhs)?,
-                            "codegen_binary_sub",
-                        )?;
+                        let lhs = self.try_into_int_value(&lhs)?;
+                        let rhs = self.try_into_int_value(&rhs)?;
+                        if self.needs_overflow_check(&binary.typ) {
+                            let result = self.codegen_checked_int_op(&binary.location, Operation::Sub, &binary.typ, lhs, rhs)?;
+                            return Ok(result.into());
+                        }
+                        let result = self.builder.build_int_sub(lhs, rhs, "codegen_binary_sub")?;
                         Ok(result.into())
                     }
                 }
@@ -1508,11 +2212,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                     )?;
                     Ok(result.into())
                 } else {
-                    let result = self.builder.build_int_mul(
-                        self.try_into_int_value(&lhs)?,
-                        self.try_into_int_value(&rhs)?,
-                        "codegen_binary_mul",
-                    )?;
+                    let lhs = self.try_into_int_value(&lhs)?;
+                    let rhs = self.try_into_int_value(&rhs)?;
+                    if self.needs_overflow_check(&binary.typ) {
+                        let result = self.codegen_checked_int_op(&binary.location, Operation::Mul, &binary.typ, lhs, rhs)?;
+                        return Ok(result.into());
+                    }
+                    let result = self.builder.build_int_mul(lhs, rhs, "codegen_binary_mul")?;
                     Ok(result.into())
                 }
             }
@@ -1527,6 +2233,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                     )?;
                     Ok(result.into())
                 } else {
+                    self.codegen_division_check(
+                        &binary.location,
+                        Operation::Div,
+                        &binary.typ,
+                        self.try_into_int_value(&lhs)?,
+                        self.try_into_int_value(&rhs)?,
+                    )?;
                     let result = self.builder.build_int_signed_div(
                         self.try_into_int_value(&lhs)?,
                         self.try_into_int_value(&rhs)?,
@@ -1546,6 +2259,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                     )?;
                     Ok(result.into())
                 } else if binary.typ.is_signed() {
+                    self.codegen_division_check(
+                        &binary.location,
+                        Operation::Modulo,
+                        &binary.typ,
+                        self.try_into_int_value(&lhs)?,
+                        self.try_into_int_value(&rhs)?,
+                    )?;
                     let result = self.builder.build_int_signed_rem(
                         self.try_into_int_value(&lhs)?,
                         self.try_into_int_value(&rhs)?,
@@ -1553,6 +2273,13 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                     )?;
                     Ok(result.into())
                 } else {
+                    self.codegen_division_check(
+                        &binary.location,
+                        Operation::Modulo,
+                        &binary.typ,
+                        self.try_into_int_value(&lhs)?,
+                        self.try_into_int_value(&rhs)?,
+                    )?;
                     let result = self.builder.build_int_unsigned_rem(
                         self.try_into_int_value(&lhs)?,
                         self.try_into_int_value(&rhs)?,
@@ -1740,11 +2467,54 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
             Operation::Assign => {
                 let var = self.codegen_expression(&binary.lhs, true)?;
                 let value = self.codegen_expression(&binary.rhs, false)?;
-                self.store_value_in_ptr(var.into_pointer_value(), value)?;
+                let store = self.store_value_in_ptr(var.into_pointer_value(), value)?;
+                if let nodes::Expression::Binary(lhs) = binary.lhs.as_ref() {
+                    self.set_access_alignment(lhs, store);
+                }
                 Ok(value)
             }
             Operation::MemberAccess => {
                 match ((*binary.lhs).get_type(), &(*binary.rhs)) {
+                    // `f.drop()`, the type checker doesn't allow any other method on closures
+                    (Type::Closure(..), nodes::Expression::FunctionCall(_)) => {
+                        let closure = self.codegen_expression(&binary.lhs, false)?.into_struct_value();
+                        let environment = self.builder.build_extract_value(closure, 0, "closure_env")?;
+                        let free = match self.module.get_function("free") {
+                            Some(free) => free,
+                            None => {
+                                let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+                                let free_type = self.context.void_type().fn_type(&[ptr_type.into()], false);
+                                self.module.add_function("free", free_type, None)
+                            }
+                        };
+                        // Closures without captures have a null environment, freeing it does nothing
+                        self.builder.build_call(free, &[environment.into()], "")?;
+                        Ok(self.context.i32_type().const_int(0, false).into())
+                    }
+                    // `tuple.0`, the type checker only allows literals on tuples and references to them
+                    (typ, nodes::Expression::Literal(index)) => {
+                        let tuple_type = self.codegen_type(typ.get_underlying_type());
+                        let mut lhs = self.codegen_expression(&binary.lhs, !typ.is_reference())?;
+                        if !lhs.is_pointer_value() {
+                            let lhs_alloca = self.allocate(lhs.get_type(), "codegen_tuple_element")?;
+                            self.store_value_in_ptr(lhs_alloca, lhs)?;
+                            lhs = lhs_alloca.into();
+                        }
+                        let index = index.value.parse::<u32>().expect("Tuple indices are checked by the type checker");
+                        let element_ptr = self.builder.build_struct_gep(
+                            tuple_type.into_struct_type(),
+                            lhs.into_pointer_value(),
+                            index,
+                            "element_ptr")?;
+                        if needs_ptr {
+                            Ok(element_ptr.into())
+                        } else {
+                            let element_type = self.codegen_type(&binary.typ);
+                            let element = self.load_value_from_ptr(element_type, element_ptr, "element_ptr_load")?;
+                            self.set_access_alignment(binary, element.as_instruction_value().unwrap());
+                            Ok(element)
+                        }
+                    }
                     (ref typ @ Type::Ref(_, _), nodes::Expression::Name(field))
                     | (ref typ @ Type::Struct(_), nodes::Expression::Name(field)) => {
                         let real_name = match typ {
@@ -1761,18 +2531,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                             self.store_value_in_ptr(lhs_alloca, lhs)?;
                             lhs = lhs_alloca.into();
                         }
-                        let Some(struct_type) = self.get_struct_type(&real_name) else {
-                            internal_panic!("Could not find struct {}", real_name)
-                        };
-                        let Some(struct_lookup) = self.struct_info.get(&real_name) else {
-                            internal_panic!("Could not find struct {}", real_name)
-                        };
-                        let offset = struct_lookup.get_field_index(&field.name);
-                        let field_ptr = self.builder.build_struct_gep(
-                            struct_type.clone(),
-                            lhs.into_pointer_value(),
-                            offset as u32,
-                            "field_ptr")?;
+                        let field_ptr = self.build_field_ptr(&real_name, lhs.into_pointer_value(), &field.name)?;
                         let field_type = self.codegen_type(&field.typ);
                         if needs_ptr {
                                 Ok(field_ptr.into())
@@ -1782,9 +2541,43 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                                 field_ptr,
                                 "field_ptr_load",
                             )?;
+                            self.set_access_alignment(binary, field_value.as_instruction_value().unwrap());
                             Ok(field_value)
                         }
                     },
+                    (Type::Ref(..) | Type::Struct(_), nodes::Expression::FunctionCall(call)) if call.is_indirect => {
+                        // `s.callback(x)` loads the function pointer or closure from the field and calls it
+                        let field_type = if call.is_closure {
+                            Type::Closure(Box::new([]), Box::new(Type::None))
+                        } else {
+                            Type::Function(Box::new([]), Box::new(Type::None), false)
+                        };
+                        let field = nodes::Expression::Binary(nodes::BinaryNode {
+                            rhs: Box::new(nodes::Expression::Name(nodes::NameNode {
+                                location: call.location,
+                                name: call.function_name,
+                                typ: field_type,
+                                is_comptime: false,
+                            })),
+                            non_constant: OnceCell::new(),
+                            ..binary.clone()
+                        });
+                        let field = self.codegen_expression(&field, false)?;
+                        let result = if call.is_closure {
+                            self.codegen_closure_call(field, call)?
+                        } else {
+                            self.codegen_indirect_call(field.into_pointer_value(), None, call)?
+                        };
+                        let val = result.try_as_basic_value().left()
+                            .unwrap_or_else(|| self.context.i32_type().const_int(0, false).into());
+                        if needs_ptr {
+                            let temp_alloc = self.allocate(val.get_type(), "indirect_call")?;
+                            self.store_value_in_ptr(temp_alloc, val)?;
+                            Ok(temp_alloc.into())
+                        } else {
+                            Ok(val)
+                        }
+                    },
                     (ref typ @ Type::Ref(_, _), nodes::Expression::FunctionCall(method_call))
                     | (ref typ @ Type::Struct(_), nodes::Expression::FunctionCall(method_call)) => {
                         let real_name = match typ {
@@ -1823,10 +2616,19 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                         if result.try_as_basic_value().left().is_none() {
                             // NOTE: The return value doesn't matter, the function returns None
                             Ok(lhs.into())
+                        } else if needs_ptr {
+                            // e.g. `(a + b).x` or `(a + b) + c`, where the result is `this` of another call
+                            let val = result.try_as_basic_value().left().unwrap();
+                            let temp_alloc = self.allocate(val.get_type(), "method_call")?;
+                            self.store_value_in_ptr(temp_alloc, val)?;
+                            Ok(temp_alloc.into())
                         } else {
                             Ok(result.try_as_basic_value().left().unwrap())
                         }
                     },
+                    (Type::Dyn(interface, _), nodes::Expression::FunctionCall(method_call)) => {
+                        self.codegen_dyn_method_call(interface, &binary.lhs, method_call)
+                    },
                     (lhs, rhs) => internal_panic!(
                         "Something went wrong: Found {:?} as lhs and {:?} as rhs of Dot operation!", lhs, rhs
                     )
@@ -1843,31 +2645,8 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 debug_assert!(array_type.is_array_type());
                 debug_assert!(index.is_int_value());
                 debug_assert!(array.is_pointer_value());
-                {
-                    // if (index >= size) { panic("Index out of bounds"); }
-                    let cond = self.builder.build_int_compare(
-                        inkwell::IntPredicate::UGE,
-                        index.into_int_value(),
-                        self.context.i64_type().const_int(array_size as u64, false),
-                        "codegen_binary_indexedaccess_cond",
-                    )?;
-                    let current_fn = self.builder.get_insert_block().unwrap().get_parent().unwrap();
-                    let panic_block = self.context.append_basic_block(current_fn, "codegen_binary_indexedaccess_panic");
-                    let normal_block = self.context.append_basic_block(current_fn, "codegen_binary_indexedaccess_normal");
-                    self.builder.build_conditional_branch(cond, panic_block, normal_block)?;
-                    self.builder.position_at_end(panic_block);
-                    let exit_fn = self.module.get_function(&"func.index_oob").unwrap();
-                    let exit_msg = self.builder.build_global_string_ptr(&format!(
-                        "{:?}: RUNTIME ERROR: Index out of bounds: Array of size {} has no index %d\n",
-                        binary.location,
-                        array_size,
-                    ), "oob_lit")?;
-                    self.builder.build_call(exit_fn, &[
-                        exit_msg.as_pointer_value().into(),
-                        index.into(),
-                    ], "run_time_error")?;
-                    self.builder.build_unreachable()?;
-                    self.builder.position_at_end(normal_block);
+                if binary.needs_bounds_check {
+                    self.codegen_bounds_check(&binary.location, index.into_int_value(), array_size)?;
                 }
                 let field_ptr = unsafe {
                     self.builder.build_gep(
@@ -1885,6 +2664,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                         field_ptr,
                         "field_ptr_load",
                     )?;
+                    self.set_access_alignment(binary, field_value.as_instruction_value().unwrap());
                     Ok(field_value)
                 }
             }
@@ -1892,6 +2672,423 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         }
     }
 
+    #[trace_call(always)]
+    fn codegen_bounds_check(&mut self, location: &Location, index: IntValue<'ctx>, size: usize) -> Result<(), BuilderError> {
+        if !self.flags.bounds_checks() {
+            return Ok(());
+        }
+        // if (index >= size) { panic("Index out of bounds"); }
+        let cond = self.builder.build_int_compare(
+            inkwell::IntPredicate::UGE,
+            index,
+            self.context.i64_type().const_int(size as u64, false),
+            "codegen_bounds_check_cond",
+        )?;
+        let current_fn = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let panic_block = self.context.append_basic_block(current_fn, "codegen_bounds_check_panic");
+        let normal_block = self.context.append_basic_block(current_fn, "codegen_bounds_check_normal");
+        self.builder.build_conditional_branch(cond, panic_block, normal_block)?;
+        self.builder.position_at_end(panic_block);
+        let exit_fn = self.module.get_function(&"func.index_oob").unwrap();
+        let location = self.codegen_runtime_location(location)?;
+        self.builder.build_call(exit_fn, &[
+            location.into(),
+            self.context.i64_type().const_int(size as u64, false).into(),
+            index.into(),
+        ], "run_time_error")?;
+        self.builder.build_unreachable()?;
+        self.builder.position_at_end(normal_block);
+        Ok(())
+    }
+
+    /// Returns file, line and column of a location
+    #[trace_call(extra)]
+    fn source_position(&mut self, location: &Location) -> (String, usize, usize) {
+        let name = format!("{location:?}");
+        let file = name.rsplit_once(':').map_or(name.as_str(), |(file, _)| file).to_string();
+        let source = self.sources.get(Path::new(&file)).map_or("", String::as_str);
+        // Parser locations may point at the whitespace or comments before the token
+        let bytes = source.as_bytes();
+        let mut byte = location.byte.min(bytes.len());
+        while byte < bytes.len() {
+            if bytes[byte].is_ascii_whitespace() {
+                byte += 1;
+            } else if bytes[byte..].starts_with(b"//") {
+                byte += bytes[byte..].iter().take_while(|b| **b != b'\n').count();
+            } else {
+                break;
+            }
+        }
+        let line_starts = self.line_starts
+            .entry(location.file_id)
+            .or_insert_with(|| std::iter::once(0).chain(source.match_indices('\n').map(|(i, _)| i + 1)).collect());
+        let line = line_starts.partition_point(|start| *start <= byte);
+        let col = byte - line_starts[line - 1] + 1;
+        (file, line, col)
+    }
+
+    #[trace_call(always)]
+    fn codegen_runtime_location(&mut self, location: &Location) -> Result<PointerValue<'ctx>, BuilderError> {
+        let (file, line, col) = self.source_position(location);
+        let location = self.builder.build_global_string_ptr(&format!("{file}:{line}:{col}"), "runtime_location")?;
+        Ok(location.as_pointer_value())
+    }
+
+    /// Lines of all statements that run when `block` is entered, nested if and while bodies have their own regions
+    #[trace_call(extra)]
+    fn coverage_lines(&mut self, block: &nodes::BlockNode<'src>, lines: &mut Vec<usize>) {
+        for statement in &block.statements {
+            let (_, line, _) = self.source_position(&statement.get_loc());
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+            if let nodes::Statement::Block(block) = statement {
+                self.coverage_lines(block, lines);
+            }
+        }
+    }
+
+    #[trace_call(always)]
+    fn codegen_coverage_counter(
+        &mut self,
+        kind: RegionKind,
+        location: &Location,
+        block: Option<&nodes::BlockNode<'src>>,
+    ) -> Result<(), BuilderError> {
+        if !self.flags.coverage {
+            return Ok(());
+        }
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let function = function.get_name().to_str().unwrap();
+        let function = function.strip_prefix("func.").unwrap_or(function).to_string();
+        let (file, line, _) = self.source_position(location);
+        let mut lines = Vec::new();
+        if kind == RegionKind::Function {
+            lines.push(line);
+        }
+        if let Some(block) = block {
+            self.coverage_lines(block, &mut lines);
+        }
+        let index = self.coverage_regions.len();
+        self.coverage_regions.push(CoverageRegion { kind, function, file, line, lines });
+
+        // counters[index] += 1
+        let i64_type = self.context.i64_type();
+        let placeholder = *self.coverage_placeholder.get_or_insert_with(|| {
+            self.module.add_global(i64_type, None, "coverage.placeholder")
+        });
+        let counter = unsafe {
+            self.builder.build_gep(
+                i64_type,
+                placeholder.as_pointer_value(),
+                &[i64_type.const_int(index as u64, false)],
+                "coverage_counter_ptr",
+            )
+        }?;
+        let value = self.load_value_from_ptr(i64_type, counter, "coverage_counter")?;
+        let value = self.builder.build_int_add(value.into_int_value(), i64_type.const_int(1, false), "coverage_counter_inc")?;
+        self.store_value_in_ptr(counter, value)?;
+        Ok(())
+    }
+
+    /// Creates the real counters and `coverage.dump()`, which is registered as a destructor in the entrypoint
+    #[trace_call(always)]
+    fn codegen_coverage_dump(&mut self) -> Result<(), BuilderError> {
+        if !self.flags.coverage {
+            return Ok(());
+        }
+        let i64_type = self.context.i64_type();
+        let count = self.coverage_regions.len();
+        let counters_type = i64_type.array_type(count as u32);
+        let counters = self.module.add_global(counters_type, None, "coverage.counters");
+        counters.set_initializer(&counters_type.const_zero());
+        if let Some(placeholder) = self.coverage_placeholder.take() {
+            placeholder.as_pointer_value().replace_all_uses_with(counters.as_pointer_value());
+            unsafe { placeholder.delete() };
+        }
+
+        let dump = self.module.get_function("coverage.dump").unwrap();
+        let entry = self.context.append_basic_block(dump, "entry");
+        self.builder.position_at_end(entry);
+        let dump_coverage = self.module.get_function("func.dumpCoverage").expect("dumpCoverage is part of the prelude and should always be found");
+        self.builder.build_call(dump_coverage, &[
+            i64_type.const_int(coverage::map_id(&self.coverage_regions), false).into(),
+            counters.as_pointer_value().into(),
+            i64_type.const_int(count as u64, false).into(),
+        ], "dump_coverage")?;
+        self.builder.build_return(None)?;
+        Ok(())
+    }
+
+    #[trace_call(always)]
+    fn codegen_panic(&mut self, kind: &str, location: &Location, message: PointerValue<'ctx>) -> Result<(), BuilderError> {
+        let panic = self.module.get_function("GLOBAL_PANIC").unwrap();
+        let kind = self.builder.build_global_string_ptr(kind, "panic_kind")?;
+        let location = self.codegen_runtime_location(location)?;
+        self.builder.build_call(panic, &[
+            kind.as_pointer_value().into(),
+            location.into(),
+            message.into(),
+        ], "run_time_error")?;
+        self.builder.build_unreachable()?;
+        Ok(())
+    }
+
+    #[trace_call(always)]
+    fn codegen_assert(&mut self, function_call: &nodes::CallNode<'src>) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        // Inlined so the panic knows where the assertion is
+        let cond = self.codegen_expression(&function_call.arguments[0], false)?;
+        let current_fn = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let panic_block = self.context.append_basic_block(current_fn, "codegen_assert_panic");
+        let normal_block = self.context.append_basic_block(current_fn, "codegen_assert_normal");
+        self.builder.build_conditional_branch(cond.into_int_value(), normal_block, panic_block)?;
+        self.builder.position_at_end(panic_block);
+        let message = match function_call.arguments.get(1) {
+            Some(message) => self.codegen_expression(message, false)?.into_pointer_value(),
+            None => self.builder.build_global_string_ptr("Condition was false", "assert_lit")?.as_pointer_value(),
+        };
+        self.codegen_panic("Assertion failed", &function_call.location, message)?;
+        self.builder.position_at_end(normal_block);
+        Ok(self.context.i32_type().const_int(0, false).into())
+    }
+
+    #[trace_call(extra)]
+    fn needs_overflow_check(&self, typ: &Type<'src>) -> bool {
+        (self.flags.overflow_checks || self.flags.debug || self.flags.sanitize(Sanitizer::Undefined))
+            && typ.is_integer()
+            && !matches!(typ, Type::Char)
+    }
+
+    /// Dividing by zero and `MIN / -1` for signed types are undefined behavior in LLVM
+    #[trace_call(always)]
+    fn codegen_division_check(
+        &mut self,
+        location: &Location,
+        operation: Operation,
+        typ: &Type<'src>,
+        dividend: IntValue<'ctx>,
+        divisor: IntValue<'ctx>,
+    ) -> Result<(), BuilderError> {
+        if !self.flags.sanitize(Sanitizer::Undefined) {
+            return Ok(());
+        }
+        // if (divisor == 0) { panic("Division by zero"); }
+        let cond = self.builder.build_int_compare(
+            inkwell::IntPredicate::EQ,
+            divisor,
+            divisor.get_type().const_zero(),
+            "codegen_division_check_cond",
+        )?;
+        let current_fn = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let panic_block = self.context.append_basic_block(current_fn, "codegen_division_check_panic");
+        let normal_block = self.context.append_basic_block(current_fn, "codegen_division_check_normal");
+        self.builder.build_conditional_branch(cond, panic_block, normal_block)?;
+        self.builder.position_at_end(panic_block);
+        let message = self.builder.build_global_string_ptr("Attempted to divide by zero", "division_lit")?;
+        self.codegen_panic("Division by zero", location, message.as_pointer_value())?;
+        self.builder.position_at_end(normal_block);
+        if !typ.is_signed() {
+            return Ok(());
+        }
+        // if (dividend == MIN && divisor == -1) { panic("Integer overflow"); }
+        let int_type = divisor.get_type();
+        let min = int_type.const_int(1 << (int_type.get_bit_width() - 1), false);
+        let is_min = self.builder.build_int_compare(inkwell::IntPredicate::EQ, dividend, min, "codegen_division_check_min")?;
+        let is_minus_one = self.builder.build_int_compare(
+            inkwell::IntPredicate::EQ,
+            divisor,
+            int_type.const_all_ones(),
+            "codegen_division_check_minus_one",
+        )?;
+        let cond = self.builder.build_and(is_min, is_minus_one, "codegen_division_check_overflow")?;
+        let panic_block = self.context.append_basic_block(current_fn, "codegen_division_check_overflow_panic");
+        let normal_block = self.context.append_basic_block(current_fn, "codegen_division_check_overflow_normal");
+        self.builder.build_conditional_branch(cond, panic_block, normal_block)?;
+        self.builder.position_at_end(panic_block);
+        let message = self.builder.build_global_string_ptr(
+            &format!("`{}` overflowed type `{}`", operation, typ),
+            "overflow_lit",
+        )?;
+        self.codegen_panic("Integer overflow", location, message.as_pointer_value())?;
+        self.builder.position_at_end(normal_block);
+        Ok(())
+    }
+
+    /// Returns the wrapped result and whether the operation overflowed
+    #[trace_call(always)]
+    fn codegen_int_with_overflow(
+        &mut self,
+        operation: Operation,
+        typ: &Type<'src>,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<(IntValue<'ctx>, IntValue<'ctx>), BuilderError> {
+        let sign = if typ.is_signed() { "s" } else { "u" };
+        let op = match operation {
+            Operation::Add => "add",
+            Operation::Sub => "sub",
+            Operation::Mul => "mul",
+            o => internal_panic!("No overflow intrinsic for {o:?}"),
+        };
+        let name = format!("llvm.{sign}{op}.with.overflow");
+        let Some(intrinsic) = Intrinsic::find(&name) else {
+            internal_panic!("Could not find intrinsic {name}")
+        };
+        let Some(function) = intrinsic.get_declaration(&self.module, &[lhs.get_type().into()]) else {
+            internal_panic!("Could not declare intrinsic {name}")
+        };
+        let call = self.builder.build_call(function, &[lhs.into(), rhs.into()], "codegen_int_with_overflow")?;
+        let result = call.try_as_basic_value().left().unwrap().into_struct_value();
+        let value = self.builder.build_extract_value(result, 0, "codegen_int_with_overflow_value")?;
+        let overflowed = self.builder.build_extract_value(result, 1, "codegen_int_with_overflow_flag")?;
+        Ok((value.into_int_value(), overflowed.into_int_value()))
+    }
+
+    #[trace_call(always)]
+    fn codegen_checked_int_op(
+        &mut self,
+        location: &Location,
+        operation: Operation,
+        typ: &Type<'src>,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>, BuilderError> {
+        // if (overflowed) { panic("Integer overflow"); }
+        let (value, overflowed) = self.codegen_int_with_overflow(operation, typ, lhs, rhs)?;
+        let current_fn = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let panic_block = self.context.append_basic_block(current_fn, "codegen_overflow_check_panic");
+        let normal_block = self.context.append_basic_block(current_fn, "codegen_overflow_check_normal");
+        self.builder.build_conditional_branch(overflowed, panic_block, normal_block)?;
+        self.builder.position_at_end(panic_block);
+        let message = self.builder.build_global_string_ptr(
+            &format!("`{}` overflowed type `{}`", operation, typ),
+            "overflow_lit",
+        )?;
+        self.codegen_panic("Integer overflow", location, message.as_pointer_value())?;
+        self.builder.position_at_end(normal_block);
+        Ok(value)
+    }
+
+    #[trace_call(always)]
+    fn codegen_arithmetic(&mut self, arithmetic: &nodes::ArithmeticNode<'src>) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let typ = arithmetic.operand_type();
+        let lhs = self.codegen_expression(&arithmetic.lhs, false)?;
+        let lhs = self.try_into_int_value(&lhs)?;
+        let rhs = self.codegen_expression(&arithmetic.rhs, false)?;
+        let rhs = self.try_into_int_value(&rhs)?;
+        let (value, overflowed) = self.codegen_int_with_overflow(arithmetic.operation, &typ, lhs, rhs)?;
+        match arithmetic.mode {
+            nodes::ArithmeticMode::Wrapping => Ok(value.into()),
+            nodes::ArithmeticMode::Checked => {
+                let Some(result) = &arithmetic.result else {
+                    internal_panic!("checked arithmetic without a result reference")
+                };
+                let ptr = self.codegen_expression(result, false)?;
+                self.store_value_in_ptr(ptr.into_pointer_value(), value)?;
+                let fits = self.builder.build_not(overflowed, "codegen_arithmetic_checked")?;
+                Ok(fits.into())
+            }
+            nodes::ArithmeticMode::Saturating => {
+                let int_type = lhs.get_type();
+                let bits = typ.get_bit_size() as u32;
+                let limit = if typ.is_signed() {
+                    // Signed results overflow towards the sign the exact result would have had
+                    let max = int_type.const_int((1u64 << (bits - 1)) - 1, false);
+                    let min = int_type.const_int(1u64 << (bits - 1), false);
+                    let zero = int_type.const_zero();
+                    let towards_min = match arithmetic.operation {
+                        Operation::Add => self.builder.build_int_compare(inkwell::IntPredicate::SLT, rhs, zero, "sat_neg")?,
+                        Operation::Sub => self.builder.build_int_compare(inkwell::IntPredicate::SGT, rhs, zero, "sat_neg")?,
+                        _ => {
+                            let xor = self.builder.build_xor(lhs, rhs, "sat_sign")?;
+                            self.builder.build_int_compare(inkwell::IntPredicate::SLT, xor, zero, "sat_neg")?
+                        }
+                    };
+                    self.builder.build_select(towards_min, min, max, "sat_limit")?.into_int_value()
+                } else if arithmetic.operation == Operation::Sub {
+                    int_type.const_zero()
+                } else {
+                    int_type.const_all_ones()
+                };
+                let result = self.builder.build_select(overflowed, limit, value, "codegen_arithmetic_saturating")?;
+                Ok(result)
+            }
+        }
+    }
+
+    #[trace_call(always)]
+    fn codegen_embed(&mut self, embed: &nodes::EmbedNode) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        if embed.length_only {
+            return Ok(self.context.i64_type().const_int(embed.content.len() as u64, false).into());
+        }
+        // Embedded files may contain null bytes, so we can't go through `build_global_string_ptr`
+        let content = self.context.const_string(&embed.content, true);
+        let global = self.module.add_global(content.get_type(), Some(AddressSpace::default()), "embedded_file");
+        global.set_initializer(&content);
+        global.set_constant(true);
+        Ok(global.as_pointer_value().into())
+    }
+
+    #[trace_call(always)]
+    fn codegen_reflection(&mut self, reflection: &nodes::ReflectionNode<'src>) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let typ = &reflection.reflected.typ;
+        let target_data = self.target_machine.get_target_data();
+        match &reflection.kind {
+            nodes::ReflectionKind::FieldCount => {
+                let Type::Struct(name) = typ else { unreachable!() };
+                let count = self.struct_info.get(name).unwrap().fields.len();
+                Ok(self.context.i64_type().const_int(count as u64, false).into())
+            }
+            nodes::ReflectionKind::FieldName(index) => {
+                let Type::Struct(name) = typ else { unreachable!() };
+                let field_names: Vec<&str> = self.struct_info.get(name).unwrap().fields.iter().map(|(n, _)| *n).collect();
+                let index = self.codegen_expression(index, false)?.into_int_value();
+                self.codegen_bounds_check(&reflection.location, index, field_names.len())?;
+                // Lookup table of all field names, so the index may be a runtime value
+                let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+                let table_type = ptr_type.array_type(field_names.len() as u32);
+                let table_name = format!("{name}.field_names");
+                let table = match self.module.get_global(&table_name) {
+                    Some(table) => table,
+                    None => {
+                        let mut names = Vec::new();
+                        for field_name in &field_names {
+                            let name_ptr = self.builder.build_global_string_ptr(field_name, "field_name")?;
+                            names.push(name_ptr.as_pointer_value());
+                        }
+                        let table = self.module.add_global(table_type, Some(AddressSpace::default()), &table_name);
+                        table.set_initializer(&ptr_type.const_array(&names));
+                        table.set_constant(true);
+                        table
+                    }
+                };
+                let name_ptr = unsafe {
+                    self.builder.build_gep(
+                        table_type,
+                        table.as_pointer_value(),
+                        &[self.context.i64_type().const_int(0, false), index],
+                        "field_name_ptr")
+                }?;
+                self.load_value_from_ptr(ptr_type, name_ptr, "field_name_load")
+            }
+            nodes::ReflectionKind::Offsetof(field) => {
+                let Type::Struct(name) = typ else { unreachable!() };
+                let struct_info = self.struct_info.get(name).unwrap();
+                let offset = struct_info.offsets[struct_info.get_field_index(field.name)];
+                Ok(self.context.i64_type().const_int(offset as u64, false).into())
+            }
+            nodes::ReflectionKind::Alignof => {
+                let llvm_type = self.codegen_type(typ);
+                let align = target_data.get_abi_alignment(&llvm_type);
+                Ok(self.context.i64_type().const_int(align as u64, false).into())
+            }
+            nodes::ReflectionKind::TypeName => {
+                let name_ptr = self.builder.build_global_string_ptr(&format!("{typ}"), "type_name")?;
+                Ok(name_ptr.as_pointer_value().into())
+            }
+        }
+    }
+
     fn escape_string_or_char_value(&self, value: &str) -> String {
         let mut new_value = Vec::new();
         let mut escaping = false;
@@ -2022,7 +3219,7 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 let value = self.builder.build_global_string_ptr(&escaped, "codegen_str_literal")?;
                 Ok(value.as_pointer_value().into())
             }
-            struct_type @ Type::Struct(_) => {
+            struct_type @ (Type::Struct(_) | Type::Tuple(_)) => {
                 debug_assert!(literal.value == KEYWORD_BLANK);
                 let underlying_type = self.codegen_type(&struct_type);
                 Ok(underlying_type.into_struct_type().const_zero().into())
@@ -2078,7 +3275,12 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
                 struct_type.into()
             },
             // Note: As pointers in LLVM are no longer typed, we can just do that
-            Type::Ref(..) | Type::Any => self.context.i64_type().ptr_type(AddressSpace::default()).as_basic_type_enum(),
+            Type::Ref(..) | Type::Any | Type::Function(..) => self.context.i64_type().ptr_type(AddressSpace::default()).as_basic_type_enum(),
+            Type::Dyn(..) | Type::Closure(..) => self.dyn_type().as_basic_type_enum(),
+            Type::Tuple(elements) => {
+                let elements: Vec<_> = elements.iter().map(|e| self.codegen_type(e)).collect();
+                self.context.struct_type(&elements, false).as_basic_type_enum()
+            }
             // Note: Void does not exist as BasicTypeEnum, so void functions are handled differently
             Type::None => internal_panic!("Type::None should never be used!"),
             Type::Unknown => internal_panic!("Type::Unknown should never be used!"),
@@ -2086,6 +3288,120 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         }
     }
 
+    /// `&dyn I` is a data pointer followed by a vtable pointer, closures are an environment pointer followed by a function pointer
+    #[trace_call(extra)]
+    fn dyn_type(&self) -> StructType<'ctx> {
+        let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+        self.context.struct_type(&[ptr_type.into(), ptr_type.into()], false)
+    }
+
+    /// The vtable of `impl I for S` holds the methods of `S` in the order they're declared in `I`
+    #[trace_call(always)]
+    fn get_vtable(&mut self, struct_name: &str, interface_name: &str) -> PointerValue<'ctx> {
+        let vtable_name = format!("vtable.{}.{}", struct_name, interface_name);
+        if let Some(vtable) = self.module.get_global(&vtable_name) {
+            return vtable.as_pointer_value();
+        }
+        let Some(interface) = self.interfaces.get(interface_name) else {
+            internal_panic!("Could not find interface {}", interface_name)
+        };
+        let mut methods = Vec::new();
+        for method in &interface.methods {
+            let method_name = format!("{}.{}", struct_name, method.name);
+            let Some(function) = self.module.get_function(&method_name) else {
+                internal_panic!("Could not find function {}", method_name)
+            };
+            methods.push(function.as_global_value().as_pointer_value());
+        }
+        let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+        let vtable_type = ptr_type.array_type(methods.len() as u32);
+        let vtable = self.module.add_global(vtable_type, Some(AddressSpace::default()), &vtable_name);
+        vtable.set_initializer(&ptr_type.const_array(&methods));
+        vtable.set_constant(true);
+        vtable.set_linkage(Linkage::Private);
+        vtable.as_pointer_value()
+    }
+
+    /// Builds a `&dyn I` from a struct reference, or passes an existing one through
+    #[trace_call(always)]
+    fn codegen_dyn_reference(&mut self, expr: &nodes::Expression<'src>, typ: &Type<'src>) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let value = self.codegen_expression(expr, false)?;
+        let (Type::Ref(underlying, _), Type::Dyn(interface, _)) = (expr.get_type(), typ) else {
+            debug_assert!(matches!(expr.get_type(), Type::Dyn(..)));
+            return Ok(value);
+        };
+        let Type::Struct(struct_name) = *underlying else {
+            internal_panic!("Expected Reference to Struct, found Reference to {underlying}")
+        };
+        let vtable = self.get_vtable(struct_name, interface);
+        let fat = self.dyn_type().get_undef();
+        let fat = self.builder.build_insert_value(fat, value, 0, "dyn_data")?;
+        let fat = self.builder.build_insert_value(fat, vtable, 1, "dyn_vtable")?;
+        Ok(fat.as_basic_value_enum())
+    }
+
+    /// Calls a method of `&dyn I` indirectly through its vtable
+    #[trace_call(always)]
+    fn codegen_dyn_method_call(
+        &mut self,
+        interface_name: &str,
+        lhs: &nodes::Expression<'src>,
+        method_call: &nodes::CallNode<'src>,
+    ) -> Result<BasicValueEnum<'ctx>, BuilderError> {
+        let Some(interface) = self.interfaces.get(interface_name) else {
+            internal_panic!("Could not find interface {}", interface_name)
+        };
+        let Some(index) = interface.methods.iter().position(|m| m.name == method_call.function_name) else {
+            internal_panic!("Interface {} has no method {}", interface_name, method_call.function_name)
+        };
+        let method = interface.methods[index].clone();
+        let method_count = interface.methods.len() as u32;
+        let ptr_type = self.context.i8_type().ptr_type(AddressSpace::default());
+        // `this` is the data pointer
+        let mut param_types = vec![ptr_type.into()];
+        for param in method.parameters.iter().skip(1) {
+            let param_type = self.codegen_type_node(&param.typ);
+            if param_type.is_struct_type() {
+                param_types.push(self.struct_type_for_arg(&param_type).into());
+            } else {
+                param_types.push(param_type.into());
+            }
+        }
+        let function_type = if method.return_type.typ == Type::None {
+            self.context.void_type().fn_type(&param_types, false)
+        } else {
+            self.codegen_type(&method.return_type.typ).fn_type(&param_types, false)
+        };
+
+        let fat = self.codegen_expression(lhs, false)?.into_struct_value();
+        let data = self.builder.build_extract_value(fat, 0, "dyn_data")?;
+        let vtable = self.builder.build_extract_value(fat, 1, "dyn_vtable")?.into_pointer_value();
+        let method_ptr = unsafe {
+            self.builder.build_gep(
+                ptr_type.array_type(method_count),
+                vtable,
+                &[self.context.i64_type().const_int(0, false), self.context.i64_type().const_int(index as u64, false)],
+                "dyn_method_ptr")
+        }?;
+        let method_ptr = self.load_value_from_ptr(ptr_type, method_ptr, "dyn_method")?.into_pointer_value();
+
+        let mut args = vec![data.into()];
+        for arg in &method_call.arguments {
+            let expr = self.codegen_expression(arg, false)?;
+            if expr.is_struct_value() {
+                args.push(self.struct_value_for_arg(expr)?.into());
+            } else {
+                args.push(expr.into());
+            }
+        }
+        let result = self.builder.build_indirect_call(function_type, method_ptr, &args, "dyn_method_call")?;
+        match result.try_as_basic_value().left() {
+            Some(value) => Ok(value),
+            // NOTE: The return value doesn't matter, the method returns None
+            None => Ok(self.context.i32_type().const_int(0, false).into()),
+        }
+    }
+
     #[trace_call(always)]
     fn get_struct_type(&self, struct_name: &str) -> Option<StructType<'ctx>> {
         if let Some(struct_type) = self.struct_defs.get(struct_name) {
@@ -2095,6 +3411,23 @@ impl<'flags, 'ctx, 'src, 'ast> LLVMCodegen<'flags, 'ctx, 'src, 'ast> {
         }
     }
 
+    /// Writes a Makefile rule with every imported and embedded file, so build tools know when to recompile
+    #[trace_call(always)]
+    pub fn write_dependencies(&self, dependencies: &[PathBuf]) -> Result<(), String> {
+        let depname = self.filename.with_extension("d");
+        let mut rule = format!("{}:", self.exename.to_str().unwrap());
+        for dependency in dependencies {
+            rule.push_str(&format!(" \\\n  {}", dependency.to_str().unwrap().replace(' ', "\\ ")));
+        }
+        rule.push('\n');
+        std::fs::write(&depname, rule)
+            .map_err(|e| format!("{}: Could not write dependency file {}: {}", ERR_STR, depname.to_str().unwrap(), e))?;
+        if self.flags.verbose {
+            println!("[INFO] Created {}", depname.to_str().unwrap());
+        }
+        Ok(())
+    }
+
     #[trace_call(always)]
     pub fn run(&mut self) -> Result<(), String> {
         println!("[INFO] Running {}", self.exename.to_str().unwrap());
@@ -7,19 +7,19 @@ use crate::frontend::tokens::Location;
 use crate::frontend::parser::Operation;
 use crate::middleend::type_checker::Type;
 use crate::backend::codegen_llvm::StructInfo;
+use crate::compiler::NOTE_STR;
 use crate::internal_panic;
 use crate::util::flags::Flags;
 
 const COMPTIME_SCOPE_LIMIT: usize = 64;
-const COMPTIME_MEMORY: usize = 64_000;
+// Addresses below this are never allocated, so a pointer to 0 is `null`
+const NULL_PADDING: usize = 8;
 
 #[derive(Debug)]
 pub enum EvalError<'src> {
     /// Error Loc, Binary Op, LHS, RHS
     BinaryNotImplemented(Location, Operation, Value, Value),
     /// Error Loc
-    StatementNotImplemented(Location),
-    /// Error Loc
     ExpressionNotImplemented(Location),
     /// Error Loc, Variable Name
     UnknownVariable(Location, &'src str),
@@ -31,6 +31,16 @@ pub enum EvalError<'src> {
     ScopeOverflow(Location),
     /// Mem Addr, Byte Count
     OutOfMemory(usize, usize),
+    /// Error Loc
+    DivisionByZero(Location),
+    /// Error Loc, Index, Array Size
+    IndexOutOfBounds(Location, i128, usize),
+    /// Error Loc, Mem Addr, Byte Count
+    InvalidMemoryAccess(Location, usize, usize),
+    /// Error Loc, Step Limit
+    StepLimitExceeded(Location, usize),
+    /// Error Loc, Message, Evaluated Comparison
+    AssertionFailed(Location, Option<&'src str>, Option<String>),
 }
 
 impl Display for EvalError<'_> {
@@ -38,8 +48,6 @@ impl Display for EvalError<'_> {
         match self {
             Self::BinaryNotImplemented(loc, op, lhs, rhs) =>
                 write!(f, "{loc}: Operation `{lhs} {op} {rhs}` is currently not supported in a {KEYWORD_COMPTIME} context."),
-            Self::StatementNotImplemented(loc) =>
-                write!(f, "{loc}: The given statement is currently not supported in a {KEYWORD_COMPTIME} context."),
             Self::ExpressionNotImplemented(loc) =>
                 write!(f, "{loc}: The given expression is currently not supported in a {KEYWORD_COMPTIME} context."),
             Self::UnknownVariable(loc, name) =>
@@ -51,7 +59,25 @@ impl Display for EvalError<'_> {
             Self::ScopeOverflow(loc) =>
                 write!(f, "{loc}: Scope Overflow when trying to call {KEYWORD_COMPTIME} function."),
             Self::OutOfMemory(ptr, size) =>
-                write!(f, "Evaluator Out Of Memory. Could not allocate {size} bytes at addr {ptr}."),
+                write!(f, "Evaluator Out Of Memory. Could not allocate {size} bytes at addr {ptr}.\n{NOTE_STR}: Use `--comptime-memory` to increase the limit."),
+            Self::DivisionByZero(loc) =>
+                write!(f, "{loc}: Division by zero in a {KEYWORD_COMPTIME} context."),
+            Self::IndexOutOfBounds(loc, index, size) =>
+                write!(f, "{loc}: Index out of bounds: Array of size {size} has no index {index}."),
+            Self::InvalidMemoryAccess(loc, ptr, size) =>
+                write!(f, "{loc}: Invalid memory access of {size} bytes at addr {ptr} in a {KEYWORD_COMPTIME} context."),
+            Self::StepLimitExceeded(loc, limit) =>
+                write!(f, "{loc}: {KEYWORD_COMPTIME} evaluation did not finish within {limit} steps.\n{NOTE_STR}: Use `--comptime-steps` to increase the limit."),
+            Self::AssertionFailed(loc, message, comparison) => {
+                write!(f, "{loc}: {KEYWORD_COMPTIME} assertion failed")?;
+                if let Some(message) = message {
+                    write!(f, ": {message}")?;
+                }
+                if let Some(comparison) = comparison {
+                    write!(f, "\n{NOTE_STR}: Condition evaluated to `{comparison}`.")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -71,15 +97,6 @@ pub enum Value {
     Array(ArrayType),
 }
 
-impl Value {
-    const BOOL_KIND: u8 = 0;
-    const PTR_KIND: u8 = 1;
-    const I128_KIND: u8 = 2;
-    const STRUCT_KIND: u8 = 3;
-    const CHAR_KIND: u8 = 4;
-    const ARRAY_KIND: u8 = 5;
-}
-
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -103,20 +120,27 @@ impl Display for Value {
 impl Value {
     pub fn in_type_bounds(&self, typ: &Type<'_>) -> bool {
         match (self, typ) {
+            (Self::None, Type::None) => true,
             (Self::Bool(_), Type::Bool) => true,
             (Self::Char(_), Type::Char) => true,
-            (Self::I128(_), Type::I64) => true,
-            (Self::I128(val), Type::Usize) => *val >= 0,
+            (Self::I128(val), Type::I64) => *val >= i64::MIN as i128 && *val <= i64::MAX as i128,
+            (Self::I128(val), Type::Usize) => *val >= 0 && *val <= u64::MAX as i128,
             (Self::I128(val), Type::I32) => *val >= i32::MIN as i128 && *val <= i32::MAX as i128,
             (Self::I128(val), Type::I16) => *val >= i16::MIN as i128 && *val <= i16::MAX as i128,
             (Self::I128(val), Type::I8) => *val >= i8::MIN as i128 && *val <= i8::MAX as i128,
+            (Self::I128(val), Type::U64) => *val >= u64::MIN as i128 && *val <= u64::MAX as i128,
             (Self::I128(val), Type::U32) => *val >= u32::MIN as i128 && *val <= u32::MAX as i128,
             (Self::I128(val), Type::U16) => *val >= u16::MIN as i128 && *val <= u16::MAX as i128,
             (Self::I128(val), Type::U8) => *val >= u8::MIN as i128 && *val <= u8::MAX as i128,
+            (Self::F64(val), Type::F32) => val.is_nan() || val.is_infinite() || val.abs() <= f32::MAX as f64,
+            (Self::F64(_), Type::F64) => true,
             (Self::Ptr(_), Type::Ref(_, _)) => true,
+            (Self::Ptr(_), Type::Any) => true,
             (Self::Ptr(_), Type::Usize) => true,
             // FIXME: We need better in_type_bounds-checks for Structs and Arrays
-            (Self::Struct(_), Type::Struct(_)) => true,
+            (Self::Struct(_), Type::Struct(_) | Type::Tuple(_)) => true,
+            // Unions are kept as their raw bytes
+            (Self::Array(_), Type::Struct(_)) => true,
             (Self::Array(e), Type::Array(_, s)) => e.len() == *s,
             (v, t) => todo!("{v:?} {t:?}"),
         }
@@ -133,23 +157,53 @@ impl Value {
 pub struct Evaluator<'flags, 'src, 'ast> {
     flags: &'flags Flags,
     functions: HashMap<&'src str, &'ast nodes::FunctionNode<'src>>,
-    variables: VecDeque<HashMap<&'src str, usize>>,
+    variables: VecDeque<Scope<'src>>,
     memory: Vec<u8>,
     blobs: HashMap<usize, usize>,
+    // String literals are allocated once and live forever, so pointers to them may escape any scope
+    strings: HashMap<Vec<u8>, usize>,
     struct_info: HashMap<&'src str, StructInfo<'src>>,
+    // Comptime functions that are currently being evaluated, with the location of their call
+    call_stack: Vec<(&'src str, Location)>,
+    steps: usize,
+    // Set while folding runtime expressions, floats are left to LLVM so they keep their precision
+    folding: bool,
+}
+
+#[derive(Default)]
+struct Scope<'src> {
+    variables: HashMap<&'src str, usize>,
+    // Memory that isn't bound to a name, e.g. references to literals or shadowed variables
+    temporaries: Vec<usize>,
+}
+
+// How a statement left its block
+#[derive(Debug, PartialEq)]
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
 }
 
 impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
     pub fn new(flags: &'flags Flags) -> Self {
         let mut variables = VecDeque::new();
-        variables.push_back(HashMap::new()); // Global variables
+        variables.push_back(Scope::default()); // Global variables
+        let mut memory = Vec::with_capacity(flags.comptime_memory);
+        // Address 0 is `null`, so we never hand it out
+        memory.resize(NULL_PADDING, 0);
         Self {
             flags,
             functions: HashMap::new(),
             variables,
-            memory: Vec::with_capacity(COMPTIME_MEMORY),
+            memory,
             blobs: HashMap::new(),
+            strings: HashMap::new(),
             struct_info: HashMap::new(),
+            call_stack: Vec::new(),
+            steps: 0,
+            folding: false,
         }
     }
 
@@ -158,7 +212,7 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
     }
 
     fn check_scope_limit(&self, location: &Location) -> Result<(), EvalError<'src>> {
-        if self.variables.len() >= COMPTIME_SCOPE_LIMIT {
+        if self.call_stack.len() >= COMPTIME_SCOPE_LIMIT {
             Err(EvalError::ScopeOverflow(*location))
         } else {
             Ok(())
@@ -168,17 +222,14 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
         if self.flags.debug {
             println!("[DEBUG] ComptimeEval: Entering scope {}", self.variables.len())
         }
-        self.variables.push_back(HashMap::new());
+        self.variables.push_back(Scope::default());
     }
     pub fn exit_scope(&mut self) {
         let freed = self.variables.pop_back().expect("Stack imbalance in Evaluator!");
         if self.flags.debug {
             println!("[DEBUG] ComptimeEval: Leaving scope {}", self.variables.len())
         }
-        let mut vars = freed.into_values().collect::<Vec<_>>();
-        vars.sort();
-        vars.reverse();
-        for ptr in vars {
+        for ptr in freed.variables.into_values().chain(freed.temporaries) {
             self.free(ptr);
         }
     }
@@ -190,26 +241,72 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
 
     pub fn add_variable(&mut self, variable: &nodes::VarDeclNode<'src>) -> Result<(), EvalError<'src>> {
         debug_assert!(variable.is_comptime);
+        // Every comptime variable gets the full step budget
+        self.steps = 0;
+        self.call_stack.clear();
         let value = self.evaluate(&variable.expression, false)?;
-        self.add_variable_by_name(&variable.name, &value)
+        self.add_variable_by_name(variable.name, &value, &variable.typ.typ)
+    }
+
+    /// Folds an expression the type checker classified as constant
+    pub fn evaluate_constant(&mut self, expression: &nodes::Expression<'src>) -> Result<Value, EvalError<'src>> {
+        self.steps = 0;
+        self.call_stack.clear();
+        self.folding = true;
+        let value = self.evaluate(expression, false);
+        self.folding = false;
+        value
     }
 
-    fn add_variable_by_name(&mut self, name: &'src str, value: &Value) -> Result<(), EvalError<'src>>{
-        let encoded = self.encode_value(value);
-        let size = encoded.len();
-        let addr = self.alloc(size)?;
-        self.memset(addr, &encoded);
-        let len = self.variables.len() - 1;
-        self.variables[len].insert(name, addr);
+    pub fn check_assertion(&mut self, assertion: &nodes::ComptimeAssertNode<'src>) -> Result<(), EvalError<'src>> {
+        self.steps = 0;
+        self.call_stack.clear();
+        self.evaluate_assertion(assertion)
+    }
+
+    fn evaluate_assertion(&mut self, assertion: &nodes::ComptimeAssertNode<'src>) -> Result<(), EvalError<'src>> {
+        // For comparisons we evaluate both sides ourselves, so we can tell the user what went wrong
+        if let nodes::Expression::Binary(binary) = &assertion.condition {
+            if binary.is_comparison() {
+                let lhs = self.evaluate(&binary.lhs, false)?;
+                let rhs = self.evaluate(&binary.rhs, false)?;
+                if self.combine_binary(binary, &lhs, &rhs)?.as_bool() {
+                    return Ok(());
+                }
+                let comparison = format!("{lhs} {} {rhs}", binary.operation);
+                return Err(EvalError::AssertionFailed(assertion.location, assertion.message, Some(comparison)));
+            }
+        }
+        if self.evaluate(&assertion.condition, false)?.as_bool() {
+            Ok(())
+        } else {
+            Err(EvalError::AssertionFailed(assertion.location, assertion.message, None))
+        }
+    }
+
+    fn add_variable_by_name(&mut self, name: &'src str, value: &Value, typ: &Type<'src>) -> Result<(), EvalError<'src>>{
+        let addr = self.alloc(self.size_of(typ))?;
+        self.store(addr, value, typ);
+        let scope = self.variables.back_mut().expect("Stack imbalance in Evaluator!");
+        if let Some(shadowed) = scope.variables.insert(name, addr) {
+            scope.temporaries.push(shadowed);
+        }
         if self.flags.debug {
             println!("[DEBUG] Added comptime variable `{}` with a value of {} at addr {addr}.", name, value);
         }
         Ok(())
     }
 
+    fn add_temporary(&mut self, value: &Value, typ: &Type<'src>) -> Result<usize, EvalError<'src>> {
+        let addr = self.alloc(self.size_of(typ))?;
+        self.store(addr, value, typ);
+        self.variables.back_mut().expect("Stack imbalance in Evaluator!").temporaries.push(addr);
+        Ok(addr)
+    }
+
     fn get_variable_ptr(&self, name: &nodes::NameNode<'src>) -> Result<usize, EvalError<'src>> {
         for scope in self.variables.iter().rev() {
-            if let Some(ptr) = scope.get(name.name) {
+            if let Some(ptr) = scope.variables.get(name.name) {
                 return Ok(*ptr)
             }
         }
@@ -218,13 +315,12 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
 
     pub fn get_variable_value(&self, name: &nodes::NameNode<'src>) -> Result<Value, EvalError<'src>> {
         let addr = self.get_variable_ptr(name)?;
-        let value = self.get_memory_by_ptr(addr);
-        self.decode_value(value, &name.typ)
+        self.load(addr, &name.typ, &name.location)
     }
 
     fn alloc(&mut self, bytes: usize) -> Result<usize, EvalError<'src>> {
         let ptr = self.memory.len();
-        if ptr + bytes > COMPTIME_MEMORY {
+        if ptr + bytes > self.flags.comptime_memory {
             return Err(EvalError::OutOfMemory(ptr, bytes));
         }
         self.memory.resize(ptr + bytes, 0);
@@ -237,249 +333,578 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
         let Some(_) = self.blobs.remove(&ptr) else {
             internal_panic!("Comptime Evaluator tried to free unallocated memory!")
         };
-        self.memory.resize(ptr, 0);
+        // Memory is a stack, but interned strings may sit above freed variables, so we only shrink to the last live blob
+        let end = self.blobs.iter().map(|(ptr, size)| ptr + size).max().unwrap_or(NULL_PADDING);
+        self.memory.truncate(end);
+        self.align_memory();
     }
 
     fn align_memory(&mut self) {
         while self.memory.len() % 8 != 0 { self.memory.push(0); }
     }
 
-    fn memset(&mut self, start: usize, bytes: &[u8]) {
-        let area = &mut self.memory[start..(start + bytes.len())];
-        area.copy_from_slice(bytes);
+    fn check_access(&self, addr: usize, size: usize, location: &Location) -> Result<(), EvalError<'src>> {
+        if addr < NULL_PADDING || addr + size > self.memory.len() {
+            Err(EvalError::InvalidMemoryAccess(*location, addr, size))
+        } else {
+            Ok(())
+        }
     }
 
-    fn decode_value(&self, bytes: &[u8], underlying: &Type<'src>) -> Result<Value, EvalError<'src>> {
-        match underlying {
-            Type::Char => {
-                debug_assert!(bytes.len() == std::mem::size_of::<u8>() + 1);
-                debug_assert!(bytes[0] == Value::CHAR_KIND);
-                let bytes = &bytes[1..];
-                Ok(Value::Char(u8::from_ne_bytes(*bytes.first_chunk().unwrap())))
-            }
-            Type::Usize |
-            Type::I8 | Type::U8 |
-            Type::I16 | Type::U16 |
-            Type::I32 | Type::U32 |
-            Type::I64 | Type::U64 => {
-                debug_assert!(bytes.len() == std::mem::size_of::<i128>() + 1);
-                debug_assert!(bytes[0] == Value::I128_KIND);
-                let bytes = &bytes[1..];
-                Ok(Value::I128(i128::from_ne_bytes(*bytes.first_chunk().unwrap())))
-            }
-            Type::Ref(_, _) => {
-                debug_assert!(bytes.len() == std::mem::size_of::<usize>() + 1);
-                debug_assert!(bytes[0] == Value::PTR_KIND);
-                let bytes = &bytes[1..];
-                Ok(Value::Ptr(usize::from_ne_bytes(*bytes.first_chunk().unwrap())))
-            }
-            Type::Struct(name) => {
-                let info = self.struct_info.get(name).unwrap();
-                debug_assert!(bytes[0] == Value::STRUCT_KIND);
-                let bytes = &bytes[1..];
-                let len = usize::from_ne_bytes(*bytes.first_chunk::<8>().unwrap());
-                debug_assert!(info.fields.len() == len);
-                let mut offset = 8;
-                let mut fields: Vec<Value> = Vec::with_capacity(len);
-                for (_, field_type) in &info.fields {
-                    let size = &bytes[offset..(offset + 8)];
-                    let size = usize::from_ne_bytes(size.try_into().unwrap());
-                    offset += 8;
-                    let field_value = &bytes[offset..(offset + size)];
-                    offset += size;
-                    let val = self.decode_value(field_value, &field_type)?;
-                    fields.push(val);
+    fn size_of(&self, typ: &Type<'src>) -> usize {
+        match typ {
+            Type::None | Type::Blank | Type::Unknown => 0,
+            Type::Bool | Type::Char | Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::I64 | Type::U64 | Type::Usize | Type::F64 | Type::Any | Type::Ref(_, _) | Type::Function(..) => 8,
+            // Data pointer and vtable pointer, or environment pointer and function pointer
+            Type::Dyn(_, _) | Type::Closure(..) => 16,
+            Type::Array(t, size) => self.size_of(t) * size,
+            Type::Struct(name) => self.struct_layout(name).1,
+            Type::Tuple(types) => {
+                let (offsets, align) = self.layout(types);
+                let end = match (offsets.last(), types.last()) {
+                    (Some(offset), Some(typ)) => offset + self.size_of(typ),
+                    _ => 0,
+                };
+                end.next_multiple_of(align)
+            }
+        }
+    }
+
+    fn align_of(&self, typ: &Type<'src>) -> usize {
+        match typ {
+            Type::Array(t, _) => self.align_of(t),
+            Type::Struct(name) => self.struct_layout(name).2,
+            Type::Tuple(elements) => self.layout(elements).1,
+            Type::Dyn(_, _) | Type::Closure(..) => 8,
+            t => self.size_of(t).max(1),
+        }
+    }
+
+    fn struct_info(&self, name: &str) -> &StructInfo<'src> {
+        let Some(info) = self.struct_info.get(name) else {
+            internal_panic!("Comptime Evaluator could not find struct {name}")
+        };
+        info
+    }
+
+    fn struct_fields(&self, name: &str) -> &[(&'src str, Type<'src>)] {
+        &self.struct_info(name).fields
+    }
+
+    // Unions are evaluated as their raw bytes, because any field may be read after another one was written
+    fn is_union(&self, typ: &Type<'src>) -> bool {
+        matches!(typ, Type::Struct(name) if self.struct_info(name).is_union)
+    }
+
+    // Field types of structs, element types of tuples
+    fn aggregate_types(&self, typ: &Type<'src>) -> Vec<Type<'src>> {
+        match typ {
+            Type::Struct(name) => self.struct_fields(name).iter().map(|(_, t)| t.clone()).collect(),
+            Type::Tuple(elements) => elements.to_vec(),
+            t => internal_panic!("Expected struct or tuple, found {t}"),
+        }
+    }
+
+    // Offsets of the fields, size and alignment, honoring `packed`, `align(N)` and unions
+    fn struct_layout(&self, name: &str) -> (Vec<usize>, usize, usize) {
+        let info = self.struct_info(name);
+        let natural: Vec<_> = info.fields.iter().map(|(_, t)| (self.size_of(t), self.align_of(t))).collect();
+        info.layout(&natural)
+    }
+
+    // Offsets of the fields of structs, elements of tuples
+    fn aggregate_offsets(&self, typ: &Type<'src>) -> Vec<usize> {
+        match typ {
+            Type::Struct(name) => self.struct_layout(name).0,
+            Type::Tuple(elements) => self.layout(elements).0,
+            t => internal_panic!("Expected struct or tuple, found {t}"),
+        }
+    }
+
+    // Same layout as LLVM uses for our structs and tuples: Every field is aligned to its natural alignment
+    fn layout(&self, types: &[Type<'src>]) -> (Vec<usize>, usize) {
+        let mut offsets = Vec::new();
+        let mut offset: usize = 0;
+        let mut align = 1;
+        for typ in types {
+            let field_align = self.align_of(typ);
+            offset = offset.next_multiple_of(field_align);
+            offsets.push(offset);
+            offset += self.size_of(typ);
+            align = align.max(field_align);
+        }
+        (offsets, align)
+    }
+
+    fn field_info(&self, struct_name: &str, field_name: &str) -> (usize, usize, Type<'src>) {
+        let fields = self.struct_fields(struct_name);
+        let Some(index) = fields.iter().position(|(name, _)| *name == field_name) else {
+            internal_panic!("Field {field_name} not found in struct {struct_name}!")
+        };
+        (index, self.struct_layout(struct_name).0[index], fields[index].1.clone())
+    }
+
+    fn store(&mut self, addr: usize, value: &Value, typ: &Type<'src>) {
+        let size = self.size_of(typ);
+        match (value, typ) {
+            (Value::Undefined | Value::None, _) => {}
+            (Value::Bool(b), _) => self.memory[addr] = *b as u8,
+            (Value::Char(c), _) => self.memory[addr] = *c,
+            (Value::I128(v), _) => self.memory[addr..addr + size].copy_from_slice(&v.to_le_bytes()[..size]),
+            (Value::F64(v), Type::F32) => self.memory[addr..addr + 4].copy_from_slice(&(*v as f32).to_le_bytes()),
+            (Value::F64(v), _) => self.memory[addr..addr + 8].copy_from_slice(&v.to_le_bytes()),
+            (Value::Ptr(p), _) => self.memory[addr..addr + 8].copy_from_slice(&(*p as u64).to_le_bytes()),
+            (Value::Array(bytes), Type::Struct(_)) => {
+                debug_assert!(self.is_union(typ));
+                for (i, byte) in bytes.iter().enumerate() {
+                    self.store(addr + i, byte, &Type::U8);
                 }
-                Ok(Value::Struct(fields))
-            }
-            Type::Bool => {
-                debug_assert!(bytes.len() == 2);
-                debug_assert!(bytes[0] == Value::BOOL_KIND);
-                Ok(Value::Bool(bytes[1] != 0))
-            }
-            Type::Array(typ, size) => {
-                debug_assert!(bytes[0] == Value::ARRAY_KIND);
-                let bytes = &bytes[1..];
-                let len = usize::from_ne_bytes(*bytes.first_chunk::<8>().unwrap());
-                debug_assert!(*size == len);
-                let mut offset = 8;
-                let mut elements: Vec<Value> = Vec::with_capacity(len);
-                for _ in 0..*size {
-                    let size = &bytes[offset..(offset + 8)];
-                    let size = usize::from_ne_bytes(size.try_into().unwrap());
-                    offset += 8;
-                    let element_value = &bytes[offset..(offset + size)];
-                    offset += size;
-                    let val = self.decode_value(element_value, typ)?;
-                    elements.push(val);
+            }
+            (Value::Struct(fields), Type::Struct(_) | Type::Tuple(_)) => {
+                let types = self.aggregate_types(typ);
+                let offsets = self.aggregate_offsets(typ);
+                for ((field, offset), typ) in fields.iter().zip(offsets).zip(types) {
+                    self.store(addr + offset, field, &typ);
+                }
+            }
+            (Value::Array(elements), Type::Array(typ, _)) => {
+                let element_size = self.size_of(typ);
+                for (i, element) in elements.iter().enumerate() {
+                    self.store(addr + i * element_size, element, typ);
                 }
-                Ok(Value::Array(elements))
             }
-            t => todo!("{t:?}")
+            (v, t) => internal_panic!("Comptime Evaluator can't store {v} as {t}"),
         }
     }
 
-    fn encode_value(&self, value: &Value) -> Vec<u8> {
-        match value {
-            Value::Bool(b) => vec![Value::BOOL_KIND, *b as u8],
-            Value::Ptr(addr) => {
-                let mut v = vec![Value::PTR_KIND];
-                v.extend(usize::to_ne_bytes(*addr).to_vec());
-                v
+    fn load(&self, addr: usize, typ: &Type<'src>, location: &Location) -> Result<Value, EvalError<'src>> {
+        let size = self.size_of(typ);
+        self.check_access(addr, size, location)?;
+        let bytes = &self.memory[addr..addr + size];
+        let value = match typ {
+            Type::None | Type::Blank | Type::Unknown => Value::None,
+            Type::Bool => Value::Bool(bytes[0] != 0),
+            Type::Char => Value::Char(bytes[0]),
+            t if t.is_integer() => {
+                // Sign- or zero-extend to 128 bits
+                let fill = if t.is_signed() && bytes[size - 1] & 0x80 != 0 { 0xFF } else { 0 };
+                let mut wide = [fill; 16];
+                wide[..size].copy_from_slice(bytes);
+                Value::I128(i128::from_le_bytes(wide))
             }
-            Value::I128(val) => {
-                let mut v = vec![Value::I128_KIND];
-                v.extend(i128::to_ne_bytes(*val).to_vec());
-                v
-            }
-            Value::Struct(fields) => {
-                let count = fields.len();
-                let mut v = vec![Value::STRUCT_KIND];
-                v.extend(count.to_ne_bytes().to_vec());
-                for f in fields {
-                    let field = self.encode_value(&f);
-                    v.extend(&field.len().to_ne_bytes());
-                    v.extend(field);
+            Type::F32 => Value::F64(f32::from_le_bytes(*bytes.first_chunk().unwrap()) as f64),
+            Type::F64 => Value::F64(f64::from_le_bytes(*bytes.first_chunk().unwrap())),
+            Type::Any | Type::Ref(_, _) => Value::Ptr(u64::from_le_bytes(*bytes.first_chunk().unwrap()) as usize),
+            t if self.is_union(t) => Value::Array(bytes.iter().map(|b| Value::I128(*b as i128)).collect()),
+            Type::Struct(_) | Type::Tuple(_) => {
+                let types = self.aggregate_types(typ);
+                let offsets = self.aggregate_offsets(typ);
+                let mut fields = Vec::with_capacity(offsets.len());
+                for (typ, offset) in types.iter().zip(offsets) {
+                    fields.push(self.load(addr + offset, typ, location)?);
                 }
-                v
-            }
-            Value::Array(elements) => {
-                let count = elements.len();
-                let mut v = vec![Value::ARRAY_KIND];
-                v.extend(count.to_ne_bytes().to_vec());
-                for e in elements {
-                    let elem = self.encode_value(&e);
-                    v.extend(&elem.len().to_ne_bytes());
-                    v.extend(elem);
+                Value::Struct(fields)
+            }
+            Type::Array(typ, len) => {
+                let element_size = self.size_of(typ);
+                let mut elements = Vec::with_capacity(*len);
+                for i in 0..*len {
+                    elements.push(self.load(addr + i * element_size, typ, location)?);
                 }
-                v
+                Value::Array(elements)
             }
-            v => todo!("{v:?}")
+            t => internal_panic!("Comptime Evaluator can't load {t}"),
+        };
+        Ok(value)
+    }
+
+    fn zero_value(&self, typ: &Type<'src>) -> Value {
+        match typ {
+            Type::Bool => Value::Bool(false),
+            Type::Char => Value::Char(0),
+            Type::F32 | Type::F64 => Value::F64(0.0),
+            Type::Any | Type::Ref(_, _) => Value::Ptr(0),
+            t if self.is_union(t) => Value::Array(vec![Value::I128(0); self.size_of(t)]),
+            Type::Struct(_) | Type::Tuple(_) => Value::Struct(self.aggregate_types(typ).iter().map(|t| self.zero_value(t)).collect()),
+            Type::Array(t, size) => Value::Array(vec![self.zero_value(t); *size]),
+            t if t.is_integer() => Value::I128(0),
+            _ => Value::None,
         }
     }
 
-    pub fn get_memory_by_ptr(&self, start: usize) -> &[u8] {
-        let Some(length) = self.blobs.get(&start) else {
-            internal_panic!("Could not get size for allocd memory starting at byte {start}")
+    // Null-terminated string at `start`, without the terminator
+    pub fn get_string_by_ptr(&self, start: usize) -> &[u8] {
+        let Some(length) = self.memory[start..].iter().position(|b| *b == 0) else {
+            internal_panic!("Comptime string at addr {start} is not null-terminated")
         };
         &self.memory[start..(start + length)]
     }
 
-    fn evaluate_block(&mut self, block: &nodes::BlockNode<'src>) -> Result<Value, EvalError<'src>> {
+    fn evaluate_block(&mut self, block: &nodes::BlockNode<'src>) -> Result<Flow, EvalError<'src>> {
+        self.enter_scope();
+        let mut flow = Flow::Normal;
+        let mut deferred = Vec::new();
         for stmt in &block.statements {
-            let stmt = self.evaluate_statement(stmt)?;
-            if stmt != Value::Undefined {
-                // Statement (for example `if` or `while`) returned
-                return Ok(stmt)
+            if let nodes::Statement::Defer(defer) = stmt {
+                // Variables declared after the `defer` could shadow the ones the statement refers to
+                let scope = self.variables.back().expect("Stack imbalance in Evaluator!");
+                deferred.push((&*defer.statement, scope.variables.clone()));
+                continue;
             }
+            flow = self.evaluate_statement(stmt)?;
+            if flow != Flow::Normal {
+                // Statement (for example `return` or `break`) left the block early
+                break;
+            }
+        }
+        // However the block is left, deferred statements run last to first
+        for (stmt, variables) in deferred.into_iter().rev() {
+            let scope = self.variables.back_mut().expect("Stack imbalance in Evaluator!");
+            let current = std::mem::replace(&mut scope.variables, variables.clone());
+            self.evaluate_statement(stmt)?;
+            let scope = self.variables.back_mut().expect("Stack imbalance in Evaluator!");
+            let declared = std::mem::replace(&mut scope.variables, current);
+            // Whatever the statement declared itself is freed with the block
+            scope.temporaries.extend(declared.into_iter().filter(|(name, addr)| variables.get(name) != Some(addr)).map(|(_, addr)| addr));
         }
-        Ok(Value::Undefined)
+        self.exit_scope();
+        Ok(flow)
     }
 
-    fn evaluate_statement(&mut self, stmt: &nodes::Statement<'src>) -> Result<Value, EvalError<'src>> {
+    // Counts statements and loop iterations, so endless loops can't hang the compiler
+    fn step(&mut self, location: &Location) -> Result<(), EvalError<'src>> {
+        self.steps += 1;
+        if self.steps > self.flags.comptime_steps {
+            Err(EvalError::StepLimitExceeded(*location, self.flags.comptime_steps))
+        } else {
+            Ok(())
+        }
+    }
+
+    // The comptime calls that led to an error, innermost first. On error, the stack isn't unwound, so it's still intact.
+    pub fn print_call_stack(&self, variable: Option<&nodes::VarDeclNode<'src>>) {
+        let mut frames = self.call_stack.iter().rev().peekable();
+        while let Some(frame @ (name, location)) = frames.next() {
+            eprintln!("{NOTE_STR}: {location}: In call to {KEYWORD_COMPTIME} function `{name}`.");
+            // Recursion would print the same frame over and over again
+            let mut repeated = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeated += 1;
+            }
+            if repeated > 0 {
+                eprintln!("{NOTE_STR}: Previous call repeated {repeated} more times.");
+            }
+        }
+        if let Some(variable) = variable {
+            eprintln!("{NOTE_STR}: {}: While evaluating {KEYWORD_COMPTIME} variable `{}`.", variable.location, variable.name);
+        }
+    }
+
+    fn evaluate_statement(&mut self, stmt: &nodes::Statement<'src>) -> Result<Flow, EvalError<'src>> {
+        self.step(&stmt.get_loc())?;
         match stmt {
             nodes::Statement::VarDecl(var_decl) => {
                 let value = self.evaluate(&var_decl.expression, false)?;
-                self.add_variable_by_name(var_decl.name, &value)?;
-                Ok(Value::Undefined)
+                self.add_variable_by_name(var_decl.name, &value, &var_decl.typ.typ)?;
+                Ok(Flow::Normal)
             }
             nodes::Statement::While(whyle) => {
                 while self.evaluate(&whyle.condition, false)?.as_bool() {
-                    let result = self.evaluate_block(&whyle.body)?;
-                    if result != Value::Undefined {
-                        return Ok(result)
+                    self.step(&whyle.location)?;
+                    match self.evaluate_block(&whyle.body)? {
+                        Flow::Break => break,
+                        ret @ Flow::Return(_) => return Ok(ret),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    // Desugared `for` loops step even after `continue`
+                    if let Some(step) = &whyle.step {
+                        self.evaluate(step, false)?;
                     }
                 }
-                Ok(Value::Undefined)
+                Ok(Flow::Normal)
             }
             nodes::Statement::If(iff) => {
                 let cond = self.evaluate(&iff.condition, false)?.as_bool();
                 if cond {
                     self.evaluate_block(&iff.if_body)
                 } else if let Some(else_body) = &iff.else_body {
-                    self.evaluate_block(&else_body)
+                    self.evaluate_block(else_body)
                 } else {
-                    Ok(Value::Undefined)
+                    Ok(Flow::Normal)
                 }
             }
             nodes::Statement::Expression(expr) => {
                 self.evaluate(expr, false)?;
-                Ok(Value::Undefined)
+                Ok(Flow::Normal)
             },
             nodes::Statement::Return(ret) => {
                 if let Some(ret_expr) = &ret.return_value {
-                    self.evaluate(ret_expr, false)
+                    Ok(Flow::Return(self.evaluate(ret_expr, false)?))
                 } else {
-                    Ok(Value::None)
+                    Ok(Flow::Return(Value::None))
                 }
             }
             nodes::Statement::Block(block) => self.evaluate_block(block),
-            stmt => {
-                if self.flags.debug {
-                    println!("[DEBUG] Not supported: {stmt:?}")
+            nodes::Statement::Break(_) => Ok(Flow::Break),
+            nodes::Statement::Continue(_) => Ok(Flow::Continue),
+            nodes::Statement::ComptimeAssert(assertion) => {
+                self.evaluate_assertion(assertion)?;
+                Ok(Flow::Normal)
+            }
+            nodes::Statement::Destructure(destructure) => {
+                let Value::Struct(elements) = self.evaluate(&destructure.expression, false)? else {
+                    internal_panic!("Expected tuple value, the Type Checker should've caught this!")
+                };
+                let types = self.aggregate_types(&destructure.typ.typ);
+                for (((_, name), value), typ) in destructure.names.iter().zip(elements).zip(types) {
+                    if *name != "_" {
+                        self.add_variable_by_name(name, &value, &typ)?;
+                    }
                 }
-                Err(EvalError::StatementNotImplemented(stmt.get_loc()))
+                Ok(Flow::Normal)
             }
+            nodes::Statement::Defer(_) => internal_panic!("Deferred statements are collected in evaluate_block"),
         }
     }
 
     fn evaluate(&mut self, expression: &nodes::Expression<'src>, needs_ptr: bool) -> Result<Value, EvalError<'src>> {
+        if needs_ptr {
+            return Ok(Value::Ptr(self.evaluate_place(expression)?));
+        }
         let intermediate = match expression {
-            nodes::Expression::Literal(lit) => self.evaluate_literal(&lit),
-            nodes::Expression::Binary(bin) => self.evaluate_binary(&bin),
-            nodes::Expression::Name(name) => self.evaluate_name(&name, needs_ptr),
-            nodes::Expression::FunctionCall(call) => self.evaluate_call(&call),
-            nodes::Expression::StructLiteral(strukt) => self.evaluate_struct(&strukt),
-            nodes::Expression::Unary(unary) => self.evaluate_unary(&unary),
-            nodes::Expression::ArrayLiteral(lit) => self.evaluate_array_literal(&lit),
-            _ => Err(EvalError::ExpressionNotImplemented(expression.get_loc())),
+            nodes::Expression::Literal(lit) => self.evaluate_literal(lit),
+            nodes::Expression::Binary(bin) => self.evaluate_binary(bin),
+            nodes::Expression::Name(name) => self.evaluate_name(name),
+            nodes::Expression::FunctionCall(call) => self.evaluate_call(call),
+            nodes::Expression::StructLiteral(strukt) => self.evaluate_struct(strukt),
+            nodes::Expression::Unary(unary) => self.evaluate_unary(unary),
+            nodes::Expression::ArrayLiteral(lit) => self.evaluate_array_literal(lit),
+            nodes::Expression::TupleLiteral(lit) => {
+                let elements = lit.elements.iter().map(|e| self.evaluate(e, false)).collect::<Result<_, _>>()?;
+                Ok(Value::Struct(elements))
+            }
+            nodes::Expression::As(expr, typ) => self.evaluate_cast(expr, &typ.typ),
+            // Our memory layout matches the one LLVM picks, so sizes agree with runtime `sizeof`
+            nodes::Expression::Sizeof(typ) => Ok(Value::I128(self.size_of(&typ.typ) as i128)),
+            nodes::Expression::Reflection(reflection) => self.evaluate_reflection(reflection),
+            nodes::Expression::Embed(embed) => self.evaluate_embed(embed),
+            nodes::Expression::Arithmetic(arithmetic) => self.evaluate_arithmetic(arithmetic),
+            // Closures only exist at runtime
+            nodes::Expression::Lambda(lambda) => Err(EvalError::ExpressionNotImplemented(lambda.location)),
         };
         let value = intermediate?;
+        if self.folding && matches!(value, Value::F64(_)) {
+            return Err(EvalError::ExpressionNotImplemented(expression.get_loc()));
+        }
+        // Every intermediate has to fit its type, otherwise we'd compute something else than the runtime does
         if !value.in_type_bounds(&expression.get_type()) {
             Err(EvalError::ValueOutOfBounds(expression.get_loc(), value, expression.get_type()))
         } else {
             Ok(value)
         }
     }
+
+    // Address of an lvalue. Other expressions are evaluated into a temporary, so we can take their address, too.
+    fn evaluate_place(&mut self, expression: &nodes::Expression<'src>) -> Result<usize, EvalError<'src>> {
+        match expression {
+            nodes::Expression::Name(name) => self.get_variable_ptr(name),
+            nodes::Expression::Unary(unary) if unary.operation == Operation::Dereference => {
+                let Value::Ptr(addr) = self.evaluate(&unary.expression, false)? else {
+                    internal_panic!("Unary Operation expects a pointer, the Type Checker should've caught this!")
+                };
+                self.check_access(addr, self.size_of(&unary.typ), &unary.location)?;
+                Ok(addr)
+            }
+            nodes::Expression::Binary(binary) if binary.operation == Operation::MemberAccess => {
+                if !matches!(&*binary.rhs, nodes::Expression::Name(_) | nodes::Expression::Literal(_)) {
+                    return Err(EvalError::ExpressionNotImplemented(binary.location));
+                }
+                let (base, lhs_type) = match binary.lhs.get_type() {
+                    Type::Ref(t, _) => {
+                        let Value::Ptr(addr) = self.evaluate(&binary.lhs, false)? else {
+                            internal_panic!("Expected pointer to struct, the Type Checker should've caught this!")
+                        };
+                        (addr, *t)
+                    }
+                    t => (self.evaluate_place(&binary.lhs)?, t),
+                };
+                let (offset, typ) = match (&lhs_type, &*binary.rhs) {
+                    (Type::Tuple(elements), nodes::Expression::Literal(index)) => {
+                        let index = index.value.parse::<usize>().expect("Tuple indices are checked by the Type Checker");
+                        (self.layout(elements).0[index], elements[index].clone())
+                    }
+                    (t, nodes::Expression::Name(field)) => {
+                        let (_, offset, typ) = self.field_info(t.get_underlying_struct_name(), field.name);
+                        (offset, typ)
+                    }
+                    _ => unreachable!(),
+                };
+                self.check_access(base + offset, self.size_of(&typ), &binary.location)?;
+                Ok(base + offset)
+            }
+            nodes::Expression::Binary(binary) if binary.operation == Operation::IndexedAccess => {
+                let base = self.evaluate_place(&binary.lhs)?;
+                let (index, element_type) = self.evaluate_index(binary)?;
+                Ok(base + index * self.size_of(&element_type))
+            }
+            expr => {
+                let value = self.evaluate(expr, false)?;
+                self.add_temporary(&value, &expr.get_type())
+            }
+        }
+    }
+
+    fn evaluate_index(&mut self, binary: &nodes::BinaryNode<'src>) -> Result<(usize, Type<'src>), EvalError<'src>> {
+        let Type::Array(element_type, size) = binary.lhs.get_type() else {
+            internal_panic!("Expected array, found {:?}", binary.lhs.get_type())
+        };
+        let Value::I128(index) = self.evaluate(&binary.rhs, false)? else {
+            internal_panic!("Array index must be an integer, the Type Checker should've caught this!")
+        };
+        if index < 0 || index >= size as i128 {
+            return Err(EvalError::IndexOutOfBounds(binary.location, index, size));
+        }
+        Ok((index as usize, *element_type))
+    }
+
     fn evaluate_array_literal(&mut self, literal: &nodes::ArrayLiteralNode<'src>) -> Result<Value, EvalError<'src>> {
         let mut res = Vec::with_capacity(literal.elements.len());
         for e in &literal.elements {
-            let val = self.evaluate(&e, false)?;
+            let val = self.evaluate(e, false)?;
             res.push(val);
         }
         Ok(Value::Array(res))
     }
     fn evaluate_unary(&mut self, unary: &nodes::UnaryNode<'src>) -> Result<Value, EvalError<'src>> {
-        let value = self.evaluate(&unary.expression, false)?;
         match &unary.operation {
             Operation::Dereference => {
-                let Value::Ptr(addr) = value else {
+                let Value::Ptr(addr) = self.evaluate(&unary.expression, false)? else {
                     internal_panic!("Unary Operation expects a pointer, the Type Checker should've caught this!")
                 };
-                match &unary.typ {
-                    Type::Char => Ok(Value::Char(self.memory[addr])),
-                    t => todo!("Unary Dereference for {t} at addr {addr}")
-                }
+                self.load(addr, &unary.typ, &unary.location)
             },
-            Operation::Negate => {
-                let Value::I128(val) = value else {
-                    internal_panic!("Unary Operation expects an integer, the Type Checker should've caught this!")
+            Operation::Reference => Ok(Value::Ptr(self.evaluate_place(&unary.expression)?)),
+            Operation::Negate => match self.evaluate(&unary.expression, false)? {
+                Value::I128(val) => Ok(Value::I128(-val)),
+                Value::F64(val) => Ok(Value::F64(-val)),
+                _ => internal_panic!("Unary Operation expects a number, the Type Checker should've caught this!"),
+            },
+            Operation::LogicalNot => Ok(Value::Bool(!self.evaluate(&unary.expression, false)?.as_bool())),
+            _ => Err(EvalError::ExpressionNotImplemented(unary.location)),
+        }
+    }
+    fn evaluate_cast(&mut self, expression: &nodes::Expression<'src>, typ: &Type<'src>) -> Result<Value, EvalError<'src>> {
+        // Integers are truncated like they are at runtime
+        let wrap = |value: i128| Self::wrap_integer(value, typ);
+        let value = self.evaluate(expression, false)?;
+        match (value, typ) {
+            // `char` counts as an integer type, so it has to come first
+            (Value::I128(v), Type::Char) => Ok(Value::Char(v as u8)),
+            (Value::Char(c), Type::Char) => Ok(Value::Char(c)),
+            (Value::I128(v), t) if t.is_integer() => Ok(Value::I128(wrap(v))),
+            (Value::Char(c), t) if t.is_integer() => Ok(Value::I128(wrap(c as i128))),
+            (Value::Bool(b), t) if t.is_integer() => Ok(Value::I128(b as i128)),
+            (Value::F64(v), t) if t.is_integer() => Ok(Value::I128(wrap(v as i128))),
+            (Value::I128(v), Type::F32 | Type::F64) => Ok(Value::F64(v as f64)),
+            (Value::F64(v), Type::F32) => Ok(Value::F64(v as f32 as f64)),
+            (Value::F64(v), Type::F64) => Ok(Value::F64(v)),
+            (Value::Bool(b), Type::Bool) => Ok(Value::Bool(b)),
+            (Value::Ptr(p), Type::Ref(_, _) | Type::Any) => Ok(Value::Ptr(p)),
+            (v @ Value::Struct(_), Type::Struct(_)) => Ok(v),
+            _ => Err(EvalError::ExpressionNotImplemented(expression.get_loc())),
+        }
+    }
+    fn wrap_integer(value: i128, typ: &Type<'src>) -> i128 {
+        let bits = typ.get_bit_size() as u32;
+        let value = value.rem_euclid(1i128 << bits);
+        if typ.is_signed() && value >= 1i128 << (bits - 1) { value - (1i128 << bits) } else { value }
+    }
+    fn evaluate_arithmetic(&mut self, arithmetic: &nodes::ArithmeticNode<'src>) -> Result<Value, EvalError<'src>> {
+        let typ = arithmetic.operand_type();
+        let lhs = self.evaluate(&arithmetic.lhs, false)?;
+        let rhs = self.evaluate(&arithmetic.rhs, false)?;
+        let (Value::I128(l), Value::I128(r)) = (&lhs, &rhs) else {
+            return Err(EvalError::BinaryNotImplemented(arithmetic.location, arithmetic.operation, lhs, rhs));
+        };
+        // Multiplying two 64 bit values can overflow even an i128
+        let (exact, overflowed) = match arithmetic.operation {
+            Operation::Add => l.overflowing_add(*r),
+            Operation::Sub => l.overflowing_sub(*r),
+            Operation::Mul => l.overflowing_mul(*r),
+            o => internal_panic!("Unexpected arithmetic intrinsic operation {o:?}"),
+        };
+        // The type is at most 64 bits wide, so wrapping modulo 2^128 first doesn't change the result
+        let wrapped = Self::wrap_integer(exact, &typ);
+        match arithmetic.mode {
+            nodes::ArithmeticMode::Wrapping => Ok(Value::I128(wrapped)),
+            nodes::ArithmeticMode::Saturating => {
+                let bits = typ.get_bit_size() as u32;
+                let (min, max) = if typ.is_signed() {
+                    (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+                } else {
+                    (0, (1i128 << bits) - 1)
+                };
+                if !overflowed {
+                    return Ok(Value::I128(exact.clamp(min, max)));
+                }
+                // The exact result is out of range for i128, so only its sign matters
+                let negative = match arithmetic.operation {
+                    Operation::Mul => (*l < 0) != (*r < 0),
+                    _ => *l < 0,
                 };
-                Ok(Value::I128(-val))
+                Ok(Value::I128(if negative { min } else { max }))
+            }
+            nodes::ArithmeticMode::Checked => {
+                let Some(result) = &arithmetic.result else {
+                    internal_panic!("checked arithmetic without a result reference")
+                };
+                let Value::Ptr(addr) = self.evaluate(result, false)? else {
+                    internal_panic!("checked arithmetic result is not a reference")
+                };
+                self.check_access(addr, self.size_of(&typ), &arithmetic.location)?;
+                self.store(addr, &Value::I128(wrapped), &typ);
+                Ok(Value::Bool(!overflowed && wrapped == exact))
             }
-            o => todo!("{o}")
         }
     }
     fn evaluate_struct(&mut self, strukt: &nodes::StructLiteralNode<'src>) -> Result<Value, EvalError<'src>> {
-        let mut res = Vec::with_capacity(strukt.fields.len());
+        // Fields may be written in any order, values are stored in declaration order
+        let struct_name = strukt.typ.get_underlying_struct_name();
+        if self.is_union(&strukt.typ) {
+            // The only field is written to zeroed memory at the start of the union
+            let (_, expression) = &strukt.fields[0];
+            let value = self.evaluate(expression, false)?;
+            let size = self.size_of(&strukt.typ);
+            let addr = self.alloc(size)?;
+            self.store(addr, &value, &expression.get_type());
+            let bytes = self.load(addr, &Type::Array(Box::new(Type::U8), size), &strukt.location);
+            self.free(addr);
+            return bytes;
+        }
+        let mut values = Vec::with_capacity(strukt.fields.len());
         for f in &strukt.fields {
             let val = self.evaluate(&f.1, false)?;
-            res.push(val);
+            let (index, _, _) = self.field_info(struct_name, f.0);
+            values.push((index, val));
+        }
+        // Fields that weren't listed are copied from the base
+        let mut res = match &strukt.base {
+            Some(base) => match self.evaluate(base, false)? {
+                Value::Struct(fields) => fields,
+                _ => unreachable!(),
+            },
+            None => vec![Value::Undefined; self.struct_fields(struct_name).len()],
+        };
+        for (index, val) in values {
+            res[index] = val;
         }
         Ok(Value::Struct(res))
     }
     fn evaluate_call(&mut self, call: &nodes::CallNode<'src>) -> Result<Value, EvalError<'src>> {
-        let Some(func) = self.functions.get(call.get_full_name()) else {
+        let Some(func) = self.functions.get(call.get_full_name()).copied() else {
             return Err(EvalError::UnknownFunction(call.location, call.get_full_name()))
         };
         debug_assert!(func.is_comptime);
@@ -487,41 +912,88 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
         let arguments = call.arguments
             .iter().map(|e|self.evaluate(e, false)).collect::<Result<Vec<Value>, _>>();
         let vals = arguments?;
-        self.enter_scope();
         self.check_scope_limit(&call.location)?;
-        // Safety: We checked if it exists a few lines earlier.
-        // Second access is needed because of borrowing rules.
-        let func = unsafe { self.functions.get(call.get_full_name()).unwrap_unchecked() };
+        self.call_stack.push((call.get_full_name(), call.location));
+        self.enter_scope();
         for (arg, param) in vals.iter().zip(&func.parameters) {
-            self.add_variable_by_name(param.name, arg)?;
+            self.add_variable_by_name(param.name, arg, &param.typ.typ)?;
         }
-        // Safety: We checked if it exists a few lines earlier.
-        // Another access is needed because of borrowing rules.
-        let func = unsafe { self.functions.get(call.get_full_name()).unwrap_unchecked() };
         let result = self.evaluate_block(&func.block)?;
         self.exit_scope();
-        Ok(result)
+        self.call_stack.pop();
+        match result {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::None),
+        }
     }
 
     fn evaluate_binary(&mut self, binary: &nodes::BinaryNode<'src>) -> Result<Value, EvalError<'src>> {
-        let lhs = self.evaluate(&binary.lhs, binary.operation == Operation::Assign)?;
+        match binary.operation {
+            Operation::Assign => {
+                let Value::Ptr(addr) = self.evaluate(&binary.lhs, true)? else { unreachable!() };
+                let rhs = self.evaluate(&binary.rhs, false)?;
+                self.store(addr, &rhs, &binary.lhs.get_type());
+                return Ok(rhs);
+            }
+            Operation::MemberAccess => {
+                if let (Type::Struct(struct_name), nodes::Expression::Name(field)) = (binary.lhs.get_type(), &*binary.rhs) {
+                    if self.is_union(&binary.lhs.get_type()) {
+                        let addr = self.evaluate_place(&nodes::Expression::Binary(binary.clone()))?;
+                        return self.load(addr, &binary.typ, &binary.location);
+                    }
+                    // Struct values don't need to live in memory, we can pick the field directly
+                    let Value::Struct(mut fields) = self.evaluate(&binary.lhs, false)? else {
+                        internal_panic!("Expected struct value, the Type Checker should've caught this!")
+                    };
+                    let (index, _, _) = self.field_info(struct_name, field.name);
+                    return Ok(fields.swap_remove(index));
+                }
+                if let (Type::Tuple(_), nodes::Expression::Literal(index)) = (binary.lhs.get_type(), &*binary.rhs) {
+                    let Value::Struct(mut elements) = self.evaluate(&binary.lhs, false)? else {
+                        internal_panic!("Expected tuple value, the Type Checker should've caught this!")
+                    };
+                    let index = index.value.parse::<usize>().expect("Tuple indices are checked by the Type Checker");
+                    return Ok(elements.swap_remove(index));
+                }
+                let addr = self.evaluate_place(&nodes::Expression::Binary(binary.clone()))?;
+                return self.load(addr, &binary.typ, &binary.location);
+            }
+            Operation::IndexedAccess => {
+                let Value::Array(mut elements) = self.evaluate(&binary.lhs, false)? else {
+                    internal_panic!("Expected array value, the Type Checker should've caught this!")
+                };
+                let (index, _) = self.evaluate_index(binary)?;
+                return Ok(elements.swap_remove(index));
+            }
+            Operation::LogicalAnd | Operation::LogicalOr => {
+                // Short-circuiting, so `p != null && *p == 0` doesn't dereference null
+                let lhs = self.evaluate(&binary.lhs, false)?.as_bool();
+                if lhs == (binary.operation == Operation::LogicalOr) {
+                    return Ok(Value::Bool(lhs));
+                }
+                return Ok(Value::Bool(self.evaluate(&binary.rhs, false)?.as_bool()));
+            }
+            _ => {}
+        }
+        let lhs = self.evaluate(&binary.lhs, false)?;
         let rhs = self.evaluate(&binary.rhs, false)?;
+        self.combine_binary(binary, &lhs, &rhs)
+    }
+
+    fn combine_binary(&self, binary: &nodes::BinaryNode<'src>, lhs: &Value, rhs: &Value) -> Result<Value, EvalError<'src>> {
+        if matches!(binary.operation, Operation::Div | Operation::Modulo) && matches!(rhs, Value::I128(0)) {
+            return Err(EvalError::DivisionByZero(binary.location));
+        }
         macro_rules! enumerate_ops {
             ($([$($vals:ident)*] [$($res:ident)*] $name:ident $op:tt)+) => {
                 {
-                    match (&binary.operation, &lhs, &rhs) {
-                        (Operation::Assign, Value::Ptr(addr), rhs) => {
-                            let vals = self.encode_value(rhs);
-                            self.memset(*addr, &vals);
-                            Ok(rhs.clone())
-                        }
-                        (Operation::Add, Value::Ptr(l), Value::I128(r)) => {
-                            if !rhs.in_type_bounds(&Type::Usize) {
-                                Err(EvalError::ValueOutOfBounds(binary.location, rhs, Type::Usize))
-                            } else {
-                                Ok(Value::Ptr(l + *r as usize))
-                            }
-                        },
+                    match (&binary.operation, lhs, rhs) {
+                        // Pointer arithmetic is done in bytes, just like at runtime
+                        (Operation::Add, Value::Ptr(p), Value::I128(offset))
+                        | (Operation::Add, Value::I128(offset), Value::Ptr(p)) => Ok(Value::Ptr((*p as i128 + offset) as usize)),
+                        (Operation::Sub, Value::Ptr(p), Value::I128(offset)) => Ok(Value::Ptr((*p as i128 - offset) as usize)),
+                        // Multiplying two 64 bit values can overflow even an i128, the saturated result is out of bounds for every type
+                        (Operation::Mul, Value::I128(l), Value::I128(r)) => Ok(Value::I128(l.saturating_mul(*r))),
                         $($((Operation::$name, Value::$vals(l), Value::$vals(r)) => Ok(Value::$res(l $op r)),)*)+
                         (o, l, r) => Err(EvalError::BinaryNotImplemented(binary.location, *o, l.clone(), r.clone()))
                     }
@@ -529,34 +1001,37 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
             };
         }
         enumerate_ops!(
-            [Char I128 F64 Ptr] [Char I128 F64 Ptr] Add +
-            [Char I128 F64 Ptr] [Char I128 F64 Ptr] Sub -
-            [I128 F64] [I128 F64] Mul *
+            [I128 F64] [I128 F64] Add +
+            [I128 F64] [I128 F64] Sub -
+            [F64] [F64] Mul *
+            [I128 F64] [I128 F64] Div /
+            [I128 F64] [I128 F64] Modulo %
+            [Bool I128] [Bool I128] BitwiseAnd &
+            [Bool I128] [Bool I128] BitwiseOr |
+            [Bool I128] [Bool I128] BitwiseXor ^
             [Bool Char I128 F64 Ptr] [Bool Bool Bool Bool Bool] NotEqual !=
             [Bool Char I128 F64 Ptr] [Bool Bool Bool Bool Bool] Equal ==
+            [Char I128 F64 Ptr] [Bool Bool Bool Bool] LessThan <
+            [Char I128 F64 Ptr] [Bool Bool Bool Bool] LessThanOrEqual <=
+            [Char I128 F64 Ptr] [Bool Bool Bool Bool] GreaterThan >
+            [Char I128 F64 Ptr] [Bool Bool Bool Bool] GreaterThanOrEqual >=
         )
     }
 
-    fn evaluate_name(&self, name: &nodes::NameNode<'src>, needs_ptr: bool) -> Result<Value, EvalError<'src>> {
-        let Ok(var) = self.get_variable_ptr(name) else {
-            internal_panic!("Evaluator encountered unknown identifier {}. This should've been caught by the Type Checker!", name.name);
-        };
-        if needs_ptr {
-            Ok(Value::Ptr(var))
-        } else {
-            let mem = self.get_memory_by_ptr(var);
-            self.decode_value(mem, &name.typ)
-        }
+    fn evaluate_name(&self, name: &nodes::NameNode<'src>) -> Result<Value, EvalError<'src>> {
+        // Runtime variables are known to the Type Checker, but not to us
+        let var = self.get_variable_ptr(name)?;
+        self.load(var, &name.typ, &name.location)
     }
 
     fn evaluate_literal(&mut self, literal: &nodes::LiteralNode<'src>) -> Result<Value, EvalError<'src>> {
         match &literal.typ {
             Type::Bool => Ok(Value::Bool(literal.value == "true")),
             Type::Char => {
-                let escaped = self.escape_string_or_char_value(&literal.value, false);
+                let escaped = self.escape_string_or_char_value(literal.value, false);
                 debug_assert!(escaped.len() == 1);
                 let value = escaped.chars().next().unwrap() as u8;
-                Ok(Value::Char(value as u8))
+                Ok(Value::Char(value))
             }
             Type::Usize
             | Type::U8 | Type::I8
@@ -568,15 +1043,62 @@ impl<'flags, 'src, 'ast> Evaluator<'flags, 'src, 'ast> {
                 if **t != Type::Char {
                     unimplemented!("codegen_literal: {:?}", literal);
                 }
-                let escaped = self.escape_string_or_char_value(&literal.value, true);
-                let ptr = self.alloc(escaped.len())?;
-                self.memset(ptr, escaped.as_bytes());
-                Ok(Value::Ptr(ptr))
+                let escaped = self.escape_string_or_char_value(literal.value, true);
+                Ok(Value::Ptr(self.intern_string(escaped.into_bytes())?))
             }
+            // `blank` is all zeroes
+            t @ (Type::Struct(_) | Type::Tuple(_) | Type::Array(_, _)) => Ok(self.zero_value(t)),
+            Type::Any => Ok(Value::Ptr(0)),
             e => unimplemented!("codegen_literal: {:?}", e),
         }
     }
 
+    // Expects the null terminator to be part of `content`
+    fn intern_string(&mut self, content: Vec<u8>) -> Result<usize, EvalError<'src>> {
+        if let Some(ptr) = self.strings.get(&content) {
+            return Ok(*ptr);
+        }
+        let ptr = self.alloc(content.len())?;
+        self.memory[ptr..ptr + content.len()].copy_from_slice(&content);
+        self.strings.insert(content, ptr);
+        Ok(ptr)
+    }
+
+    fn evaluate_embed(&mut self, embed: &nodes::EmbedNode<'src>) -> Result<Value, EvalError<'src>> {
+        if embed.length_only {
+            return Ok(Value::I128(embed.content.len() as i128));
+        }
+        let mut content = embed.content.clone();
+        content.push(0);
+        Ok(Value::Ptr(self.intern_string(content)?))
+    }
+
+    fn evaluate_reflection(&mut self, reflection: &nodes::ReflectionNode<'src>) -> Result<Value, EvalError<'src>> {
+        let typ = &reflection.reflected.typ;
+        match &reflection.kind {
+            nodes::ReflectionKind::FieldCount => {
+                let Type::Struct(name) = typ else { unreachable!() };
+                Ok(Value::I128(self.struct_fields(name).len() as i128))
+            }
+            nodes::ReflectionKind::FieldName(index) => {
+                let Type::Struct(name) = typ else { unreachable!() };
+                let Value::I128(index) = self.evaluate(index, false)? else { unreachable!() };
+                let fields = self.struct_fields(name);
+                if index < 0 || index as usize >= fields.len() {
+                    return Err(EvalError::IndexOutOfBounds(reflection.location, index, fields.len()));
+                }
+                let field_name = format!("{}\0", fields[index as usize].0);
+                Ok(Value::Ptr(self.intern_string(field_name.into_bytes())?))
+            }
+            nodes::ReflectionKind::Offsetof(field) => {
+                let Type::Struct(name) = typ else { unreachable!() };
+                Ok(Value::I128(self.field_info(name, field.name).1 as i128))
+            }
+            nodes::ReflectionKind::Alignof => Ok(Value::I128(self.align_of(typ) as i128)),
+            nodes::ReflectionKind::TypeName => Ok(Value::Ptr(self.intern_string(format!("{typ}\0").into_bytes())?)),
+        }
+    }
+
     fn escape_string_or_char_value(&self, value: &str, null_terminated: bool) -> String {
         let mut new_value = Vec::new();
         let mut escaping = false;
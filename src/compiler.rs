@@ -13,10 +13,11 @@ use crate::backend::codegen_llvm::LLVMCodegen;
 use crate::frontend::lexer::Lexer;
 use crate::frontend::parser::Parser;
 use crate::frontend::pp;
+use crate::middleend::bounds_checker::BoundsChecker;
 use crate::middleend::flow_checker::FlowChecker;
 use crate::middleend::type_checker::TypeChecker;
 use crate::util::printer::Printer;
-use crate::util::flags::Flags;
+use crate::util::flags::{CovFlags, Flags};
 
 pub const ERR_STR: &str = "\x1b[91merror\x1b[0m";
 pub const WARN_STR: &str = "\x1b[93mwarning\x1b[0m";
@@ -123,7 +124,7 @@ pub fn run_everything(
     context: &Context,
 ) -> Result<(), String> {
     let now = Instant::now();
-    let source_code: String = pp::load_project(&flags)?;
+    let (source_code, dependencies, sources) = pp::load_project(&flags)?;
     if flags.verbose {
         println!("[INFO] Preprocessing took {:?}", now.elapsed());
     }
@@ -131,7 +132,8 @@ pub fn run_everything(
     let mut parser = Parser::new(&flags, &mut lexer, &source_code);
     let mut type_checker = TypeChecker::new(&flags);
     let mut flow_checker = FlowChecker::new(&flags);
-    let mut codegen = LLVMCodegen::new(&flags, &context);
+    let mut bounds_checker = BoundsChecker::new(flags);
+    let mut codegen = LLVMCodegen::new(&flags, &context, sources);
     let now = Instant::now();
     let mut parsed_ast = parser.parse_project()?;
     if flags.verbose {
@@ -158,6 +160,16 @@ pub fn run_everything(
         println!("[INFO] Flow Checking took {:?}", now.elapsed());
     }
 
+    let now = Instant::now();
+    bounds_checker.check_project(&mut parsed_ast);
+    if flags.verbose {
+        println!("[INFO] Bounds Checking took {:?}", now.elapsed());
+    }
+
+    if flags.emit_deps {
+        codegen.write_dependencies(&dependencies)?;
+    }
+
     let now = Instant::now();
     codegen.codegen_project(&parsed_ast)?;
     if flags.verbose {
@@ -175,6 +187,9 @@ pub fn run_everything(
 }
 #[trace_call(always)]
 fn compile() -> Result<(), String> {
+    if std::env::args().nth(1).is_some_and(|arg| arg == "cov") {
+        return crate::util::coverage::report(&CovFlags::parse_flags());
+    }
     let now = Instant::now();
     let mut flags = Flags::parse_flags();
     flags.imports.push(String::from("."));
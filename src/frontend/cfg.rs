@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::frontend::tokens::{Token, TokenType};
+use crate::util::flags::Flags;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CfgValue {
+    // cfgs that weren't passed on the command line
+    Unset,
+    Bool(bool),
+    Int(i128),
+    Str(String),
+}
+
+impl Display for CfgValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unset => write!(f, "an unset cfg"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Int(i) => write!(f, "{i}"),
+            Self::Str(s) => write!(f, "\"{s}\""),
+        }
+    }
+}
+
+impl CfgValue {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "true" => Self::Bool(true),
+            "false" => Self::Bool(false),
+            v => match v.parse::<i128>() {
+                Ok(i) => Self::Int(i),
+                Err(_) => Self::Str(v.to_string()),
+            }
+        }
+    }
+}
+
+/// Everything a `comptime if` condition can ask about the current build
+pub struct Cfg {
+    values: HashMap<String, CfgValue>,
+}
+
+impl Cfg {
+    pub fn new(flags: &Flags) -> Self {
+        let mut values = HashMap::new();
+        for (name, value) in &flags.cfgs {
+            let value = match value {
+                Some(v) => CfgValue::parse(v),
+                None => CfgValue::Bool(true),
+            };
+            values.insert(name.clone(), value);
+        }
+        // Builtins can't be overwritten by the user, they describe the actual target
+        for (name, value) in Self::builtins(flags) {
+            values.insert(name.to_string(), value);
+        }
+        Self { values }
+    }
+
+    fn builtins(flags: &Flags) -> Vec<(&'static str, CfgValue)> {
+        // We only ever compile for the host
+        vec![
+            ("TARGET_OS", CfgValue::Str(std::env::consts::OS.to_string())),
+            ("TARGET_ARCH", CfgValue::Str(std::env::consts::ARCH.to_string())),
+            ("TARGET_POINTER_WIDTH", CfgValue::Int(usize::BITS as i128)),
+            ("BUILD_DEBUG", CfgValue::Bool(flags.optimizations.is_debug())),
+        ]
+    }
+
+    /// The builtins as comptime variables, so regular code can use them too
+    pub fn declarations(flags: &Flags) -> String {
+        let mut decls = String::new();
+        for (name, value) in Self::builtins(flags) {
+            let typ = match value {
+                CfgValue::Unset => continue,
+                CfgValue::Bool(_) => "bool",
+                CfgValue::Int(_) => "usize",
+                CfgValue::Str(_) => "&char",
+            };
+            decls.push_str(&format!("comptime {name}: {typ} = {value};"));
+        }
+        decls
+    }
+
+    pub fn evaluate(&self, condition: &[Token]) -> Result<bool, String> {
+        let mut evaluator = CfgEvaluator { cfg: self, tokens: condition, index: 0 };
+        let value = evaluator.evaluate_or()?;
+        if let Some(t) = condition.get(evaluator.index) {
+            return Err(format!("Unexpected token `{t}` in condition."));
+        }
+        match value {
+            CfgValue::Unset => Ok(false),
+            CfgValue::Bool(b) => Ok(b),
+            v => Err(format!("Condition evaluated to {v}, expected a bool.")),
+        }
+    }
+}
+
+// Conditions only know `||`, `&&`, `==`, `!=`, `!` and parentheses
+struct CfgEvaluator<'cfg, 'tkn, 'src> {
+    cfg: &'cfg Cfg,
+    tokens: &'tkn [Token<'src>],
+    index: usize,
+}
+
+impl CfgEvaluator<'_, '_, '_> {
+    fn eat(&mut self, token_type: TokenType) -> bool {
+        match self.tokens.get(self.index) {
+            Some(t) if t.token_type == token_type => {
+                self.index += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_bool(value: CfgValue, op: &str) -> Result<bool, String> {
+        match value {
+            CfgValue::Unset => Ok(false),
+            CfgValue::Bool(b) => Ok(b),
+            v => Err(format!("Operator `{op}` expects a bool, found {v}.")),
+        }
+    }
+
+    fn evaluate_or(&mut self) -> Result<CfgValue, String> {
+        let mut lhs = self.evaluate_and()?;
+        while self.eat(TokenType::DoublePipe) {
+            let rhs = self.evaluate_and()?;
+            lhs = CfgValue::Bool(Self::expect_bool(lhs, "||")? | Self::expect_bool(rhs, "||")?);
+        }
+        Ok(lhs)
+    }
+
+    fn evaluate_and(&mut self) -> Result<CfgValue, String> {
+        let mut lhs = self.evaluate_comparison()?;
+        while self.eat(TokenType::DoubleAmpersand) {
+            let rhs = self.evaluate_comparison()?;
+            lhs = CfgValue::Bool(Self::expect_bool(lhs, "&&")? & Self::expect_bool(rhs, "&&")?);
+        }
+        Ok(lhs)
+    }
+
+    fn evaluate_comparison(&mut self) -> Result<CfgValue, String> {
+        let lhs = self.evaluate_unary()?;
+        let equal = if self.eat(TokenType::CmpEq) {
+            true
+        } else if self.eat(TokenType::CmpNeq) {
+            false
+        } else {
+            return Ok(lhs);
+        };
+        let rhs = self.evaluate_unary()?;
+        // An unset cfg is never equal to any value
+        if lhs == CfgValue::Unset || rhs == CfgValue::Unset {
+            return Ok(CfgValue::Bool((lhs == rhs) == equal));
+        }
+        if std::mem::discriminant(&lhs) != std::mem::discriminant(&rhs) {
+            return Err(format!("Can't compare {lhs} with {rhs}."));
+        }
+        Ok(CfgValue::Bool((lhs == rhs) == equal))
+    }
+
+    fn evaluate_unary(&mut self) -> Result<CfgValue, String> {
+        if self.eat(TokenType::Exclamation) {
+            let value = self.evaluate_unary()?;
+            return Ok(CfgValue::Bool(!Self::expect_bool(value, "!")?));
+        }
+        if self.eat(TokenType::OpenRound) {
+            let value = self.evaluate_or()?;
+            if !self.eat(TokenType::ClosingRound) {
+                return Err(String::from("Expected `)` in condition."));
+            }
+            return Ok(value);
+        }
+        let Some(token) = self.tokens.get(self.index) else {
+            return Err(String::from("Unexpected end of condition."));
+        };
+        self.index += 1;
+        match token.token_type {
+            TokenType::KeywordTrue => Ok(CfgValue::Bool(true)),
+            TokenType::KeywordFalse => Ok(CfgValue::Bool(false)),
+            TokenType::LiteralString => Ok(CfgValue::Str(token.value.to_string())),
+            TokenType::LiteralInteger => Ok(CfgValue::parse(token.value)),
+            TokenType::Identifier => Ok(self.cfg.values.get(token.value).cloned().unwrap_or(CfgValue::Unset)),
+            _ => Err(format!("Unexpected token `{token}` in condition.")),
+        }
+    }
+}
@@ -39,6 +39,20 @@ impl<'src> Lexer<'src> {
         t
     }
 
+    pub fn peek_nth(&mut self, n: usize) -> Option<Token<'src>> {
+        let save = self.ptr;
+        let orig = self.content;
+        let ws = self.whitespace_offset;
+        let mut t = self.next();
+        for _ in 0..n {
+            t = self.next();
+        }
+        self.ptr = save;
+        self.content = orig;
+        self.whitespace_offset = ws;
+        t
+    }
+
     pub fn next_with_whitespace(&mut self) -> (usize, Option<Token<'src>>) {
         let t = self.__next(true);
         let ws = self.whitespace_offset;
@@ -207,6 +221,7 @@ impl<'src> Lexer<'src> {
                 ["&&" DoubleAmpersand],
                 ["&" Ampersand],
                 ["..." VarArg],
+                [".." DotDot],
                 ["." Dot],
                 ["!=" CmpNeq],
                 ["==" CmpEq],
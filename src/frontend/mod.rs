@@ -1,3 +1,4 @@
+pub mod cfg;
 pub mod nodes;
 pub mod parser;
 pub mod pp;
@@ -1,8 +1,10 @@
+use std::cell::OnceCell;
 use std::fs;
 
 use crate::frontend::tokens::Location;
 use crate::frontend::parser::Operation;
 use crate::middleend::type_checker::Type;
+use crate::internal_panic;
 
 use tracer::trace_call;
 
@@ -17,8 +19,12 @@ macro_rules! fn_is_inlinable {
 #[derive(Debug, Clone)]
 pub struct FileNode<'src> {
     pub globals: Vec<VarDeclNode<'src>>,
+    pub asserts: Vec<ComptimeAssertNode<'src>>,
     pub externs: Vec<ExternNode<'src>>,
     pub structs: Vec<StructNode<'src>>,
+    pub interfaces: Vec<InterfaceNode<'src>>,
+    pub impls: Vec<ImplNode<'src>>,
+    pub aliases: Vec<TypeAliasNode<'src>>,
     pub functions: Vec<FunctionNode<'src>>,
     pub compiler_flags: CompilerFlagsNode<'src>,
 }
@@ -102,6 +108,9 @@ pub struct StructNode<'src> {
     pub name: &'src str,
     pub fields: Vec<FieldNode<'src>>,
     pub methods: Vec<MethodNode<'src>>,
+    // `union Name { ... }`, all fields start at offset 0
+    pub is_union: bool,
+    pub layout: Layout,
 }
 
 impl<'src> StructNode<'src> {
@@ -116,12 +125,75 @@ pub struct FieldNode<'src> {
     pub location: Location,
     pub name: &'src str,
     pub type_def: TypeNode<'src>,
+    pub layout: Layout,
+    // `name: Type = value;`, used when a struct literal omits the field
+    pub default: Option<Expression<'src>>,
+}
+
+/// `packed` and `align(N)` in front of a struct, union or field
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Layout {
+    pub packed: bool,
+    pub align: Option<usize>,
+}
+
+impl Layout {
+    // Alignment of a field or struct whose members are naturally aligned to `natural`
+    #[trace_call(extra)]
+    pub fn apply(&self, natural: usize) -> usize {
+        let align = if self.packed { 1 } else { natural };
+        align.max(self.align.unwrap_or(1))
+    }
+}
+
+/// `interface Name { func method(&this, ...) -> T; ... }`
+#[derive(Debug, Clone)]
+pub struct InterfaceNode<'src> {
+    pub location: Location,
+    pub name: &'src str,
+    pub methods: Vec<InterfaceMethodNode<'src>>,
+}
+
+/// A method signature without a body
+#[derive(Debug, Clone)]
+pub struct InterfaceMethodNode<'src> {
+    pub location: Location,
+    pub name: &'src str,
+    pub return_type: TypeNode<'src>,
+    pub parameters: Vec<ParameterNode<'src>>,
+    pub is_unsafe: bool,
+}
+
+/// `impl Interface for Struct;`, the methods themselves are declared in the struct
+#[derive(Debug, Clone)]
+pub struct ImplNode<'src> {
+    pub location: Location,
+    pub interface: &'src str,
+    pub struct_name: &'src str,
+}
+
+/// `type Name = Type;`, the TypeChecker replaces every use of `Name` with `Type`
+#[derive(Debug, Clone)]
+pub struct TypeAliasNode<'src> {
+    pub location: Location,
+    pub name: &'src str,
+    pub typ: TypeNode<'src>,
+}
+
+/// `T: Interface` in `func name<T: Interface>(...)`
+#[derive(Debug, Clone)]
+pub struct TypeParameterNode<'src> {
+    pub location: Location,
+    pub name: &'src str,
+    pub bound: &'src str,
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionNode<'src> {
     pub location: Location,
     pub name: &'src str,
+    // Generic functions are never generated themselves, the TypeChecker instantiates them for every used type
+    pub type_parameters: Vec<TypeParameterNode<'src>>,
     pub return_type: TypeNode<'src>,
     pub parameters: Vec<ParameterNode<'src>>,
     pub block: BlockNode<'src>,
@@ -218,6 +290,9 @@ pub enum Statement<'src> {
     While(WhileNode<'src>),
     Break(BreakNode),
     Continue(ContinueNode),
+    ComptimeAssert(ComptimeAssertNode<'src>),
+    Destructure(DestructureNode<'src>),
+    Defer(DeferNode<'src>),
 }
 
 impl<'src> Statement<'src> {
@@ -231,6 +306,9 @@ impl<'src> Statement<'src> {
             Self::While(e) => e.location,
             Self::Break(e) => e.location,
             Self::Continue(e) => e.location,
+            Self::ComptimeAssert(e) => e.location,
+            Self::Destructure(e) => e.location,
+            Self::Defer(e) => e.location,
         }
     }
 }
@@ -246,6 +324,24 @@ pub struct VarDeclNode<'src> {
     pub is_unsafe: bool,
 }
 
+// `let (a, b) = f();`, the names are declared in the order of the tuple elements
+#[derive(Debug, Clone)]
+pub struct DestructureNode<'src> {
+    pub location: Location,
+    pub names: Vec<(Location, &'src str)>,
+    // Type::Unknown if there's no annotation, the type checker infers it from the expression then
+    pub typ: TypeNode<'src>,
+    pub expression: Expression<'src>,
+    pub is_mutable: bool,
+}
+
+// `defer <statement>;`, the statement runs when the enclosing block is left, in reverse order of the defers
+#[derive(Debug, Clone)]
+pub struct DeferNode<'src> {
+    pub location: Location,
+    pub statement: Box<Statement<'src>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct IfNode<'src> {
     pub location: Location,
@@ -254,6 +350,13 @@ pub struct IfNode<'src> {
     pub else_body: Option<BlockNode<'src>>,
 }
 
+#[derive(Debug, Clone)]
+pub struct ComptimeAssertNode<'src> {
+    pub location: Location,
+    pub condition: Expression<'src>,
+    pub message: Option<&'src str>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ReturnNode<'src> {
     pub location: Location,
@@ -311,12 +414,17 @@ pub enum Expression<'src> {
     Literal(LiteralNode<'src>),
     StructLiteral(StructLiteralNode<'src>),
     ArrayLiteral(ArrayLiteralNode<'src>),
+    TupleLiteral(TupleLiteralNode<'src>),
     Unary(UnaryNode<'src>),
     Binary(BinaryNode<'src>),
     // Parenthesis(Expression),
     FunctionCall(CallNode<'src>),
     Sizeof(TypeNode<'src>),
+    Reflection(ReflectionNode<'src>),
+    Embed(EmbedNode<'src>),
+    Arithmetic(ArithmeticNode<'src>),
     As(Box<Expression<'src>>, TypeNode<'src>),
+    Lambda(LambdaNode<'src>),
 }
 
 impl<'src> Expression<'src> {
@@ -327,11 +435,16 @@ impl<'src> Expression<'src> {
             Self::Literal(e) => e.location,
             Self::StructLiteral(e) => e.location,
             Self::ArrayLiteral(e) => e.location,
+            Self::TupleLiteral(e) => e.location,
             Self::Unary(e) => e.location,
             Self::Binary(e) => e.location,
             Self::FunctionCall(e) => e.location,
             Self::Sizeof(e) => e.location,
+            Self::Reflection(e) => e.location,
+            Self::Embed(e) => e.location,
+            Self::Arithmetic(e) => e.location,
             Self::As(e, _) => e.get_loc(),
+            Self::Lambda(e) => e.location,
         }
     }
 
@@ -341,12 +454,17 @@ impl<'src> Expression<'src> {
             Self::Name(e) => e.typ.clone(),
             Self::StructLiteral(e) => e.typ.clone(),
             Self::ArrayLiteral(e) => e.typ.clone(),
+            Self::TupleLiteral(e) => e.typ.clone(),
             Self::Literal(e) => e.typ.clone(),
             Self::Unary(e) => e.typ.clone(),
             Self::Binary(e) => e.typ.clone(),
             Self::FunctionCall(e) => e.typ.clone(),
             Self::Sizeof(_e) => Type::Usize,
+            Self::Reflection(e) => e.kind.get_type(),
+            Self::Embed(e) => e.get_type(),
+            Self::Arithmetic(e) => e.typ.clone(),
             Self::As(_, t) => t.typ.clone(),
+            Self::Lambda(e) => e.typ.clone(),
         }
     }
 
@@ -357,12 +475,56 @@ impl<'src> Expression<'src> {
             Self::Name(e) => e.typ = typ,
             Self::StructLiteral(e) => e.typ = typ,
             Self::ArrayLiteral(e) => e.typ = typ,
+            Self::TupleLiteral(e) => e.typ = typ,
             Self::Literal(e) => e.typ = typ,
             Self::Unary(e) => e.typ = typ,
             Self::Binary(e) => e.typ = typ,
             Self::FunctionCall(e) => e.typ = typ,
             Self::Sizeof(e) => todo!(),
+            // Their type follows from what they reflect on or embed
+            Self::Reflection(_) => internal_panic!("Attempted to set the type of a reflection intrinsic to `{}`", typ),
+            Self::Embed(_) => internal_panic!("Attempted to set the type of an embed intrinsic to `{}`", typ),
+            Self::Arithmetic(e) => e.typ = typ,
             Self::As(e, t) => todo!(),
+            Self::Lambda(e) => e.typ = typ,
+        }
+    }
+
+    /// Returns the location of the first subexpression that can't be evaluated at compile time
+    #[trace_call(extra)]
+    pub fn find_non_constant(&self) -> Option<Location> {
+        match &self {
+            Self::Literal(_) | Self::Sizeof(_) | Self::Embed(_) => None,
+            Self::Name(e) => if e.is_comptime { None } else { Some(e.location) },
+            Self::StructLiteral(e) => e.fields.iter()
+                .find_map(|(_, f)| f.find_non_constant())
+                .or_else(|| e.base.as_ref().and_then(|b| b.find_non_constant())),
+            Self::ArrayLiteral(e) => e.elements.iter().find_map(|e| e.find_non_constant()),
+            Self::TupleLiteral(e) => e.elements.iter().find_map(|e| e.find_non_constant()),
+            Self::Unary(e) => *e.non_constant.get_or_init(|| match e.operation {
+                Operation::Reference | Operation::Dereference => Some(e.location),
+                _ => e.expression.find_non_constant(),
+            }),
+            Self::Binary(e) => *e.non_constant.get_or_init(|| match (e.operation, e.rhs.as_ref()) {
+                (Operation::Assign, _) => Some(e.location),
+                // Field and tuple element accesses are fine, method calls are not
+                (Operation::MemberAccess, Self::Name(_) | Self::Literal(_)) => e.lhs.find_non_constant(),
+                (Operation::MemberAccess, rhs) => Some(rhs.get_loc()),
+                _ => e.lhs.find_non_constant().or_else(|| e.rhs.find_non_constant()),
+            }),
+            Self::FunctionCall(e) => Some(e.location),
+            Self::Reflection(e) => match &e.kind {
+                ReflectionKind::FieldName(index) => index.find_non_constant(),
+                _ => None,
+            },
+            Self::Arithmetic(e) => match e.mode {
+                // Writes to its result reference
+                ArithmeticMode::Checked => Some(e.location),
+                _ => e.lhs.find_non_constant().or_else(|| e.rhs.find_non_constant()),
+            },
+            Self::As(e, _) => e.find_non_constant(),
+            // Closures need a function to live in
+            Self::Lambda(e) => Some(e.location),
         }
     }
 
@@ -391,6 +553,26 @@ impl<'src> Expression<'src> {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct CaptureNode<'src> {
+    pub location: Location,
+    pub name: &'src str,
+    pub is_reference: bool,
+    pub is_mutable: bool,
+    // Set by the type checker to the type of the captured variable
+    pub typ: Type<'src>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LambdaNode<'src> {
+    pub location: Location,
+    pub captures: Vec<CaptureNode<'src>>,
+    pub parameters: Vec<ParameterNode<'src>>,
+    pub return_type: TypeNode<'src>,
+    pub block: BlockNode<'src>,
+    pub typ: Type<'src>,
+}
+
 #[derive(Debug, Clone)]
 pub struct LiteralNode<'src> {
     pub location: Location,
@@ -403,6 +585,8 @@ pub struct StructLiteralNode<'src> {
     pub location: Location,
     pub struct_name: &'src str,
     pub fields: Vec<(&'src str, Expression<'src>)>,
+    // `Name { a: 1, ..base }` copies all fields that aren't listed from `base`
+    pub base: Option<Box<Expression<'src>>>,
     pub typ: Type<'src>,
 }
 
@@ -418,12 +602,21 @@ pub struct ArrayLiteralNode<'src> {
     pub size: Option<usize>,
 }
 
+#[derive(Debug, Clone)]
+pub struct TupleLiteralNode<'src> {
+    pub location: Location,
+    pub elements: Vec<Expression<'src>>,
+    pub typ: Type<'src>,
+}
+
 #[derive(Debug, Clone)]
 pub struct UnaryNode<'src> {
     pub location: Location,
     pub operation: Operation,
     pub expression: Box<Expression<'src>>,
     pub typ: Type<'src>,
+    // Cached result of `find_non_constant`, which the LLVMCodegen asks for at every subexpression
+    pub non_constant: OnceCell<Option<Location>>,
 }
 
 #[derive(Debug, Clone)]
@@ -433,6 +626,10 @@ pub struct BinaryNode<'src> {
     pub lhs: Box<Expression<'src>>,
     pub rhs: Box<Expression<'src>>,
     pub typ: Type<'src>,
+    // Only used for IndexedAccess, cleared by `a[unsafe i]` and the BoundsChecker
+    pub needs_bounds_check: bool,
+    // Cached result of `find_non_constant`, which the LLVMCodegen asks for at every subexpression
+    pub non_constant: OnceCell<Option<Location>>,
 }
 
 impl<'src> BinaryNode<'src> {
@@ -462,6 +659,114 @@ impl<'src> BinaryNode<'src> {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum ReflectionKind<'src> {
+    FieldCount,
+    FieldName(Box<Expression<'src>>),
+    Offsetof(NameNode<'src>),
+    Alignof,
+    TypeName,
+}
+
+impl<'src> ReflectionKind<'src> {
+    #[trace_call(extra)]
+    pub fn get_type(&self) -> Type<'src> {
+        match self {
+            Self::FieldName(_) | Self::TypeName => Type::Ref(Box::new(Type::Char), false),
+            Self::FieldCount | Self::Offsetof(_) | Self::Alignof => Type::Usize,
+        }
+    }
+
+    #[trace_call(extra)]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FieldCount => "fieldCount",
+            Self::FieldName(_) => "fieldName",
+            Self::Offsetof(_) => "offsetof",
+            Self::Alignof => "alignof",
+            Self::TypeName => "typeName",
+        }
+    }
+
+    #[trace_call(extra)]
+    pub fn needs_struct(&self) -> bool {
+        matches!(self, Self::FieldCount | Self::FieldName(_) | Self::Offsetof(_))
+    }
+}
+
+/// Intrinsics like `offsetof(T, field)` that query a type instead of a value
+#[derive(Debug, Clone)]
+pub struct ReflectionNode<'src> {
+    pub location: Location,
+    pub kind: ReflectionKind<'src>,
+    pub reflected: TypeNode<'src>,
+}
+
+/// `embedFile("path")` and `embedFileLen("path")`, the path is kept as written in the source
+#[derive(Debug, Clone)]
+pub struct EmbedNode<'src> {
+    pub location: Location,
+    pub path: &'src str,
+    pub content: Vec<u8>,
+    pub length_only: bool,
+}
+
+impl<'src> EmbedNode<'src> {
+    #[trace_call(extra)]
+    pub fn get_type(&self) -> Type<'src> {
+        if self.length_only {
+            Type::Usize
+        } else {
+            Type::Ref(Box::new(Type::Char), false)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticMode {
+    Wrapping,
+    Checked,
+    Saturating,
+}
+
+/// `wrappingAdd(a, b)`, `saturatingSub(a, b)`, `checkedMul(a, b, &mut result)`, ...
+/// Explicit about what happens on overflow, regardless of `--overflow-checks`
+#[derive(Debug, Clone)]
+pub struct ArithmeticNode<'src> {
+    pub location: Location,
+    pub mode: ArithmeticMode,
+    pub operation: Operation,
+    pub lhs: Box<Expression<'src>>,
+    pub rhs: Box<Expression<'src>>,
+    // Only used by `checked*`, receives the wrapped result
+    pub result: Option<Box<Expression<'src>>>,
+    pub typ: Type<'src>,
+}
+
+impl<'src> ArithmeticNode<'src> {
+    #[trace_call(extra)]
+    pub fn name(&self) -> String {
+        let mode = match self.mode {
+            ArithmeticMode::Wrapping => "wrapping",
+            ArithmeticMode::Checked => "checked",
+            ArithmeticMode::Saturating => "saturating",
+        };
+        let operation = match self.operation {
+            Operation::Add => "Add",
+            Operation::Sub => "Sub",
+            Operation::Mul => "Mul",
+            o => internal_panic!("Unexpected arithmetic intrinsic operation {o:?}"),
+        };
+        format!("{mode}{operation}")
+    }
+
+    /// The type of the operands, `checked*` itself returns a bool
+    #[trace_call(extra)]
+    pub fn operand_type(&self) -> Type<'src> {
+        self.lhs.get_type()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CallNode<'src> {
     pub location: Location,
@@ -469,6 +774,10 @@ pub struct CallNode<'src> {
     pub arguments: Vec<Expression<'src>>,
     pub typ: Type<'src>,
     pub is_extern: bool,
+    // Set by the type checker if the call goes through a function pointer
+    pub is_indirect: bool,
+    // Set by the type checker if that function pointer is a closure, which also passes its environment
+    pub is_closure: bool,
 }
 
 impl<'src> CallNode<'src> {
@@ -488,4 +797,6 @@ pub struct NameNode<'src> {
     pub location: Location,
     pub name: &'src str,
     pub typ: Type<'src>,
+    // Set by the type checker if the name refers to a comptime variable
+    pub is_comptime: bool,
 }
@@ -1,9 +1,12 @@
+use std::cell::OnceCell;
 use std::collections::{HashSet, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
+use std::fs;
 use std::path::PathBuf;
 
 use super::lexer::Lexer;
 use super::nodes::{self, CompilerFlag};
+use super::pp::{find_embedded_file, import_paths, unescape_path, EMBED_INTRINSICS};
 use crate::compiler::{ERR_STR, NOTE_STR, WARN_STR};
 use crate::internal_panic;
 use crate::frontend::tokens::*;
@@ -26,6 +29,9 @@ pub enum ParserError<'src> {
     InvalidArraySize(Location),
     ArrayWithSpecifiedSizeMoreThanOneElement(Location),
     InvalidCharLiteral(Location, &'src str),
+    EmbedFailed(Location, &'src str, String),
+    InvalidDeferredStatement(Location, TokenType),
+    InvalidAlignment(Location, &'src str),
 }
 
 impl Display for ParserError<'_> {
@@ -61,6 +67,9 @@ impl Display for ParserError<'_> {
             Self::InvalidArraySize(l) => format!("{l:?}: Invalid array size."),
             Self::ArrayWithSpecifiedSizeMoreThanOneElement(l) => format!("{l:?}: Arrays with a specified size can only have one element."),
             Self::InvalidCharLiteral(loc, lit) => format!("{loc:?}: Invalid character literal `{lit}`."),
+            Self::EmbedFailed(l, path, err) => format!("{l:?}: Could not embed file `{path}`: {err}"),
+            Self::InvalidDeferredStatement(l, t) => format!("{l:?}: {t} statements can't be deferred."),
+            Self::InvalidAlignment(l, a) => format!("{l:?}: Alignment must be a power of two, found `{a}`."),
         };
         let message = format!("{}: {}", ERR_STR, error_msg);
         write!(f, "{}", message)
@@ -317,6 +326,18 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         self.lexer.peek()
     }
 
+    #[trace_call(extra)]
+    fn peek_nth(&mut self, n: usize) -> Option<Token<'src>> {
+        self.lexer.peek_nth(n)
+    }
+
+    // `comptime assert(...)` looks like a call, variables need a `:` after their name
+    #[trace_call(extra)]
+    fn at_comptime_assert(&mut self) -> bool {
+        matches!(self.peek(), Some(t) if t.token_type == TokenType::Identifier && t.value == "assert")
+            && matches!(self.peek_nth(1), Some(t) if t.token_type == TokenType::OpenRound)
+    }
+
     #[trace_call(extra)]
     fn next(&mut self) -> Option<Token<'src>> {
         match self.lexer.next() {
@@ -458,8 +479,12 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
             println!("[INFO] Parsing `{}`", filepath.to_str().unwrap());
         }
         let mut globals = vec![];
+        let mut asserts = vec![];
         let mut externs = vec![];
         let mut structs = vec![];
+        let mut interfaces = vec![];
+        let mut impls = vec![];
+        let mut aliases = vec![];
         let mut functions = vec![];
 
         const RECOVER_TOKENS: [TokenType; 3] = [
@@ -500,7 +525,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                 TokenType::KeywordImport => {
                     todo!("Error: Imports are handled by the Preprocessor.")
                 }
-                TokenType::KeywordStruct => {
+                TokenType::KeywordStruct | TokenType::KeywordUnion | TokenType::KeywordPacked | TokenType::KeywordAlign => {
                     let Ok(parsed_struct) = self.parse_struct() else {
                         self.recover(&RECOVER_TOKENS);
                         valid = false;
@@ -508,6 +533,30 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     };
                     structs.push(parsed_struct);
                 }
+                TokenType::KeywordInterface => {
+                    let Ok(parsed_interface) = self.parse_interface() else {
+                        self.recover(&RECOVER_TOKENS);
+                        valid = false;
+                        continue;
+                    };
+                    interfaces.push(parsed_interface);
+                }
+                TokenType::KeywordImpl => {
+                    let Ok(parsed_impl) = self.parse_impl() else {
+                        self.recover(&RECOVER_TOKENS);
+                        valid = false;
+                        continue;
+                    };
+                    impls.push(parsed_impl);
+                }
+                TokenType::KeywordType => {
+                    let Ok(parsed_alias) = self.parse_type_alias() else {
+                        self.recover(&RECOVER_TOKENS);
+                        valid = false;
+                        continue;
+                    };
+                    aliases.push(parsed_alias);
+                }
                 t @ TokenType::KeywordMut | t @ TokenType::KeywordLet => {
                     self.expect(t)?;
                     let Ok(parsed_global) = self.parse_stmt_var_decl(t == TokenType::KeywordMut, false, false) else {
@@ -524,6 +573,14 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                         return Err(());
                     };
                     match tkn.token_type {
+                        TokenType::Identifier if self.at_comptime_assert() => {
+                            let Ok(parsed_assert) = self.parse_comptime_assert() else {
+                                self.recover(&RECOVER_TOKENS);
+                                valid = false;
+                                continue;
+                            };
+                            asserts.push(parsed_assert);
+                        }
                         TokenType::Identifier => {
                             let Ok(parsed_global) = self.parse_stmt_var_decl(false, true, false) else {
                                 self.recover(&RECOVER_TOKENS);
@@ -632,10 +689,16 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     self.report_error(ParserError::UnexpectedTokenMany(
                         tkn.location,
                         vec![
+                            TokenType::KeywordAlign,
                             TokenType::KeywordExtern,
                             TokenType::KeywordFunc,
+                            TokenType::KeywordImpl,
                             TokenType::KeywordImport,
+                            TokenType::KeywordInterface,
+                            TokenType::KeywordPacked,
                             TokenType::KeywordStruct,
+                            TokenType::KeywordType,
+                            TokenType::KeywordUnion,
                             TokenType::KeywordUnsafe
                         ],
                         tkn.token_type,
@@ -650,8 +713,12 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
 
         let file = nodes::FileNode {
             globals,
+            asserts,
             externs,
             structs,
+            interfaces,
+            impls,
+            aliases,
             functions,
             compiler_flags
         };
@@ -731,7 +798,11 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
     #[trace_call(always)]
     fn parse_struct(&mut self)-> Result<nodes::StructNode<'src>, ()> {
         let location = self.get_location();
-        self.expect(TokenType::KeywordStruct)?;
+        let layout = self.parse_layout()?;
+        let is_union = self.eat(TokenType::KeywordUnion);
+        if !is_union {
+            self.expect(TokenType::KeywordStruct)?;
+        }
 
         let struct_name = self.expect(TokenType::Identifier)?;
         let name = struct_name.value;
@@ -753,7 +824,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                 break;
             }
             match tkn.token_type {
-                TokenType::Identifier => {
+                TokenType::Identifier | TokenType::KeywordPacked | TokenType::KeywordAlign => {
                     let parsed_field = self.parse_field();
                     if parsed_field.is_err() {
                         self.recover(&RECOVER_TOKENS);
@@ -816,24 +887,150 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
             name,
             fields,
             methods,
+            is_union,
+            layout,
         })
     }
 
     // #[trace_call(always)]
     fn parse_field(&mut self)-> Result<nodes::FieldNode<'src>, ()> {
         let location = self.get_location();
+        let layout = self.parse_layout()?;
         let name_token = self.expect(TokenType::Identifier)?;
         let name = name_token.value;
         self.expect(TokenType::Colon)?;
         let type_def = self.parse_type_node()?;
+        let default = if self.eat(TokenType::Equal) {
+            Some(self.parse_expression(0, Associativity::Left)?)
+        } else {
+            None
+        };
         self.expect(TokenType::Semi)?;
         Ok(nodes::FieldNode {
             location,
             name,
             type_def,
+            layout,
+            default,
+        })
+    }
+
+    // `packed` and `align(N)` in front of structs, unions and fields
+    #[trace_call(always)]
+    fn parse_layout(&mut self) -> Result<nodes::Layout, ()> {
+        let mut layout = nodes::Layout::default();
+        loop {
+            if self.eat(TokenType::KeywordPacked) {
+                layout.packed = true;
+            } else if self.eat(TokenType::KeywordAlign) {
+                self.expect(TokenType::OpenRound)?;
+                let align = self.expect(TokenType::LiteralInteger)?;
+                self.expect(TokenType::ClosingRound)?;
+                match align.value.parse::<usize>() {
+                    Ok(n) if n.is_power_of_two() => layout.align = Some(n),
+                    _ => {
+                        self.report_error(ParserError::InvalidAlignment(align.location, align.value));
+                        return Err(());
+                    }
+                }
+            } else {
+                return Ok(layout);
+            }
+        }
+    }
+
+    #[trace_call(always)]
+    fn parse_interface(&mut self) -> Result<nodes::InterfaceNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::KeywordInterface)?;
+
+        let name = self.expect(TokenType::Identifier)?.value;
+
+        // `this` in a method signature refers to the implementing struct
+        self.current_struct = Some(name);
+        self.expect(TokenType::OpenCurly)?;
+        let mut methods = vec![];
+        while !self.parsed_eof() && !self.at(TokenType::ClosingCurly) {
+            let location = self.get_location();
+            let is_unsafe = self.eat(TokenType::KeywordUnsafe);
+            self.expect(TokenType::KeywordFunc)?;
+            let method_name = self.expect(TokenType::Identifier)?;
+            self.expect(TokenType::OpenRound)?;
+            let parameters = self.parse_parameters(true)?;
+            self.expect(TokenType::ClosingRound)?;
+            let return_type = self.parse_return_type()?;
+            self.expect(TokenType::Semi)?;
+            methods.push(nodes::InterfaceMethodNode {
+                location,
+                name: method_name.value,
+                return_type,
+                parameters,
+                is_unsafe,
+            });
+        }
+        self.expect(TokenType::ClosingCurly)?;
+        self.current_struct = None;
+        Ok(nodes::InterfaceNode {
+            location,
+            name,
+            methods,
+        })
+    }
+
+    #[trace_call(always)]
+    fn parse_impl(&mut self) -> Result<nodes::ImplNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::KeywordImpl)?;
+        let interface = self.expect(TokenType::Identifier)?.value;
+        self.expect(TokenType::KeywordFor)?;
+        let struct_name = self.expect(TokenType::Identifier)?.value;
+        self.expect(TokenType::Semi)?;
+        Ok(nodes::ImplNode {
+            location,
+            interface,
+            struct_name,
         })
     }
 
+    #[trace_call(always)]
+    fn parse_type_alias(&mut self) -> Result<nodes::TypeAliasNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::KeywordType)?;
+        let name = self.expect(TokenType::Identifier)?.value;
+        self.expect(TokenType::Equal)?;
+        let typ = self.parse_type_node()?;
+        self.expect(TokenType::Semi)?;
+        Ok(nodes::TypeAliasNode {
+            location,
+            name,
+            typ,
+        })
+    }
+
+    // #[trace_call(always)]
+    fn parse_type_parameters(&mut self) -> Result<Vec<nodes::TypeParameterNode<'src>>, ()> {
+        let mut type_parameters = vec![];
+        if !self.eat(TokenType::CmpLt) {
+            return Ok(type_parameters);
+        }
+        while !self.parsed_eof() && !self.at(TokenType::CmpGt) {
+            let location = self.get_location();
+            let name = self.expect(TokenType::Identifier)?.value;
+            self.expect(TokenType::Colon)?;
+            let bound = self.expect(TokenType::Identifier)?.value;
+            type_parameters.push(nodes::TypeParameterNode {
+                location,
+                name,
+                bound,
+            });
+            if !self.eat(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::CmpGt)?;
+        Ok(type_parameters)
+    }
+
     // #[trace_call(always)]
     fn parse_function(&mut self, is_unsafe: bool, is_comptime: bool) -> Result<nodes::FunctionNode<'src>, ()> {
         let location = self.get_location();
@@ -843,6 +1040,8 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
 
         self.current_function = Some(name.value);
 
+        let type_parameters = self.parse_type_parameters()?;
+
         self.expect(TokenType::OpenRound)?;
         let parameters = self.parse_parameters(false)?;
         self.expect(TokenType::ClosingRound)?;
@@ -855,6 +1054,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         Ok(nodes::FunctionNode {
             location,
             name: name.value, 
+            type_parameters,
             return_type,
             parameters,
             block,
@@ -1019,19 +1219,36 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         Ok(match tkn.token_type {
             TokenType::KeywordComptime => {
                 self.expect(TokenType::KeywordComptime)?;
-                let comp_stmt = self.parse_stmt_var_decl(false, true, false)?;
-                nodes::Statement::VarDecl(comp_stmt)
+                if self.at_comptime_assert() {
+                    let assert_stmt = self.parse_comptime_assert()?;
+                    nodes::Statement::ComptimeAssert(assert_stmt)
+                } else {
+                    let comp_stmt = self.parse_stmt_var_decl(false, true, false)?;
+                    nodes::Statement::VarDecl(comp_stmt)
+                }
             }
             TokenType::KeywordMut => {
                 self.expect(TokenType::KeywordMut)?;
+                if self.at(TokenType::OpenRound) {
+                    let destructure_stmt = self.parse_stmt_destructure(true)?;
+                    return Ok(nodes::Statement::Destructure(destructure_stmt));
+                }
                 let mut_stmt = self.parse_stmt_var_decl(true, false, false)?;
                 nodes::Statement::VarDecl(mut_stmt)
             }
             TokenType::KeywordLet => {
                 self.expect(TokenType::KeywordLet)?;
+                if self.at(TokenType::OpenRound) {
+                    let destructure_stmt = self.parse_stmt_destructure(false)?;
+                    return Ok(nodes::Statement::Destructure(destructure_stmt));
+                }
                 let let_stmt = self.parse_stmt_var_decl(false, false, false)?;
                 nodes::Statement::VarDecl(let_stmt)
             }
+            TokenType::KeywordFunc => {
+                let func_stmt = self.parse_stmt_nested_function()?;
+                nodes::Statement::VarDecl(func_stmt)
+            }
             TokenType::KeywordIf => {
                 let if_stmt = self.parse_stmt_if(is_unsafe)?;
                 nodes::Statement::If(if_stmt)
@@ -1055,6 +1272,10 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                 let continue_stmt = self.parse_stmt_continue()?;
                 nodes::Statement::Continue(continue_stmt)
             }
+            TokenType::KeywordDefer => {
+                let defer_stmt = self.parse_stmt_defer(is_unsafe)?;
+                nodes::Statement::Defer(defer_stmt)
+            }
             TokenType::KeywordUnsafe => {
                 self.expect(TokenType::KeywordUnsafe)?;
                 let block = self.parse_block(true)?;
@@ -1072,6 +1293,48 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         })
     }
 
+    // #[trace_call(always)]
+    fn parse_comptime_assert(&mut self) -> Result<nodes::ComptimeAssertNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::Identifier)?;
+        self.expect(TokenType::OpenRound)?;
+        let condition = self.parse_expression(0, Associativity::Left)?;
+        let message = if self.eat(TokenType::Comma) {
+            Some(self.expect(TokenType::LiteralString)?.value)
+        } else {
+            None
+        };
+        self.expect(TokenType::ClosingRound)?;
+        self.expect(TokenType::Semi)?;
+        Ok(nodes::ComptimeAssertNode {
+            location,
+            condition,
+            message,
+        })
+    }
+
+    // `func name[captures](params) -> R { ... }` inside of a block is a closure bound to `name`
+    // #[trace_call(always)]
+    fn parse_stmt_nested_function(&mut self) -> Result<nodes::VarDeclNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::KeywordFunc)?;
+        let name_token = self.expect(TokenType::Identifier)?;
+        let lambda = self.parse_expr_lambda(location)?;
+        let typ = Type::Closure(
+            lambda.parameters.iter().map(|p| p.typ.typ.clone()).collect(),
+            Box::new(lambda.return_type.typ.clone()),
+        );
+        Ok(nodes::VarDeclNode {
+            location,
+            name: name_token.value,
+            typ: nodes::TypeNode { location, typ },
+            expression: nodes::Expression::Lambda(lambda),
+            is_mutable: false,
+            is_comptime: false,
+            is_unsafe: false,
+        })
+    }
+
     // #[trace_call(always)]
     fn parse_stmt_var_decl(&mut self, is_mutable: bool, is_comptime: bool, is_unsafe: bool)-> Result<nodes::VarDeclNode<'src>, ()> {
         let location = self.get_location();
@@ -1092,6 +1355,37 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         })
     }
 
+    // `let (a, b): (i32, bool) = f();`, the type annotation is optional
+    // #[trace_call(always)]
+    fn parse_stmt_destructure(&mut self, is_mutable: bool) -> Result<nodes::DestructureNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::OpenRound)?;
+        let mut names = vec![];
+        while !self.at(TokenType::ClosingRound) {
+            let name_token = self.expect(TokenType::Identifier)?;
+            names.push((name_token.location, name_token.value));
+            if !self.eat(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::ClosingRound)?;
+        let typ = if self.eat(TokenType::Colon) {
+            self.parse_type_node()?
+        } else {
+            nodes::TypeNode { location, typ: Type::Unknown }
+        };
+        self.expect(TokenType::Equal)?;
+        let expression = self.parse_expression(0, Associativity::Left)?;
+        self.expect(TokenType::Semi)?;
+        Ok(nodes::DestructureNode {
+            location,
+            names,
+            typ,
+            expression,
+            is_mutable,
+        })
+    }
+
     // #[trace_call(always)]
     fn parse_stmt_for(&mut self, is_unsafe: bool) -> Result<nodes::Statement<'src>, ()> {
         let location = self.get_location();
@@ -1288,6 +1582,28 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         Ok(nodes::ContinueNode { location })
     }
 
+    #[trace_call(always)]
+    fn parse_stmt_defer(&mut self, is_unsafe: bool) -> Result<nodes::DeferNode<'src>, ()> {
+        let location = self.get_location();
+        self.expect(TokenType::KeywordDefer)?;
+        // Declarations would only be visible after the deferred statement already ran
+        if let Some(tkn) = self.peek() {
+            if matches!(
+                tkn.token_type,
+                TokenType::KeywordLet | TokenType::KeywordMut | TokenType::KeywordComptime
+                | TokenType::KeywordFunc | TokenType::KeywordDefer
+            ) {
+                self.report_error(ParserError::InvalidDeferredStatement(self.get_location(), tkn.token_type));
+                return Err(());
+            }
+        }
+        let statement = self.parse_statement(is_unsafe)?;
+        Ok(nodes::DeferNode {
+            location,
+            statement: Box::new(statement),
+        })
+    }
+
     // Roughly inspired by:
     // https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Operators/Operator_Precedence#table
     // If it works for JS, it should work for us too
@@ -1415,15 +1731,45 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                 let array_literal = self.parse_expr_array_literal()?;
                 Ok(nodes::Expression::ArrayLiteral(array_literal))
             }
+            TokenType::Identifier if self.at_embed() => {
+                let embed = self.parse_expr_embed()?;
+                Ok(nodes::Expression::Embed(embed))
+            }
+            TokenType::Identifier if self.at_reflection() => {
+                let reflection = self.parse_expr_reflection()?;
+                Ok(nodes::Expression::Reflection(reflection))
+            }
+            TokenType::Identifier if self.at_arithmetic() => {
+                let arithmetic = self.parse_expr_arithmetic()?;
+                Ok(nodes::Expression::Arithmetic(arithmetic))
+            }
             TokenType::Identifier => {
                 let identifier = self.parse_expr_identifier()?;
                 Ok(identifier)
             }
             TokenType::OpenRound => {
+                let location = self.get_location();
                 self.expect(TokenType::OpenRound)?;
                 let expression = self.parse_expression(0, Associativity::Left)?;
+                if !self.eat(TokenType::Comma) {
+                    self.expect(TokenType::ClosingRound)?;
+                    return Ok(expression);
+                }
+                // `(a, b)` is a tuple, `(a)` is just `a`
+                let mut elements = vec![expression];
+                elements.extend(self.parse_arguments()?);
                 self.expect(TokenType::ClosingRound)?;
-                Ok(expression)
+                Ok(nodes::Expression::TupleLiteral(nodes::TupleLiteralNode {
+                    location,
+                    elements,
+                    typ: Type::Unknown,
+                }))
+            }
+            TokenType::KeywordFunc => {
+                let location = self.get_location();
+                self.expect(TokenType::KeywordFunc)?;
+                let lambda = self.parse_expr_lambda(location)?;
+                Ok(nodes::Expression::Lambda(lambda))
             }
             TokenType::KeywordTrue | TokenType::KeywordFalse => {
                 let bool_literal = self.parse_expr_bool_literal()?;
@@ -1459,6 +1805,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     location: this_token.location,
                     name: "this",
                     typ: Type::Struct(self.current_struct.as_ref().unwrap()),
+                    is_comptime: false,
                 };
                 Ok(nodes::Expression::Name(this_literal))
             }
@@ -1472,6 +1819,133 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         }
     }
 
+    // Reflection intrinsics take a type as their first argument, so they can't be parsed as regular calls
+    #[trace_call(extra)]
+    fn at_reflection(&mut self) -> bool {
+        matches!(self.peek(), Some(t) if matches!(t.value, "fieldCount" | "fieldName" | "offsetof" | "alignof" | "typeName"))
+            && matches!(self.peek_nth(1), Some(t) if t.token_type == TokenType::OpenRound)
+    }
+
+    #[trace_call(extra)]
+    fn at_embed(&mut self) -> bool {
+        matches!(self.peek(), Some(t) if EMBED_INTRINSICS.contains(&t.value))
+            && matches!(self.peek_nth(1), Some(t) if t.token_type == TokenType::OpenRound)
+    }
+
+    #[trace_call(always)]
+    fn parse_expr_embed(&mut self) -> Result<nodes::EmbedNode<'src>, ()> {
+        let name_token = self.expect(TokenType::Identifier)?;
+        self.expect(TokenType::OpenRound)?;
+        let path = self.expect(TokenType::LiteralString)?;
+        self.expect(TokenType::ClosingRound)?;
+        let filename = unescape_path(path.value);
+        let Some(filepath) = find_embedded_file(&path.location.filename(), &import_paths(self.flags), &filename) else {
+            self.report_error(ParserError::EmbedFailed(path.location, path.value, "File not found".to_string()));
+            return Err(());
+        };
+        let content = match fs::read(filepath) {
+            Ok(content) => content,
+            Err(err) => {
+                self.report_error(ParserError::EmbedFailed(path.location, path.value, err.to_string()));
+                return Err(());
+            }
+        };
+        Ok(nodes::EmbedNode {
+            location: name_token.location,
+            path: path.value,
+            content,
+            length_only: name_token.value == "embedFileLen",
+        })
+    }
+
+    #[trace_call(always)]
+    fn parse_expr_reflection(&mut self) -> Result<nodes::ReflectionNode<'src>, ()> {
+        let name_token = self.expect(TokenType::Identifier)?;
+        self.expect(TokenType::OpenRound)?;
+        let reflected = self.parse_type_node()?;
+        let kind = match name_token.value {
+            "fieldCount" => nodes::ReflectionKind::FieldCount,
+            "fieldName" => {
+                self.expect(TokenType::Comma)?;
+                let index = self.parse_expression(0, Associativity::Left)?;
+                nodes::ReflectionKind::FieldName(Box::new(index))
+            }
+            "offsetof" => {
+                self.expect(TokenType::Comma)?;
+                let field_token = self.expect(TokenType::Identifier)?;
+                nodes::ReflectionKind::Offsetof(nodes::NameNode {
+                    location: field_token.location,
+                    name: field_token.value,
+                    typ: Type::Usize,
+                    is_comptime: false,
+                })
+            }
+            "alignof" => nodes::ReflectionKind::Alignof,
+            "typeName" => nodes::ReflectionKind::TypeName,
+            _ => internal_panic!("Unexpected reflection intrinsic {}", name_token.value),
+        };
+        self.expect(TokenType::ClosingRound)?;
+        Ok(nodes::ReflectionNode {
+            location: name_token.location,
+            kind,
+            reflected,
+        })
+    }
+
+    // `wrappingAdd` => (Wrapping, Add), etc.
+    fn split_arithmetic_intrinsic(name: &str) -> Option<(nodes::ArithmeticMode, Operation)> {
+        let (mode, operation) = if let Some(op) = name.strip_prefix("wrapping") {
+            (nodes::ArithmeticMode::Wrapping, op)
+        } else if let Some(op) = name.strip_prefix("checked") {
+            (nodes::ArithmeticMode::Checked, op)
+        } else if let Some(op) = name.strip_prefix("saturating") {
+            (nodes::ArithmeticMode::Saturating, op)
+        } else {
+            return None;
+        };
+        let operation = match operation {
+            "Add" => Operation::Add,
+            "Sub" => Operation::Sub,
+            "Mul" => Operation::Mul,
+            _ => return None,
+        };
+        Some((mode, operation))
+    }
+
+    #[trace_call(extra)]
+    fn at_arithmetic(&mut self) -> bool {
+        matches!(self.peek(), Some(t) if Self::split_arithmetic_intrinsic(t.value).is_some())
+            && matches!(self.peek_nth(1), Some(t) if t.token_type == TokenType::OpenRound)
+    }
+
+    #[trace_call(always)]
+    fn parse_expr_arithmetic(&mut self) -> Result<nodes::ArithmeticNode<'src>, ()> {
+        let name_token = self.expect(TokenType::Identifier)?;
+        let Some((mode, operation)) = Self::split_arithmetic_intrinsic(name_token.value) else {
+            internal_panic!("Unexpected arithmetic intrinsic {}", name_token.value)
+        };
+        self.expect(TokenType::OpenRound)?;
+        let lhs = self.parse_expression(0, Associativity::Left)?;
+        self.expect(TokenType::Comma)?;
+        let rhs = self.parse_expression(0, Associativity::Left)?;
+        let result = if mode == nodes::ArithmeticMode::Checked {
+            self.expect(TokenType::Comma)?;
+            Some(Box::new(self.parse_expression(0, Associativity::Left)?))
+        } else {
+            None
+        };
+        self.expect(TokenType::ClosingRound)?;
+        Ok(nodes::ArithmeticNode {
+            location: name_token.location,
+            mode,
+            operation,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            result,
+            typ: Type::Unknown,
+        })
+    }
+
     #[trace_call(always)]
     fn matches_unary_expression(&mut self) -> bool {
         // TOKEN_TYPE_HANDLE_HERE
@@ -1534,7 +2008,12 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
             let typ = self.parse_type_node()?;
             return Ok(nodes::Expression::As(Box::new(lhs), typ))
         }
+        let mut needs_bounds_check = true;
         let rhs = if op == Operation::IndexedAccess {
+            // a[unsafe i] opts out of the bounds check
+            if self.eat(TokenType::KeywordUnsafe) {
+                needs_bounds_check = false;
+            }
             // Precedence 0 is like an imaginary bracket around the expression
             // This is to ensure that the expression is parsed as a single unit
             // i.e. a[0-3] is parsed as a[(0-3)] and not a[(3)-0], which causes an error (Found -, expected ])
@@ -1550,6 +2029,8 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
             lhs: Box::new(lhs),
             rhs: Box::new(rhs),
             typ: Type::Unknown,
+            needs_bounds_check,
+            non_constant: OnceCell::new(),
         }))
     }
 
@@ -1570,6 +2051,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     operation: Operation::Negate,
                     expression: Box::new(expression),
                     typ: Type::Unknown,
+                    non_constant: OnceCell::new(),
                 })
             }
             TokenType::Ampersand => {
@@ -1581,6 +2063,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     operation: Operation::Reference,
                     expression: Box::new(expression),
                     typ: Type::Ref(Box::new(Type::Unknown), is_mutable),
+                    non_constant: OnceCell::new(),
                 })
             }
             TokenType::DoubleAmpersand => {
@@ -1594,12 +2077,14 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     operation: Operation::Reference,
                     expression: Box::new(expression),
                     typ: inner_type,
+                    non_constant: OnceCell::new(),
                 };
                 Ok(nodes::UnaryNode {
                     location,
                     operation: Operation::Reference,
                     expression: Box::new(nodes::Expression::Unary(inner_ref)),
                     typ: outer_type,
+                    non_constant: OnceCell::new(),
                 })
             }
             TokenType::Asterisk => {
@@ -1610,6 +2095,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     operation: Operation::Dereference,
                     expression: Box::new(expression),
                     typ: Type::Unknown,
+                    non_constant: OnceCell::new(),
                 })
             }
             TokenType::Exclamation => {
@@ -1620,6 +2106,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
                     operation: Operation::LogicalNot,
                     expression: Box::new(expression),
                     typ: Type::Bool,
+                    non_constant: OnceCell::new(),
                 })
             }
             tt => {
@@ -1652,7 +2139,13 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         let location = ident.location;
         self.expect(TokenType::OpenCurly)?;
         let mut fields = vec![];
+        let mut base = None;
         while !self.parsed_eof() && !self.at(TokenType::ClosingCurly) {
+            if self.eat(TokenType::DotDot) {
+                // The base has to come last
+                base = Some(Box::new(self.parse_expression(0, Associativity::Left)?));
+                break;
+            }
             let name_token = self.expect(TokenType::Identifier)?;
             let name = name_token.value;
             self.expect(TokenType::Colon)?;
@@ -1667,6 +2160,7 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
             location,
             struct_name: ident.value,
             fields,
+            base,
             typ: Type::Struct(ident.value),
         })
     }
@@ -1798,7 +2292,8 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         Ok(nodes::NameNode {
             location: ident.location,
             name,
-            typ: Type::Unknown
+            typ: Type::Unknown,
+            is_comptime: false,
         })
     }
 
@@ -1813,6 +2308,8 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
 
         Ok(nodes::CallNode {
             is_extern: false,
+            is_indirect: false,
+            is_closure: false,
             function_name,
             location,
             arguments,
@@ -1820,6 +2317,59 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
         })
     }
 
+    // Everything after `func` of `func[captures](params) -> R { ... }`, the captures are optional
+    // #[trace_call(always)]
+    fn parse_expr_lambda(&mut self, location: Location) -> Result<nodes::LambdaNode<'src>, ()> {
+        let mut captures = vec![];
+        if self.eat(TokenType::OpenSquare) {
+            while !self.parsed_eof() && !self.at(TokenType::ClosingSquare) {
+                let capture_location = self.get_location();
+                let is_reference = self.eat(TokenType::Ampersand);
+                let is_mutable = is_reference && self.eat(TokenType::KeywordMut);
+                let name = if self.at(TokenType::KeywordThis) {
+                    self.expect(TokenType::KeywordThis)?
+                } else {
+                    self.expect(TokenType::Identifier)?
+                };
+                captures.push(nodes::CaptureNode {
+                    location: capture_location,
+                    name: name.value,
+                    is_reference,
+                    is_mutable,
+                    typ: Type::Unknown,
+                });
+                if !self.eat(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.expect(TokenType::ClosingSquare)?;
+        }
+
+        self.expect(TokenType::OpenRound)?;
+        let parameters = self.parse_parameters(false)?;
+        self.expect(TokenType::ClosingRound)?;
+
+        let return_type = self.parse_return_type()?;
+
+        // Returns inside of the body belong to the closure, not to the surrounding function
+        let outer_function = self.current_function.replace(KEYWORD_CLOSURE);
+        let block = self.parse_block(false);
+        self.current_function = outer_function;
+
+        let typ = Type::Closure(
+            parameters.iter().map(|p| p.typ.typ.clone()).collect(),
+            Box::new(return_type.typ.clone()),
+        );
+        Ok(nodes::LambdaNode {
+            location,
+            captures,
+            parameters,
+            return_type,
+            block: block?,
+            typ,
+        })
+    }
+
     #[trace_call(always)]
     fn parse_arguments(&mut self)-> Result<Vec<nodes::Expression<'src>>, ()> {
         let mut arguments = Vec::new();
@@ -1847,12 +2397,69 @@ impl<'flags: 'src, 'lexer, 'src> Parser<'flags, 'lexer, 'src> {
             })
         } else if self.eat(TokenType::Ampersand) {
             let is_mutable = self.eat(TokenType::KeywordMut);
+            if self.eat(TokenType::KeywordDyn) {
+                let interface = self.expect(TokenType::Identifier)?;
+                return Ok(nodes::TypeNode {
+                    location,
+                    typ: Type::Dyn(interface.value, is_mutable),
+                });
+            }
             let typ = self.parse_type_node()?;
             let typ = Type::Ref(Box::new(typ.typ), is_mutable);
             Ok(nodes::TypeNode {
                 location,
                 typ,
             })
+        } else if self.at(TokenType::KeywordFunc) || self.at(TokenType::KeywordUnsafe) {
+            // `func(i32, &char) -> bool`
+            let is_unsafe = self.eat(TokenType::KeywordUnsafe);
+            self.expect(TokenType::KeywordFunc)?;
+            self.expect(TokenType::OpenRound)?;
+            let mut parameters = vec![];
+            while !self.at(TokenType::ClosingRound) {
+                parameters.push(self.parse_type_node()?.typ);
+                if !self.eat(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.expect(TokenType::ClosingRound)?;
+            let return_type = self.parse_return_type()?;
+            Ok(nodes::TypeNode {
+                location,
+                typ: Type::Function(parameters.into(), Box::new(return_type.typ), is_unsafe),
+            })
+        } else if self.eat(TokenType::KeywordClosure) {
+            // `closure(i32) -> i32`
+            self.expect(TokenType::OpenRound)?;
+            let mut parameters = vec![];
+            while !self.at(TokenType::ClosingRound) {
+                parameters.push(self.parse_type_node()?.typ);
+                if !self.eat(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.expect(TokenType::ClosingRound)?;
+            let return_type = self.parse_return_type()?;
+            Ok(nodes::TypeNode {
+                location,
+                typ: Type::Closure(parameters.into(), Box::new(return_type.typ)),
+            })
+        } else if self.eat(TokenType::OpenRound) {
+            // `(i32, &char)`, `(i32)` is just `i32`
+            let mut elements = vec![self.parse_type_node()?.typ];
+            while self.eat(TokenType::Comma) {
+                elements.push(self.parse_type_node()?.typ);
+            }
+            self.expect(TokenType::ClosingRound)?;
+            let typ = if elements.len() == 1 {
+                elements.pop().unwrap()
+            } else {
+                Type::Tuple(elements.into())
+            };
+            Ok(nodes::TypeNode {
+                location,
+                typ,
+            })
         } else if self.eat(TokenType::OpenSquare) {
             let typ = self.parse_type_node()?;
             self.expect(TokenType::Semi)?;
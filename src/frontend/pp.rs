@@ -1,21 +1,41 @@
 use crate::compiler::{ERR_STR, FILE_EXT, NOTE_STR};
+use crate::frontend::cfg::Cfg;
 use crate::frontend::tokens::{Token, TokenType, KEYWORD_FILEMARKER};
 use crate::internal_panic;
 use crate::util::flags::Flags;
 use crate::frontend::lexer::Lexer;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+// Intrinsics that take a filepath, which we resolve here so they use the same search paths as imports
+pub const EMBED_INTRINSICS: [&str; 2] = ["embedFile", "embedFileLen"];
 
 fn path_buf_to_str(path: &PathBuf) -> &str {
     path.to_str().unwrap()
 }
+
+/// Turns the contents of a string literal back into a path, every backslash escapes the character after it
+pub fn unescape_path(literal: &str) -> String {
+    let mut path = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => path.extend(chars.next()),
+            c => path.push(c),
+        }
+    }
+    path
+}
+
 enum PpError<'src> {
     FileNotFoundInImportPaths(String, String),
     OtherErrors(String),
     UnexpectedEOF,
     UnexpectedToken(TokenType, Token<'src>),
+    InvalidCondition(String, String),
+    UnterminatedCondition(String),
 }
 
 impl Display for PpError<'_> {
@@ -25,14 +45,26 @@ impl Display for PpError<'_> {
             Self::OtherErrors(other) => write!(f, "{other}"),
             Self::UnexpectedEOF => write!(f, "error: Expected String literal after `import`, found End Of File."),
             Self::UnexpectedToken(expected, received) => write!(f, "{ERR_STR}: Unexpected token `{}`, expected `{}` instead.", received, expected),
+            Self::InvalidCondition(current, msg) => write!(f, "{ERR_STR}: Invalid `comptime if` condition: {msg}\n{NOTE_STR}: Error happened when processing {current}."),
+            Self::UnterminatedCondition(current) => write!(f, "{ERR_STR}: Missing `}}` for `comptime if` block.\n{NOTE_STR}: Error happened when processing {current}."),
         }
     }
 }
 
+// A `comptime if` block we're currently in
+struct CfgBlock {
+    curly_depth: usize,
+    active: bool,
+    taken: bool,
+}
+
 pub struct Preprocessor<'flags, 'lexer, 'src> {
     flags: &'flags Flags,
     lexer: &'lexer mut Lexer<'src>,
     import_paths: Vec<PathBuf>,
+    cfg: Cfg,
+    cfg_blocks: Vec<CfgBlock>,
+    curly_depth: usize,
 }
 
 impl<'flags, 'lexer, 'src> Preprocessor<'flags, 'lexer, 'src> {
@@ -40,11 +72,88 @@ impl<'flags, 'lexer, 'src> Preprocessor<'flags, 'lexer, 'src> {
         Self {
             flags,
             lexer,
-            import_paths: Vec::new()
+            import_paths: Vec::new(),
+            cfg: Cfg::new(flags),
+            cfg_blocks: Vec::new(),
+            curly_depth: 0,
         }
     }
 
-    fn process(&mut self, imported_files: &mut HashSet<PathBuf>, origin: &PathBuf, content: &'src str) -> Result<String, Vec<PpError>> {
+    // Excluded code and the `comptime if` itself are replaced by spaces, so locations stay the same
+    fn blank(after: &mut String, ws: usize, token: &Token) {
+        after.push_str(&" ".repeat(ws + token.to_string().len()));
+    }
+
+    fn blank_next(&mut self, after: &mut String, expected: TokenType, origin: &PathBuf) -> Result<Token<'src>, Vec<PpError<'src>>> {
+        loop {
+            let (ws, Some(t)) = self.lexer.next_with_whitespace() else {
+                return Err(vec![PpError::UnterminatedCondition(path_buf_to_str(origin).to_string())]);
+            };
+            Self::blank(after, ws, &t);
+            if t.token_type == TokenType::Comment {
+                continue;
+            }
+            if t.token_type != expected {
+                return Err(vec![PpError::UnexpectedToken(expected, t)]);
+            }
+            return Ok(t);
+        }
+    }
+
+    // Handles `if (condition) {`, `comptime` was already consumed
+    fn open_cfg_block(&mut self, after: &mut String, origin: &PathBuf, taken_before: bool) -> Result<(), Vec<PpError<'src>>> {
+        self.blank_next(after, TokenType::KeywordIf, origin)?;
+        self.blank_next(after, TokenType::OpenRound, origin)?;
+        let mut condition = Vec::new();
+        let mut round_depth = 1;
+        loop {
+            let (ws, Some(t)) = self.lexer.next_with_whitespace() else {
+                return Err(vec![PpError::UnterminatedCondition(path_buf_to_str(origin).to_string())]);
+            };
+            Self::blank(after, ws, &t);
+            match t.token_type {
+                TokenType::Comment => continue,
+                TokenType::OpenRound => round_depth += 1,
+                TokenType::ClosingRound => round_depth -= 1,
+                _ => {}
+            }
+            if round_depth == 0 {
+                break;
+            }
+            condition.push(t);
+        }
+        let result = self.cfg.evaluate(&condition)
+            .map_err(|msg| vec![PpError::InvalidCondition(path_buf_to_str(origin).to_string(), msg)])?;
+        self.blank_next(after, TokenType::OpenCurly, origin)?;
+        self.curly_depth += 1;
+        self.cfg_blocks.push(CfgBlock {
+            curly_depth: self.curly_depth,
+            active: result && !taken_before,
+            taken: result || taken_before,
+        });
+        Ok(())
+    }
+
+    // Handles an optional `else {` or `else if (condition) {` after a `comptime if` block was closed
+    fn continue_cfg_block(&mut self, after: &mut String, origin: &PathBuf, taken: bool) -> Result<(), Vec<PpError<'src>>> {
+        if !matches!(self.lexer.peek(), Some(t) if t.token_type == TokenType::KeywordElse) {
+            return Ok(());
+        }
+        self.blank_next(after, TokenType::KeywordElse, origin)?;
+        if matches!(self.lexer.peek(), Some(t) if t.token_type == TokenType::KeywordIf) {
+            return self.open_cfg_block(after, origin, taken);
+        }
+        self.blank_next(after, TokenType::OpenCurly, origin)?;
+        self.curly_depth += 1;
+        self.cfg_blocks.push(CfgBlock {
+            curly_depth: self.curly_depth,
+            active: !taken,
+            taken: true,
+        });
+        Ok(())
+    }
+
+    fn process(&mut self, imported_files: &mut HashMap<PathBuf, String>, embedded_files: &mut HashSet<PathBuf>, origin: &PathBuf, content: &'src str) -> Result<String, Vec<PpError>> {
         let mut after = String::with_capacity(content.len());
         self.lexer.load(content);
         while let (ws, Some(t)) = self.lexer.next_with_whitespace() {
@@ -52,9 +161,35 @@ impl<'flags, 'lexer, 'src> Preprocessor<'flags, 'lexer, 'src> {
                 println!("[DEBUG] Whitespace: {}", ws);
                 println!("[DEBUG] Preproc::next() -> {:?}", t);
             }
+            match t.token_type {
+                TokenType::KeywordComptime if matches!(self.lexer.peek(), Some(n) if n.token_type == TokenType::KeywordIf) => {
+                    Self::blank(&mut after, ws, &t);
+                    self.open_cfg_block(&mut after, origin, false)?;
+                    continue;
+                }
+                TokenType::OpenCurly => self.curly_depth += 1,
+                TokenType::ClosingCurly => {
+                    if matches!(self.cfg_blocks.last(), Some(b) if b.curly_depth == self.curly_depth) {
+                        let block = self.cfg_blocks.pop().unwrap();
+                        self.curly_depth -= 1;
+                        Self::blank(&mut after, ws, &t);
+                        self.continue_cfg_block(&mut after, origin, block.taken)?;
+                        continue;
+                    }
+                    self.curly_depth = self.curly_depth.saturating_sub(1);
+                }
+                _ => {}
+            }
+            if !self.cfg_blocks.iter().all(|b| b.active) {
+                Self::blank(&mut after, ws, &t);
+                continue;
+            }
             let Token { token_type: TokenType::KeywordImport, .. } = t else {
                 after.push_str(&" ".repeat(ws));
                 after.push_str(&t.to_string());
+                if t.token_type == TokenType::Identifier && EMBED_INTRINSICS.contains(&t.value) {
+                    self.process_embed(embedded_files, origin)?;
+                }
                 continue;
             };
             let Some(path) = self.lexer.next() else {
@@ -83,7 +218,7 @@ impl<'flags, 'lexer, 'src> Preprocessor<'flags, 'lexer, 'src> {
                         let _s = path_buf_to_str(&filepath);
                         internal_panic!("fs::metadata(&{_s}).is_ok() == true, yet reading failed")
                     };
-                    let import = pre_process(&self.flags, imported_files, &self.import_paths, &filepath, &import_data)
+                    let import = pre_process(&self.flags, imported_files, embedded_files, &self.import_paths, &filepath, &import_data)
                         .map_err(|vpe|vec![PpError::OtherErrors(vpe)])?;
                     let fname = filepath.to_str().unwrap();
                     after.push_str(&format!("{KEYWORD_FILEMARKER} START \"{fname}\""));
@@ -98,9 +233,27 @@ impl<'flags, 'lexer, 'src> Preprocessor<'flags, 'lexer, 'src> {
                 return Err(vec![PpError::FileNotFoundInImportPaths(path_buf_to_str(origin).to_string(), filename)]);
             }
         }
+        if !self.cfg_blocks.is_empty() {
+            return Err(vec![PpError::UnterminatedCondition(path_buf_to_str(origin).to_string())]);
+        }
         Ok(after)
     }
 
+    // Checks that the file in `embedFile("path")` exists, the Parser resolves the path again using `find_embedded_file`.
+    // The source is left untouched, the resolved path is longer than the literal and would shift every location after it.
+    fn process_embed(&mut self, embedded_files: &mut HashSet<PathBuf>, origin: &PathBuf) -> Result<(), Vec<PpError<'src>>> {
+        let is_embed = matches!(self.lexer.peek_nth(0), Some(t) if t.token_type == TokenType::OpenRound);
+        let Some(literal) = self.lexer.peek_nth(1).filter(|t| is_embed && t.token_type == TokenType::LiteralString) else {
+            return Ok(());
+        };
+        let filename = unescape_path(literal.value);
+        let Some(filepath) = find_embedded_file(origin, &self.import_paths, &filename) else {
+            return Err(vec![PpError::FileNotFoundInImportPaths(path_buf_to_str(origin).to_string(), filename)]);
+        };
+        embedded_files.insert(filepath);
+        Ok(())
+    }
+
     fn add_import(&mut self, path: PathBuf) {
         self.import_paths.push(path);
     }
@@ -108,15 +261,16 @@ impl<'flags, 'lexer, 'src> Preprocessor<'flags, 'lexer, 'src> {
 
 fn pre_process(
     flags: &Flags,
-    imported_files: &mut HashSet<PathBuf>,
+    imported_files: &mut HashMap<PathBuf, String>,
+    embedded_files: &mut HashSet<PathBuf>,
     imports: &Vec<PathBuf>,
     path: &PathBuf,
     content: &str
 ) -> Result<String, String> {
-    if imported_files.contains(path) {
+    if imported_files.contains_key(path) {
         return Ok(String::new());
     }
-    imported_files.insert(path.to_path_buf());
+    imported_files.insert(path.to_path_buf(), content.to_string());
     if flags.verbose {
         println!("[INFO] Reading file {0}", path_buf_to_str(path))
     }
@@ -125,12 +279,24 @@ fn pre_process(
     for path in imports {
         pp.add_import(path.to_path_buf());
     }
-    let tmp = pp.process(imported_files, &path, &content);
+    let tmp = pp.process(imported_files, embedded_files, &path, &content);
     tmp.map_err(|vpe|vpe.iter().map(|e|e.to_string()).collect::<Vec<_>>().join("\n"))
 }
 
-pub fn load_project(flags: &Flags) -> Result<String, String> {
-    let mut imports = Vec::with_capacity(flags.imports.len());
+// Original contents of every imported file, by path
+pub type Sources = HashMap<PathBuf, String>;
+
+/// Returns the preprocessed source, every file it depends on and the original contents of every imported file
+/// Embedded files are relative to the file that embeds them, before falling back to the import paths
+pub fn find_embedded_file(origin: &Path, import_paths: &[PathBuf], filename: &str) -> Option<PathBuf> {
+    origin.parent().into_iter().chain(import_paths.iter().map(|path| path.as_path()))
+        .map(|path| path.join(filename))
+        .find(|filepath| fs::metadata(filepath).map(|m| m.is_file()).unwrap_or(false))
+}
+
+/// The paths searched for imports and embedded files, in order
+pub fn import_paths(flags: &Flags) -> Vec<PathBuf> {
+    let mut imports = Vec::with_capacity(flags.imports.len() + 2);
     for i in &flags.imports {
         imports.push(PathBuf::from(i));
     }
@@ -138,10 +304,26 @@ pub fn load_project(flags: &Flags) -> Result<String, String> {
     if let Some(parent) = flags.input.parent() {
         imports.push(parent.to_path_buf());
     }
+    imports
+}
+
+/// Returns the preprocessed source, every file it depends on and the original contents of every imported file
+pub fn load_project(flags: &Flags) -> Result<(String, Vec<PathBuf>, Sources), String> {
+    let imports = import_paths(flags);
     match fs::read_to_string(&flags.input) {
-        Ok(content) => {
-            let content = format!("import \"prelude.bufo\";{KEYWORD_FILEMARKER} START \"{0}\"{content} {KEYWORD_FILEMARKER} END \"{0}\"", flags.input.to_str().unwrap());
-            pre_process(flags, &mut HashSet::new(), &imports, &flags.input, &content)
+        Ok(original) => {
+            // The target constants are declared before anything else, so every file can use them
+            let builtins = format!("{KEYWORD_FILEMARKER} START \"<builtins>\"{} {KEYWORD_FILEMARKER} END \"<builtins>\"", Cfg::declarations(flags));
+            let content = format!("{builtins}import \"prelude.bufo\";{KEYWORD_FILEMARKER} START \"{0}\"{original} {KEYWORD_FILEMARKER} END \"{0}\"", flags.input.to_str().unwrap());
+            let mut imported_files = HashMap::new();
+            let mut embedded_files = HashSet::new();
+            let source = pre_process(flags, &mut imported_files, &mut embedded_files, &imports, &flags.input, &content)?;
+            // The main file was stored with the builtins and prelude wrapped around it
+            imported_files.insert(flags.input.clone(), original);
+            let mut dependencies: Vec<PathBuf> = imported_files.keys().cloned().chain(embedded_files).collect();
+            dependencies.sort();
+            dependencies.dedup();
+            Ok((source, dependencies, imported_files))
         },
         Err(_e) => todo!()
     }
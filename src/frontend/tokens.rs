@@ -28,6 +28,14 @@ impl Location {
             len
         }
     }
+    pub fn filename(&self) -> PathBuf {
+        let file = unsafe { FILENAMES.get(self.file_id) };
+        let Some(file) = file else {
+            internal_panic!("Invalid file_id {}", self.file_id)
+        };
+        file.clone()
+    }
+
     #[trace_call(extra)]
     pub fn new(file_id: usize, byte: usize) -> Self {
         Self { file_id, byte }
@@ -46,13 +54,7 @@ impl Display for Location {
 }
 impl Debug for Location {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        let file = unsafe { FILENAMES.get(self.file_id) };
-        if file.is_none() {
-            internal_panic!("Invalid file_id {}", self.file_id)
-        } else {
-            let file = file.unwrap();
-            write!(f, "{}:{}", file.to_str().unwrap(), self.byte)
-        }
+        write!(f, "{}:{}", self.filename().to_str().unwrap(), self.byte)
     }
 }
 
@@ -90,12 +92,16 @@ impl<'src> Token<'src> {
     }
 }
 
+pub const KEYWORD_ALIGN: &str = "align";
 pub const KEYWORD_AS: &str = "as";
 pub const KEYWORD_BLANK: &str = "blank";
 pub const KEYWORD_BREAK: &str = "break";
+pub const KEYWORD_CLOSURE: &str = "closure";
 pub const KEYWORD_COMPILER_FLAGS: &str = "compiler_flags";
 pub const KEYWORD_COMPTIME: &str = "comptime";
 pub const KEYWORD_CONTINUE: &str = "continue";
+pub const KEYWORD_DEFER: &str = "defer";
+pub const KEYWORD_DYN: &str = "dyn";
 pub const KEYWORD_ELSE: &str = "else";
 pub const KEYWORD_EXTERN: &str = "extern";
 pub const KEYWORD_FALSE: &str = "false";
@@ -103,16 +109,20 @@ pub const KEYWORD_FILEMARKER: &str = "FILEMARKER_INTERNAL_USE_ONLY";
 pub const KEYWORD_FOR: &str = "for";
 pub const KEYWORD_FUNCTION: &str = "func";
 pub const KEYWORD_IF: &str = "if";
+pub const KEYWORD_IMPL: &str = "impl";
 pub const KEYWORD_IMPORT: &str = "import";
+pub const KEYWORD_INTERFACE: &str = "interface";
 pub const KEYWORD_LET: &str = "let";
 pub const KEYWORD_MUT: &str = "mut";
 pub const KEYWORD_NULL: &str = "null";
+pub const KEYWORD_PACKED: &str = "packed";
 pub const KEYWORD_RETURN: &str = "return";
 pub const KEYWORD_SIZEOF: &str = "sizeof";
 pub const KEYWORD_STRUCT: &str = "struct";
 pub const KEYWORD_THIS: &str = "this";
 pub const KEYWORD_TRUE: &str = "true";
-pub const KEYWORD_TYPE: &str = "type"; // Reserved for future use
+pub const KEYWORD_TYPE: &str = "type";
+pub const KEYWORD_UNION: &str = "union";
 pub const KEYWORD_UNSAFE: &str = "unsafe";
 pub const KEYWORD_WHILE: &str = "while";
 
@@ -122,12 +132,16 @@ pub const KEYWORD_WHILE: &str = "while";
 pub enum TokenType {
     Unknown,
     Comment,
+    KeywordAlign,
     KeywordAs,
     KeywordBlank,
     KeywordBreak,
+    KeywordClosure,
     KeywordCompilerFlags,
     KeywordComptime,
     KeywordContinue,
+    KeywordDefer,
+    KeywordDyn,
     KeywordElse,
     KeywordExtern,
     KeywordFalse,
@@ -135,16 +149,20 @@ pub enum TokenType {
     KeywordFor,
     KeywordFunc,
     KeywordIf,
+    KeywordImpl,
     KeywordImport,
+    KeywordInterface,
     KeywordLet,
     KeywordMut,
     KeywordNull,
+    KeywordPacked,
     KeywordReturn,
     KeywordSizeof,
     KeywordStruct,
     KeywordThis,
     KeywordTrue,
     KeywordType,
+    KeywordUnion,
     KeywordUnsafe,
     KeywordWhile,
     LiteralChar,
@@ -167,6 +185,7 @@ pub enum TokenType {
     Dot,
     Exclamation,
     VarArg,
+    DotDot,
     Arrow,
     Equal,
     Plus,
@@ -187,12 +206,16 @@ pub enum TokenType {
 impl TokenType {
     pub fn try_from_keyword(kw: &str) -> Option<Self> {
         match kw {
+            KEYWORD_ALIGN => Some(Self::KeywordAlign),
             KEYWORD_AS => Some(Self::KeywordAs),
             KEYWORD_BLANK => Some(Self::KeywordBlank),
             KEYWORD_BREAK => Some(Self::KeywordBreak),
+            KEYWORD_CLOSURE => Some(Self::KeywordClosure),
             KEYWORD_COMPILER_FLAGS => Some(Self::KeywordCompilerFlags),
             KEYWORD_COMPTIME => Some(Self::KeywordComptime),
             KEYWORD_CONTINUE => Some(Self::KeywordContinue),
+            KEYWORD_DEFER => Some(Self::KeywordDefer),
+            KEYWORD_DYN => Some(Self::KeywordDyn),
             KEYWORD_ELSE => Some(Self::KeywordElse),
             KEYWORD_EXTERN => Some(Self::KeywordExtern),
             KEYWORD_FALSE => Some(Self::KeywordFalse),
@@ -200,16 +223,20 @@ impl TokenType {
             KEYWORD_FOR => Some(Self::KeywordFor),
             KEYWORD_FUNCTION => Some(Self::KeywordFunc),
             KEYWORD_IF => Some(Self::KeywordIf),
+            KEYWORD_IMPL => Some(Self::KeywordImpl),
             KEYWORD_IMPORT => Some(Self::KeywordImport),
+            KEYWORD_INTERFACE => Some(Self::KeywordInterface),
             KEYWORD_LET => Some(Self::KeywordLet),
             KEYWORD_MUT => Some(Self::KeywordMut),
             KEYWORD_NULL => Some(Self::KeywordNull),
+            KEYWORD_PACKED => Some(Self::KeywordPacked),
             KEYWORD_RETURN => Some(Self::KeywordReturn),
             KEYWORD_SIZEOF => Some(Self::KeywordSizeof),
             KEYWORD_STRUCT => Some(Self::KeywordStruct),
             KEYWORD_THIS => Some(Self::KeywordThis),
             KEYWORD_TRUE => Some(Self::KeywordTrue),
             KEYWORD_TYPE => Some(Self::KeywordType),
+            KEYWORD_UNION => Some(Self::KeywordUnion),
             KEYWORD_UNSAFE => Some(Self::KeywordUnsafe),
             KEYWORD_WHILE => Some(Self::KeywordWhile),
             _ => None
@@ -245,12 +272,16 @@ impl Display for TokenType {
             Self::ClosingCurly => write!(f, "`}}`"),
             Self::OpenSquare => write!(f, "`[`"),
             Self::ClosingSquare => write!(f, "`]`"),
+            Self::KeywordAlign => write!(f, "`{}`", KEYWORD_ALIGN),
             Self::KeywordAs => write!(f, "`{}`", KEYWORD_AS),
             Self::KeywordBlank => write!(f, "`{}`", KEYWORD_BLANK),
             Self::KeywordBreak => write!(f, "`{}`", KEYWORD_BREAK),
+            Self::KeywordClosure => write!(f, "`{}`", KEYWORD_CLOSURE),
             Self::KeywordCompilerFlags => write!(f, "`{}`", KEYWORD_COMPILER_FLAGS),
             Self::KeywordComptime => write!(f, "`{}`", KEYWORD_COMPTIME),
             Self::KeywordContinue => write!(f, "`{}`", KEYWORD_CONTINUE),
+            Self::KeywordDefer => write!(f, "`{}`", KEYWORD_DEFER),
+            Self::KeywordDyn => write!(f, "`{}`", KEYWORD_DYN),
             Self::KeywordElse => write!(f, "`{}`", KEYWORD_ELSE),
             Self::KeywordExtern => write!(f, "`{}`", KEYWORD_EXTERN),
             Self::KeywordFalse => write!(f, "`{}`", KEYWORD_FALSE),
@@ -258,16 +289,20 @@ impl Display for TokenType {
             Self::KeywordFor => write!(f, "`{}`", KEYWORD_FOR),
             Self::KeywordFunc => write!(f, "`{}`", KEYWORD_FUNCTION),
             Self::KeywordIf => write!(f, "`{}`", KEYWORD_IF),
+            Self::KeywordImpl => write!(f, "`{}`", KEYWORD_IMPL),
             Self::KeywordImport => write!(f, "`{}`", KEYWORD_IMPORT),
+            Self::KeywordInterface => write!(f, "`{}`", KEYWORD_INTERFACE),
             Self::KeywordLet => write!(f, "`{}`", KEYWORD_LET),
             Self::KeywordMut => write!(f, "`{}`", KEYWORD_MUT),
             Self::KeywordNull => write!(f, "`{}`", KEYWORD_NULL),
+            Self::KeywordPacked => write!(f, "`{}`", KEYWORD_PACKED),
             Self::KeywordReturn => write!(f, "`{}`", KEYWORD_RETURN),
             Self::KeywordSizeof => write!(f, "`{}`", KEYWORD_SIZEOF),
             Self::KeywordStruct => write!(f, "`{}`", KEYWORD_STRUCT),
             Self::KeywordTrue => write!(f, "`{}`", KEYWORD_TRUE),
             Self::KeywordThis => write!(f, "`{}`", KEYWORD_THIS),
             Self::KeywordType => write!(f, "`{}`", KEYWORD_TYPE),
+            Self::KeywordUnion => write!(f, "`{}`", KEYWORD_UNION),
             Self::KeywordUnsafe => write!(f, "`{}`", KEYWORD_UNSAFE),
             Self::KeywordWhile => write!(f, "`{}`", KEYWORD_WHILE),
             Self::Colon => write!(f, "`:`"),
@@ -280,6 +315,7 @@ impl Display for TokenType {
             Self::Dot => write!(f, "`.`"),
             Self::Exclamation => write!(f, "`!`"),
             Self::VarArg => write!(f, "`...`"),
+            Self::DotDot => write!(f, "`..`"),
             Self::Arrow => write!(f, "`->`"),
             Self::Equal => write!(f, "`=`"),
             Self::Plus => write!(f, "`+`"),
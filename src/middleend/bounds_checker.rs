@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+
+use tracer::trace_call;
+
+use crate::util::flags::Flags;
+use crate::frontend::nodes;
+use crate::frontend::parser::Operation;
+
+use crate::middleend::type_checker::Type;
+
+/// Removes array bounds checks that can be proven to never fail.
+///
+/// An indexed access `a[i]` into an array of size N doesn't need a check if
+/// - `i` is an integer literal smaller than N, or
+/// - the access is guarded by a loop or if condition `i < M` with M <= N,
+///   and `i` is a local variable that can't change inside the guarded body.
+pub struct BoundsChecker<'flags, 'src> {
+    flags: &'flags Flags,
+    // Upper bounds (exclusive) of variables in the current guarded bodies
+    bounds: Vec<(&'src str, u64)>,
+    // Names we can't reason about, i.e. globals and variables whose address is taken
+    escaped: HashSet<&'src str>,
+    globals: HashSet<&'src str>,
+    elided: usize,
+}
+
+impl<'flags, 'src> BoundsChecker<'flags, 'src> {
+    pub fn new(flags: &'flags Flags) -> Self {
+        Self {
+            flags,
+            bounds: Vec::new(),
+            escaped: HashSet::new(),
+            globals: HashSet::new(),
+            elided: 0,
+        }
+    }
+
+    #[trace_call(always)]
+    pub fn check_project(&mut self, file: &mut nodes::FileNode<'src>) {
+        for global in &file.globals {
+            self.globals.insert(global.name);
+        }
+        for strukt in &mut file.structs {
+            for method in &mut strukt.methods {
+                self.check_body(&mut method.block);
+            }
+        }
+        for function in &mut file.functions {
+            self.check_body(&mut function.block);
+        }
+        if self.flags.verbose {
+            println!("[INFO] Removed {} bounds checks", self.elided);
+        }
+    }
+
+    fn check_body(&mut self, block: &mut nodes::BlockNode<'src>) {
+        self.escaped = self.globals.clone();
+        collect_escaped_block(block, &mut self.escaped);
+        debug_assert!(self.bounds.is_empty());
+        self.check_block(block);
+    }
+
+    fn check_block(&mut self, block: &mut nodes::BlockNode<'src>) {
+        for statement in &mut block.statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &mut nodes::Statement<'src>) {
+        match statement {
+            nodes::Statement::Block(block) => self.check_block(block),
+            nodes::Statement::Expression(expression) => self.check_expression(expression),
+            nodes::Statement::VarDecl(var_decl) => self.check_expression(&mut var_decl.expression),
+            nodes::Statement::Destructure(destructure) => self.check_expression(&mut destructure.expression),
+            nodes::Statement::If(if_node) => {
+                self.check_expression(&mut if_node.condition);
+                self.check_guarded_block(&if_node.condition, &mut if_node.if_body);
+                if let Some(else_body) = &mut if_node.else_body {
+                    self.check_block(else_body);
+                }
+            }
+            nodes::Statement::Return(return_node) => {
+                if let Some(value) = &mut return_node.return_value {
+                    self.check_expression(value);
+                }
+            }
+            nodes::Statement::While(while_node) => {
+                self.check_expression(&mut while_node.condition);
+                if let Some(step) = &mut while_node.step {
+                    self.check_expression(step);
+                }
+                self.check_guarded_block(&while_node.condition, &mut while_node.body);
+            }
+            nodes::Statement::Defer(defer_node) => self.check_statement(&mut defer_node.statement),
+            nodes::Statement::Break(_)
+            | nodes::Statement::Continue(_)
+            | nodes::Statement::ComptimeAssert(_) => {}
+        }
+    }
+
+    fn check_guarded_block(&mut self, condition: &nodes::Expression<'src>, block: &mut nodes::BlockNode<'src>) {
+        let previous = self.bounds.len();
+        let mut guards = Vec::new();
+        collect_guards(condition, &mut guards);
+        for (name, bound) in guards {
+            if !self.escaped.contains(name) && !modifies_block(block, name) {
+                self.bounds.push((name, bound));
+            }
+        }
+        self.check_block(block);
+        self.bounds.truncate(previous);
+    }
+
+    fn check_expression(&mut self, expression: &mut nodes::Expression<'src>) {
+        match expression {
+            nodes::Expression::Name(_)
+            | nodes::Expression::Literal(_)
+            | nodes::Expression::Sizeof(_)
+            | nodes::Expression::Embed(_) => {}
+            nodes::Expression::StructLiteral(literal) => {
+                for (_, field) in &mut literal.fields {
+                    self.check_expression(field);
+                }
+                if let Some(base) = &mut literal.base {
+                    self.check_expression(base);
+                }
+            }
+            nodes::Expression::ArrayLiteral(literal) => {
+                for element in &mut literal.elements {
+                    self.check_expression(element);
+                }
+            }
+            nodes::Expression::TupleLiteral(literal) => {
+                for element in &mut literal.elements {
+                    self.check_expression(element);
+                }
+            }
+            nodes::Expression::Unary(unary) => self.check_expression(&mut unary.expression),
+            nodes::Expression::Binary(binary) => {
+                self.check_expression(&mut binary.lhs);
+                self.check_expression(&mut binary.rhs);
+                if binary.operation == Operation::IndexedAccess && binary.needs_bounds_check && self.is_in_bounds(binary) {
+                    binary.needs_bounds_check = false;
+                    self.elided += 1;
+                }
+            }
+            nodes::Expression::FunctionCall(call) => {
+                for argument in &mut call.arguments {
+                    self.check_expression(argument);
+                }
+            }
+            nodes::Expression::Reflection(reflection) => {
+                if let nodes::ReflectionKind::FieldName(index) = &mut reflection.kind {
+                    self.check_expression(index);
+                }
+            }
+            nodes::Expression::Arithmetic(arithmetic) => {
+                self.check_expression(&mut arithmetic.lhs);
+                self.check_expression(&mut arithmetic.rhs);
+                if let Some(result) = &mut arithmetic.result {
+                    self.check_expression(result);
+                }
+            }
+            nodes::Expression::As(expression, _) => self.check_expression(expression),
+            nodes::Expression::Lambda(lambda) => {
+                // The body is a function of its own, captures by reference may change whenever it's called
+                let bounds = std::mem::take(&mut self.bounds);
+                let mut escaped = self.globals.clone();
+                escaped.extend(lambda.captures.iter().filter(|c| c.is_reference).map(|c| c.name));
+                collect_escaped_block(&lambda.block, &mut escaped);
+                let escaped = std::mem::replace(&mut self.escaped, escaped);
+                self.check_block(&mut lambda.block);
+                self.escaped = escaped;
+                self.bounds = bounds;
+            }
+        }
+    }
+
+    fn is_in_bounds(&self, binary: &nodes::BinaryNode<'src>) -> bool {
+        let Type::Array(_, size) = binary.lhs.get_type() else {
+            return false;
+        };
+        let size = size as u64;
+        match binary.rhs.as_ref() {
+            nodes::Expression::Literal(literal) => literal.value.parse::<u64>().is_ok_and(|index| index < size),
+            nodes::Expression::Name(name) => {
+                name.typ == Type::Usize
+                    && self.bounds.iter().rev().any(|(var, bound)| *var == name.name && *bound <= size)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Collects `name < N` facts that hold whenever `condition` is true.
+fn collect_guards<'src>(condition: &nodes::Expression<'src>, guards: &mut Vec<(&'src str, u64)>) {
+    let nodes::Expression::Binary(binary) = condition else {
+        return;
+    };
+    let literal = |expression: &nodes::Expression| match expression {
+        nodes::Expression::Literal(literal) => literal.value.parse::<u64>().ok(),
+        _ => None,
+    };
+    match (binary.operation, binary.lhs.as_ref(), binary.rhs.as_ref()) {
+        (Operation::LogicalAnd, lhs, rhs) => {
+            collect_guards(lhs, guards);
+            collect_guards(rhs, guards);
+        }
+        (Operation::LessThan, nodes::Expression::Name(name), bound)
+        | (Operation::GreaterThan, bound, nodes::Expression::Name(name)) => {
+            if let Some(bound) = literal(bound) {
+                guards.push((name.name, bound));
+            }
+        }
+        (Operation::LessThanOrEqual, nodes::Expression::Name(name), bound)
+        | (Operation::GreaterThanOrEqual, bound, nodes::Expression::Name(name)) => {
+            if let Some(bound) = literal(bound).and_then(|bound| bound.checked_add(1)) {
+                guards.push((name.name, bound));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collects all names whose address is taken, those may be modified through pointers.
+/// Captures by reference take the address, too.
+fn collect_escaped_block<'src>(block: &nodes::BlockNode<'src>, escaped: &mut HashSet<&'src str>) {
+    for_each_statement(block, &mut |statement| {
+        for_each_expression(statement, &mut |expression| collect_escaped(expression, escaped));
+    });
+}
+
+fn collect_escaped<'src>(expression: &nodes::Expression<'src>, escaped: &mut HashSet<&'src str>) {
+    if let nodes::Expression::Lambda(lambda) = expression {
+        escaped.extend(lambda.captures.iter().filter(|c| c.is_reference).map(|c| c.name));
+    }
+    if let nodes::Expression::Unary(unary) = expression {
+        if unary.operation == Operation::Reference {
+            if let nodes::Expression::Name(name) = unary.expression.as_ref() {
+                escaped.insert(name.name);
+            }
+        }
+    }
+    for_each_subexpression(expression, &mut |sub| collect_escaped(sub, escaped));
+}
+
+/// Returns true if `name` is assigned or redeclared anywhere in `block`.
+fn modifies_block(block: &nodes::BlockNode, name: &str) -> bool {
+    let mut modified = false;
+    for_each_statement(block, &mut |statement| {
+        if let nodes::Statement::VarDecl(var_decl) = statement {
+            modified |= var_decl.name == name;
+        }
+        if let nodes::Statement::Destructure(destructure) = statement {
+            modified |= destructure.names.iter().any(|(_, n)| *n == name);
+        }
+        for_each_expression(statement, &mut |expression| modified |= modifies_expression(expression, name));
+    });
+    modified
+}
+
+fn modifies_expression(expression: &nodes::Expression, name: &str) -> bool {
+    if let nodes::Expression::Binary(binary) = expression {
+        if binary.operation == Operation::Assign {
+            if let nodes::Expression::Name(lhs) = binary.lhs.as_ref() {
+                if lhs.name == name {
+                    return true;
+                }
+            }
+        }
+    }
+    let mut modified = false;
+    for_each_subexpression(expression, &mut |sub| modified |= modifies_expression(sub, name));
+    modified
+}
+
+/// Calls `f` on every statement in `block`, including those in nested blocks.
+fn for_each_statement<'a, 'src>(block: &'a nodes::BlockNode<'src>, f: &mut impl FnMut(&'a nodes::Statement<'src>)) {
+    for statement in &block.statements {
+        for_each_nested_statement(statement, f);
+    }
+}
+
+/// Calls `f` on `statement` and every statement nested in it.
+fn for_each_nested_statement<'a, 'src>(statement: &'a nodes::Statement<'src>, f: &mut impl FnMut(&'a nodes::Statement<'src>)) {
+    f(statement);
+    match statement {
+        nodes::Statement::Block(block) => for_each_statement(block, f),
+        nodes::Statement::If(if_node) => {
+            for_each_statement(&if_node.if_body, f);
+            if let Some(else_body) = &if_node.else_body {
+                for_each_statement(else_body, f);
+            }
+        }
+        nodes::Statement::While(while_node) => for_each_statement(&while_node.body, f),
+        nodes::Statement::Defer(defer_node) => for_each_nested_statement(&defer_node.statement, f),
+        _ => {}
+    }
+}
+
+/// Calls `f` on the top level expressions of `statement`, nested blocks are not visited.
+fn for_each_expression<'a, 'src>(statement: &'a nodes::Statement<'src>, f: &mut impl FnMut(&'a nodes::Expression<'src>)) {
+    match statement {
+        nodes::Statement::Expression(expression) => f(expression),
+        nodes::Statement::VarDecl(var_decl) => f(&var_decl.expression),
+        nodes::Statement::Destructure(destructure) => f(&destructure.expression),
+        nodes::Statement::If(if_node) => f(&if_node.condition),
+        nodes::Statement::Return(return_node) => {
+            if let Some(value) = &return_node.return_value {
+                f(value);
+            }
+        }
+        nodes::Statement::While(while_node) => {
+            f(&while_node.condition);
+            if let Some(step) = &while_node.step {
+                f(step);
+            }
+        }
+        nodes::Statement::Block(_)
+        | nodes::Statement::Defer(_)
+        | nodes::Statement::Break(_)
+        | nodes::Statement::Continue(_)
+        | nodes::Statement::ComptimeAssert(_) => {}
+    }
+}
+
+fn for_each_subexpression<'a, 'src>(expression: &'a nodes::Expression<'src>, f: &mut impl FnMut(&'a nodes::Expression<'src>)) {
+    match expression {
+        nodes::Expression::Name(_)
+        | nodes::Expression::Literal(_)
+        | nodes::Expression::Sizeof(_)
+        | nodes::Expression::Embed(_)
+        | nodes::Expression::Lambda(_) => {}
+        nodes::Expression::StructLiteral(literal) => {
+            literal.fields.iter().for_each(|(_, field)| f(field));
+            literal.base.iter().for_each(|base| f(base));
+        }
+        nodes::Expression::ArrayLiteral(literal) => literal.elements.iter().for_each(f),
+        nodes::Expression::TupleLiteral(literal) => literal.elements.iter().for_each(f),
+        nodes::Expression::Unary(unary) => f(&unary.expression),
+        nodes::Expression::Binary(binary) => {
+            f(&binary.lhs);
+            f(&binary.rhs);
+        }
+        nodes::Expression::FunctionCall(call) => call.arguments.iter().for_each(f),
+        nodes::Expression::Reflection(reflection) => {
+            if let nodes::ReflectionKind::FieldName(index) = &reflection.kind {
+                f(index);
+            }
+        }
+        nodes::Expression::Arithmetic(arithmetic) => {
+            f(&arithmetic.lhs);
+            f(&arithmetic.rhs);
+            if let Some(result) = &arithmetic.result {
+                f(result);
+            }
+        }
+        nodes::Expression::As(expression, _) => f(expression),
+    }
+}
@@ -5,6 +5,7 @@ use tracer::{trace_call, trace_panic};
 use crate::util::flags::Flags;
 use crate::compiler::{ERR_STR, WARN_STR, NOTE_STR};
 use crate::frontend::nodes;
+use crate::frontend::parser::Operation;
 use crate::frontend::tokens::KEYWORD_COMPTIME;
 use crate::frontend::tokens::Location;
 use crate::internal_panic;
@@ -21,6 +22,16 @@ enum FlowError<'src> {
     NormalFuncInComptime(Location, &'src str, Location),
     /// Error Loc, Func Name, Decl Loc
     ComptimeFuncInNormal(Location, &'src str, Location),
+    /// Error Loc
+    IndirectCallInComptime(Location),
+    /// Error Loc
+    ClosureInComptime(Location),
+    /// Error Loc
+    ClosureDoesntAlwaysReturn(Location),
+    /// Error Loc, Var Name, Capture Loc
+    ClosureOutlivesCapture(Location, &'src str, Location),
+    /// Flow kind, Error Loc, Defer Loc
+    ExitFromDefer(&'static str, Location, Location),
 }
 
 impl Display for FlowError<'_> {
@@ -33,7 +44,17 @@ impl Display for FlowError<'_> {
             Self::NormalFuncInComptime(loc, name, decl)
                 => write!(f, "{ERR_STR}: {loc}: Call to normal function in a {KEYWORD_COMPTIME} function.\n{NOTE_STR}: {decl}: Function `{name}` declared here."),
             Self::ComptimeFuncInNormal(loc, name, decl)
-                => write!(f, "{ERR_STR}: {loc}: Call to {KEYWORD_COMPTIME} function in runtime context.\n{NOTE_STR}: {decl}: Function `{name}` declared to be {KEYWORD_COMPTIME} here.")
+                => write!(f, "{ERR_STR}: {loc}: Call to {KEYWORD_COMPTIME} function in runtime context.\n{NOTE_STR}: {decl}: Function `{name}` declared to be {KEYWORD_COMPTIME} here."),
+            Self::IndirectCallInComptime(loc)
+                => write!(f, "{ERR_STR}: {loc}: Call through a function pointer in a {KEYWORD_COMPTIME} function."),
+            Self::ClosureInComptime(loc)
+                => write!(f, "{ERR_STR}: {loc}: Closure in a {KEYWORD_COMPTIME} function."),
+            Self::ClosureDoesntAlwaysReturn(loc)
+                => write!(f, "{ERR_STR}: {loc}: Closure does not always return a value"),
+            Self::ClosureOutlivesCapture(loc, name, capture_loc)
+                => write!(f, "{ERR_STR}: {loc}: Closure may outlive `{name}`, which it captures by reference.\n{NOTE_STR}: {capture_loc}: `{name}` is captured here."),
+            Self::ExitFromDefer(kind, loc, defer_loc)
+                => write!(f, "{ERR_STR}: {loc}: `{kind}` can't leave a deferred statement.\n{NOTE_STR}: {defer_loc}: Statement is deferred here."),
         }
     }
 }
@@ -50,8 +71,21 @@ enum FlowType {
     AlwaysContinue
 }
 
+/// A closure that captures a variable by reference, it must not outlive that variable
+#[derive(Debug, Copy, Clone)]
+struct Borrow<'src> {
+    // Index of the scope the captured variable lives in
+    depth: usize,
+    name: &'src str,
+    capture: Location,
+}
+
 pub struct FlowChecker<'flags, 'src> {
     loop_stack: Vec<()>,
+    // Location of the `defer` whose statement is currently checked, it has to run to completion
+    defer_location: Option<Location>,
+    // Local variables of the current function, innermost scope last, with the borrow of the closure they may hold
+    variables: Vec<HashMap<&'src str, Option<Borrow<'src>>>>,
     flags: &'flags Flags,
     functions: HashMap<&'src str, (bool, Location)>,
     errors: Vec<FlowError<'src>>,
@@ -61,6 +95,8 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
     pub fn new(flags: &'flags Flags) -> FlowChecker {
         FlowChecker {
             loop_stack: Vec::new(),
+            defer_location: None,
+            variables: Vec::new(),
             flags,
             functions: HashMap::new(),
             errors: Vec::new()
@@ -110,6 +146,9 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         for _ext in &mut file.externs {
             // self.check_extern(ext)?;
         }
+        for assert_node in &file.asserts {
+            let _ = self.check_expression_node(&assert_node.condition, true);
+        }
         for strukt in &mut file.structs {
             let _ = self.check_struct(strukt);
         }
@@ -133,6 +172,7 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
 
     #[trace_call(always)]
     fn check_method(&mut self, method: &mut nodes::MethodNode<'src>) -> Result<(), ()> {
+        self.variables = vec![method.parameters.iter().map(|p| (p.name, None)).collect()];
         let flow = self.check_block(&mut method.block, &[FlowType::AlwaysReturn], false)?;
         if flow != FlowType::AlwaysReturn {
             if method.return_type.typ != Type::None {
@@ -153,6 +193,7 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
 
     #[trace_call(always)]
     fn check_function(&mut self, function: &mut nodes::FunctionNode<'src>) -> Result<(), ()> {
+        self.variables = vec![function.parameters.iter().map(|p| (p.name, None)).collect()];
         let flow = self.check_block(&mut function.block, &[FlowType::AlwaysReturn], function.is_comptime)?;
         if flow != FlowType::AlwaysReturn {
             if function.return_type.typ != Type::None {
@@ -176,6 +217,7 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         // println!("{:?}: check_block: {:?}", block.location, early_exit);
         let mut flow = FlowType::Linear;
         let mut exit_index = None;
+        self.variables.push(HashMap::new());
         for index in 0..block.statements.len() {
             let statement = &mut block.statements[index];
             if let Ok(f) = self.check_statement(statement, early_exit, is_comptime) {
@@ -193,6 +235,7 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
                 break;
             }
         }
+        self.variables.pop();
         #[cfg(not(feature = "old_codegen"))]
         if let Some(index) = exit_index {
             block.statements.truncate(index + 1);
@@ -208,20 +251,35 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
     #[trace_call(always)]
     fn check_statement(&mut self, statement: &mut nodes::Statement<'src>, early_exit: &[FlowType], is_comptime: bool) -> Result<FlowType, ()> {
         match statement {
-            nodes::Statement::Expression(expr_node) => self.check_expression_node(expr_node, is_comptime),
+            nodes::Statement::Expression(expr_node) => {
+                self.check_closures(expr_node, is_comptime);
+                self.check_expression_node(expr_node, is_comptime)
+            }
             nodes::Statement::Block(block_node) => self.check_block(block_node, early_exit, is_comptime),
             nodes::Statement::VarDecl(var_node) => self.check_stmt_var_decl(var_node, is_comptime),
             nodes::Statement::If(if_node) => self.check_stmt_if(if_node, early_exit, is_comptime),
-            nodes::Statement::Return(return_node) => self.check_stmt_return(return_node),
+            nodes::Statement::Return(return_node) => self.check_stmt_return(return_node, is_comptime),
             nodes::Statement::While(while_node) => self.check_stmt_while(while_node, is_comptime),
             nodes::Statement::Break(break_node) => self.check_stmt_break(break_node),
             nodes::Statement::Continue(continue_node) => self.check_stmt_continue(continue_node),
+            // Assertions are always evaluated at compile time
+            nodes::Statement::ComptimeAssert(assert_node) => self.check_expression_node(&assert_node.condition, true),
+            nodes::Statement::Destructure(destructure) => self.check_stmt_destructure(destructure, is_comptime),
+            nodes::Statement::Defer(defer_node) => self.check_stmt_defer(defer_node, is_comptime),
         }
     }
 
     #[trace_call(always)]
     fn check_expression_node(&mut self, expr: &nodes::Expression<'src>, is_comptime: bool) -> Result<FlowType, ()> {
         if let nodes::Expression::FunctionCall(call) = expr {
+            if call.is_indirect {
+                // Function pointers can only point to normal functions
+                if is_comptime {
+                    self.report_error(FlowError::IndirectCallInComptime(call.location));
+                    return Err(());
+                }
+                return Ok(FlowType::Linear);
+            }
             let Some(func) = self.functions.get(call.get_full_name()) else {
                 internal_panic!("FlowChecker could not find function {}", call.get_full_name())
             };
@@ -244,8 +302,208 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         Ok(FlowType::Linear)
     }
 
+    /// Checks the bodies of closures in `expr` and makes sure closures borrowing a variable don't outlive it
     #[trace_call(always)]
-    fn check_stmt_var_decl(&mut self, let_node: &nodes::VarDeclNode<'src>, is_comptime: bool) -> Result<FlowType, ()> {
+    fn check_closures(&mut self, expr: &mut nodes::Expression<'src>, is_comptime: bool) {
+        match expr {
+            nodes::Expression::Name(_)
+            | nodes::Expression::Literal(_)
+            | nodes::Expression::Sizeof(_)
+            | nodes::Expression::Embed(_) => (),
+            nodes::Expression::StructLiteral(literal) => {
+                for (_, field) in &mut literal.fields {
+                    self.check_closures(field, is_comptime);
+                }
+                if let Some(base) = &mut literal.base {
+                    self.check_closures(base, is_comptime);
+                }
+            }
+            nodes::Expression::ArrayLiteral(literal) => {
+                for element in &mut literal.elements {
+                    self.check_closures(element, is_comptime);
+                }
+            }
+            nodes::Expression::TupleLiteral(literal) => {
+                for element in &mut literal.elements {
+                    self.check_closures(element, is_comptime);
+                }
+            }
+            nodes::Expression::Unary(unary) => self.check_closures(&mut unary.expression, is_comptime),
+            nodes::Expression::Binary(binary) => {
+                self.check_closures(&mut binary.lhs, is_comptime);
+                self.check_closures(&mut binary.rhs, is_comptime);
+                if binary.operation == Operation::Assign {
+                    self.check_assignment(&binary.lhs, &binary.rhs);
+                }
+            }
+            nodes::Expression::FunctionCall(call) => {
+                for argument in &mut call.arguments {
+                    self.check_closures(argument, is_comptime);
+                }
+                self.check_call_arguments(call);
+            }
+            nodes::Expression::Reflection(reflection) => {
+                if let nodes::ReflectionKind::FieldName(index) = &mut reflection.kind {
+                    self.check_closures(index, is_comptime);
+                }
+            }
+            nodes::Expression::Arithmetic(arithmetic) => {
+                self.check_closures(&mut arithmetic.lhs, is_comptime);
+                self.check_closures(&mut arithmetic.rhs, is_comptime);
+                if let Some(result) = &mut arithmetic.result {
+                    self.check_closures(result, is_comptime);
+                }
+            }
+            nodes::Expression::As(expr, _) => self.check_closures(expr, is_comptime),
+            nodes::Expression::Lambda(lambda) => {
+                let _ = self.check_lambda(lambda, is_comptime);
+            }
+        }
+    }
+
+    #[trace_call(always)]
+    fn check_lambda(&mut self, lambda: &mut nodes::LambdaNode<'src>, is_comptime: bool) -> Result<(), ()> {
+        if is_comptime {
+            self.report_error(FlowError::ClosureInComptime(lambda.location));
+            return Err(());
+        }
+        // The body is a function of its own, it only knows its captures and parameters
+        let scope = lambda.captures.iter().map(|c| (c.name, None))
+            .chain(lambda.parameters.iter().map(|p| (p.name, None)))
+            .collect();
+        let outer_variables = std::mem::replace(&mut self.variables, vec![scope]);
+        let outer_loops = std::mem::take(&mut self.loop_stack);
+        let outer_defer = self.defer_location.take();
+        let flow = self.check_block(&mut lambda.block, &[FlowType::AlwaysReturn], false);
+        self.defer_location = outer_defer;
+        self.loop_stack = outer_loops;
+        self.variables = outer_variables;
+        if flow? != FlowType::AlwaysReturn && lambda.return_type.typ != Type::None {
+            self.report_error(FlowError::ClosureDoesntAlwaysReturn(lambda.location));
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// Returns the innermost variable `expr` borrows if it is, or contains, a closure that captures by reference
+    #[trace_call(extra)]
+    fn borrow_of(&self, expr: &nodes::Expression<'src>) -> Option<Borrow<'src>> {
+        match expr {
+            nodes::Expression::Lambda(lambda) => lambda.captures.iter().fold(None, |borrow, capture| {
+                let captured = if capture.is_reference {
+                    // Globals live forever, so borrowing them is fine
+                    self.find_variable(capture.name).map(|(depth, _)| Borrow {
+                        depth,
+                        name: capture.name,
+                        capture: capture.location,
+                    })
+                } else {
+                    // Copying a closure copies its borrow, too
+                    self.find_variable(capture.name).and_then(|(_, borrow)| borrow)
+                };
+                innermost(borrow, captured)
+            }),
+            nodes::Expression::Name(name) => self.find_variable(name.name).and_then(|(_, borrow)| borrow),
+            nodes::Expression::StructLiteral(literal) => literal.fields.iter()
+                .map(|(_, field)| field)
+                .chain(literal.base.as_deref())
+                .fold(None, |borrow, field| innermost(borrow, self.borrow_of(field))),
+            nodes::Expression::ArrayLiteral(literal) => literal.elements.iter()
+                .fold(None, |borrow, element| innermost(borrow, self.borrow_of(element))),
+            nodes::Expression::TupleLiteral(literal) => literal.elements.iter()
+                .fold(None, |borrow, element| innermost(borrow, self.borrow_of(element))),
+            nodes::Expression::Binary(binary) if matches!(binary.operation, Operation::MemberAccess | Operation::IndexedAccess) => {
+                self.borrow_of(&binary.lhs)
+            }
+            nodes::Expression::As(expr, _) => self.borrow_of(expr),
+            // We don't look into the callee, so anything it returns may be built from its arguments
+            nodes::Expression::FunctionCall(call) if may_hold_closure(&call.typ) => {
+                let callee = if call.is_closure {
+                    self.find_variable(call.function_name).and_then(|(_, borrow)| borrow)
+                } else {
+                    None
+                };
+                call.arguments.iter()
+                    .fold(callee, |borrow, argument| innermost(borrow, self.borrow_of(argument)))
+            }
+            _ => None,
+        }
+    }
+
+    #[trace_call(extra)]
+    fn find_variable(&self, name: &str) -> Option<(usize, Option<Borrow<'src>>)> {
+        self.variables.iter().enumerate().rev()
+            .find_map(|(depth, scope)| scope.get(name).map(|borrow| (depth, *borrow)))
+    }
+
+    /// Storing a closure in a variable that lives longer than one of its captures would leave it dangling
+    #[trace_call(always)]
+    fn check_assignment(&mut self, lhs: &nodes::Expression<'src>, rhs: &nodes::Expression<'src>) {
+        if let Some(borrow) = self.borrow_of(rhs) {
+            self.store_borrow(lhs, borrow, rhs.get_loc());
+        }
+    }
+
+    /// A function taking `&mut` may store any closure passed alongside it through that reference
+    #[trace_call(always)]
+    fn check_call_arguments(&mut self, call: &nodes::CallNode<'src>) {
+        let Some(borrow) = call.arguments.iter()
+            .fold(None, |borrow, argument| innermost(borrow, self.borrow_of(argument))) else {
+            return;
+        };
+        for argument in &call.arguments {
+            if !argument.get_type().is_mutable_ref() {
+                continue;
+            }
+            match argument {
+                nodes::Expression::Unary(unary) if unary.operation == Operation::Reference => {
+                    self.store_borrow(&unary.expression, borrow, call.location)
+                }
+                _ => self.report_error(FlowError::ClosureOutlivesCapture(
+                    call.location,
+                    borrow.name,
+                    borrow.capture,
+                )),
+            }
+        }
+    }
+
+    /// Records that `lhs` now holds a closure with `borrow`, if it outlives the captured variable that's an error
+    #[trace_call(always)]
+    fn store_borrow(&mut self, lhs: &nodes::Expression<'src>, borrow: Borrow<'src>, location: Location) {
+        // Find the variable that is written to, writes through references may end up anywhere
+        let mut target = lhs;
+        let root = loop {
+            match target {
+                nodes::Expression::Name(name) => break Some(name.name),
+                nodes::Expression::Binary(binary)
+                    if matches!(binary.operation, Operation::MemberAccess | Operation::IndexedAccess)
+                    && !binary.lhs.get_type().is_reference() => target = &binary.lhs,
+                _ => break None,
+            }
+        };
+        let variable = root.and_then(|name| self.find_variable(name).map(|(depth, _)| (name, depth)));
+        match variable {
+            Some((name, depth)) if depth >= borrow.depth => {
+                let scope = &mut self.variables[depth];
+                let previous = scope.get(name).copied().flatten();
+                if previous.is_none_or(|previous| previous.depth < borrow.depth) {
+                    scope.insert(name, Some(borrow));
+                }
+            }
+            _ => self.report_error(FlowError::ClosureOutlivesCapture(
+                location,
+                borrow.name,
+                borrow.capture,
+            )),
+        }
+    }
+
+    #[trace_call(always)]
+    fn check_stmt_var_decl(&mut self, let_node: &mut nodes::VarDeclNode<'src>, is_comptime: bool) -> Result<FlowType, ()> {
+        self.check_closures(&mut let_node.expression, is_comptime || let_node.is_comptime);
+        let borrow = self.borrow_of(&let_node.expression);
+        self.variables.last_mut().expect("Variables are declared in blocks").insert(let_node.name, borrow);
         if is_comptime && let_node.is_comptime {
             eprintln!(
                 "{WARN_STR}: {}: The {} specifier for variables has no use in {} functions.",
@@ -260,11 +518,40 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         Ok(FlowType::Linear)
     }
 
+    #[trace_call(always)]
+    fn check_stmt_destructure(&mut self, destructure: &mut nodes::DestructureNode<'src>, is_comptime: bool) -> Result<FlowType, ()> {
+        self.check_closures(&mut destructure.expression, is_comptime);
+        // Every variable may hold a closure of the tuple, so they all share its borrow
+        let borrow = self.borrow_of(&destructure.expression);
+        let scope = self.variables.last_mut().expect("Variables are declared in blocks");
+        for (_, name) in &destructure.names {
+            scope.insert(name, borrow);
+        }
+        if !is_comptime {
+            let _ = self.check_expression_node(&destructure.expression, false);
+        }
+        Ok(FlowType::Linear)
+    }
+
+    #[trace_call(always)]
+    fn check_stmt_defer(&mut self, defer_node: &mut nodes::DeferNode<'src>, is_comptime: bool) -> Result<FlowType, ()> {
+        // Loops around the `defer` can't be left from within the deferred statement, only loops inside of it
+        let outer_loops = std::mem::take(&mut self.loop_stack);
+        let outer_defer = self.defer_location.replace(defer_node.location);
+        let flow = self.check_statement(&mut defer_node.statement, &[], is_comptime);
+        self.defer_location = outer_defer;
+        self.loop_stack = outer_loops;
+        flow?;
+        // The statement only runs when the block is left, so the `defer` itself never changes control flow
+        Ok(FlowType::Linear)
+    }
+
     #[trace_call(always)]
     fn check_stmt_if(&mut self, if_node: &mut nodes::IfNode<'src>, early_exit: &[FlowType], is_comptime: bool) -> Result<FlowType, ()> {
         // let cond_flow = self.check_expression_node(&if_node.condition)?;
         // debug_assert!(cond_flow == FlowType::Linear);
         // Later on we might want to check if the condition always exits
+        self.check_closures(&mut if_node.condition, is_comptime);
         let if_flow = self.check_block(&mut if_node.if_body, early_exit, is_comptime)?;
         if let Some(else_branch) = &mut if_node.else_body {
             let else_flow = self.check_block(else_branch, early_exit, is_comptime)?;
@@ -303,7 +590,21 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
     }
 
     #[trace_call(always)]
-    fn check_stmt_return(&mut self, _return_node: &nodes::ReturnNode) -> Result<FlowType, ()> {
+    fn check_stmt_return(&mut self, return_node: &mut nodes::ReturnNode<'src>, is_comptime: bool) -> Result<FlowType, ()> {
+        if let Some(defer_location) = self.defer_location {
+            self.report_error(FlowError::ExitFromDefer("return", return_node.location, defer_location));
+            return Err(());
+        }
+        if let Some(value) = &mut return_node.return_value {
+            self.check_closures(value, is_comptime);
+            if let Some(borrow) = self.borrow_of(value) {
+                self.report_error(FlowError::ClosureOutlivesCapture(
+                    value.get_loc(),
+                    borrow.name,
+                    borrow.capture,
+                ));
+            }
+        }
         Ok(FlowType::AlwaysReturn)
     }
 
@@ -312,6 +613,10 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         // let cond_flow = self.check_expression_node(&while_node.condition)?;
         // debug_assert!(cond_flow == FlowType::Linear);
         // Later on we might want to check if the condition always exits
+        self.check_closures(&mut while_node.condition, is_comptime);
+        if let Some(step) = &mut while_node.step {
+            self.check_closures(step, is_comptime);
+        }
         self.loop_stack.push(());
         let block_flow = self.check_block(
             &mut while_node.body,
@@ -335,7 +640,10 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
 
     #[trace_call(always)]
     fn check_stmt_break(&mut self, break_node: &nodes::BreakNode) -> Result<FlowType, ()> {
-        if self.loop_stack.is_empty() {
+        if let (true, Some(defer_location)) = (self.loop_stack.is_empty(), self.defer_location) {
+            self.report_error(FlowError::ExitFromDefer("break", break_node.location, defer_location));
+            Err(())
+        } else if self.loop_stack.is_empty() {
             self.report_error(FlowError::BreakOrContinueOutsideLoop("break", break_node.location));
             Err(())
             // Err(format!(
@@ -352,7 +660,10 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         &mut self,
         continue_node: &nodes::ContinueNode,
     ) -> Result<FlowType, ()> {
-        if self.loop_stack.is_empty() {
+        if let (true, Some(defer_location)) = (self.loop_stack.is_empty(), self.defer_location) {
+            self.report_error(FlowError::ExitFromDefer("continue", continue_node.location, defer_location));
+            Err(())
+        } else if self.loop_stack.is_empty() {
             self.report_error(FlowError::BreakOrContinueOutsideLoop("continue", continue_node.location));
             Err(())
         } else {
@@ -360,3 +671,21 @@ impl<'flags, 'src> FlowChecker<'flags, 'src> {
         }
     }
 }
+
+/// Picks the borrow of the variable that dies first
+fn innermost<'src>(lhs: Option<Borrow<'src>>, rhs: Option<Borrow<'src>>) -> Option<Borrow<'src>> {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(if lhs.depth >= rhs.depth { lhs } else { rhs }),
+        (lhs, rhs) => lhs.or(rhs),
+    }
+}
+
+/// Whether a value of type `typ` may contain a closure, structs and interfaces are assumed to
+fn may_hold_closure(typ: &Type) -> bool {
+    match typ {
+        Type::Closure(..) | Type::Struct(_) | Type::Dyn(..) => true,
+        Type::Ref(inner, _) | Type::Array(inner, _) => may_hold_closure(inner),
+        Type::Tuple(elements) => elements.iter().any(may_hold_closure),
+        _ => false,
+    }
+}
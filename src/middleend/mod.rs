@@ -1,2 +1,3 @@
 pub mod type_checker;
-pub mod flow_checker;
\ No newline at end of file
+pub mod flow_checker;
+pub mod bounds_checker;
\ No newline at end of file
@@ -1,9 +1,10 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
 
 use crate::frontend::nodes;
 use crate::frontend::parser::Operation;
-use crate::frontend::tokens::{Location, KEYWORD_NULL};
+use crate::frontend::tokens::{Location, KEYWORD_CLOSURE, KEYWORD_COMPTIME, KEYWORD_NULL};
 
 use crate::compiler::{ERR_STR, WARN_STR, NOTE_STR};
 use crate::internal_panic;
@@ -40,7 +41,12 @@ pub enum PrimitiveKind {
 }
 
 macro_rules! check_function {
-    ($tc:ident, $call_node:ident, $func_info:ident, $typ:expr) => {{
+    ($tc:ident, $call_node:ident, $func_info:ident, $typ:expr) => {
+        check_function!($tc, $call_node, $func_info, $typ, None::<Vec<Type>>)
+    };
+    // `$checked` holds the argument types if the caller already checked them
+    ($tc:ident, $call_node:ident, $func_info:ident, $typ:expr, $checked:expr) => {{
+        let checked: Option<Vec<Type>> = $checked;
         let return_type = $func_info.return_type.t.clone();
         match $call_node.arguments.len().cmp(&$func_info.parameters.len()) {
             std::cmp::Ordering::Less => {
@@ -115,20 +121,27 @@ macro_rules! check_function {
                 // }
             }
         } else {
-            for (mut arg, param) in $call_node
+            for (index, (mut arg, param)) in $call_node
                 .arguments
                 .iter_mut()
                 .zip($func_info.parameters.clone())
+                .enumerate()
             {
                 let expected = param.typ;
-                let Ok(arg_type) = $tc.type_check_expression(arg, MutState::Immut) else {
-                    continue;
+                let arg_type = match &checked {
+                    Some(types) => types[index].clone(),
+                    None => {
+                        let Ok(arg_type) = $tc.type_check_expression(arg, MutState::Immut) else {
+                            continue;
+                        };
+                        arg_type
+                    }
                 };
                 if arg_type == Type::Unknown {
                     // We need to `infer` the type again
                     $tc.type_check_expression_with_type(&mut arg, &expected)?;
                     // debug_assert!(!expected.is_struct());
-                } else if arg_type != expected {
+                } else if arg_type != expected && !$tc.coerce_to_dyn(arg, &arg_type, &expected) {
                     $tc.report_error(TypeError::ArgParamTypeMismatch(
                         arg.get_loc(),
                         arg_type.clone(),
@@ -157,6 +170,7 @@ macro_rules! check_parameters {
                 location: param.location,
                 typ: param.typ.typ.clone(),
                 mut_state: MutState::mutable(param.is_mutable, param.typ.typ.is_mutable_ref()),
+                is_comptime: false,
             };
             let index_of = parameters.iter().position(|p| p.name == param.name);
             if index_of.is_some() {
@@ -266,6 +280,54 @@ enum TypeError<'src> {
     NonPrimitiveTypeCast(Location, Location, Type<'src>, Location, Type<'src>),
     /// Syntax: Error Loc, Type
     BlankReference(Location, Type<'src>),
+    /// Syntax: Error Loc, Intrinsic Name, Type
+    ReflectionOnNonStruct(Location, &'static str, Type<'src>),
+    /// Syntax: Global Loc, Global Name, Subexpression Loc
+    NonConstantGlobal(Location, &'src str, Location),
+    /// Syntax: Field Loc, Field Name, Subexpression Loc
+    NonConstantDefault(Location, &'src str, Location),
+    /// Syntax: Error Loc, Intrinsic Name, Type
+    ArithmeticOnNonInteger(Location, String, Type<'src>),
+    /// Syntax: Error Loc
+    InvalidPanicHandler(Location),
+    /// Syntax: Error Loc, Interface Name
+    UnknownInterface(Location, &'src str),
+    /// Syntax: Impl Loc, Struct Name, Method Name, Interface Name, Interface Method Loc
+    MissingInterfaceMethod(Location, &'src str, &'src str, &'src str, Location),
+    /// Syntax: Method Loc, Method Name, Interface Name, Interface Method Loc
+    InterfaceMethodMismatch(Location, &'src str, &'src str, Location),
+    /// Syntax: Error Loc, Type Parameter Name, Type Parameter Loc
+    CannotInferTypeParameter(Location, &'src str, Location),
+    /// Syntax: Error Loc, Type, Interface Name, Type Parameter Loc, Type Parameter Name
+    UnsatisfiedBound(Location, Type<'src>, &'src str, Location, &'src str),
+    /// Syntax: Error Loc, Method Name, Interface Loc, Interface Name
+    UnknownInterfaceMethod(Location, &'src str, Location, &'src str),
+    /// Syntax: Error Loc, Method Name, Interface Name, Method Loc
+    DynIncompatibleMethod(Location, &'src str, &'src str, Location),
+    /// Syntax: Error Loc, Operation, Struct Name, Method Name, Struct Loc
+    MissingOperatorMethod(Location, Operation, &'src str, &'static str, Location),
+    /// Syntax: Method Loc, Method Name, Expected Return Type
+    InvalidOperatorMethod(Location, &'static str, Type<'src>),
+    /// Syntax: Error Loc, Function Kind, Function Name, Function Loc
+    FunctionAddressNotAllowed(Location, &'static str, &'src str, Location),
+    /// Syntax: Error Loc, Name, Found Type
+    CallOnNonFunction(Location, &'src str, Type<'src>),
+    /// Syntax: Error Loc, Var Name, Decl Loc
+    ComptimeCapture(Location, &'src str, Location),
+    /// Syntax: Error Loc, Index, Tuple Type
+    TupleIndexOutOfBounds(Location, &'src str, Type<'src>),
+    /// Syntax: Error Loc, Variable Count, Found Type
+    DestructureMismatch(Location, usize, Type<'src>),
+    /// Syntax: Error Loc
+    CannotInferDestructure(Location),
+    /// Syntax: Alias Loc, Alias Name
+    RecursiveTypeAlias(Location, &'src str),
+    /// Syntax: Error Loc
+    UnsafeUnionRead(Location),
+    /// Syntax: Error Loc, Union Name, Union Loc
+    InvalidUnionLiteral(Location, &'src str, Location),
+    /// Syntax: Error Loc, Field Name, Struct Name, Struct Loc
+    ReferenceToPackedField(Location, &'src str, &'src str, Location),
 }
 
 impl<'src> Display for TypeError<'src> {
@@ -562,6 +624,166 @@ impl<'src> Display for TypeError<'src> {
                     ERR_STR, err, typ, KEYWORD_NULL
                 )
             }
+            TypeError::ReflectionOnNonStruct(loc, name, typ) => {
+                write!(
+                    f,
+                    "{}: {:?}: `{}` expects a struct type, found type `{}`.",
+                    ERR_STR, loc, name, typ
+                )
+            }
+            TypeError::NonConstantGlobal(loc, name, expr_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Global variable `{}` must be initialized with a constant expression.\n{}: {:?}: This expression can't be evaluated at compile time.",
+                    ERR_STR, loc, name, NOTE_STR, expr_loc
+                )
+            }
+            TypeError::NonConstantDefault(loc, name, expr_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Default value of field `{}` must be a constant expression.\n{}: {:?}: This expression can't be evaluated at compile time.",
+                    ERR_STR, loc, name, NOTE_STR, expr_loc
+                )
+            }
+            TypeError::ArithmeticOnNonInteger(loc, name, typ) => {
+                write!(
+                    f,
+                    "{}: {:?}: `{}` expects integer operands, found type `{}`.",
+                    ERR_STR, loc, name, typ
+                )
+            }
+            TypeError::InvalidPanicHandler(loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Invalid signature for `panicHandler`.\n{}: Expected `func panicHandler(kind: &char, location: &char, message: &char)`.",
+                    ERR_STR, loc, NOTE_STR
+                )
+            }
+            TypeError::UnknownInterface(loc, name) => {
+                write!(f, "{}: {:?}: Unknown interface `{}`.", ERR_STR, loc, name)
+            }
+            TypeError::MissingInterfaceMethod(impl_loc, struct_name, method_name, interface_name, method_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Struct `{}` does not implement method `{}` of interface `{}`.\n{}: {:?}: Method `{}` is declared here.",
+                    ERR_STR, impl_loc, struct_name, method_name, interface_name, NOTE_STR, method_loc, method_name
+                )
+            }
+            TypeError::InterfaceMethodMismatch(method_loc, method_name, interface_name, interface_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Signature of method `{}` does not match interface `{}`.\n{}: {:?}: Method `{}` is declared here.",
+                    ERR_STR, method_loc, method_name, interface_name, NOTE_STR, interface_loc, method_name
+                )
+            }
+            TypeError::CannotInferTypeParameter(loc, name, param_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Could not infer type parameter `{}` from the arguments.\n{}: {:?}: Type parameter `{}` is declared here.",
+                    ERR_STR, loc, name, NOTE_STR, param_loc, name
+                )
+            }
+            TypeError::UnsatisfiedBound(loc, typ, interface_name, param_loc, param_name) => {
+                write!(
+                    f,
+                    "{}: {:?}: Type `{}` does not implement interface `{}`.\n{}: {:?}: Type parameter `{}` requires `{}` here.",
+                    ERR_STR, loc, typ, interface_name, NOTE_STR, param_loc, param_name, interface_name
+                )
+            }
+            TypeError::UnknownInterfaceMethod(error_loc, method_name, interface_loc, interface_name) => {
+                write!(
+                    f,
+                    "{}: {:?}: Attempted to call unknown method `{}`.\n{}: {:?}: Interface `{}` is declared here.",
+                    ERR_STR, error_loc, method_name, NOTE_STR, interface_loc, interface_name
+                )
+            }
+            TypeError::MissingOperatorMethod(error_loc, op, struct_name, method_name, struct_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Operator `{}` is not defined for struct `{}`.\n{}: {:?}: Declare a method `{}` to support it.",
+                    ERR_STR, error_loc, op, struct_name, NOTE_STR, struct_loc, method_name
+                )
+            }
+            TypeError::InvalidOperatorMethod(method_loc, method_name, expected) => {
+                write!(
+                    f,
+                    "{}: {:?}: Method `{}` must return `{}` to be used as an operator.",
+                    ERR_STR, method_loc, method_name, expected
+                )
+            }
+            TypeError::FunctionAddressNotAllowed(error_loc, fn_kind, fn_name, fn_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Can't take the address of {} `{}`.\n{}: {:?}: `{}` is declared here.",
+                    ERR_STR, error_loc, fn_kind, fn_name, NOTE_STR, fn_loc, fn_name
+                )
+            }
+            TypeError::CallOnNonFunction(error_loc, name, found) => {
+                write!(
+                    f,
+                    "{}: {:?}: `{}` is of type `{}` and can't be called.",
+                    ERR_STR, error_loc, name, found
+                )
+            }
+            TypeError::ComptimeCapture(error_loc, name, decl_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Can't capture {} variable `{}` in a closure.\n{}: {:?}: `{}` is declared here.",
+                    ERR_STR, error_loc, KEYWORD_COMPTIME, name, NOTE_STR, decl_loc, name
+                )
+            }
+            TypeError::TupleIndexOutOfBounds(error_loc, index, typ) => {
+                write!(
+                    f,
+                    "{}: {:?}: Tuple of type `{}` has no element `{}`.",
+                    ERR_STR, error_loc, typ, index
+                )
+            }
+            TypeError::DestructureMismatch(error_loc, count, typ) => {
+                write!(
+                    f,
+                    "{}: {:?}: Can't destructure value of type `{}` into {} variables.",
+                    ERR_STR, error_loc, typ, count
+                )
+            }
+            TypeError::CannotInferDestructure(error_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Could not infer the type of the destructured value, please annotate it.",
+                    ERR_STR, error_loc
+                )
+            }
+            TypeError::RecursiveTypeAlias(alias_loc, name) => {
+                write!(
+                    f,
+                    "{}: {:?}: Type alias `{}` refers to itself.",
+                    ERR_STR, alias_loc, name
+                )
+            }
+            TypeError::UnsafeUnionRead(loc) => {
+                write!(f, "{}: {:?}: Reading a field of a union is unsafe.\n{}: Use an `unsafe {{}}` block if you really want to do that.", ERR_STR, loc, NOTE_STR)
+            }
+            TypeError::InvalidUnionLiteral(error_loc, union_name, union_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: A literal of union `{}` has to initialize exactly one field.\n{}: {:?}: Union is declared here.",
+                    ERR_STR, error_loc, union_name, NOTE_STR, union_loc
+                )
+            }
+            TypeError::ReferenceToPackedField(error_loc, field_name, struct_name, struct_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Can't take a reference to packed field `{}` of struct `{}`, it may be unaligned.\n{}: {:?}: Struct is declared here.",
+                    ERR_STR, error_loc, field_name, struct_name, NOTE_STR, struct_loc
+                )
+            }
+            TypeError::DynIncompatibleMethod(error_loc, method_name, interface_name, method_loc) => {
+                write!(
+                    f,
+                    "{}: {:?}: Method `{}` can't be called through `&dyn {}`.\n{}: {:?}: Only methods taking `this` by reference can be called dynamically.",
+                    ERR_STR, error_loc, method_name, interface_name, NOTE_STR, method_loc
+                )
+            }
         }
     }
 }
@@ -589,6 +811,14 @@ pub enum Type<'src> {
     // TODO: More unit tests for references
     Ref(Box<Type<'src>>, bool), // bool is mutability
     Array(Box<Type<'src>>, usize),
+    // `&dyn Interface`, a reference to any struct implementing the interface, bool is mutability
+    Dyn(&'src str, bool),
+    // `func(A, B) -> R`, a pointer to a function, R is None if it returns nothing, bool is unsafety
+    Function(Box<[Type<'src>]>, Box<Type<'src>>, bool),
+    // `closure(A, B) -> R`, a function pointer together with the environment it captured
+    Closure(Box<[Type<'src>]>, Box<Type<'src>>),
+    // `(A, B)`, an anonymous struct with at least two elements
+    Tuple(Box<[Type<'src>]>),
     // Reserved for later use
     F32,
     F64,
@@ -620,6 +850,14 @@ impl<'src> PartialEq for Type<'src> {
             (Type::Struct(lhs), Type::Struct(rhs)) => lhs == rhs,
             (Type::Ref(lhs, l), Type::Ref(rhs, r)) => lhs == rhs && l == r,
             (Type::Array(lhs, l), Type::Array(rhs, r)) => lhs == rhs && l == r,
+            (Type::Dyn(lhs, l), Type::Dyn(rhs, r)) => lhs == rhs && l == r,
+            (Type::Function(lhs_params, lhs_ret, l), Type::Function(rhs_params, rhs_ret, r)) => {
+                lhs_params == rhs_params && lhs_ret == rhs_ret && l == r
+            }
+            (Type::Closure(lhs_params, lhs_ret), Type::Closure(rhs_params, rhs_ret)) => {
+                lhs_params == rhs_params && lhs_ret == rhs_ret
+            }
+            (Type::Tuple(lhs), Type::Tuple(rhs)) => lhs == rhs,
             (Type::Blank, Type::Blank) => true,
             _ => false,
         }
@@ -727,6 +965,7 @@ impl<'src> Type<'src> {
     pub fn is_mutable_ref(&self) -> bool {
         match self {
             Type::Ref(_, is_mut) => *is_mut,
+            Type::Dyn(_, is_mut) => *is_mut,
             _ => false,
         }
     }
@@ -798,6 +1037,28 @@ impl<'src> Display for Type<'src> {
             Type::Ref(t, true) => write!(fmt, "&mut {}", t),
             Type::Ref(t, false) => write!(fmt, "&{}", t),
             Type::Array(t, len) => write!(fmt, "[{}; {}]", t, len),
+            Type::Dyn(interface, true) => write!(fmt, "&mut dyn {}", interface),
+            Type::Dyn(interface, false) => write!(fmt, "&dyn {}", interface),
+            Type::Function(parameters, return_type, is_unsafe) => {
+                let parameters: Vec<_> = parameters.iter().map(|p| p.to_string()).collect();
+                write!(fmt, "{}func({})", if *is_unsafe { "unsafe " } else { "" }, parameters.join(", "))?;
+                if **return_type != Type::None {
+                    write!(fmt, " -> {}", return_type)?;
+                }
+                Ok(())
+            }
+            Type::Closure(parameters, return_type) => {
+                let parameters: Vec<_> = parameters.iter().map(|p| p.to_string()).collect();
+                write!(fmt, "{}({})", KEYWORD_CLOSURE, parameters.join(", "))?;
+                if **return_type != Type::None {
+                    write!(fmt, " -> {}", return_type)?;
+                }
+                Ok(())
+            }
+            Type::Tuple(elements) => {
+                let elements: Vec<_> = elements.iter().map(|e| e.to_string()).collect();
+                write!(fmt, "({})", elements.join(", "))
+            }
             Type::Any => write!(fmt, "Any"),
             _ => write!(fmt, "{}", format!("{:?}", self).to_lowercase()),
         }
@@ -838,14 +1099,49 @@ impl<'src> Function<'src> {
         }
         parameters
     }
+
+    /// Replaces every type parameter in the signature by the type it's bound to
+    #[trace_call(extra)]
+    fn substitute(&self, bindings: &[(&'src str, Type<'src>)]) -> Self {
+        let mut func = self.clone();
+        substitute_type(&mut func.return_type.t, bindings);
+        for param in &mut func.parameters {
+            substitute_type(&mut param.typ, bindings);
+        }
+        func
+    }
+
+    /// Parameter names and locations may differ, everything else must be the same
+    #[trace_call(extra)]
+    fn has_same_signature(&self, other: &Self) -> bool {
+        self.has_this == other.has_this
+            && self.is_unsafe == other.is_unsafe
+            && self.return_type.t == other.return_type.t
+            && self.parameters.len() == other.parameters.len()
+            && self.parameters.iter().zip(&other.parameters).all(|(p, q)| p.typ == q.typ)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+struct Interface<'src> {
+    name: &'src str,
+    location: Location,
+    // Kept in declaration order, so errors are reported in a deterministic order
+    methods: Vec<(&'src str, Function<'src>)>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Struct<'src> {
     name: &'src str,
     location: Location,
     fields: HashMap<&'src str, TypeLoc<'src>>,
     known_methods: HashMap<&'src str, Function<'src>>,
+    is_union: bool,
+    // Fields of packed structs and fields marked `packed`, they may be unaligned
+    packed_fields: HashSet<&'src str>,
+    // Default values of fields, type checked once the struct itself is checked
+    defaults: HashMap<&'src str, nodes::Expression<'src>>,
+    defaults_checked: bool,
 }
 
 impl<'src> Struct<'src> {
@@ -856,6 +1152,10 @@ impl<'src> Struct<'src> {
             location,
             fields: HashMap::new(),
             known_methods: HashMap::new(),
+            is_union: false,
+            packed_fields: HashSet::new(),
+            defaults: HashMap::new(),
+            defaults_checked: false,
         }
     }
 
@@ -874,6 +1174,9 @@ impl<'src> Struct<'src> {
             None => {
                 let typ = field.type_def.typ.clone();
                 self.fields.insert(name, TypeLoc::new(*location, typ.clone()));
+                if let Some(default) = &field.default {
+                    self.defaults.insert(name, default.clone());
+                }
                 Ok(())
             }
         }
@@ -936,6 +1239,7 @@ pub struct Variable<'src> {
     location: Location,
     typ: Type<'src>,
     mut_state: MutStateVal,
+    is_comptime: bool,
 }
 
 impl<'src> Variable<'src> {
@@ -946,6 +1250,7 @@ impl<'src> Variable<'src> {
             location,
             typ,
             mut_state,
+            is_comptime: false,
         }
     }
     #[trace_call(extra)]
@@ -960,6 +1265,19 @@ pub struct TypeChecker<'flags, 'src> {
     struct_indices: HashMap<&'src str, usize>,
     structs: Vec<Struct<'src>>,
     functions: HashMap<&'src str, Function<'src>>,
+    interfaces: HashMap<&'src str, Interface<'src>>,
+    // (Struct, Interface) -> Location of the impl
+    impls: HashMap<(&'src str, &'src str), Location>,
+    // Generic functions as written by the user, instantiated at every call with new types
+    generic_functions: HashMap<&'src str, nodes::FunctionNode<'src>>,
+    // Comptime functions only exist in the compiler, so their address can't be taken
+    comptime_functions: HashSet<&'src str>,
+    // Instantiated functions that still need to be type checked
+    instances: Vec<nodes::FunctionNode<'src>>,
+    // (Name, Bound) of the type parameters of the generic function that's currently checked
+    type_parameters: Vec<(&'src str, &'src str)>,
+    // Return types of the closures whose bodies are currently checked, innermost last
+    closure_return_types: Vec<TypeLoc<'src>>,
     known_variables: VecDeque<HashMap<&'src str, Variable<'src>>>,
     unsafe_depth: usize,
     #[cfg(feature = "old_codegen")]
@@ -978,6 +1296,13 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
             struct_indices: HashMap::new(),
             structs: Vec::new(),
             functions: HashMap::new(),
+            interfaces: HashMap::new(),
+            impls: HashMap::new(),
+            generic_functions: HashMap::new(),
+            comptime_functions: HashSet::new(),
+            instances: Vec::new(),
+            type_parameters: Vec::new(),
+            closure_return_types: Vec::new(),
             known_variables,
             unsafe_depth: 0,
             #[cfg(feature = "old_codegen")]
@@ -1071,11 +1396,15 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         let name = struct_node.name;
         let location = struct_node.location;
         let mut strukt = Struct::new(name, location);
+        strukt.is_union = struct_node.is_union;
         for field in &struct_node.fields {
             match strukt.add_field(field) {
                 Ok(()) => (),
                 Err(e) => errors.push(e),
             }
+            if struct_node.layout.packed || field.layout.packed {
+                strukt.packed_fields.insert(field.name);
+            }
         }
         for method in &struct_node.methods {
             match strukt.add_method(method) {
@@ -1097,6 +1426,98 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         errors
     }
 
+    #[trace_call(always)]
+    fn add_interface(&mut self, interface_node: &nodes::InterfaceNode<'src>) -> Vec<TypeError<'src>> {
+        let mut errors = Vec::new();
+        let mut interface = Interface {
+            name: interface_node.name,
+            location: interface_node.location,
+            methods: Vec::new(),
+        };
+        for method in &interface_node.methods {
+            let (parameters, mut param_errors) = check_parameters!(tc, method);
+            errors.append(&mut param_errors);
+            let func = Function {
+                location: method.location,
+                return_type: TypeLoc::new(method.return_type.location, method.return_type.typ.clone()),
+                has_this: parameters.first().is_some_and(|p| p.name == "this"),
+                parameters,
+                is_unsafe: method.is_unsafe,
+                is_vararg: false,
+                is_extern: false,
+                is_used: false,
+            };
+            if let Some((_, m)) = interface.methods.iter().find(|(name, _)| *name == method.name) {
+                errors.push(TypeError::Redeclaration(
+                    "Method",
+                    method.location,
+                    method.name,
+                    m.location,
+                ));
+            } else {
+                interface.methods.push((method.name, func));
+            }
+        }
+        if let Some(i) = self.interfaces.get(interface_node.name) {
+            errors.push(TypeError::Redeclaration(
+                "Interface",
+                interface_node.location,
+                interface_node.name,
+                i.location,
+            ));
+        } else {
+            self.interfaces.insert(interface_node.name, interface);
+        }
+        errors
+    }
+
+    /// Checks that the struct has every method of the interface with the same signature
+    #[trace_call(always)]
+    fn add_impl(&mut self, impl_node: &nodes::ImplNode<'src>) -> Vec<TypeError<'src>> {
+        let mut errors = Vec::new();
+        let Some(interface) = self.interfaces.get(impl_node.interface) else {
+            errors.push(TypeError::UnknownInterface(impl_node.location, impl_node.interface));
+            return errors;
+        };
+        let Some(strukt) = self.get_struct(impl_node.struct_name) else {
+            errors.push(TypeError::UnknownType(impl_node.location, Type::Struct(impl_node.struct_name)));
+            return errors;
+        };
+        // `this` in the interface refers to the implementing struct
+        let bindings = [(interface.name, Type::Struct(strukt.name))];
+        for (name, expected) in &interface.methods {
+            let expected = expected.substitute(&bindings);
+            match strukt.get_method(name) {
+                None => errors.push(TypeError::MissingInterfaceMethod(
+                    impl_node.location,
+                    strukt.name,
+                    name,
+                    interface.name,
+                    expected.location,
+                )),
+                Some(method) if !method.has_same_signature(&expected) => errors.push(TypeError::InterfaceMethodMismatch(
+                    method.location,
+                    name,
+                    interface.name,
+                    expected.location,
+                )),
+                Some(_) => (),
+            }
+        }
+        let key = (impl_node.struct_name, impl_node.interface);
+        if let Some(location) = self.impls.get(&key) {
+            errors.push(TypeError::Redeclaration(
+                "Impl",
+                impl_node.location,
+                impl_node.interface,
+                *location,
+            ));
+        } else {
+            self.impls.insert(key, impl_node.location);
+        }
+        errors
+    }
+
     #[trace_call(always)]
     fn add_function(&mut self, function: &nodes::FunctionNode<'src>) -> Vec<TypeError<'src>> {
         let name = function.name;
@@ -1112,8 +1533,18 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
             is_unsafe: function.is_unsafe,
             is_vararg: false,
             is_extern: false,
-            is_used: function.name == "main" || function.name == "index_oob" || function.name == "setupStdHandles",
+            // Called by the compiler, even if the user never does
+            is_used: matches!(function.name, "main" | "index_oob" | "setupStdHandles" | "defaultPanicHandler" | "panicHandler" | "dumpCoverage"),
         };
+        if name == "panicHandler" {
+            let str_type = Type::Ref(Box::new(Type::Char), false);
+            let valid = func.return_type.t == Type::None
+                && func.parameters.len() == 3
+                && func.parameters.iter().all(|p| p.typ == str_type);
+            if !valid {
+                errors.push(TypeError::InvalidPanicHandler(location));
+            }
+        }
         if self.externs.contains_key(&name) {
             let external = &self.externs[&name];
             errors.push(TypeError::ExternFunction(
@@ -1150,11 +1581,29 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 self.report_error(error);
             }
         }
+        for interface in &project.interfaces {
+            let interface_errors = self.add_interface(interface);
+            for error in interface_errors {
+                self.report_error(error);
+            }
+        }
+        for impl_node in &project.impls {
+            let impl_errors = self.add_impl(impl_node);
+            for error in impl_errors {
+                self.report_error(error);
+            }
+        }
         for func in &project.functions {
             let func_errors = self.add_function(func);
             for error in func_errors {
                 self.report_error(error);
             }
+            if !func.type_parameters.is_empty() {
+                self.generic_functions.insert(func.name, func.clone());
+            }
+            if func.is_comptime {
+                self.comptime_functions.insert(func.name);
+            }
         }
     }
 
@@ -1360,6 +1809,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 }
             }
         }
+        perform_step!(self.resolve_type_aliases(project));
         perform_step!(self.fill_lookup(project));
         perform_step!(self.type_check_file(project));
         perform_step!(self.find_recursive_structs(project));
@@ -1367,10 +1817,109 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         Ok(())
     }
 
+    /// Aliases are transparent, so every use of one is replaced by the type it stands for before anything else is checked
+    #[trace_call(always)]
+    fn resolve_type_aliases(&mut self, project: &mut nodes::FileNode<'src>) {
+        let mut bindings: Vec<(&'src str, Type<'src>)> = Vec::new();
+        let mut locations = Vec::new();
+        for (index, alias) in project.aliases.iter().enumerate() {
+            let previous = project.aliases[..index].iter().map(|a| (a.name, a.location))
+                .chain(project.structs.iter().map(|s| (s.name, s.location)))
+                .find(|(name, _)| *name == alias.name);
+            if let Some((_, location)) = previous {
+                self.report_error(TypeError::Redeclaration("Type", alias.location, alias.name, location));
+            } else {
+                bindings.push((alias.name, alias.typ.typ.clone()));
+                locations.push(alias.location);
+            }
+        }
+        if bindings.is_empty() {
+            return;
+        }
+        let indices: HashMap<&'src str, usize> = bindings.iter().enumerate().map(|(index, (name, _))| (*name, index)).collect();
+        let mut states = vec![AliasState::Unresolved; bindings.len()];
+        for ((name, _), location) in bindings.iter().zip(locations) {
+            if !resolve_alias_type(&mut Type::Struct(name), &bindings, &indices, &mut states) {
+                self.report_error(TypeError::RecursiveTypeAlias(location, name));
+            }
+        }
+        let bindings: Vec<_> = bindings.into_iter().zip(states).map(|((name, _), state)| match state {
+            AliasState::Resolved(typ) => (name, typ),
+            _ => (name, Type::Unknown),
+        }).collect();
+
+        for alias in &mut project.aliases {
+            substitute_type(&mut alias.typ.typ, &bindings);
+        }
+        for global in &mut project.globals {
+            substitute_type(&mut global.typ.typ, &bindings);
+            substitute_expression(&mut global.expression, &bindings);
+        }
+        for assert_node in &mut project.asserts {
+            substitute_expression(&mut assert_node.condition, &bindings);
+        }
+        for extern_node in &mut project.externs {
+            for param in &mut extern_node.parameters {
+                substitute_type(&mut param.typ.typ, &bindings);
+            }
+            substitute_type(&mut extern_node.return_type.typ, &bindings);
+        }
+        for strukt in &mut project.structs {
+            for field in &mut strukt.fields {
+                substitute_type(&mut field.type_def.typ, &bindings);
+                if let Some(default) = &mut field.default {
+                    substitute_expression(default, &bindings);
+                }
+            }
+            for method in &mut strukt.methods {
+                for param in &mut method.parameters {
+                    substitute_type(&mut param.typ.typ, &bindings);
+                }
+                substitute_type(&mut method.return_type.typ, &bindings);
+                // Returns in methods know their struct, so there's nothing to rename
+                substitute_block(&mut method.block, method.name, method.name, &bindings);
+            }
+        }
+        for interface in &mut project.interfaces {
+            for method in &mut interface.methods {
+                for param in &mut method.parameters {
+                    substitute_type(&mut param.typ.typ, &bindings);
+                }
+                substitute_type(&mut method.return_type.typ, &bindings);
+            }
+        }
+        for impl_node in &mut project.impls {
+            if let Some((_, Type::Struct(name))) = bindings.iter().find(|(alias, _)| *alias == impl_node.struct_name) {
+                impl_node.struct_name = name;
+            }
+        }
+        for function in &mut project.functions {
+            // Type parameters shadow aliases of the same name
+            let bindings: Vec<_> = bindings.iter()
+                .filter(|(alias, _)| !function.type_parameters.iter().any(|p| p.name == *alias))
+                .cloned()
+                .collect();
+            substitute_function(function, function.name, &bindings);
+        }
+    }
+
     #[trace_call(always)]
     fn type_check_file(&mut self, module: &mut nodes::FileNode<'src>) {
+        for alias in &mut module.aliases {
+            self.type_check_type_node(&mut alias.typ);
+        }
         for global in &mut module.globals {
             self.type_check_stmt_var_decl(global);
+            if global.is_comptime {
+                continue;
+            }
+            // Globals are initialized before any code runs
+            if let Some(location) = global.expression.find_non_constant() {
+                self.report_error(TypeError::NonConstantGlobal(global.location, global.name, location));
+            }
+        }
+        for assert_node in &mut module.asserts {
+            self.type_check_stmt_comptime_assert(assert_node);
         }
         for extern_node in &mut module.externs {
             self.type_check_extern(extern_node);
@@ -1378,55 +1927,259 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         for s in &mut module.structs {
             self.type_check_struct(s);
         }
+        for i in &mut module.interfaces {
+            self.type_check_interface(i);
+        }
+        // Generic functions are only checked against their bounds, their instances are generated instead
+        let (mut generic_functions, functions) = std::mem::take(&mut module.functions)
+            .into_iter()
+            .partition(|f| !f.type_parameters.is_empty());
+        module.functions = functions;
+        for f in &mut generic_functions {
+            self.type_check_generic_function(f);
+        }
         for f in &mut module.functions {
             self.type_check_function(f);
         }
+        if !self.errors.is_empty() {
+            // Instances would only report the same errors again
+            return;
+        }
+        while let Some(mut instance) = self.instances.pop() {
+            self.type_check_function(&mut instance);
+            module.functions.push(instance);
+        }
     }
 
     #[trace_call(always)]
-    fn type_check_extern(&mut self, extern_node: &mut nodes::ExternNode<'src>) {
-        for p in &mut extern_node.parameters {
-            self.type_check_parameter(p);
-        }
-        self.type_check_type_node(&mut extern_node.return_type);
-        #[cfg(feature = "old_codegen")]
-        {
-            self.current_stack_size = 0;
+    fn type_check_interface(&mut self, interface_node: &mut nodes::InterfaceNode<'src>) {
+        for method in &mut interface_node.methods {
+            for param in &mut method.parameters {
+                // `this` has the type of the interface, which isn't a struct
+                if param.name != "this" {
+                    self.type_check_type_node(&mut param.typ);
+                }
+            }
+            self.type_check_type_node(&mut method.return_type);
         }
     }
 
+    /// Every type parameter becomes a struct that only has the methods of its bound,
+    /// so the body may only use what every implementation provides.
     #[trace_call(always)]
-    fn type_check_struct(&mut self, struct_node: &mut nodes::StructNode<'src>) {
-        for field in &mut struct_node.fields {
-            self.type_check_field(field);
+    fn type_check_generic_function(&mut self, function: &mut nodes::FunctionNode<'src>) {
+        debug_assert!(self.type_parameters.is_empty());
+        for type_parameter in &function.type_parameters {
+            if let Some(strukt) = self.get_struct(type_parameter.name) {
+                self.report_error(TypeError::Redeclaration(
+                    "Type",
+                    type_parameter.location,
+                    type_parameter.name,
+                    strukt.location,
+                ));
+                continue;
+            }
+            let Some(interface) = self.interfaces.get(type_parameter.bound) else {
+                self.report_error(TypeError::UnknownInterface(type_parameter.location, type_parameter.bound));
+                continue;
+            };
+            let bindings = [(interface.name, Type::Struct(type_parameter.name))];
+            let mut strukt = Struct::new(type_parameter.name, type_parameter.location);
+            for (name, method) in &interface.methods {
+                strukt.known_methods.insert(name, method.substitute(&bindings));
+            }
+            self.insert_struct(strukt);
+            self.type_parameters.push((type_parameter.name, type_parameter.bound));
         }
-        for method in &mut struct_node.methods {
-            self.type_check_method(method, &struct_node.name);
+        self.type_check_function(function);
+        while let Some((name, _)) = self.type_parameters.pop() {
+            // Type parameters were the last structs to be inserted
+            debug_assert!(self.struct_indices[name] == self.structs.len() - 1);
+            self.struct_indices.remove(name);
+            self.structs.pop();
         }
     }
 
-    #[trace_call(always)]
-    fn type_check_field(&mut self, field: &mut nodes::FieldNode<'src>) {
-        self.type_check_type_node(&mut field.type_def)
+    #[trace_call(extra)]
+    fn implements(&self, struct_name: &str, interface: &str) -> bool {
+        self.impls.contains_key(&(struct_name, interface))
+            || self.type_parameters.contains(&(struct_name, interface))
     }
 
-    #[trace_call(always)]
-    fn type_check_method(&mut self, method: &mut nodes::MethodNode<'src>, struct_name: &str) {
-        debug_assert!(self.known_variables.len() == 1); // Global variables
-        debug_assert!(self.has_struct(&struct_name));
-        #[cfg(feature = "old_codegen")]
-        debug_assert!(self.current_stack_size == 0);
+    /// `&S` coerces to `&dyn I` and `&mut S` to both `&dyn I` and `&mut dyn I`, if `S` implements `I`.
+    /// `&mut dyn I` also coerces to `&dyn I`.
+    /// The methods of `I` are called through the vtable, so they are marked as used.
+    #[trace_call(extra)]
+    fn can_coerce_to_dyn(&mut self, found: &Type<'src>, expected: &Type<'src>) -> bool {
+        let (struct_name, interface) = match (found, expected) {
+            (Type::Dyn(found_interface, found_mutable), Type::Dyn(interface, expected_mutable)) => {
+                return found_interface == interface && (*found_mutable || !expected_mutable);
+            }
+            (Type::Ref(underlying, found_mutable), Type::Dyn(interface, expected_mutable)) => match underlying.as_ref() {
+                Type::Struct(struct_name) if *found_mutable || !expected_mutable => (*struct_name, *interface),
+                _ => return false,
+            },
+            _ => return false,
+        };
+        if !self.impls.contains_key(&(struct_name, interface)) {
+            return false;
+        }
+        let methods: Vec<_> = self.interfaces[interface].methods.iter().map(|(name, _)| *name).collect();
+        if let Some(strukt) = self.get_struct_mut(struct_name) {
+            for name in methods {
+                if let Some(method) = strukt.get_method_mut(name) {
+                    method.is_used = true;
+                }
+            }
+        }
+        true
+    }
 
-        for param in &mut method.parameters {
-            self.type_check_parameter(param);
+    /// Wraps `expression` in a cast to `expected` if it is a struct reference that coerces to it.
+    #[trace_call(extra)]
+    fn coerce_to_dyn(&mut self, expression: &mut nodes::Expression<'src>, found: &Type<'src>, expected: &Type<'src>) -> bool {
+        if !self.can_coerce_to_dyn(found, expected) {
+            return false;
         }
+        let location = expression.get_loc();
+        let placeholder = nodes::Expression::Literal(nodes::LiteralNode {
+            location,
+            value: "",
+            typ: Type::None,
+        });
+        let inner = std::mem::replace(expression, placeholder);
+        *expression = nodes::Expression::As(Box::new(inner), nodes::TypeNode {
+            location,
+            typ: expected.clone(),
+        });
+        true
+    }
 
-        let Some(struct_info) = self.get_struct(&struct_name) else {
-            // Lookup of structs and methods is done before ever evaluating any methods,
-            // so known_structs should always contain the struct
-            unreachable!()
-        };
-        let Some(method_info) = struct_info.known_methods.get(&method.name) else {
+    /// Infers the type parameters of a generic function from the arguments of `func_call`,
+    /// and returns the name of the instance for those types with the types of the arguments, if they were checked.
+    #[trace_call(always)]
+    fn instantiate_generic_function(&mut self, func_call: &mut nodes::CallNode<'src>) -> Result<(&'src str, Option<Vec<Type<'src>>>), ()> {
+        let generic = self.generic_functions[func_call.function_name].clone();
+        if func_call.arguments.len() != generic.parameters.len() {
+            // Reported when checking the call against the generic function itself
+            return Ok((func_call.function_name, None));
+        }
+        let type_parameters: Vec<_> = generic.type_parameters.iter().map(|t| t.name).collect();
+        let mut bindings = Vec::new();
+        let mut arg_types = Vec::with_capacity(func_call.arguments.len());
+        for (arg, param) in func_call.arguments.iter_mut().zip(&generic.parameters) {
+            let arg_type = self.type_check_expression(arg, MutState::Immut)?;
+            bind_type_parameters(&param.typ.typ, &arg_type, &type_parameters, &mut bindings);
+            arg_types.push(arg_type);
+        }
+        let mut type_names = Vec::new();
+        let mut is_concrete = true;
+        for type_parameter in &generic.type_parameters {
+            let Some((_, typ)) = bindings.iter().find(|(name, _)| *name == type_parameter.name) else {
+                self.report_error(TypeError::CannotInferTypeParameter(
+                    func_call.location,
+                    type_parameter.name,
+                    type_parameter.location,
+                ));
+                return Err(());
+            };
+            match typ {
+                Type::Struct(name) if self.implements(name, type_parameter.bound) => {
+                    is_concrete &= !self.type_parameters.iter().any(|(t, _)| t == name);
+                    type_names.push(*name);
+                }
+                _ => {
+                    self.report_error(TypeError::UnsatisfiedBound(
+                        func_call.location,
+                        typ.clone(),
+                        type_parameter.bound,
+                        type_parameter.location,
+                        type_parameter.name,
+                    ));
+                    return Err(());
+                }
+            }
+        }
+        let name = format!("{}<{}>", generic.name, type_names.join(", "));
+        if let Some((name, _)) = self.functions.get_key_value(name.as_str()) {
+            return Ok((name, Some(arg_types)));
+        }
+        // Function names borrow from the source, instances live until the compiler exits anyway
+        let name: &'src str = Box::leak(name.into_boxed_str());
+        let mut instance = generic;
+        instance.type_parameters.clear();
+        substitute_function(&mut instance, name, &bindings);
+        for error in self.add_function(&instance) {
+            self.report_error(error);
+        }
+        // Calls inside other generic functions only need the signature
+        if is_concrete {
+            self.instances.push(instance);
+        }
+        Ok((name, Some(arg_types)))
+    }
+
+    #[trace_call(always)]
+    fn type_check_extern(&mut self, extern_node: &mut nodes::ExternNode<'src>) {
+        for p in &mut extern_node.parameters {
+            self.type_check_parameter(p);
+        }
+        self.type_check_type_node(&mut extern_node.return_type);
+        #[cfg(feature = "old_codegen")]
+        {
+            self.current_stack_size = 0;
+        }
+    }
+
+    #[trace_call(always)]
+    fn type_check_struct(&mut self, struct_node: &mut nodes::StructNode<'src>) {
+        for field in &mut struct_node.fields {
+            self.type_check_field(field);
+        }
+        let defaults = struct_node.fields.iter()
+            .filter_map(|field| field.default.as_ref().map(|default| (field.name, default.clone())))
+            .collect();
+        if let Some(strukt) = self.get_struct_mut(struct_node.name) {
+            strukt.defaults = defaults;
+            strukt.defaults_checked = true;
+        }
+        for method in &mut struct_node.methods {
+            self.type_check_method(method, &struct_node.name);
+        }
+    }
+
+    #[trace_call(always)]
+    fn type_check_field(&mut self, field: &mut nodes::FieldNode<'src>) {
+        self.type_check_type_node(&mut field.type_def);
+        let Some(default) = &mut field.default else {
+            return;
+        };
+        if self.type_check_field_value(default, &field.type_def.typ).is_err() {
+            return;
+        }
+        // Defaults are copied into every literal that omits them
+        if let Some(location) = default.find_non_constant() {
+            self.report_error(TypeError::NonConstantDefault(field.location, field.name, location));
+        }
+    }
+
+    #[trace_call(always)]
+    fn type_check_method(&mut self, method: &mut nodes::MethodNode<'src>, struct_name: &str) {
+        debug_assert!(self.known_variables.len() == 1); // Global variables
+        debug_assert!(self.has_struct(&struct_name));
+        #[cfg(feature = "old_codegen")]
+        debug_assert!(self.current_stack_size == 0);
+
+        for param in &mut method.parameters {
+            self.type_check_parameter(param);
+        }
+
+        let Some(struct_info) = self.get_struct(&struct_name) else {
+            // Lookup of structs and methods is done before ever evaluating any methods,
+            // so known_structs should always contain the struct
+            unreachable!()
+        };
+        let Some(method_info) = struct_info.known_methods.get(&method.name) else {
             // Lookup of structs and methods is done before ever evaluating any methods,
             // so known_methods should always contain the method
             unreachable!()
@@ -1513,6 +2266,23 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
             nodes::Statement::Expression(expression_node) => {
                 let _ = self.type_check_expression(expression_node, MutState::Immut);
             }
+            nodes::Statement::ComptimeAssert(assert_node) => self.type_check_stmt_comptime_assert(assert_node),
+            nodes::Statement::Destructure(destructure) => self.type_check_stmt_destructure(destructure),
+            nodes::Statement::Defer(defer_node) => self.type_check_statement(&mut defer_node.statement),
+        }
+    }
+
+    #[trace_call(always)]
+    fn type_check_stmt_comptime_assert(&mut self, assert_node: &mut nodes::ComptimeAssertNode<'src>) {
+        let Ok(cond_type) = self.type_check_expression(&mut assert_node.condition, MutState::Immut) else {
+            return;
+        };
+        if cond_type != Type::Bool {
+            self.report_error(TypeError::TypeMismatch(
+                assert_node.condition.get_loc(),
+                Type::Bool,
+                cond_type,
+            ));
         }
     }
 
@@ -1540,6 +2310,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                     location: let_node.location,
                     typ: let_node.typ.typ.clone(),
                     mut_state: MutState::mutable(let_node.is_mutable, let_node.typ.typ.is_mutable_ref()),
+                    is_comptime: let_node.is_comptime,
                 };
 
                 if let_node.is_unsafe {
@@ -1574,7 +2345,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                     // We need to `infer` it
                     let _res = self.type_check_expression_with_type(&mut let_node.expression, &var.typ);
                 } else {
-                    if expr_type != var.typ {
+                    if expr_type != var.typ && !self.coerce_to_dyn(&mut let_node.expression, &expr_type, &var.typ) {
                         self.report_error(TypeError::TypeMismatch(
                             let_node.expression.get_loc(),
                             var.typ.clone(),
@@ -1586,6 +2357,70 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         }
     }
 
+    #[trace_call(always)]
+    fn type_check_stmt_destructure(&mut self, destructure: &mut nodes::DestructureNode<'src>) {
+        let is_annotated = destructure.typ.typ != Type::Unknown;
+        if is_annotated {
+            self.type_check_type_node(&mut destructure.typ);
+        }
+        let typ = match self.type_check_expression(&mut destructure.expression, MutState::Immut) {
+            Ok(_) if is_annotated && destructure.typ.typ == Type::Unknown => Err(()),
+            Ok(Type::Unknown) if is_annotated => {
+                self.type_check_expression_with_type(&mut destructure.expression, &destructure.typ.typ)
+            }
+            Ok(Type::Unknown) => {
+                self.report_error(TypeError::CannotInferDestructure(destructure.expression.get_loc()));
+                Err(())
+            }
+            Ok(typ) if is_annotated && typ != destructure.typ.typ => {
+                self.report_error(TypeError::TypeMismatch(
+                    destructure.expression.get_loc(),
+                    destructure.typ.typ.clone(),
+                    typ,
+                ));
+                Err(())
+            }
+            typ => typ,
+        };
+        // Variables are declared even if the value is broken, so later uses don't report them as undeclared
+        let element_types = match typ {
+            Ok(Type::Tuple(elements)) if elements.len() == destructure.names.len() => elements.into_vec(),
+            Ok(typ) => {
+                self.report_error(TypeError::DestructureMismatch(
+                    destructure.location,
+                    destructure.names.len(),
+                    typ,
+                ));
+                vec![Type::Unknown; destructure.names.len()]
+            }
+            Err(()) => vec![Type::Unknown; destructure.names.len()],
+        };
+        destructure.typ.typ = Type::Tuple(element_types.clone().into());
+        for (&(location, name), typ) in destructure.names.iter().zip(element_types) {
+            // `_` discards the element
+            if name == "_" {
+                continue;
+            }
+            if let Some(var) = self.get_variable_in_current_scope(name) {
+                self.report_error(TypeError::Redeclaration(
+                    "Variable",
+                    location,
+                    name,
+                    var.location,
+                ));
+                continue;
+            }
+            let var = Variable {
+                name,
+                location,
+                mut_state: MutState::mutable(destructure.is_mutable, typ.is_mutable_ref()),
+                typ,
+                is_comptime: false,
+            };
+            self.get_current_scope().insert(name, var);
+        }
+    }
+
     #[trace_call(always)]
     fn type_check_stmt_if(&mut self, if_node: &mut nodes::IfNode<'src>) {
         let Ok(cond_type) = self.type_check_expression(&mut if_node.condition, MutState::Immut) else {
@@ -1608,7 +2443,10 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
     fn type_check_stmt_return(&mut self, return_node: &mut nodes::ReturnNode<'src>) {
         debug_assert!(return_node.typ == Type::Unknown);
 
-        let (expected_return_type, location) = if return_node.strukt.is_none() {
+        let (expected_return_type, location) = if let Some(closure) = self.closure_return_types.last() {
+            // We're returning from a closure
+            (closure.t.clone(), closure.l)
+        } else if return_node.strukt.is_none() {
             // We're returning from a normal function
             let Some(function) = self.get_function(&return_node.function) else {
                 unreachable!()
@@ -1652,7 +2490,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 );
                 // Successfully `inferred` type, we can now proceed as normal
                 expected_return_type
-            } else if expr_type != expected_return_type {
+            } else if expr_type != expected_return_type && !self.coerce_to_dyn(ret_expr, &expr_type, &expected_return_type) {
                 // Signature expects `expected_return_type`, `return {expr}` has other type for expr
                 self.report_error(TypeError::WrongReturnType(
                     return_node.location,
@@ -1730,10 +2568,19 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
             nodes::Expression::ArrayLiteral(literal) => {
                 self.type_check_expr_array_literal(literal, mut_state)
             }
+            nodes::Expression::TupleLiteral(literal) => {
+                self.type_check_expr_tuple_literal(literal, mut_state)
+            }
             nodes::Expression::Sizeof(typ) => {
                 self.type_check_type_node(typ);
                 Ok(Type::Usize)
             }
+            nodes::Expression::Reflection(reflection) => {
+                self.type_check_expr_reflection(reflection, mut_state)
+            }
+            nodes::Expression::Embed(embed) => Ok(embed.get_type()),
+            nodes::Expression::Arithmetic(arithmetic) => self.type_check_expr_arithmetic(arithmetic),
+            nodes::Expression::Lambda(lambda) => self.type_check_expr_lambda(lambda),
             nodes::Expression::As(expr, typ) => {
                 self.type_check_type_node(typ);
                 if typ.typ == Type::Unknown {
@@ -1745,7 +2592,26 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 }
                 let new_type = typ.typ.clone();
                 match (&expr_type, &new_type) {
-                    (Type::Array(_, _), _) | (_, Type::Array(_, _)) | (Type::Struct(_), _) | (_, Type::Struct(_)) => {
+                    (_, Type::Dyn(..)) => {
+                        if expr_type != new_type && !self.can_coerce_to_dyn(&expr_type, &new_type) {
+                            self.report_error(TypeError::TypeMismatch(
+                                _error_loc,
+                                new_type.clone(),
+                                expr_type.clone(),
+                            ));
+                        }
+                    }
+                    (Type::Dyn(..) | Type::Closure(..), _) | (_, Type::Closure(..)) => {
+                        self.report_error(TypeError::NonPrimitiveTypeCast(
+                            _error_loc,
+                            expr.get_loc(),
+                            expr.get_type(),
+                            typ.location,
+                            typ.typ.clone()
+                        ));
+                    }
+                    (Type::Array(_, _), _) | (_, Type::Array(_, _)) | (Type::Struct(_), _) | (_, Type::Struct(_))
+                    | (Type::Tuple(_), _) | (_, Type::Tuple(_)) => {
                         self.report_error(TypeError::NonPrimitiveTypeCast(
                             _error_loc,
                             expr.get_loc(),
@@ -1995,6 +2861,43 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 array_literal.typ = Type::Array(Box::new(*elem_type.clone()), literal_size);
                 Ok(array_literal.typ.clone())
             }
+            nodes::Expression::TupleLiteral(tuple_literal) => {
+                let found = || Type::Tuple(tuple_literal.elements.iter().map(|e| e.get_type()).collect());
+                let Type::Tuple(element_types) = typ else {
+                    let found = found();
+                    self.report_error(TypeError::TypeMismatch(tuple_literal.location, typ.clone(), found));
+                    return Err(());
+                };
+                if element_types.len() != tuple_literal.elements.len() {
+                    let found = found();
+                    self.report_error(TypeError::TypeMismatch(tuple_literal.location, typ.clone(), found));
+                    return Err(());
+                }
+                let mut is_valid = true;
+                for (element, element_type) in tuple_literal.elements.iter_mut().zip(element_types.iter()) {
+                    let found = element.get_type();
+                    if found == Type::Unknown {
+                        is_valid &= self.type_check_expression_with_type(element, element_type).is_ok();
+                    } else if found != *element_type && !self.coerce_to_dyn(element, &found, element_type) {
+                        self.report_error(TypeError::TypeMismatch(element.get_loc(), element_type.clone(), found));
+                        is_valid = false;
+                    }
+                }
+                if !is_valid {
+                    return Err(());
+                }
+                tuple_literal.typ = typ.clone();
+                Ok(typ.clone())
+            }
+            nodes::Expression::Arithmetic(arithmetic) => {
+                // `checked*` always knows its type, so only the others end up here
+                debug_assert!(arithmetic.mode != nodes::ArithmeticMode::Checked);
+                self.type_check_expression_with_type(&mut arithmetic.lhs, typ)?;
+                self.type_check_expression_with_type(&mut arithmetic.rhs, typ)?;
+                self.check_arithmetic_operand_type(arithmetic, typ)?;
+                arithmetic.typ = typ.clone();
+                Ok(typ.clone())
+            }
             e => internal_panic!(
                 "type_check_expression_with_type for {:?} is not implemented yet!",
                 e
@@ -2045,6 +2948,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                     return Err(());
                 }
                 name_node.typ = var.typ.clone();
+                name_node.is_comptime = var.is_comptime;
                 Ok(var.typ)
             }
             e => internal_panic!(
@@ -2075,8 +2979,16 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                     return Err(());
                 }
                 name_node.typ = var.typ.clone();
+                name_node.is_comptime = var.is_comptime;
                 Ok(var.typ)
             }
+            None if self.is_function(name_node.name) || self.is_extern(name_node.name) => {
+                if mut_state != MutState::Immut {
+                    self.report_error(TypeError::CantMutateTemporary(name_node.location));
+                    return Err(());
+                }
+                self.type_check_function_address(name_node)
+            }
             None => {
                 self.report_error(TypeError::UndeclaredVariable(
                     name_node.location,
@@ -2087,6 +2999,174 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         }
     }
 
+    /// The name of a function used as a value is a pointer to that function
+    #[trace_call(always)]
+    fn type_check_function_address(&mut self, name_node: &mut nodes::NameNode<'src>) -> Result<Type<'src>, ()> {
+        let name = name_node.name;
+        let function = match self.get_extern_mut(name) {
+            Some(e) => e,
+            None => self.get_function_mut(name).expect("caller checked that the function exists"),
+        };
+        function.is_used = true;
+        let function = function.clone();
+        let kind = if self.generic_functions.contains_key(name) {
+            Some("generic function")
+        } else if self.comptime_functions.contains(name) {
+            Some("comptime function")
+        } else if function.is_vararg {
+            Some("variadic function")
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
+            self.report_error(TypeError::FunctionAddressNotAllowed(
+                name_node.location,
+                kind,
+                name,
+                function.location,
+            ));
+            return Err(());
+        }
+        name_node.typ = Type::Function(
+            function.parameters.iter().map(|p| p.typ.clone()).collect(),
+            Box::new(function.return_type.t),
+            function.is_unsafe,
+        );
+        Ok(name_node.typ.clone())
+    }
+
+    /// Calls through a value of type `func(...)` or `closure(...)` are checked like calls to a function with that signature
+    #[trace_call(always)]
+    fn type_check_indirect_call(
+        &mut self,
+        func_call: &mut nodes::CallNode<'src>,
+        typ: Type<'src>,
+        location: Location,
+    ) -> Result<Type<'src>, ()> {
+        let (parameters, return_type, is_unsafe, is_closure) = match typ {
+            Type::Function(parameters, return_type, is_unsafe) => (parameters, return_type, is_unsafe, false),
+            Type::Closure(parameters, return_type) => (parameters, return_type, false, true),
+            _ => {
+                self.report_error(TypeError::CallOnNonFunction(
+                    func_call.location,
+                    func_call.function_name,
+                    typ,
+                ));
+                return Err(());
+            }
+        };
+        if is_unsafe && self.unsafe_depth == 0 {
+            self.report_error(TypeError::UnsafeCallInSafeContext(
+                "Function pointer",
+                func_call.location,
+                func_call.function_name,
+                location,
+            ));
+            return Err(());
+        }
+        func_call.is_indirect = true;
+        func_call.is_closure = is_closure;
+        let function = Function {
+            location,
+            return_type: TypeLoc::new(location, *return_type),
+            parameters: parameters.into_vec().into_iter().map(|typ| Variable {
+                name: "_",
+                location,
+                mut_state: MutState::mutable(false, typ.is_mutable_ref()),
+                typ,
+                is_comptime: false,
+            }).collect(),
+            has_this: false,
+            is_unsafe,
+            is_vararg: false,
+            is_extern: false,
+            is_used: true,
+        };
+        check_function!(self, func_call, function, "Function pointer")
+    }
+
+    /// Closures are checked like functions that only see globals, their captures and their parameters
+    #[trace_call(always)]
+    fn type_check_expr_lambda(&mut self, lambda: &mut nodes::LambdaNode<'src>) -> Result<Type<'src>, ()> {
+        let mut scope: HashMap<&'src str, Variable<'src>> = HashMap::new();
+        for capture in &mut lambda.captures {
+            let Some(var) = self.get_variable(capture.name) else {
+                self.report_error(TypeError::UndeclaredVariable(
+                    capture.location,
+                    capture.name,
+                ));
+                continue;
+            };
+            if let Some(other) = scope.get(capture.name) {
+                self.report_error(TypeError::Redeclaration(
+                    "Capture",
+                    capture.location,
+                    capture.name,
+                    other.location,
+                ));
+                continue;
+            }
+            // The capture is still declared, so its uses don't report errors of their own
+            if var.is_comptime {
+                self.report_error(TypeError::ComptimeCapture(
+                    capture.location,
+                    capture.name,
+                    var.location,
+                ));
+            } else if capture.is_mutable && var.mut_state & MutState::MutVar == 0 {
+                self.report_error(TypeError::ImmutableModification(
+                    capture.location,
+                    capture.name,
+                    var.location,
+                ));
+            }
+            capture.typ = var.typ.clone();
+            // Captures by value are copies, so only captures by `&mut` can be assigned to
+            let mut_state = MutState::mutable(capture.is_mutable, var.typ.is_mutable_ref());
+            scope.insert(capture.name, Variable::new(capture.name, capture.location, var.typ, mut_state));
+        }
+
+        for param in &mut lambda.parameters {
+            self.type_check_parameter(param);
+        }
+        let (parameters, errors) = check_parameters!(self, lambda);
+        for error in errors {
+            self.report_error(error);
+        }
+        for param in parameters {
+            if let Some(capture) = scope.get(param.name) {
+                self.report_error(TypeError::Redeclaration(
+                    "Parameter",
+                    param.location,
+                    param.name,
+                    capture.location,
+                ));
+                continue;
+            }
+            scope.insert(param.name, param);
+        }
+        self.type_check_type_node(&mut lambda.return_type);
+
+        // The body can't see the locals of the surrounding function, only the globals
+        let outer_scopes = self.known_variables.split_off(1);
+        self.known_variables.push_back(scope);
+        let outer_unsafe_depth = std::mem::replace(&mut self.unsafe_depth, 0);
+        self.closure_return_types.push(TypeLoc::new(lambda.location, lambda.return_type.typ.clone()));
+
+        self.type_check_block(&mut lambda.block);
+
+        self.closure_return_types.pop();
+        self.unsafe_depth = outer_unsafe_depth;
+        self.known_variables.truncate(1);
+        self.known_variables.extend(outer_scopes);
+
+        lambda.typ = Type::Closure(
+            lambda.parameters.iter().map(|p| p.typ.typ.clone()).collect(),
+            Box::new(lambda.return_type.typ.clone()),
+        );
+        Ok(lambda.typ.clone())
+    }
+
     #[trace_call(always)]
     fn type_check_expr_unary(&mut self, unary_expr: &mut nodes::UnaryNode<'src>, mut_state: MutStateVal) -> Result<Type<'src>, ()> {
         match unary_expr.operation {
@@ -2117,6 +3197,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                     // TODO: Should we allow references to references?
                     self.report_error(TypeError::NestedReferenceNotAllowedYet(unary_expr.location));
                 }
+                self.check_not_packed(&unary_expr.expression, unary_expr.location)?;
                 unary_expr.typ = Type::Ref(Box::new(expr_type.clone()), is_mutable);
                 Ok(unary_expr.typ.clone())
             }
@@ -2200,29 +3281,190 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         }
     }
 
+    /// Operators on structs call specially named methods, `a + b` becomes `a.op_add(b)`.
+    /// `op_cmp` returns a negative number, zero or a positive number and is used for all comparisons,
+    /// unless `op_eq` exists, which takes precedence for `==` and `!=`.
     #[trace_call(always)]
-    fn type_check_expr_binary_logical(&mut self, binary_expr: &mut nodes::BinaryNode<'src>) -> Result<Type<'src>, ()> {
-        assert!(binary_expr.is_logical());
-        let lhs_type = self.type_check_expression(&mut binary_expr.lhs, MutState::Immut)?;
-        let rhs_type = self.type_check_expression(&mut binary_expr.rhs, MutState::Immut)?;
-        if lhs_type != Type::Bool {
-            self.report_error(TypeError::TypeMismatch(
-                binary_expr.lhs.get_loc(),
-                Type::Bool,
-                lhs_type.clone(),
+    fn type_check_expr_operator_method(
+        &mut self,
+        binary_expr: &mut nodes::BinaryNode<'src>,
+        struct_name: &'src str,
+        rhs_type: Type<'src>,
+    ) -> Result<Type<'src>, ()> {
+        let Some(strukt) = self.get_struct(struct_name) else {
+            internal_panic!("Could not find struct {}", struct_name)
+        };
+        let has_eq = strukt.get_method("op_eq").is_some();
+        let method_name = match binary_expr.operation {
+            Operation::Add => "op_add",
+            Operation::Sub => "op_sub",
+            Operation::Mul => "op_mul",
+            Operation::Div => "op_div",
+            Operation::Modulo => "op_mod",
+            Operation::IndexedAccess => "op_index",
+            Operation::Equal | Operation::NotEqual if has_eq => "op_eq",
+            _ => "op_cmp",
+        };
+        let Some(method) = strukt.get_method(method_name) else {
+            self.report_error(TypeError::MissingOperatorMethod(
+                binary_expr.location,
+                binary_expr.operation,
+                struct_name,
+                method_name,
+                strukt.location,
             ));
             return Err(());
+        };
+        let expected_return = match method_name {
+            "op_eq" => Some(Type::Bool),
+            "op_cmp" => Some(Type::I32),
+            _ => None,
+        };
+        if let Some(expected) = expected_return {
+            if method.return_type.t != expected {
+                self.report_error(TypeError::InvalidOperatorMethod(method.location, method_name, expected));
+                return Err(());
+            }
         }
-        if rhs_type != Type::Bool {
-            self.report_error(TypeError::TypeMismatch(
-                binary_expr.rhs.get_loc(),
-                Type::Bool,
-                rhs_type.clone(),
+        let mut method = method.clone();
+        if let Some(strukt) = self.get_struct_mut(struct_name) {
+            let Some(method) = strukt.get_method_mut(method_name) else {
+                unreachable!()
+            };
+            method.is_used = true;
+        }
+        if method.is_unsafe && self.unsafe_depth == 0 {
+            self.report_error(TypeError::UnsafeCallInSafeContext(
+                "Method",
+                binary_expr.location,
+                method_name,
+                method.location,
             ));
             return Err(());
         }
-        binary_expr.typ = Type::Bool;
-        Ok(Type::Bool)
+        if method.has_this {
+            // The operands were checked by the caller, the LHS is passed as `this`
+            match &method.parameters[0].typ {
+                Type::Ref(_, is_mutable) => {
+                    if *is_mutable {
+                        self.check_mutable_place(&binary_expr.lhs)?;
+                    }
+                    self.check_not_packed(&binary_expr.lhs, binary_expr.lhs.get_loc())?;
+                }
+                expected => if *expected != Type::Struct(struct_name) {
+                    self.report_error(TypeError::TypeMismatch(binary_expr.lhs.get_loc(), expected.clone(), Type::Struct(struct_name)));
+                    return Err(());
+                }
+            }
+            method.parameters.remove(0);
+        }
+
+        let location = binary_expr.location;
+        let literal = |value, typ| nodes::Expression::Literal(nodes::LiteralNode { location, value, typ });
+        let lhs = std::mem::replace(&mut binary_expr.lhs, Box::new(literal("", Type::None)));
+        let rhs = std::mem::replace(&mut binary_expr.rhs, Box::new(literal("", Type::None)));
+        let method_call = nodes::BinaryNode {
+            location,
+            operation: Operation::MemberAccess,
+            lhs,
+            rhs: Box::new(nodes::Expression::FunctionCall(nodes::CallNode {
+                location,
+                function_name: method_name,
+                arguments: vec![*rhs],
+                typ: Type::Unknown,
+                is_extern: false,
+                is_indirect: false,
+                is_closure: false,
+            })),
+            typ: Type::Unknown,
+            needs_bounds_check: false,
+            non_constant: OnceCell::new(),
+        };
+        let mut method_call = method_call;
+        let nodes::Expression::FunctionCall(call_node) = method_call.rhs.as_mut() else {
+            unreachable!()
+        };
+        let result = check_function!(self, call_node, method, "Method", Some(vec![rhs_type]))?;
+        method_call.typ = result.clone();
+        match (method_name, binary_expr.operation) {
+            // `a != b` becomes `a.op_eq(b) == false`
+            ("op_eq", Operation::NotEqual) => {
+                binary_expr.operation = Operation::Equal;
+                *binary_expr.lhs = nodes::Expression::Binary(method_call);
+                *binary_expr.rhs = literal("false", Type::Bool);
+                binary_expr.typ = Type::Bool;
+                Ok(Type::Bool)
+            }
+            // `a < b` becomes `a.op_cmp(b) < 0`
+            ("op_cmp", _) => {
+                *binary_expr.lhs = nodes::Expression::Binary(method_call);
+                *binary_expr.rhs = literal("0", Type::I32);
+                binary_expr.typ = Type::Bool;
+                Ok(Type::Bool)
+            }
+            _ => {
+                *binary_expr = method_call;
+                Ok(result)
+            }
+        }
+    }
+
+    /// Whether `expr` may be borrowed mutably, without checking it again
+    #[trace_call(always)]
+    fn check_mutable_place(&mut self, expr: &nodes::Expression<'src>) -> Result<(), ()> {
+        match expr {
+            nodes::Expression::Name(name) => match self.get_variable(name.name) {
+                Some(var) if var.mut_state != MutState::Immut => Ok(()),
+                Some(var) => {
+                    self.report_error(TypeError::ImmutableModification(name.location, name.name, var.location));
+                    Err(())
+                }
+                None => {
+                    self.report_error(TypeError::CantMutateTemporary(name.location));
+                    Err(())
+                }
+            },
+            nodes::Expression::Binary(binary) if matches!(binary.operation, Operation::MemberAccess | Operation::IndexedAccess) => {
+                match binary.lhs.get_type() {
+                    Type::Ref(_, true) => Ok(()),
+                    Type::Ref(_, false) => {
+                        self.report_error(TypeError::CantMutateTemporary(binary.location));
+                        Err(())
+                    }
+                    _ => self.check_mutable_place(&binary.lhs),
+                }
+            }
+            nodes::Expression::Unary(unary) if unary.operation == Operation::Dereference && unary.expression.get_type().is_mutable_ref() => Ok(()),
+            _ => {
+                self.report_error(TypeError::CantMutateTemporary(expr.get_loc()));
+                Err(())
+            }
+        }
+    }
+
+    #[trace_call(always)]
+    fn type_check_expr_binary_logical(&mut self, binary_expr: &mut nodes::BinaryNode<'src>) -> Result<Type<'src>, ()> {
+        assert!(binary_expr.is_logical());
+        let lhs_type = self.type_check_expression(&mut binary_expr.lhs, MutState::Immut)?;
+        let rhs_type = self.type_check_expression(&mut binary_expr.rhs, MutState::Immut)?;
+        if lhs_type != Type::Bool {
+            self.report_error(TypeError::TypeMismatch(
+                binary_expr.lhs.get_loc(),
+                Type::Bool,
+                lhs_type.clone(),
+            ));
+            return Err(());
+        }
+        if rhs_type != Type::Bool {
+            self.report_error(TypeError::TypeMismatch(
+                binary_expr.rhs.get_loc(),
+                Type::Bool,
+                rhs_type.clone(),
+            ));
+            return Err(());
+        }
+        binary_expr.typ = Type::Bool;
+        Ok(Type::Bool)
     }
 
     #[trace_call(always)]
@@ -2231,7 +3473,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         let lhs_type = self.type_check_expression(&mut binary_expr.lhs, MutState::Immut)?;
         let rhs_type = self.type_check_expression(&mut binary_expr.rhs, MutState::Immut)?;
         match (&lhs_type, &rhs_type) {
-            (Type::Struct(..), _) | (_, Type::Struct(..)) => {
+            (Type::Struct(..), _) | (_, Type::Struct(..)) | (Type::Tuple(..), _) | (_, Type::Tuple(..)) => {
                 // NOTE: Modify this once more features (ahem, operator overload) exist
                 self.report_error(TypeError::BinaryTypeMismatch(
                     binary_expr.location,
@@ -2327,6 +3569,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 binary_expr.typ = Type::Usize;
                 Ok(Type::Usize)
             }
+            (Type::Struct(struct_name), _) => self.type_check_expr_operator_method(binary_expr, struct_name, rhs_type.clone()),
             (typ @ Type::Ref(..), other @ Type::Usize) | (typ @ Type::Ref(..), other @ Type::Unknown) => {
                 if *other == Type::Unknown {
                     self.type_check_expression_with_type(&mut binary_expr.rhs, &Type::Usize)?;
@@ -2371,10 +3614,11 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 binary_expr.typ = typ.clone();
                 Ok(typ.clone())
             }
-            (Type::Struct(..), _) | (_, Type::Struct(..))
+            (_, Type::Struct(..))
+            | (Type::Tuple(..), _) | (_, Type::Tuple(..))
             | (Type::Array(..), _) | (_, Type::Array(..))
             | (Type::Bool, _) | (_, Type::Bool) => {
-                // NOTE: Modify this once more features (ahem, operator overload) exist
+                // NOTE: Only the struct on the LHS can overload an operator
                 self.report_error(TypeError::BinaryTypeMismatch(
                     binary_expr.location,
                     binary_expr.operation.clone(),
@@ -2422,9 +3666,11 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         let lhs_type = self.type_check_expression(&mut binary_expr.lhs, MutState::Immut)?;
         let rhs_type = self.type_check_expression(&mut binary_expr.rhs, MutState::Immut)?;
         match (&lhs_type, &rhs_type) {
-            (Type::Struct(..), _) | (_, Type::Struct(..))
+            (Type::Struct(struct_name), _) => self.type_check_expr_operator_method(binary_expr, struct_name, rhs_type.clone()),
+            (_, Type::Struct(..))
+            | (Type::Tuple(..), _) | (_, Type::Tuple(..))
             | (Type::Array(..), _) | (_, Type::Array(..)) => {
-                // NOTE: Modify this once more features (ahem, operator overload) exist
+                // NOTE: Only the struct on the LHS can overload an operator
                 self.report_error(TypeError::BinaryTypeMismatch(
                     binary_expr.location,
                     binary_expr.operation.clone(),
@@ -2480,6 +3726,13 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
             return Err(());
         }
         let lhs_type = self.type_check_expression(&mut assign_expr.lhs, MutState::MutVar)?;
+        if let nodes::Expression::Binary(nodes::BinaryNode { operation: Operation::MemberAccess, rhs, .. }) = assign_expr.lhs.as_ref() {
+            // Method calls, including overloaded `a[i]`, don't return a place we could assign to
+            if matches!(rhs.as_ref(), nodes::Expression::FunctionCall(..)) {
+                self.report_error(TypeError::InvalidLValue(assign_expr.lhs.get_loc()));
+                return Err(());
+            }
+        }
         let rhs_type = self.type_check_expression(&mut assign_expr.rhs, MutState::Immut)?;
         debug_assert!(lhs_type != Type::Unknown);
 
@@ -2488,6 +3741,9 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
             let typ = self.type_check_expression_with_type(&mut assign_expr.rhs, &lhs_type)?;
             assign_expr.typ = typ.clone();
             Ok(typ)
+        } else if self.coerce_to_dyn(&mut assign_expr.rhs, &rhs_type, &lhs_type) {
+            assign_expr.typ = lhs_type.clone();
+            Ok(lhs_type)
         } else if lhs_type != rhs_type {
             self.report_error(TypeError::TypeMismatch(
                 assign_expr.location,
@@ -2501,6 +3757,91 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         }
     }
 
+    /// `tuple.0`, the index has to be an integer literal
+    #[trace_call(always)]
+    fn type_check_expr_tuple_access(
+        &mut self,
+        binary_expr: &mut nodes::BinaryNode<'src>,
+        elements: &[Type<'src>],
+    ) -> Result<Type<'src>, ()> {
+        let nodes::Expression::Literal(index) = binary_expr.rhs.as_mut() else {
+            self.report_error(TypeError::DotOnNonStruct(binary_expr.lhs.get_loc()));
+            return Err(());
+        };
+        let Some(element) = index.value.parse::<usize>().ok().and_then(|i| elements.get(i)) else {
+            self.report_error(TypeError::TupleIndexOutOfBounds(
+                index.location,
+                index.value,
+                Type::Tuple(elements.into()),
+            ));
+            return Err(());
+        };
+        index.typ = Type::Usize;
+        binary_expr.typ = element.clone();
+        Ok(element.clone())
+    }
+
+    /// References assume natural alignment, so they can't point into a packed struct.
+    /// Fields of structs behind a reference are checked when that reference was taken.
+    #[trace_call(always)]
+    fn check_not_packed(&mut self, expr: &nodes::Expression<'src>, location: Location) -> Result<(), ()> {
+        let nodes::Expression::Binary(binary) = expr else {
+            return Ok(());
+        };
+        let lhs_type = binary.lhs.get_type();
+        if lhs_type.is_reference() {
+            return Ok(());
+        }
+        if let (Operation::MemberAccess, Type::Struct(struct_name), nodes::Expression::Name(field)) = (binary.operation, &lhs_type, binary.rhs.as_ref()) {
+            if let Some(strukt) = self.get_struct(struct_name) {
+                if strukt.packed_fields.contains(field.name) {
+                    self.report_error(TypeError::ReferenceToPackedField(
+                        location,
+                        field.name,
+                        strukt.name,
+                        strukt.location,
+                    ));
+                    return Err(());
+                }
+            }
+        }
+        if matches!(binary.operation, Operation::MemberAccess | Operation::IndexedAccess) {
+            self.check_not_packed(&binary.lhs, location)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `f.drop()` frees the environment of a closure, it's the only method closures have
+    #[trace_call(always)]
+    fn type_check_expr_closure_drop(&mut self, binary_expr: &mut nodes::BinaryNode<'src>) -> Result<Type<'src>, ()> {
+        let nodes::Expression::FunctionCall(call_node) = binary_expr.rhs.as_mut() else {
+            self.report_error(TypeError::DotOnNonStruct(binary_expr.lhs.get_loc()));
+            return Err(());
+        };
+        if call_node.function_name != "drop" {
+            self.report_error(TypeError::InvalidMemberAccess(
+                call_node.location,
+                "Calling anything but `drop()` on a closure",
+            ));
+            return Err(());
+        }
+        if !call_node.arguments.is_empty() {
+            self.report_error(TypeError::TooManyArguments(
+                "Method",
+                call_node.location,
+                call_node.function_name,
+                call_node.arguments.len(),
+                binary_expr.lhs.get_loc(),
+                0,
+            ));
+            return Err(());
+        }
+        call_node.typ = Type::None;
+        binary_expr.typ = Type::None;
+        Ok(Type::None)
+    }
+
     #[trace_call(always)]
     fn type_check_expr_member_access(
         &mut self,
@@ -2508,6 +3849,20 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         mut_state: MutStateVal,
     ) -> Result<Type<'src>, ()> {
         let lhs_type = self.type_check_expression(&mut binary_expr.lhs, mut_state)?;
+        if let Type::Dyn(interface, is_mutable) = lhs_type {
+            return self.type_check_expr_dyn_method_call(binary_expr, interface, is_mutable);
+        }
+        if let Type::Tuple(elements) = &lhs_type {
+            return self.type_check_expr_tuple_access(binary_expr, elements);
+        }
+        if let Type::Ref(inner, _) = &lhs_type {
+            if let Type::Tuple(elements) = inner.as_ref() {
+                return self.type_check_expr_tuple_access(binary_expr, elements);
+            }
+        }
+        if let Type::Closure(..) = lhs_type {
+            return self.type_check_expr_closure_drop(binary_expr);
+        }
         let (is_ref, strukt) = match &lhs_type {
             Type::Ref(orig_type, _) => {
                 let Type::Struct(ref struct_name) = **orig_type else {
@@ -2542,6 +3897,11 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         match &mut (*binary_expr.rhs) {
             nodes::Expression::Name(name_node) => {
                 if let Some(field) = strukt.get_field(&name_node.name) {
+                    if strukt.is_union && mut_state == MutState::Immut && self.unsafe_depth == 0 {
+                        // Writing a union field is fine, reading it reinterprets whatever was written last
+                        self.report_error(TypeError::UnsafeUnionRead(name_node.location));
+                        return Err(());
+                    }
                     binary_expr.typ = field.t.clone();
                     name_node.typ = field.t.clone();
                     Ok(field.t.clone())
@@ -2556,6 +3916,15 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 }
             }
             nodes::Expression::FunctionCall(call_node) => {
+                if let (None, Some(field)) = (strukt.get_method(call_node.function_name), strukt.get_field(call_node.function_name)) {
+                    if strukt.is_union && self.unsafe_depth == 0 {
+                        self.report_error(TypeError::UnsafeUnionRead(call_node.location));
+                        return Err(());
+                    }
+                    let result = self.type_check_indirect_call(call_node, field.t.clone(), field.l)?;
+                    binary_expr.typ = result.clone();
+                    return Ok(result);
+                }
                 let Some(method) = strukt.get_method(&call_node.function_name) else {
                     self.report_error(TypeError::UnknownMethod(
                         call_node.location,
@@ -2589,11 +3958,13 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                         let this_arg = if is_ref {
                             *binary_expr.lhs.clone()
                         } else {
+                            self.check_not_packed(&binary_expr.lhs, binary_expr.lhs.get_loc())?;
                             let new_node = nodes::UnaryNode {
                                 location: binary_expr.lhs.get_loc(),
                                 operation: Operation::Reference,
                                 expression: binary_expr.lhs.clone(),
                                 typ: expected_this.clone(),
+                                non_constant: OnceCell::new(),
                             };
                             nodes::Expression::Unary(new_node)
                         };
@@ -2623,6 +3994,65 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         }
     }
 
+    /// Method calls on `&dyn I` are checked against the signature in the interface.
+    #[trace_call(always)]
+    fn type_check_expr_dyn_method_call(
+        &mut self,
+        binary_expr: &mut nodes::BinaryNode<'src>,
+        interface_name: &'src str,
+        is_mutable: bool,
+    ) -> Result<Type<'src>, ()> {
+        let nodes::Expression::FunctionCall(call_node) = &mut (*binary_expr.rhs) else {
+            self.report_error(TypeError::DotOnNonStruct(binary_expr.lhs.get_loc()));
+            return Err(());
+        };
+        let interface = &self.interfaces[interface_name];
+        let Some((_, method)) = interface.methods.iter().find(|(name, _)| *name == call_node.function_name) else {
+            self.report_error(TypeError::UnknownInterfaceMethod(
+                call_node.location,
+                call_node.function_name,
+                interface.location,
+                interface.name,
+            ));
+            return Err(());
+        };
+        let mut method = method.clone();
+        let needs_mutable = match method.parameters.first() {
+            Some(this) if method.has_this && matches!(this.typ, Type::Ref(..)) => this.typ.is_mutable_ref(),
+            _ => {
+                self.report_error(TypeError::DynIncompatibleMethod(
+                    call_node.location,
+                    call_node.function_name,
+                    interface_name,
+                    method.location,
+                ));
+                return Err(());
+            }
+        };
+        if needs_mutable && !is_mutable {
+            self.report_error(TypeError::TypeMismatch(
+                binary_expr.lhs.get_loc(),
+                Type::Dyn(interface_name, true),
+                Type::Dyn(interface_name, false),
+            ));
+            return Err(());
+        }
+        if method.is_unsafe && self.unsafe_depth == 0 {
+            self.report_error(TypeError::UnsafeCallInSafeContext(
+                "Method",
+                call_node.location,
+                call_node.function_name,
+                method.location,
+            ));
+            return Err(());
+        }
+        // The receiver was already checked by the caller and is passed as the data pointer of the fat reference
+        method.parameters.remove(0);
+        let result = check_function!(self, call_node, method, "Method")?;
+        binary_expr.typ = result.clone();
+        Ok(result)
+    }
+
     #[trace_call(always)]
     fn type_check_expr_indexed_access(
         &mut self,
@@ -2633,6 +4063,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         let lhs_type = self.type_check_expression(&mut binary_expr.lhs, mut_state)?;
         let rhs_type = self.type_check_expression(&mut binary_expr.rhs, MutState::Immut)?;
         match (lhs_type, rhs_type) {
+            (Type::Struct(struct_name), rhs_type) => self.type_check_expr_operator_method(binary_expr, struct_name, rhs_type),
             (Type::Unknown, Type::Unknown) => Ok(Type::Unknown),
             (Type::Array(typ, _), Type::Unknown) => {
                 let rhs_typ = self.type_check_expression_with_type(&mut binary_expr.rhs, &Type::Usize)?;
@@ -2710,33 +4141,48 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 continue;
             };
             let field_type = field_info.t.clone();
-            let Ok(expr_type) = self.type_check_expression(&mut field.1, MutState::Immut) else {
+            if self.type_check_field_value(&mut field.1, &field_type).is_err() {
                 continue;
-            };
-            if expr_type == Type::Unknown {
-                // We couldn't determine the type of the expression
-                // We need to `infer` it
-                let t1 = self.type_check_expression_with_type(&mut field.1, &field_type)?;
-                debug_assert!(t1 == field_type);
-            } else if expr_type != field_type {
-                self.report_error(TypeError::TypeMismatch(
-                    field.1.get_loc(),
-                    field_type.clone(),
-                    expr_type,
-                ));
             }
             fields.insert(field.0, field_type);
         }
+        if let Some(base) = &mut literal.base {
+            let expected = Type::Struct(literal.struct_name);
+            let base_type = self.type_check_expression(base, MutState::Immut)?;
+            if base_type != expected {
+                self.report_error(TypeError::TypeMismatch(base.get_loc(), expected, base_type));
+            }
+        }
+        if strukt.is_union {
+            if literal.fields.len() != 1 || literal.base.is_some() {
+                self.report_error(TypeError::InvalidUnionLiteral(literal.location, literal.struct_name, strukt.location));
+            }
+            return Ok(());
+        }
+        if literal.base.is_some() {
+            // All remaining fields are copied from the base
+            return Ok(());
+        }
         let mut errors = vec![];
         for field in strukt.fields.iter() {
-            if !fields.contains_key(field.0) {
+            if fields.contains_key(field.0) {
+                continue;
+            }
+            let Some(default) = strukt.defaults.get(field.0) else {
                 errors.push(TypeError::MissingField(
                     literal.location,
                     field.0,
                     strukt.location,
                     literal.struct_name,
                 ));
+                continue;
+            };
+            let mut default = default.clone();
+            // Literals in global initializers are checked before any struct
+            if !strukt.defaults_checked && self.type_check_field_value(&mut default, &field.1.t).is_err() {
+                continue;
             }
+            literal.fields.push((field.0, default));
         }
         for error in errors {
             self.report_error(error);
@@ -2744,6 +4190,58 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
         Ok(())
     }
 
+    #[trace_call(always)]
+    fn type_check_field_value(
+        &mut self,
+        expression: &mut nodes::Expression<'src>,
+        field_type: &Type<'src>,
+    ) -> Result<(), ()> {
+        let expr_type = self.type_check_expression(expression, MutState::Immut)?;
+        if expr_type == Type::Unknown {
+            // We couldn't determine the type of the expression
+            // We need to `infer` it
+            let t1 = self.type_check_expression_with_type(expression, field_type)?;
+            debug_assert!(t1 == *field_type);
+        } else if expr_type != *field_type && !self.coerce_to_dyn(expression, &expr_type, field_type) {
+            self.report_error(TypeError::TypeMismatch(
+                expression.get_loc(),
+                field_type.clone(),
+                expr_type,
+            ));
+        }
+        Ok(())
+    }
+
+    #[trace_call(always)]
+    fn type_check_expr_tuple_literal(
+        &mut self,
+        literal: &mut nodes::TupleLiteralNode<'src>,
+        mut_state: MutStateVal,
+    ) -> Result<Type<'src>, ()> {
+        if mut_state != MutState::Immut {
+            self.report_error(TypeError::CantMutateTemporary(
+                literal.location
+            ));
+        }
+        let mut element_types = Vec::with_capacity(literal.elements.len());
+        let mut is_valid = true;
+        for element in &mut literal.elements {
+            match self.type_check_expression(element, MutState::Immut) {
+                Ok(typ) => element_types.push(typ),
+                Err(()) => is_valid = false,
+            }
+        }
+        if !is_valid {
+            return Err(());
+        }
+        if element_types.contains(&Type::Unknown) {
+            // Needs to be inferred from the context, like integer literals
+            return Ok(Type::Unknown);
+        }
+        literal.typ = Type::Tuple(element_types.into());
+        Ok(literal.typ.clone())
+    }
+
     #[trace_call(always)]
     fn type_check_expr_array_literal(
         &mut self,
@@ -2780,7 +4278,7 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 } else if typ == Type::Unknown {
                     typ = elem_type;
                     inferred_location = Some(elem.get_loc());
-                } else if elem_type != typ {
+                } else if elem_type != typ && !self.coerce_to_dyn(elem, &elem_type, &typ) {
                     debug_assert!(inferred_location.is_some());
                     self.report_error(TypeError::ArrayLiteralElementTypeMismatch(
                         elem.get_loc(),
@@ -2826,6 +4324,19 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 func_call.location,
             ));
         }
+        let var = self.get_variable(func_call.function_name).filter(|var| {
+            // Variables only shadow functions if they can be called
+            matches!(var.typ, Type::Function(..) | Type::Closure(..)) || !(self.is_function(var.name) || self.is_extern(var.name))
+        });
+        if let Some(var) = var {
+            return self.type_check_indirect_call(func_call, var.typ, var.location);
+        }
+        let mut checked_arguments = None;
+        if self.generic_functions.contains_key(func_call.function_name) {
+            let (name, arg_types) = self.instantiate_generic_function(func_call)?;
+            func_call.function_name = name;
+            checked_arguments = arg_types;
+        }
         if let Some(e) = self.get_extern_mut(&func_call.function_name) {
             e.is_used = true;
         } else if let Some(f) = self.get_function_mut(&func_call.function_name) {
@@ -2865,7 +4376,129 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                 return Err(());
             }
         }
-        check_function!(self, func_call, function, "Function")
+        check_function!(self, func_call, function, "Function", checked_arguments)
+    }
+
+    #[trace_call(always)]
+    fn type_check_expr_arithmetic(&mut self, arithmetic: &mut nodes::ArithmeticNode<'src>) -> Result<Type<'src>, ()> {
+        let lhs_type = self.type_check_expression(&mut arithmetic.lhs, MutState::Immut)?;
+        let rhs_type = self.type_check_expression(&mut arithmetic.rhs, MutState::Immut)?;
+        // `checked*` writes its result through a mutable reference, which also tells us the operand type
+        let expected = match &mut arithmetic.result {
+            Some(result) => match self.type_check_expression(result, MutState::Immut)? {
+                Type::Ref(typ, true) => Some(*typ),
+                found => {
+                    let expected = if lhs_type == Type::Unknown { rhs_type.clone() } else { lhs_type.clone() };
+                    self.report_error(TypeError::TypeMismatch(
+                        result.get_loc(),
+                        Type::Ref(Box::new(expected), true),
+                        found,
+                    ));
+                    return Err(());
+                }
+            },
+            None => None,
+        };
+        let typ = match (&lhs_type, &rhs_type, &expected) {
+            (Type::Unknown, Type::Unknown, None) => return Ok(Type::Unknown),
+            (Type::Unknown, Type::Unknown, Some(typ)) => {
+                self.type_check_expression_with_type(&mut arithmetic.lhs, typ)?;
+                self.type_check_expression_with_type(&mut arithmetic.rhs, typ)?
+            }
+            (Type::Unknown, typ, _) => self.type_check_expression_with_type(&mut arithmetic.lhs, typ)?,
+            (typ, Type::Unknown, _) => self.type_check_expression_with_type(&mut arithmetic.rhs, typ)?,
+            (lhs, rhs, _) => {
+                if lhs != rhs {
+                    self.report_error(TypeError::BinaryTypeMismatch(
+                        arithmetic.location,
+                        arithmetic.operation,
+                        arithmetic.lhs.get_loc(),
+                        lhs.clone(),
+                        arithmetic.rhs.get_loc(),
+                        rhs.clone(),
+                    ));
+                    return Err(());
+                }
+                lhs.clone()
+            }
+        };
+        self.check_arithmetic_operand_type(arithmetic, &typ)?;
+        if let Some(expected) = expected {
+            if expected != typ {
+                self.report_error(TypeError::TypeMismatch(
+                    arithmetic.result.as_ref().unwrap().get_loc(),
+                    Type::Ref(Box::new(typ), true),
+                    Type::Ref(Box::new(expected), true),
+                ));
+                return Err(());
+            }
+            arithmetic.typ = Type::Bool;
+        } else {
+            arithmetic.typ = typ;
+        }
+        Ok(arithmetic.typ.clone())
+    }
+
+    #[trace_call(extra)]
+    fn check_arithmetic_operand_type(&mut self, arithmetic: &nodes::ArithmeticNode<'src>, typ: &Type<'src>) -> Result<(), ()> {
+        if typ.is_integer() && !matches!(typ, Type::Char) {
+            Ok(())
+        } else {
+            self.report_error(TypeError::ArithmeticOnNonInteger(
+                arithmetic.location,
+                arithmetic.name(),
+                typ.clone(),
+            ));
+            Err(())
+        }
+    }
+
+    #[trace_call(always)]
+    fn type_check_expr_reflection(&mut self, reflection: &mut nodes::ReflectionNode<'src>, mut_state: MutStateVal) -> Result<Type<'src>, ()> {
+        self.type_check_type_node(&mut reflection.reflected);
+        if reflection.reflected.typ == Type::Unknown {
+            return Err(());
+        }
+        if !reflection.kind.needs_struct() {
+            return Ok(reflection.kind.get_type());
+        }
+        let Type::Struct(struct_name) = reflection.reflected.typ else {
+            self.report_error(TypeError::ReflectionOnNonStruct(
+                reflection.reflected.location,
+                reflection.kind.name(),
+                reflection.reflected.typ.clone(),
+            ));
+            return Err(());
+        };
+        match &mut reflection.kind {
+            nodes::ReflectionKind::FieldName(index) => {
+                let mut index_type = self.type_check_expression(index, mut_state)?;
+                if index_type == Type::Unknown {
+                    index_type = self.type_check_expression_with_type(index, &Type::Usize)?;
+                }
+                if index_type != Type::Usize {
+                    self.report_error(TypeError::ArrayIndexRequiresUsize(
+                        index.get_loc(),
+                        index_type,
+                    ));
+                    return Err(());
+                }
+            }
+            nodes::ReflectionKind::Offsetof(field) => {
+                let strukt = self.get_struct(struct_name).expect("type_check_type_node checked that the struct exists");
+                if !strukt.fields.contains_key(field.name) {
+                    self.report_error(TypeError::UnknownField(
+                        field.location,
+                        field.name,
+                        strukt.location,
+                        strukt.name,
+                    ));
+                    return Err(());
+                }
+            }
+            _ => {}
+        }
+        Ok(reflection.kind.get_type())
     }
 
     #[trace_call(always)]
@@ -2897,11 +4530,284 @@ impl<'flags, 'src> TypeChecker<'flags, 'src> {
                         return;
                     }
                     *underlying = Type::Struct(name);
+                } else if let Type::Dyn(name, _) = underlying {
+                    if !self.interfaces.contains_key(name) {
+                        self.report_error(TypeError::UnknownInterface(type_node.location, name));
+                        *underlying = Type::Unknown;
+                    }
+                } else if let Type::Function(..) | Type::Closure(..) = underlying {
+                    if !self.type_check_function_type(type_node.location, underlying) {
+                        *underlying = Type::Unknown;
+                    }
+                } else if let Type::Tuple(..) = underlying {
+                    if !self.type_check_tuple_type(type_node.location, underlying) {
+                        *underlying = Type::Unknown;
+                    }
                 }
             },
+            Type::Dyn(name, _) => {
+                if !self.interfaces.contains_key(name) {
+                    self.report_error(TypeError::UnknownInterface(type_node.location, name));
+                    type_node.typ = Type::Unknown;
+                }
+            }
+            Type::Function(..) | Type::Closure(..) => {
+                if !self.type_check_function_type(type_node.location, &mut type_node.typ) {
+                    type_node.typ = Type::Unknown;
+                }
+            }
+            Type::Tuple(..) => {
+                if !self.type_check_tuple_type(type_node.location, &mut type_node.typ) {
+                    type_node.typ = Type::Unknown;
+                }
+            }
             t => {
                 debug_assert!(!t.is_compound(), "{t}");
             }
         }
     }
+
+    /// Checks the parameter and return types of `func(...) -> R` and `closure(...) -> R`, returns false if any of them is unknown
+    #[trace_call(extra)]
+    fn type_check_function_type(&mut self, location: Location, typ: &mut Type<'src>) -> bool {
+        let (Type::Function(parameters, return_type, _) | Type::Closure(parameters, return_type)) = typ else {
+            internal_panic!("Expected function type, got {typ}");
+        };
+        let mut is_valid = true;
+        for typ in parameters.iter_mut().chain(std::iter::once(return_type.as_mut())) {
+            let mut type_node = nodes::TypeNode { location, typ: typ.clone() };
+            self.type_check_type_node(&mut type_node);
+            is_valid &= *type_node.typ.get_underlying_type() != Type::Unknown;
+            *typ = type_node.typ;
+        }
+        is_valid
+    }
+
+    /// Checks the element types of `(A, B)`, returns false if any of them is unknown
+    #[trace_call(extra)]
+    fn type_check_tuple_type(&mut self, location: Location, typ: &mut Type<'src>) -> bool {
+        let Type::Tuple(elements) = typ else {
+            internal_panic!("Expected tuple type, got {typ}");
+        };
+        let mut is_valid = true;
+        for typ in elements.iter_mut() {
+            let mut type_node = nodes::TypeNode { location, typ: typ.clone() };
+            self.type_check_type_node(&mut type_node);
+            is_valid &= *type_node.typ.get_underlying_type() != Type::Unknown;
+            *typ = type_node.typ;
+        }
+        is_valid
+    }
+}
+
+/// Binds type parameters in `param` to the corresponding parts of `arg`, the first binding wins.
+fn bind_type_parameters<'src>(param: &Type<'src>, arg: &Type<'src>, type_parameters: &[&'src str], bindings: &mut Vec<(&'src str, Type<'src>)>) {
+    match (param, arg) {
+        (Type::Struct(name), _) if type_parameters.contains(name) && !bindings.iter().any(|(bound, _)| bound == name) => {
+            bindings.push((name, arg.clone()));
+        }
+        (Type::Ref(param, _), Type::Ref(arg, _))
+        | (Type::Array(param, _), Type::Array(arg, _)) => bind_type_parameters(param, arg, type_parameters, bindings),
+        (Type::Tuple(params), Type::Tuple(args)) if params.len() == args.len() => {
+            for (param, arg) in params.iter().zip(args.iter()) {
+                bind_type_parameters(param, arg, type_parameters, bindings);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn substitute_type<'src>(typ: &mut Type<'src>, bindings: &[(&'src str, Type<'src>)]) {
+    match typ {
+        Type::Struct(name) => {
+            if let Some((_, bound)) = bindings.iter().find(|(param, _)| param == name) {
+                *typ = bound.clone();
+            }
+        }
+        Type::Ref(inner, _) | Type::Array(inner, _) => substitute_type(inner, bindings),
+        Type::Function(parameters, return_type, _) | Type::Closure(parameters, return_type) => {
+            for typ in parameters.iter_mut().chain(std::iter::once(return_type.as_mut())) {
+                substitute_type(typ, bindings);
+            }
+        }
+        Type::Tuple(elements) => elements.iter_mut().for_each(|typ| substitute_type(typ, bindings)),
+        _ => (),
+    }
+}
+
+#[derive(Clone)]
+enum AliasState<'src> {
+    Unresolved,
+    Resolving,
+    Resolved(Type<'src>),
+    Recursive,
+}
+
+/// Replaces the aliases in `typ` with the types they stand for, every alias is only resolved once.
+/// Returns false if `typ` refers to an alias that refers to itself.
+fn resolve_alias_type<'src>(
+    typ: &mut Type<'src>,
+    aliases: &[(&'src str, Type<'src>)],
+    indices: &HashMap<&'src str, usize>,
+    states: &mut [AliasState<'src>],
+) -> bool {
+    match typ {
+        Type::Struct(name) => {
+            let Some(&index) = indices.get(name) else {
+                return true;
+            };
+            match &states[index] {
+                AliasState::Resolved(resolved) => {
+                    *typ = resolved.clone();
+                    true
+                }
+                AliasState::Resolving | AliasState::Recursive => false,
+                AliasState::Unresolved => {
+                    states[index] = AliasState::Resolving;
+                    let mut resolved = aliases[index].1.clone();
+                    if resolve_alias_type(&mut resolved, aliases, indices, states) {
+                        *typ = resolved.clone();
+                        states[index] = AliasState::Resolved(resolved);
+                        true
+                    } else {
+                        states[index] = AliasState::Recursive;
+                        false
+                    }
+                }
+            }
+        }
+        Type::Ref(inner, _) | Type::Array(inner, _) => resolve_alias_type(inner, aliases, indices, states),
+        Type::Function(parameters, return_type, _) | Type::Closure(parameters, return_type) => {
+            parameters.iter_mut().chain(std::iter::once(return_type.as_mut()))
+                .all(|typ| resolve_alias_type(typ, aliases, indices, states))
+        }
+        Type::Tuple(elements) => elements.iter_mut().all(|typ| resolve_alias_type(typ, aliases, indices, states)),
+        _ => true,
+    }
+}
+
+/// Turns a copy of a generic function into the instance `name`.
+fn substitute_function<'src>(function: &mut nodes::FunctionNode<'src>, name: &'src str, bindings: &[(&'src str, Type<'src>)]) {
+    let generic_name = function.name;
+    function.name = name;
+    for param in &mut function.parameters {
+        substitute_type(&mut param.typ.typ, bindings);
+    }
+    substitute_type(&mut function.return_type.typ, bindings);
+    substitute_block(&mut function.block, generic_name, name, bindings);
+}
+
+fn substitute_block<'src>(block: &mut nodes::BlockNode<'src>, generic_name: &str, name: &'src str, bindings: &[(&'src str, Type<'src>)]) {
+    for statement in &mut block.statements {
+        substitute_statement(statement, generic_name, name, bindings);
+    }
+}
+
+fn substitute_statement<'src>(statement: &mut nodes::Statement<'src>, generic_name: &str, name: &'src str, bindings: &[(&'src str, Type<'src>)]) {
+    match statement {
+        nodes::Statement::Block(block) => substitute_block(block, generic_name, name, bindings),
+        nodes::Statement::Expression(expression) => substitute_expression(expression, bindings),
+        nodes::Statement::VarDecl(var_decl) => {
+            substitute_type(&mut var_decl.typ.typ, bindings);
+            substitute_expression(&mut var_decl.expression, bindings);
+        }
+        nodes::Statement::If(if_node) => {
+            substitute_expression(&mut if_node.condition, bindings);
+            substitute_block(&mut if_node.if_body, generic_name, name, bindings);
+            if let Some(else_body) = &mut if_node.else_body {
+                substitute_block(else_body, generic_name, name, bindings);
+            }
+        }
+        nodes::Statement::Return(return_node) => {
+            // Returns look up the return type of their function by name
+            if return_node.strukt.is_none() && return_node.function == generic_name {
+                return_node.function = name;
+            }
+            if let Some(value) = &mut return_node.return_value {
+                substitute_expression(value, bindings);
+            }
+        }
+        nodes::Statement::While(while_node) => {
+            substitute_expression(&mut while_node.condition, bindings);
+            if let Some(step) = &mut while_node.step {
+                substitute_expression(step, bindings);
+            }
+            substitute_block(&mut while_node.body, generic_name, name, bindings);
+        }
+        nodes::Statement::ComptimeAssert(assert_node) => substitute_expression(&mut assert_node.condition, bindings),
+        nodes::Statement::Destructure(destructure) => {
+            substitute_type(&mut destructure.typ.typ, bindings);
+            substitute_expression(&mut destructure.expression, bindings);
+        }
+        nodes::Statement::Defer(defer_node) => substitute_statement(&mut defer_node.statement, generic_name, name, bindings),
+        nodes::Statement::Break(_) | nodes::Statement::Continue(_) => (),
+    }
+}
+
+fn substitute_expression<'src>(expression: &mut nodes::Expression<'src>, bindings: &[(&'src str, Type<'src>)]) {
+    match expression {
+        nodes::Expression::Name(_)
+        | nodes::Expression::Literal(_)
+        | nodes::Expression::Embed(_) => (),
+        nodes::Expression::StructLiteral(literal) => {
+            let mut typ = Type::Struct(literal.struct_name);
+            substitute_type(&mut typ, bindings);
+            if let Type::Struct(struct_name) = typ {
+                literal.struct_name = struct_name;
+            }
+            for (_, field) in &mut literal.fields {
+                substitute_expression(field, bindings);
+            }
+            if let Some(base) = &mut literal.base {
+                substitute_expression(base, bindings);
+            }
+        }
+        nodes::Expression::ArrayLiteral(literal) => {
+            for element in &mut literal.elements {
+                substitute_expression(element, bindings);
+            }
+        }
+        nodes::Expression::TupleLiteral(literal) => {
+            for element in &mut literal.elements {
+                substitute_expression(element, bindings);
+            }
+        }
+        nodes::Expression::Unary(unary) => substitute_expression(&mut unary.expression, bindings),
+        nodes::Expression::Binary(binary) => {
+            substitute_expression(&mut binary.lhs, bindings);
+            substitute_expression(&mut binary.rhs, bindings);
+        }
+        nodes::Expression::FunctionCall(call) => {
+            for argument in &mut call.arguments {
+                substitute_expression(argument, bindings);
+            }
+        }
+        nodes::Expression::Sizeof(type_node) => substitute_type(&mut type_node.typ, bindings),
+        nodes::Expression::Reflection(reflection) => {
+            substitute_type(&mut reflection.reflected.typ, bindings);
+            if let nodes::ReflectionKind::FieldName(index) = &mut reflection.kind {
+                substitute_expression(index, bindings);
+            }
+        }
+        nodes::Expression::Arithmetic(arithmetic) => {
+            substitute_expression(&mut arithmetic.lhs, bindings);
+            substitute_expression(&mut arithmetic.rhs, bindings);
+            if let Some(result) = &mut arithmetic.result {
+                substitute_expression(result, bindings);
+            }
+        }
+        nodes::Expression::As(expression, type_node) => {
+            substitute_expression(expression, bindings);
+            substitute_type(&mut type_node.typ, bindings);
+        }
+        nodes::Expression::Lambda(lambda) => {
+            for param in &mut lambda.parameters {
+                substitute_type(&mut param.typ.typ, bindings);
+            }
+            substitute_type(&mut lambda.return_type.typ, bindings);
+            substitute_type(&mut lambda.typ, bindings);
+            // Returns inside of the body belong to the closure, so there's nothing to rename
+            substitute_block(&mut lambda.block, KEYWORD_CLOSURE, KEYWORD_CLOSURE, bindings);
+        }
+    }
 }
@@ -1,13 +1,18 @@
 // TODO: Write C compliant Parser
-// TODO: Check out clang's AST output
 // NOTE: Tools such as rs-bindgen parse the output of clang's AST
-use std::collections::{HashMap, VecDeque};
+//       Using `--bind-clang` or `--bind-json`, we do the same instead of lexing the header ourselves
+use std::collections::{HashMap, HashSet, VecDeque};
 
+use regex::Regex;
+use serde_json::Value;
 use tracer::trace_call;
 
 use super::flags::Flags;
-use crate::{compiler::{FILE_EXT, WARN_STR}, frontend::nodes::CompilerFlag, internal_panic};
-use crate::frontend::tokens;
+use crate::{compiler::{ERR_STR, FILE_EXT, WARN_STR}, frontend::nodes::CompilerFlag, internal_panic};
+use crate::frontend::tokens::{self, Location, TokenType};
+
+// Names of Bufo's builtin types, a typedef with one of these names can't be declared as a `type` alias
+const BUILTIN_TYPES: [&str; 14] = ["i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "usize", "bool", "char", "Any", "f32", "f64"];
 
 #[allow(unused)]
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,6 +24,8 @@ enum CType {
     Const(Box<CType>),
     Double,
     Float,
+    // Pointer to a function, return type and parameter types
+    Function(Box<CType>, Vec<CType>),
     Int,
     Long,
     Pointer(Box<CType>),
@@ -39,19 +46,14 @@ enum CNode<'src> {
         name: String,
         values: Vec<CNode<'src>>,
     },
-    EnumVariant {
+    Constant {
         name: String,
-        value: Option<Box<CNode<'src>>>,
+        value: CConst,
     },
     Field {
         name: String,
         ty: CType,
     },
-    FuncPtr {
-        name: String,
-        ret: Box<CNode<'src>>,
-        args: Vec<CNode<'src>>,
-    },
     FuncDecl {
         name: String,
         ret: Box<CNode<'src>>,
@@ -60,7 +62,10 @@ enum CNode<'src> {
     },
     Number(String),
     Type(CType),
-    TypeAlias {},
+    TypeAlias {
+        name: String,
+        ty: CType,
+    },
     Typedef {
         name: String,
         ty: Box<CNode<'src>>,
@@ -68,6 +73,12 @@ enum CNode<'src> {
     Struct {
         name: String,
         fields: Vec<CNode<'src>>,
+        packed: bool,
+    },
+    Union {
+        name: String,
+        fields: Vec<CNode<'src>>,
+        packed: bool,
     },
 }
 
@@ -93,6 +104,7 @@ enum CToken {
     OpenCurly,
     OpenRound,
     OpenSquare,
+    Operator(String),
     Semicolon,
     Short,
     Signed,
@@ -103,12 +115,257 @@ enum CToken {
     Void,
 }
 
-fn filter_string(s: &str) -> &str {
-    if s == "type" {
-        "typ"
+// Values of `#define`s and enum constants
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CConst {
+    Int(i128, CType),
+    // Contents of the literal without the quotes, escape sequences are kept as is
+    Char(String),
+    Str(String),
+}
+
+fn int_info(ty: &CType) -> Option<(bool, u32)> {
+    let (signed, ty) = match ty {
+        CType::Signed(ty) => (true, ty),
+        CType::Unsigned(ty) => (false, ty),
+        _ => return None,
+    };
+    match **ty {
+        CType::Byte => Some((signed, 8)),
+        CType::Short => Some((signed, 16)),
+        CType::Int => Some((signed, 32)),
+        CType::Long => Some((signed, 64)),
+        _ => None,
+    }
+}
+
+fn int_type(signed: bool, bits: u32) -> CType {
+    let ty = match bits {
+        8 => CType::Byte,
+        16 => CType::Short,
+        32 => CType::Int,
+        _ => CType::Long,
+    };
+    if signed {
+        CType::Signed(Box::new(ty))
+    } else {
+        CType::Unsigned(Box::new(ty))
+    }
+}
+
+// Truncates or sign-extends the value like C would when storing it in the given type
+fn wrap_int(value: i128, ty: &CType) -> i128 {
+    let Some((signed, bits)) = int_info(ty) else {
+        internal_panic!("Expected integer type, found {:?}", ty);
+    };
+    let modulo = 1i128 << bits;
+    let value = value.rem_euclid(modulo);
+    if signed && value >= modulo / 2 {
+        value - modulo
+    } else {
+        value
+    }
+}
+
+// The smallest of `int`, `unsigned int`, `long`, `unsigned long` that can hold the value
+fn fitting_int_type(value: i128) -> Option<CType> {
+    [(true, 32), (false, 32), (true, 64), (false, 64)]
+        .into_iter()
+        .map(|(signed, bits)| int_type(signed, bits))
+        .find(|ty| wrap_int(value, ty) == value)
+}
+
+// Integer promotion and the usual arithmetic conversions of C
+fn promote_int(ty: &CType) -> CType {
+    match int_info(ty) {
+        Some((signed, 64)) => int_type(signed, 64),
+        Some((false, 32)) => int_type(false, 32),
+        _ => int_type(true, 32),
+    }
+}
+
+fn common_int_type(lhs: &CType, rhs: &CType) -> CType {
+    let (lhs_signed, lhs_bits) = int_info(&promote_int(lhs)).unwrap();
+    let (rhs_signed, rhs_bits) = int_info(&promote_int(rhs)).unwrap();
+    if lhs_signed == rhs_signed {
+        int_type(lhs_signed, lhs_bits.max(rhs_bits))
+    } else {
+        let (signed_bits, unsigned_bits) = if lhs_signed { (lhs_bits, rhs_bits) } else { (rhs_bits, lhs_bits) };
+        if unsigned_bits >= signed_bits {
+            int_type(false, unsigned_bits)
+        } else {
+            int_type(true, signed_bits)
+        }
+    }
+}
+
+fn parse_int_literal(literal: &str) -> Result<CConst, String> {
+    let lower = literal.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(['u', 'l']);
+    let suffix = &lower[digits.len()..];
+    let (radix, digits) = if let Some(hex) = digits.strip_prefix("0x") {
+        (16, hex)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        (2, bin)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
     } else {
-        s
+        (10, digits)
+    };
+    let value = i128::from_str_radix(digits, radix)
+        .map_err(|_| format!("`{}` is not an integer literal", literal))?;
+    let candidates: &[(bool, u32)] = match (suffix.contains('u'), suffix.contains('l'), radix == 10) {
+        (false, false, true) => &[(true, 32), (true, 64)],
+        (false, false, false) => &[(true, 32), (false, 32), (true, 64), (false, 64)],
+        (true, false, _) => &[(false, 32), (false, 64)],
+        (false, true, true) => &[(true, 64)],
+        (false, true, false) => &[(true, 64), (false, 64)],
+        (true, true, _) => &[(false, 64)],
+    };
+    candidates
+        .iter()
+        .map(|(signed, bits)| int_type(*signed, *bits))
+        .find(|ty| wrap_int(value, ty) == value)
+        .map(|ty| CConst::Int(value, ty))
+        .ok_or_else(|| format!("Integer literal `{}` is too big", literal))
+}
+
+fn char_literal_value(literal: &str) -> Result<i128, String> {
+    let chars = literal.chars().collect::<Vec<_>>();
+    let value = match chars.as_slice() {
+        [c] => *c as i128,
+        ['\\', 'n'] => '\n' as i128,
+        ['\\', 'r'] => '\r' as i128,
+        ['\\', 't'] => '\t' as i128,
+        ['\\', 'a'] => 7,
+        ['\\', 'b'] => 8,
+        ['\\', 'f'] => 12,
+        ['\\', 'v'] => 11,
+        ['\\', c @ ('\\' | '\'' | '"' | '?')] => *c as i128,
+        ['\\', 'x', hex @ ..] => i128::from_str_radix(&hex.iter().collect::<String>(), 16)
+            .map_err(|_| format!("Invalid character literal `'{}'`", literal))?,
+        ['\\', oct @ ..] => i128::from_str_radix(&oct.iter().collect::<String>(), 8)
+            .map_err(|_| format!("Invalid character literal `'{}'`", literal))?,
+        _ => return Err(format!("Invalid character literal `'{}'`", literal)),
+    };
+    if !(0..=255).contains(&value) {
+        return Err(format!("Character literal `'{}'` does not fit into a char", literal));
+    }
+    Ok(value)
+}
+
+fn tokenize_const_expr(expr: &str) -> Result<Vec<String>, String> {
+    let chars = expr.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let start = index;
+        if c.is_whitespace() {
+            index += 1;
+            continue;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            while index < chars.len() && (chars[index].is_ascii_alphanumeric() || chars[index] == '_' || chars[index] == '.') {
+                index += 1;
+            }
+        } else if c == '\'' || c == '"' {
+            index += 1;
+            while index < chars.len() && chars[index] != c {
+                if chars[index] == '\\' {
+                    index += 1;
+                }
+                index += 1;
+            }
+            if index >= chars.len() {
+                return Err(format!("Unterminated literal in `{}`", expr));
+            }
+            index += 1;
+        } else {
+            let two = chars[index..(index + 2).min(chars.len())].iter().collect::<String>();
+            index += if ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||"].contains(&two.as_str()) { 2 } else { 1 };
+        }
+        tokens.push(chars[start..index].iter().collect());
+    }
+    Ok(tokens)
+}
+
+fn binary_precedence(op: &str) -> Option<u8> {
+    match op {
+        "*" | "/" | "%" => Some(10),
+        "+" | "-" => Some(9),
+        "<<" | ">>" => Some(8),
+        "<" | "<=" | ">" | ">=" => Some(7),
+        "==" | "!=" => Some(6),
+        "&" => Some(5),
+        "^" => Some(4),
+        "|" => Some(3),
+        "&&" => Some(2),
+        "||" => Some(1),
+        _ => None,
+    }
+}
+
+// Removes comments, but keeps newlines so `#define`s stay on their own line
+fn strip_c_comments(source: &str) -> String {
+    let chars = source.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(source.len());
+    let mut index = 0;
+    let mut quote = None;
+    while index < chars.len() {
+        let c = chars[index];
+        let next = chars.get(index + 1).copied();
+        match quote {
+            Some(q) => {
+                result.push(c);
+                if c == '\\' {
+                    if let Some(n) = next {
+                        result.push(n);
+                    }
+                    index += 1;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '/' && next == Some('/') => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+                continue;
+            }
+            None if c == '/' && next == Some('*') => {
+                index += 2;
+                while index < chars.len() && !(chars[index] == '*' && chars.get(index + 1) == Some(&'/')) {
+                    if chars[index] == '\n' {
+                        result.push('\n');
+                    }
+                    index += 1;
+                }
+                index += 2;
+                result.push(' ');
+                continue;
+            }
+            None => {
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                result.push(c);
+            }
+        }
+        index += 1;
     }
+    result
+}
+
+// Declarations collected while walking clang's JSON AST
+#[derive(Default)]
+struct ClangDecls<'src> {
+    nodes: Vec<CNode<'src>>,
+    // Records from included headers, only emitted if the header itself references them
+    included: Vec<(String, CNode<'src>)>,
+    // Unnamed records and enums, keyed by their clang id, waiting for a typedef to name them
+    unnamed: HashMap<String, CNode<'src>>,
+    defined: HashSet<String>,
+    functions: HashSet<String>,
 }
 
 #[allow(unused)]
@@ -120,11 +377,19 @@ pub struct Bindgen<'flags> {
     lookahead: VecDeque<CToken>,
     known_types: Vec<String>,
     aliases: HashMap<String, CType>,
+    // Aliases that are emitted as `type` declarations, all other aliases are inlined
+    type_aliases: HashSet<String>,
+    // clang's JSON only mentions the file of a location if it changed, so we need to keep track of it
+    clang_in_include: bool,
+    // Enum constants and evaluated `#define`s, so later constants can refer to them
+    constants: HashMap<String, CConst>,
+    // Bodies of object-like macros, evaluated lazily because macros may refer to macros defined after them
+    macros: HashMap<String, String>,
     #[allow(unused)]
     flags: &'flags Flags,
 }
 
-impl<'flags, 'src> Bindgen<'flags> {
+impl<'flags: 'src, 'src> Bindgen<'flags> {
     pub fn new(flags: &'flags Flags) -> Bindgen<'flags> {
         Bindgen {
             input_path: String::new(),
@@ -133,7 +398,11 @@ impl<'flags, 'src> Bindgen<'flags> {
             current_char: 0,
             lookahead: VecDeque::new(),
             aliases: HashMap::new(),
+            type_aliases: HashSet::new(),
             known_types: vec!["__builtin_va_list".to_string()],
+            clang_in_include: false,
+            constants: HashMap::new(),
+            macros: HashMap::new(),
             flags,
         }.filepath(&flags.gen_bind.as_ref().unwrap())
     }
@@ -144,7 +413,10 @@ impl<'flags, 'src> Bindgen<'flags> {
         Bindgen {
             source,
             input_path: filepath.to_string(),
-            output_path: filepath.replace(".h", &(".".to_string() + FILE_EXT)),
+            output_path: match &self.flags.bind_output {
+                Some(output) => output.clone(),
+                None => filepath.replace(".h", &(".".to_string() + FILE_EXT)),
+            },
             ..self
         }
     }
@@ -221,15 +493,9 @@ impl<'flags, 'src> Bindgen<'flags> {
                 }
             }
             '0'..='9' => {
-                let num = fill_buffer!(c, |c: char| !c.is_numeric() || c == '\0');
-                if num == "0" && self.current_char() == 'x' {
-                    let mut num = num;
-                    num.push(self.next_char());
-                    let num = fill_buffer!(num, |c: char| !c.is_numeric() || c == '\0');
-                    CToken::Number(num)
-                } else {
-                    CToken::Number(num)
-                }
+                // Includes hex digits and suffixes like `0xFFu`, they're handled when evaluating constants
+                let num = fill_buffer!(c, |c: char| !c.is_alphanumeric() || c == '\0');
+                CToken::Number(num)
             }
             '\0' => CToken::EOF,
             '(' => CToken::OpenRound,
@@ -243,10 +509,30 @@ impl<'flags, 'src> Bindgen<'flags> {
             '*' => CToken::Asterisk,
             '=' => CToken::Equals,
             '#' => {
-                let _ = fill_buffer!(c, |c: char| { c == '\r' || c == '\n' || c == '\0' });
+                // Preprocessor directives are handled by `parse_defines`, so we skip them including line continuations
+                loop {
+                    let line = fill_buffer!(c, |c: char| { c == '\r' || c == '\n' || c == '\0' });
+                    self.current_char += 1;
+                    if !line.trim_end().ends_with('\\') {
+                        break;
+                    }
+                }
+                return self.next_token();
+            }
+            '/' if self.current_char() == '/' => {
+                let _ = fill_buffer!(c, |c: char| { c == '\n' || c == '\0' });
+                return self.next_token();
+            }
+            '/' if self.current_char() == '*' => {
+                while !self.lexed_eof() && !(self.next_char() == '*' && !self.lexed_eof() && self.current_char() == '/') {}
                 self.current_char += 1;
                 return self.next_token();
             }
+            '<' | '>' if self.current_char() == c => {
+                self.current_char += 1;
+                CToken::Operator(format!("{}{}", c, c))
+            }
+            '|' | '&' | '^' | '~' | '!' | '+' | '-' | '/' | '%' | '<' | '>' => CToken::Operator(c.to_string()),
             '.' => {
                 let v = fill_buffer!(c, |c: char| c != '.');
                 if v == "..." {
@@ -328,13 +614,44 @@ impl<'flags, 'src> Bindgen<'flags> {
             println!("[INFO] Running bindgen");
         }
         let now = std::time::Instant::now();
-        let c_ast = self.parse_header()?;
+        let defines = self.collect_defines();
+        let c_ast = if let Some(json_path) = &self.flags.bind_json {
+            let json = std::fs::read_to_string(json_path)
+                .map_err(|e| format!("{}: Could not read clang AST `{}`: {}", ERR_STR, json_path, e))?;
+            self.parse_clang_ast(&json)?
+        } else if self.flags.bind_clang {
+            let json = self.dump_clang_ast()?;
+            self.parse_clang_ast(&json)?
+        } else {
+            self.parse_header()?
+        };
         if self.flags.verbose {
             println!("[INFO] Parsing header took {:?}", now.elapsed());
         }
-        
+
+        let now = std::time::Instant::now();
+        let CNode::AST(mut nodes) = c_ast else {
+            internal_panic!("Expected bindgen frontend to return CNode::AST, found {:?}", c_ast);
+        };
+        nodes.extend(self.eval_defines(defines));
+        let mut nodes = self.filter_and_sort(nodes);
+        for node in &nodes {
+            if let CNode::TypeAlias { name, .. } | CNode::Enum { name, .. } = node {
+                if !name.is_empty() {
+                    self.type_aliases.insert(name.clone());
+                }
+            }
+        }
+        let flags = self.flags_from_cli();
+        if !matches!(&flags, CNode::Flags(f) if f.is_empty()) {
+            nodes.insert(0, flags);
+        }
+        if self.flags.verbose {
+            println!("[INFO] Filtering declarations took {:?}", now.elapsed());
+        }
+
         let now = std::time::Instant::now();
-        let mut genned = self.ast_to_string(&c_ast);
+        let mut genned = self.ast_to_string(&CNode::AST(nodes));
         genned = "/* Auto-generated by Bufo's bindgen */\n".to_string() + &genned;
         if self.flags.verbose {
             println!("[INFO] Generating code took {:?}", now.elapsed());
@@ -351,16 +668,25 @@ impl<'flags, 'src> Bindgen<'flags> {
     fn type_to_string(&self, typ: &CType) -> String {
         match typ {
             CType::Struct(name) => {
-                if self.aliases.contains_key(name) {
+                if self.type_aliases.contains(name) {
+                    self.rename(name)
+                } else if self.aliases.contains_key(name) {
                     self.type_to_string(self.aliases.get(name).unwrap())
                 } else {
-                    format!("{}", name)
+                    self.rename(name)
                 }
             },
             CType::Byte => format!("8"),
             CType::Char => format!("char"),
             CType::Double => format!("f64"),
             CType::Float => format!("f32"),
+            CType::Function(ret, params) => {
+                let params = params.iter().map(|p| self.type_to_string(p)).collect::<Vec<_>>().join(", ");
+                match **ret {
+                    CType::Void => format!("func({})", params),
+                    _ => format!("func({}) -> {}", params, self.type_to_string(ret)),
+                }
+            },
             CType::Int => format!("32"),
             CType::Long => format!("64"),
             CType::Short => format!("16"),
@@ -407,26 +733,32 @@ impl<'flags, 'src> Bindgen<'flags> {
                     result += &format!( "{}\n", self.ast_to_string(node));
                 }
             }
-            CNode::Enum { name, .. } => {
-                result += &format!( "/* TODO: Codegen Enum */ struct {} {{}}", name);
-                // result += &format!( "enum {} {{\n", name);
-                // for value in values {
-                //     result += &format!( "    {},\n", value);
-                // }
-                // write!(f, "}}")
+            CNode::Enum { name, values } => {
+                // Bufo doesn't have enums, their constants become comptime values and the type is an alias for i32
+                result += &values.iter().map(|v| self.ast_to_string(v)).collect::<Vec<_>>().join("\n");
+                if self.type_aliases.contains(name) {
+                    result += &format!("\ntype {} = i32;", self.rename(name));
+                }
             }
-            CNode::EnumVariant { name, value } => {
-                if let Some(value) = value {
-                    result += &format!("{} = {}", name, self.ast_to_string(value))
-                } else {
-                    result += &format!("{}", name)
+            CNode::Constant { name, value } => {
+                let name = self.rename(name);
+                match value {
+                    CConst::Int(value, ty) => {
+                        result += &format!("comptime {}: {} = {};", name, self.type_to_string(ty), value)
+                    }
+                    CConst::Char(c) if c.len() == 1 || matches!(c.as_str(), "\\\\" | "\\0" | "\\r" | "\\n" | "\\t" | "\\'" | "\\\"") => {
+                        result += &format!("comptime {}: char = '{}';", name, c)
+                    }
+                    CConst::Char(c) => {
+                        let value = char_literal_value(c)
+                            .unwrap_or_else(|e| internal_panic!("Character constant `{}` wasn't validated: {}", name, e));
+                        result += &format!("comptime {}: u8 = {};", name, value)
+                    }
+                    CConst::Str(s) => result += &format!("comptime {}: &char = \"{}\";", name, s),
                 }
             }
             CNode::Field { name, ty } => {
-                result += &format!("{}: {}", filter_string(&name), self.type_to_string(ty))
-            }
-            CNode::FuncPtr { name, .. } => {
-                result += &format!("/* TODO: Codegen FuncPtr */ struct {} {{}}", name)
+                result += &format!("{}: {}", self.rename(name), self.type_to_string(ty))
             }
             CNode::FuncDecl { name, ret, args, vararg } => {
                 let mut args = args.iter().map(|a| format!("{}", self.ast_to_string(a))).collect::<Vec<String>>().join(", ");
@@ -434,9 +766,9 @@ impl<'flags, 'src> Bindgen<'flags> {
                     args.push_str(", ...");
                 }
                 if let CNode::Type(CType::Void) = ret.as_ref() {
-                    result += &format!("unsafe extern {}({});", filter_string(name), args)
+                    result += &format!("unsafe extern {}({});", name, args)
                 } else {
-                    result += &format!("unsafe extern {}({}) -> {};", filter_string(name), args, self.ast_to_string(ret))
+                    result += &format!("unsafe extern {}({}) -> {};", name, args, self.ast_to_string(ret))
                 }
             }
             CNode::Number(n) => {
@@ -450,14 +782,16 @@ impl<'flags, 'src> Bindgen<'flags> {
             CNode::Type(ty) => {
                 result += &format!("{}", self.type_to_string(ty))
             },
-            CNode::TypeAlias { .. } => {
-                // When binding we replace the alias with the original type
+            CNode::TypeAlias { name, ty } => {
+                result += &format!("type {} = {};", self.rename(name), self.type_to_string(ty))
             },
             CNode::Typedef { ty, .. } => {
                 result += &format!("{}", self.ast_to_string(ty))
             },
-            CNode::Struct { name, fields } => {
-                result += &format!( "struct {} {{\n", filter_string(name));
+            CNode::Struct { name, fields, packed } | CNode::Union { name, fields, packed } => {
+                let keyword = if matches!(ast, CNode::Union { .. }) { "union" } else { "struct" };
+                let packed = if *packed { "packed " } else { "" };
+                result += &format!("{}{} {} {{\n", packed, keyword, self.rename(name));
                 for field in fields {
                     result += &format!( "    {};\n", self.ast_to_string(field));
                 }
@@ -467,6 +801,762 @@ impl<'flags, 'src> Bindgen<'flags> {
         result
     }
 
+    #[trace_call(always)]
+    fn dump_clang_ast(&self) -> Result<String, String> {
+        if self.flags.verbose {
+            println!("[INFO] Running `clang -Xclang -ast-dump=json -fsyntax-only {}`", self.input_path);
+        }
+        let output = std::process::Command::new("clang")
+            .args(["-Xclang", "-ast-dump=json", "-fsyntax-only"])
+            .arg(&self.input_path)
+            .output()
+            .map_err(|e| format!("{}: Could not run clang: {}", ERR_STR, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{}: clang failed to parse `{}`:\n{}",
+                ERR_STR,
+                self.input_path,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        String::from_utf8(output.stdout).map_err(|e| format!("{}: clang returned invalid UTF-8: {}", ERR_STR, e))
+    }
+
+    #[trace_call(always)]
+    fn parse_clang_ast(&mut self, json: &str) -> Result<CNode<'src>, String> {
+        let root: Value = serde_json::from_str(json)
+            .map_err(|e| format!("{}: Could not parse clang AST: {}", ERR_STR, e))?;
+        if root["kind"] != "TranslationUnitDecl" {
+            return Err(format!("{}: Expected `TranslationUnitDecl` at the root of the clang AST, found {}", ERR_STR, root["kind"]));
+        }
+        let mut decls = ClangDecls::default();
+        self.clang_inner_decls(&root, &mut decls)?;
+
+        // Pull in every record of an included header that is (transitively) referenced by the header itself
+        let mut ast = decls.nodes;
+        let mut referenced = Vec::new();
+        for node in &ast {
+            self.collect_referenced_structs(node, &mut referenced);
+        }
+        while let Some(name) = referenced.pop() {
+            if let Some(index) = decls.included.iter().position(|(n, _)| *n == name) {
+                let (_, node) = decls.included.remove(index);
+                decls.defined.insert(name);
+                self.collect_referenced_structs(&node, &mut referenced);
+                ast.push(node);
+            } else if !decls.defined.contains(&name) && !self.aliases.contains_key(&name) {
+                // Opaque struct, we only ever see it behind a pointer
+                decls.defined.insert(name.clone());
+                ast.push(CNode::Struct { name, fields: Vec::new(), packed: false });
+            }
+        }
+        Ok(CNode::AST(ast))
+    }
+
+    #[trace_call(extra)]
+    fn clang_inner_decls(&mut self, parent: &Value, decls: &mut ClangDecls<'src>) -> Result<(), String> {
+        let Some(inner) = parent["inner"].as_array() else {
+            return Ok(());
+        };
+        for decl in inner {
+            self.clang_track_location(&decl["loc"]);
+            let from_include = self.clang_in_include;
+            if decl["kind"] == "LinkageSpecDecl" {
+                self.clang_inner_decls(decl, decls)?;
+                continue;
+            }
+            self.clang_track_node(decl);
+            if decl["isImplicit"] == true {
+                continue;
+            }
+            if let Err(e) = self.clang_decl(decl, from_include, decls) {
+                if !from_include {
+                    println!("{}: {}. The declaration will be ignored.", WARN_STR, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[trace_call(extra)]
+    fn clang_track_location(&mut self, loc: &Value) {
+        if let Some(spelling) = loc.get("spellingLoc") {
+            self.clang_track_location(spelling);
+        }
+        if let Some(expansion) = loc.get("expansionLoc") {
+            self.clang_track_location(expansion);
+        }
+        if loc.get("file").is_some() {
+            self.clang_in_include = loc.get("includedFrom").is_some();
+        }
+    }
+
+    #[trace_call(extra)]
+    fn clang_track_node(&mut self, node: &Value) {
+        self.clang_track_location(&node["range"]["begin"]);
+        self.clang_track_location(&node["range"]["end"]);
+        if let Some(inner) = node["inner"].as_array() {
+            for child in inner {
+                self.clang_track_location(&child["loc"]);
+                self.clang_track_node(child);
+            }
+        }
+    }
+
+    #[trace_call(extra)]
+    fn clang_decl(&mut self, decl: &Value, from_include: bool, decls: &mut ClangDecls<'src>) -> Result<(), String> {
+        let name = decl["name"].as_str().unwrap_or("").to_string();
+        match decl["kind"].as_str().unwrap_or("") {
+            "RecordDecl" => {
+                let node = self.clang_record(decl, decls)?;
+                if name.is_empty() {
+                    decls.unnamed.insert(decl["id"].as_str().unwrap_or("").to_string(), node);
+                } else if decl["completeDefinition"] == true && !decls.defined.contains(&name) {
+                    if from_include {
+                        decls.included.push((name.clone(), node));
+                    } else {
+                        decls.defined.insert(name.clone());
+                        decls.nodes.push(node);
+                    }
+                }
+                if !name.is_empty() && !self.known_types.contains(&name) {
+                    self.known_types.push(name);
+                }
+            }
+            "EnumDecl" => {
+                let node = self.clang_enum(decl, &name)?;
+                if name.is_empty() {
+                    decls.unnamed.insert(decl["id"].as_str().unwrap_or("").to_string(), node.clone());
+                }
+                if !from_include {
+                    decls.nodes.push(node);
+                }
+            }
+            "TypedefDecl" => {
+                if let Some(id) = Self::clang_find_decl_id(decl) {
+                    if let Some(unnamed) = decls.unnamed.remove(&id) {
+                        // `typedef struct { ... } name;` gives the struct its name
+                        let node = match unnamed {
+                            CNode::Struct { fields, packed, .. } => CNode::Struct { name: name.clone(), fields, packed },
+                            CNode::Union { fields, packed, .. } => CNode::Union { name: name.clone(), fields, packed },
+                            CNode::Enum { values, .. } => {
+                                // The unnamed enum was already emitted for its constants, the typedef replaces it
+                                let same = |v: &Vec<CNode>| v.iter().map(Self::node_name).eq(values.iter().map(Self::node_name));
+                                decls.nodes.retain(|n| !matches!(n, CNode::Enum { name, values: v } if name.is_empty() && same(v)));
+                                self.aliases.insert(name.clone(), CType::Signed(Box::new(CType::Int)));
+                                CNode::Enum { name: name.clone(), values }
+                            }
+                            n => n,
+                        };
+                        if !self.known_types.contains(&name) {
+                            self.known_types.push(name.clone());
+                        }
+                        if from_include {
+                            decls.included.push((name.clone(), node));
+                        } else {
+                            decls.defined.insert(name);
+                            decls.nodes.push(node);
+                        }
+                        return Ok(());
+                    }
+                }
+                let ty = self.clang_type(&decl["type"])
+                    .map_err(|e| format!("Typedef `{}`: {}", name, e))?;
+                if !self.known_types.contains(&name) {
+                    self.known_types.push(name.clone());
+                }
+                if ty == CType::Struct(name.clone()) || self.aliases.contains_key(&name) {
+                    // `typedef struct foo foo;` would otherwise alias itself
+                    return Ok(());
+                }
+                self.aliases.insert(name.clone(), ty.clone());
+                if from_include {
+                    decls.included.push((name.clone(), CNode::TypeAlias { name, ty }));
+                } else {
+                    decls.nodes.push(CNode::TypeAlias { name, ty });
+                }
+            }
+            "FunctionDecl" => {
+                if from_include || decl["storageClass"] == "static" || decls.functions.contains(&name) {
+                    return Ok(());
+                }
+                let node = self.clang_function(decl, &name)?;
+                decls.functions.insert(name);
+                decls.nodes.push(node);
+            }
+            "VarDecl" => {
+                return Err(format!("Global variables (here: `{}`) are not supported yet", name));
+            }
+            kind => {
+                if self.flags.debug {
+                    println!("[DEBUG] Skipping clang declaration of kind `{}`", kind);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[trace_call(extra)]
+    fn clang_find_decl_id(node: &Value) -> Option<String> {
+        if let Some(id) = node["decl"]["id"].as_str() {
+            return Some(id.to_string());
+        }
+        node["inner"].as_array()?.iter().find_map(Self::clang_find_decl_id)
+    }
+
+    #[trace_call(always)]
+    fn clang_record(&mut self, decl: &Value, decls: &mut ClangDecls<'src>) -> Result<CNode<'src>, String> {
+        let name = decl["name"].as_str().unwrap_or("").to_string();
+        let is_union = decl["tagUsed"] == "union";
+        // `__attribute__((packed))` shows up as an attribute node inside the record
+        let packed = decl["inner"].as_array().is_some_and(|inner| inner.iter().any(|m| m["kind"] == "PackedAttr"));
+        let mut fields = Vec::new();
+        // Nested unnamed records are declared right before the field that uses them
+        let mut last_unnamed = None;
+        // Bitfields are merged into storage units of their declared type: (type, bits used)
+        let mut bitfield: Option<(CType, u64)> = None;
+        let empty = Vec::new();
+        for (index, member) in decl["inner"].as_array().unwrap_or(&empty).iter().enumerate() {
+            match member["kind"].as_str().unwrap_or("") {
+                "RecordDecl" | "EnumDecl" if member["name"].as_str().unwrap_or("").is_empty() => {
+                    last_unnamed = Some(if member["kind"] == "EnumDecl" {
+                        CNode::Type(CType::Signed(Box::new(CType::Int)))
+                    } else {
+                        self.clang_record(member, decls)?
+                    });
+                }
+                "RecordDecl" => {
+                    self.clang_decl(member, false, decls)?;
+                }
+                "FieldDecl" => {
+                    let field_name = match member["name"].as_str() {
+                        Some(n) if !n.is_empty() => n.to_string(),
+                        _ => format!("__anon{}", index),
+                    };
+                    let qual_type = member["type"]["qualType"].as_str().unwrap_or("");
+                    let ty = if qual_type.contains("(unnamed") || qual_type.contains("(anonymous") {
+                        match last_unnamed.take() {
+                            Some(CNode::Type(ty)) => ty,
+                            Some(CNode::Struct { fields: inner, packed, .. }) | Some(CNode::Union { fields: inner, packed, .. }) => {
+                                let nested_name = format!("{}_{}", name, field_name);
+                                let node = if qual_type.starts_with("union") {
+                                    CNode::Union { name: nested_name.clone(), fields: inner, packed }
+                                } else {
+                                    CNode::Struct { name: nested_name.clone(), fields: inner, packed }
+                                };
+                                self.known_types.push(nested_name.clone());
+                                decls.defined.insert(nested_name.clone());
+                                decls.nodes.push(node);
+                                CType::Struct(nested_name)
+                            }
+                            _ => return Err(format!("Field `{}` of unknown unnamed type in `{}`", field_name, name)),
+                        }
+                    } else {
+                        self.clang_type(&member["type"])
+                            .map_err(|e| format!("Field `{}` in `{}`: {}", field_name, name, e))?
+                    };
+                    if member["isBitfield"] == true {
+                        if bitfield.is_none() {
+                            println!("{}: Bitfields (here: `{}`) are not supported yet and will be merged into fields of their underlying type", WARN_STR, field_name);
+                        }
+                        let width = Self::clang_find_value(member)
+                            .and_then(|v| v.parse::<u64>().ok())
+                            .ok_or_else(|| format!("Bitfield `{}` in `{}` without a width", field_name, name))?;
+                        let size = self.ctype_bits(&ty);
+                        match &mut bitfield {
+                            Some((unit, used)) if *unit == ty && *used + width <= size => *used += width,
+                            _ => {
+                                if width != 0 {
+                                    fields.push(CNode::Field { name: format!("__bitfield{}", index), ty: ty.clone() });
+                                }
+                                bitfield = Some((ty, width));
+                            }
+                        }
+                        continue;
+                    }
+                    bitfield = None;
+                    fields.push(CNode::Field { name: field_name, ty });
+                }
+                _ => {}
+            }
+        }
+        if is_union {
+            Ok(CNode::Union { name, fields, packed })
+        } else {
+            Ok(CNode::Struct { name, fields, packed })
+        }
+    }
+
+    #[trace_call(extra)]
+    fn clang_find_value(node: &Value) -> Option<String> {
+        if let Some(value) = node["value"].as_str() {
+            return Some(value.to_string());
+        }
+        node["inner"].as_array()?.iter().find_map(Self::clang_find_value)
+    }
+
+    #[trace_call(always)]
+    fn clang_enum(&mut self, decl: &Value, name: &str) -> Result<CNode<'src>, String> {
+        let mut values = Vec::new();
+        let mut next_value: i128 = 0;
+        let empty = Vec::new();
+        for constant in decl["inner"].as_array().unwrap_or(&empty) {
+            if constant["kind"] != "EnumConstantDecl" {
+                continue;
+            }
+            if let Some(value) = Self::clang_find_value(constant) {
+                next_value = value.parse()
+                    .map_err(|_| format!("Enum constant `{}` with non-integer value `{}`", constant["name"], value))?;
+            }
+            let name = constant["name"].as_str().unwrap_or("").to_string();
+            values.push(self.enum_constant(name, next_value)?);
+            next_value += 1;
+        }
+        if !name.is_empty() {
+            self.aliases.insert(name.to_string(), CType::Signed(Box::new(CType::Int)));
+            if !self.known_types.contains(&name.to_string()) {
+                self.known_types.push(name.to_string());
+            }
+        }
+        Ok(CNode::Enum { name: name.to_string(), values })
+    }
+
+    // Enum constants are `int` in C, but compilers widen them if the value doesn't fit
+    #[trace_call(extra)]
+    fn enum_constant(&mut self, name: String, value: i128) -> Result<CNode<'src>, String> {
+        let Some(ty) = fitting_int_type(value) else {
+            return Err(format!("Enum constant `{}` with value {} doesn't fit into 64 bits", name, value));
+        };
+        let value = CConst::Int(value, ty);
+        self.constants.insert(name.clone(), value.clone());
+        Ok(CNode::Constant { name, value })
+    }
+
+    #[trace_call(always)]
+    fn clang_function(&mut self, decl: &Value, name: &str) -> Result<CNode<'src>, String> {
+        let qual_type = decl["type"]["qualType"].as_str().unwrap_or("");
+        let ret = match Self::split_function_type(qual_type) {
+            Some(ret) => self.parse_qual_type(ret),
+            None => Err(format!("Invalid function type `{}`", qual_type)),
+        }.map_err(|e| format!("Function `{}`: {}", name, e))?;
+        let mut args = Vec::new();
+        let empty = Vec::new();
+        for (index, param) in decl["inner"].as_array().unwrap_or(&empty).iter().enumerate() {
+            if param["kind"] != "ParmVarDecl" {
+                continue;
+            }
+            let param_name = match param["name"].as_str() {
+                Some(n) if !n.is_empty() => n.to_string(),
+                _ => format!("arg{}", index),
+            };
+            let ty = self.clang_type(&param["type"])
+                .map_err(|e| format!("Function `{}`: {}", name, e))?;
+            args.push(CNode::Field { name: param_name, ty });
+        }
+        Ok(CNode::FuncDecl {
+            name: name.to_string(),
+            ret: Box::new(CNode::Type(ret)),
+            args,
+            vararg: decl["variadic"] == true,
+        })
+    }
+
+    // `int (const char *, ...)` -> `int`
+    fn split_function_type(qual_type: &str) -> Option<&str> {
+        let qual_type = qual_type.trim();
+        if !qual_type.ends_with(')') {
+            return None;
+        }
+        let mut depth = 0;
+        for (index, c) in qual_type.char_indices().rev() {
+            match c {
+                ')' => depth += 1,
+                '(' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(qual_type[..index].trim());
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    #[trace_call(extra)]
+    fn clang_type(&self, ty: &Value) -> Result<CType, String> {
+        let qual_type = ty["qualType"].as_str().unwrap_or("");
+        match self.parse_qual_type(qual_type) {
+            Ok(t) => Ok(t),
+            Err(e) => match ty["desugaredQualType"].as_str() {
+                // Typedefs we don't know about (e.g. from ignored system headers) are resolved by clang
+                Some(desugared) => self.parse_qual_type(desugared),
+                None => Err(e),
+            },
+        }
+    }
+
+    #[trace_call(extra)]
+    fn parse_qual_type(&self, qual_type: &str) -> Result<CType, String> {
+        let qual_type = qual_type.trim();
+        // `int (*)(const char *, int)`, parameters that are function pointers themselves become `Any`
+        if let Some(start) = qual_type.find("(*)") {
+            let params = qual_type[start + 3..].trim().strip_prefix('(').and_then(|p| p.strip_suffix(')'));
+            if let Some(params) = params.filter(|p| !p.contains("...") && !p.contains('(')) {
+                let ret = self.parse_qual_type(&qual_type[..start])?;
+                let params = match params.trim() {
+                    "" | "void" => Vec::new(),
+                    params => params.split(',').map(|p| self.parse_qual_type(p)).collect::<Result<Vec<_>, _>>()?,
+                };
+                return Ok(CType::Function(Box::new(ret), params));
+            }
+        }
+        if qual_type.contains("(*") || qual_type.contains("(^") {
+            // Function pointers and pointers to arrays
+            return Ok(CType::Pointer(Box::new(CType::Void)));
+        }
+        if qual_type.ends_with(']') {
+            let Some(start) = qual_type.find('[') else {
+                return Err(format!("Invalid array type `{}`", qual_type));
+            };
+            let mut ty = self.parse_qual_type(&qual_type[..start])?;
+            let sizes = qual_type[start + 1..qual_type.len() - 1].split("][").collect::<Vec<_>>();
+            for size in sizes.iter().rev() {
+                let size = size.trim();
+                ty = if size.is_empty() {
+                    CType::Pointer(Box::new(ty))
+                } else if size.chars().all(|c| c.is_ascii_digit()) {
+                    CType::Array(Box::new(ty), CToken::Number(size.to_string()))
+                } else {
+                    return Err(format!("Unsupported array size `{}`", size));
+                };
+            }
+            return Ok(ty);
+        }
+        let words = qual_type
+            .split(|c: char| c.is_whitespace() || c == '*')
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>();
+        if let Some(star) = qual_type.rfind('*') {
+            let trailing = &qual_type[star + 1..];
+            if trailing.split_whitespace().all(|w| ["const", "volatile", "restrict", "__restrict"].contains(&w)) {
+                let ty = self.parse_qual_type(&qual_type[..star])?;
+                return Ok(CType::Pointer(Box::new(ty)));
+            }
+        }
+        let is_const = words.contains(&"const");
+        let words = words
+            .into_iter()
+            .filter(|w| !["const", "volatile", "restrict", "__restrict"].contains(w))
+            .collect::<Vec<_>>();
+        let signed = !words.contains(&"unsigned");
+        let sized = |ty: CType| if signed {
+            CType::Signed(Box::new(ty))
+        } else {
+            CType::Unsigned(Box::new(ty))
+        };
+        let ty = match words.as_slice() {
+            ["void"] => CType::Void,
+            ["_Bool"] | ["bool"] => CType::Bool,
+            ["char"] => CType::Char,
+            ["signed", "char"] | ["unsigned", "char"] => sized(CType::Byte),
+            ["float"] => CType::Float,
+            ["double"] => CType::Double,
+            ["struct" | "union", name] => CType::Struct(name.to_string()),
+            ["enum", _] => CType::Signed(Box::new(CType::Int)),
+            ["__builtin_va_list"] | ["va_list"] => CType::Pointer(Box::new(CType::Void)),
+            [name] if self.aliases.contains_key(*name) || self.known_types.contains(&name.to_string()) => {
+                CType::Struct(name.to_string())
+            }
+            w if w.contains(&"short") => sized(CType::Short),
+            w if w.contains(&"long") && !w.contains(&"double") => sized(CType::Long),
+            w if !w.is_empty() && w.iter().all(|w| ["int", "signed", "unsigned"].contains(w)) => sized(CType::Int),
+            _ => return Err(format!("Unsupported type `{}`", qual_type)),
+        };
+        if is_const {
+            Ok(CType::Const(Box::new(ty)))
+        } else {
+            Ok(ty)
+        }
+    }
+
+    // Collects object-like macros from the header, so enums can refer to them. They're evaluated by `eval_defines`.
+    // clang's AST doesn't contain macros, so both frontends scan the header source for them.
+    #[trace_call(always)]
+    fn collect_defines(&mut self) -> Vec<String> {
+        let source = strip_c_comments(&self.source.iter().collect::<String>()).replace("\\\r\n", " ").replace("\\\n", " ");
+        let mut names = Vec::new();
+        for line in source.lines() {
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                continue;
+            };
+            let Some(define) = directive.trim_start().strip_prefix("define") else {
+                continue;
+            };
+            if !define.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let define = define.trim_start();
+            let name_len = define.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(define.len());
+            let (name, body) = define.split_at(name_len);
+            if body.starts_with('(') {
+                println!("{}: Function-like macros (here: `{}`) are not supported and will be ignored", WARN_STR, name);
+                continue;
+            }
+            let body = body.trim();
+            if name.is_empty() || body.is_empty() || TokenType::try_from_keyword(body).is_some() {
+                // Include guards, feature flags and the like
+                continue;
+            }
+            self.macros.insert(name.to_string(), body.to_string());
+            names.push(name.to_string());
+        }
+        names
+    }
+
+    #[trace_call(always)]
+    fn eval_defines(&mut self, names: Vec<String>) -> Vec<CNode<'src>> {
+        let mut constants = Vec::new();
+        for name in names {
+            match self.resolve_constant(&name) {
+                Ok(Some(value)) => constants.push(CNode::Constant { name, value }),
+                Ok(None) => {}
+                Err(e) => println!("{}: Could not evaluate macro `{}`: {}. The macro will be ignored", WARN_STR, name, e),
+            }
+        }
+        constants
+    }
+
+    // Looks up an enum constant or evaluates a macro the first time it's used
+    #[trace_call(extra)]
+    fn resolve_constant(&mut self, name: &str) -> Result<Option<CConst>, String> {
+        if let Some(value) = self.constants.get(name) {
+            return Ok(Some(value.clone()));
+        }
+        // Removing the body while evaluating it catches macros referring to themselves
+        let Some(body) = self.macros.remove(name) else {
+            return Ok(None);
+        };
+        let value = self.eval_const_expr(&body)?;
+        self.constants.insert(name.to_string(), value.clone());
+        Ok(Some(value))
+    }
+
+    #[trace_call(extra)]
+    fn eval_const_expr(&mut self, expr: &str) -> Result<CConst, String> {
+        let tokens = tokenize_const_expr(expr)?;
+        let mut index = 0;
+        let value = self.eval_binary(&tokens, &mut index, 0)?;
+        if let Some(token) = tokens.get(index) {
+            return Err(format!("Unexpected `{}` in constant expression `{}`", token, expr));
+        }
+        Ok(value)
+    }
+
+    #[trace_call(extra)]
+    fn eval_binary(&mut self, tokens: &[String], index: &mut usize, min_precedence: u8) -> Result<CConst, String> {
+        let mut lhs = self.eval_unary(tokens, index)?;
+        while let Some(precedence) = tokens.get(*index).and_then(|op| binary_precedence(op)) {
+            if precedence <= min_precedence {
+                break;
+            }
+            let op = tokens[*index].clone();
+            *index += 1;
+            let rhs = self.eval_binary(tokens, index, precedence)?;
+            lhs = Self::eval_binary_op(&op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    #[trace_call(extra)]
+    fn eval_unary(&mut self, tokens: &[String], index: &mut usize) -> Result<CConst, String> {
+        let Some(token) = tokens.get(*index) else {
+            return Err("Unexpected end of constant expression".to_string());
+        };
+        *index += 1;
+        match token.as_str() {
+            op @ ("-" | "+" | "~" | "!") => {
+                let (value, ty) = Self::const_as_int(self.eval_unary(tokens, index)?)?;
+                let ty = promote_int(&ty);
+                let value = match op {
+                    "-" => -value,
+                    "+" => value,
+                    "~" => !value,
+                    _ => return Ok(CConst::Int((value == 0) as i128, int_type(true, 32))),
+                };
+                Ok(CConst::Int(wrap_int(value, &ty), ty))
+            }
+            "(" => {
+                let close = tokens[*index..].iter().position(|t| t == ")");
+                if let Some((close, ty)) = close.and_then(|c| Some((c, self.cast_type(&tokens[*index..*index + c])?))) {
+                    *index += close + 1;
+                    let (value, _) = Self::const_as_int(self.eval_unary(tokens, index)?)?;
+                    let value = if ty == CType::Bool { (value != 0) as i128 } else { wrap_int(value, &ty) };
+                    let ty = if ty == CType::Bool { int_type(false, 8) } else { ty };
+                    return Ok(CConst::Int(value, ty));
+                }
+                let value = self.eval_binary(tokens, index, 0)?;
+                if tokens.get(*index).map(|t| t.as_str()) != Some(")") {
+                    return Err("Expected `)` in constant expression".to_string());
+                }
+                *index += 1;
+                Ok(value)
+            }
+            t if t.starts_with('\'') => {
+                // Multi-character and out of range literals are rejected here, so they never reach codegen
+                let literal = &t[1..t.len() - 1];
+                char_literal_value(literal)?;
+                Ok(CConst::Char(literal.to_string()))
+            }
+            t if t.starts_with('"') => {
+                // Adjacent string literals are concatenated
+                let mut string = t[1..t.len() - 1].to_string();
+                while let Some(next) = tokens.get(*index).filter(|t| t.starts_with('"')) {
+                    string += &next[1..next.len() - 1];
+                    *index += 1;
+                }
+                Ok(CConst::Str(string))
+            }
+            t if t.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+                let is_hex = t.starts_with("0x") || t.starts_with("0X");
+                if t.contains('.') || (!is_hex && t.contains(['e', 'E', 'f', 'F'])) {
+                    return Err(format!("Floating point constants (here: `{}`) are not supported yet", t));
+                }
+                parse_int_literal(t)
+            }
+            t if t.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => self
+                .resolve_constant(t)?
+                .ok_or_else(|| format!("`{}` is not a constant", t)),
+            t => Err(format!("Unexpected `{}` in constant expression", t)),
+        }
+    }
+
+    // The integer type of a cast like `(unsigned long)`, or `None` if the parentheses don't contain a type
+    #[trace_call(extra)]
+    fn cast_type(&self, tokens: &[String]) -> Option<CType> {
+        if tokens.is_empty() || !tokens.iter().all(|t| t.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')) {
+            return None;
+        }
+        if tokens.len() == 1 && (self.constants.contains_key(&tokens[0]) || self.macros.contains_key(&tokens[0])) {
+            return None;
+        }
+        let mut ty = self.parse_qual_type(&tokens.join(" ")).ok()?;
+        loop {
+            ty = match ty {
+                CType::Const(ty) => *ty,
+                CType::Struct(name) => self.aliases.get(&name)?.clone(),
+                CType::Char => return Some(int_type(true, 8)),
+                CType::Bool => return Some(CType::Bool),
+                ty => return int_info(&ty).map(|_| ty),
+            };
+        }
+    }
+
+    #[trace_call(extra)]
+    fn const_as_int(value: CConst) -> Result<(i128, CType), String> {
+        match value {
+            CConst::Int(value, ty) => Ok((value, ty)),
+            CConst::Char(c) => Ok((char_literal_value(&c)?, int_type(true, 32))),
+            CConst::Str(s) => Err(format!("String literal `\"{}\"` used in arithmetic", s)),
+        }
+    }
+
+    #[trace_call(extra)]
+    fn eval_binary_op(op: &str, lhs: CConst, rhs: CConst) -> Result<CConst, String> {
+        let (lhs, lhs_ty) = Self::const_as_int(lhs)?;
+        let (rhs, rhs_ty) = Self::const_as_int(rhs)?;
+        let int = int_type(true, 32);
+        if op == "<<" || op == ">>" {
+            // Shifts don't use the common type, the result has the type of the promoted left operand
+            let ty = promote_int(&lhs_ty);
+            let bits = int_info(&ty).unwrap().1 as i128;
+            if rhs < 0 || rhs >= bits {
+                return Err(format!("Shift by {} is out of range for a {}-bit value", rhs, bits));
+            }
+            let lhs = wrap_int(lhs, &ty);
+            let value = if op == "<<" { lhs << rhs } else { lhs >> rhs };
+            return Ok(CConst::Int(wrap_int(value, &ty), ty));
+        }
+        let ty = common_int_type(&lhs_ty, &rhs_ty);
+        let (l, r) = (wrap_int(lhs, &ty), wrap_int(rhs, &ty));
+        let value = match op {
+            "*" => l.wrapping_mul(r),
+            "/" | "%" if r == 0 => return Err("Division by zero in constant expression".to_string()),
+            "/" => l / r,
+            "%" => l % r,
+            "+" => l + r,
+            "-" => l - r,
+            "&" => l & r,
+            "^" => l ^ r,
+            "|" => l | r,
+            "<" => return Ok(CConst::Int((l < r) as i128, int)),
+            "<=" => return Ok(CConst::Int((l <= r) as i128, int)),
+            ">" => return Ok(CConst::Int((l > r) as i128, int)),
+            ">=" => return Ok(CConst::Int((l >= r) as i128, int)),
+            "==" => return Ok(CConst::Int((l == r) as i128, int)),
+            "!=" => return Ok(CConst::Int((l != r) as i128, int)),
+            "&&" => return Ok(CConst::Int((lhs != 0 && rhs != 0) as i128, int)),
+            "||" => return Ok(CConst::Int((lhs != 0 || rhs != 0) as i128, int)),
+            _ => internal_panic!("Unexpected binary operator `{}`", op),
+        };
+        Ok(CConst::Int(wrap_int(value, &ty), ty))
+    }
+
+    #[trace_call(extra)]
+    fn ctype_bits(&self, ty: &CType) -> u64 {
+        match ty {
+            CType::Bool | CType::Byte | CType::Char => 8,
+            CType::Short => 16,
+            CType::Int | CType::Float => 32,
+            CType::Long | CType::Double | CType::Pointer(_) => 64,
+            CType::Signed(ty) | CType::Unsigned(ty) | CType::Const(ty) => self.ctype_bits(ty),
+            CType::Struct(name) => match self.aliases.get(name) {
+                Some(ty) => self.ctype_bits(ty),
+                None => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    #[trace_call(extra)]
+    fn collect_referenced_structs(&self, node: &CNode, referenced: &mut Vec<String>) {
+        fn from_type(bindgen: &Bindgen, ty: &CType, referenced: &mut Vec<String>) {
+            match ty {
+                CType::Struct(name) => {
+                    referenced.push(name.clone());
+                    if let Some(alias) = bindgen.aliases.get(name) {
+                        from_type(bindgen, alias, referenced);
+                    }
+                }
+                CType::Array(ty, _) | CType::Const(ty) | CType::Pointer(ty)
+                | CType::Signed(ty) | CType::Unsigned(ty) => from_type(bindgen, ty, referenced),
+                CType::Function(ret, params) => {
+                    from_type(bindgen, ret, referenced);
+                    for param in params {
+                        from_type(bindgen, param, referenced);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match node {
+            CNode::Struct { fields, .. } | CNode::Union { fields, .. } => {
+                for field in fields {
+                    self.collect_referenced_structs(field, referenced);
+                }
+            }
+            CNode::FuncDecl { ret, args, .. } => {
+                self.collect_referenced_structs(ret, referenced);
+                for arg in args {
+                    self.collect_referenced_structs(arg, referenced);
+                }
+            }
+            CNode::Typedef { ty, .. } => self.collect_referenced_structs(ty, referenced),
+            CNode::Field { ty, .. } | CNode::Type(ty) | CNode::TypeAlias { ty, .. } => from_type(self, ty, referenced),
+            _ => {}
+        }
+    }
+
     #[trace_call(extra)]
     fn is_type(&self, token: &CToken) -> bool {
         match token {
@@ -487,65 +1577,102 @@ impl<'flags, 'src> Bindgen<'flags> {
     }
 
     #[trace_call(always)]
-    fn ask_user_for_flags(&self) -> Result<CNode<'src>, String> {
-        // if self.flags.gen_bind.is_none() {
-        //     return Err("gen_bind is not set".to_string());
-        // }
-        // let mut flags = Vec::new();
-        // if self.flags.debug {
-        //     println!("[DEBUG] Asking user for flags");
-        // }
-        // println!("The compiler is about to parse the header file `{}`", self.input_path);
-        // println!("Please only enter one flag per line, or leave empty to skip this step");
-
-        // println!("Please enter the library paths that should be used to compile this file");
-        // println!("Note: This is the same as the `-L` flag in gcc or clang.");
-        // loop {
-        //     let mut input = String::new();
-        //     std::io::stdin().read_line(&mut input).unwrap();
-        //     let flag = input.trim();
-        //     if flag.is_empty() {
-        //         break;
-        //     }
-        //     let flag = CompilerFlag::LibPath(Location::anonymous(), flag);
-        //     flags.push(flag);            
-        // }
-
-        // println!("Please enter the libraries that should be linked to compile this file");
-        // println!("Note: This is the same as the `-l` flag in gcc or clang.");
-        // loop {
-        //     let mut input = String::new();
-        //     std::io::stdin().read_line(&mut input).unwrap();
-        //     let flag = input.trim();
-        //     if flag.is_empty() {
-        //         break;
-        //     }
-        //     let flag = CompilerFlag::Library(Location::anonymous(), flag);
-        //     flags.push(flag);            
-        // }
-
-        // println!("Please enter the linker flags that should be used to compile this file");
-        // println!("Note: This is the same as the `-Xlinker` flag in gcc or clang.");
-        // loop {
-        //     let mut input = String::new();
-        //     std::io::stdin().read_line(&mut input).unwrap();
-        //     let flag = input.trim();
-        //     if flag.is_empty() {
-        //         break;
-        //     }
-        //     let flag = CompilerFlag::Linker(Location::anonymous(), flag);
-        //     flags.push(flag);
-        // }
-        panic!("This is literally a dead end with the current lifetime setup :^)")
-        // Ok(CNode::Flags(flags))
+    fn flags_from_cli(&self) -> CNode<'flags> {
+        let mut flags = Vec::new();
+        for path in &self.flags.bind_libpaths {
+            flags.push(CompilerFlag::LibPath(Location::anonymous(), path.as_str()));
+        }
+        for lib in &self.flags.bind_libs {
+            flags.push(CompilerFlag::Library(Location::anonymous(), lib.as_str()));
+        }
+        for flag in &self.flags.bind_linker_flags {
+            flags.push(CompilerFlag::Linker(Location::anonymous(), flag.as_str()));
+        }
+        CNode::Flags(flags)
+    }
+
+    // Identifiers colliding with Bufo keywords are renamed using the pattern passed to `--bind-rename`
+    #[trace_call(extra)]
+    fn rename(&self, ident: &str) -> String {
+        if TokenType::try_from_keyword(ident).is_some() {
+            self.flags.bind_rename.replace("{}", ident)
+        } else {
+            ident.to_string()
+        }
+    }
+
+    #[trace_call(extra)]
+    fn node_name<'a>(node: &'a CNode) -> &'a str {
+        match node {
+            CNode::Constant { name, .. }
+            | CNode::Enum { name, .. }
+            | CNode::FuncDecl { name, .. }
+            | CNode::Struct { name, .. }
+            | CNode::Typedef { name, .. }
+            | CNode::TypeAlias { name, .. }
+            | CNode::Union { name, .. } => name,
+            _ => "",
+        }
+    }
+
+    // Applies the allow- and blocklists and sorts the declarations, so the output doesn't depend on the order in the header.
+    // Types that are referenced by a declaration we keep are always emitted, unless they're blocked explicitly.
+    #[trace_call(always)]
+    fn filter_and_sort(&self, nodes: Vec<CNode<'src>>) -> Vec<CNode<'src>> {
+        fn allowed(name: &str, allow: &[Regex], block: &[Regex]) -> bool {
+            (allow.is_empty() || allow.iter().any(|r| r.is_match(name))) && !block.iter().any(|r| r.is_match(name))
+        }
+        let mut constants = Vec::new();
+        let mut functions = Vec::new();
+        let mut types = Vec::new();
+        let mut optional_types = Vec::new();
+        let mut referenced = Vec::new();
+        for node in nodes {
+            let name = Self::node_name(&node);
+            match node {
+                // Aliases shadowing a builtin type or aliasing `void` can't be declared in Bufo, they stay inlined
+                CNode::TypeAlias { ty: CType::Void, .. } => {}
+                CNode::TypeAlias { .. } if BUILTIN_TYPES.contains(&name) => {}
+                CNode::Constant { .. } => constants.push(node),
+                CNode::Enum { .. } if name.is_empty() => constants.push(node),
+                CNode::FuncDecl { .. } => {
+                    if !allowed(name, &self.flags.bind_allow_fns, &self.flags.bind_block_fns) {
+                        continue;
+                    }
+                    if TokenType::try_from_keyword(name).is_some() {
+                        println!("{}: Function `{}` collides with a Bufo keyword and will be ignored", WARN_STR, name);
+                        continue;
+                    }
+                    self.collect_referenced_structs(&node, &mut referenced);
+                    functions.push(node);
+                }
+                _ if allowed(name, &self.flags.bind_allow_types, &self.flags.bind_block_types) => {
+                    self.collect_referenced_structs(&node, &mut referenced);
+                    types.push(node);
+                }
+                _ if !self.flags.bind_block_types.iter().any(|r| r.is_match(name)) => optional_types.push(node),
+                _ => {}
+            }
+        }
+        while let Some(name) = referenced.pop() {
+            if let Some(index) = optional_types.iter().position(|t| Self::node_name(t) == name) {
+                let node = optional_types.remove(index);
+                self.collect_referenced_structs(&node, &mut referenced);
+                types.push(node);
+            }
+        }
+        constants.sort_by(|a, b| Self::node_name(a).cmp(Self::node_name(b)));
+        types.sort_by(|a, b| Self::node_name(a).cmp(Self::node_name(b)));
+        functions.sort_by(|a, b| Self::node_name(a).cmp(Self::node_name(b)));
+        constants.extend(types);
+        constants.extend(functions);
+        constants
     }
 
     #[trace_call(always)]
     fn parse_header(&mut self) -> Result<CNode<'src>, String> {
         self.fill_lookup();
         let mut ast = Vec::new();
-        let flags = self.ask_user_for_flags()?;
-        ast.push(flags);
         while !self.parsed_eof() {
             match self.nth(0) {
                 CToken::Typedef => {
@@ -555,6 +1682,14 @@ impl<'flags, 'src> Bindgen<'flags> {
                     }
                     ast.push(typedef);
                 }
+                CToken::Enum => {
+                    let ewnum = self.parse_enum()?;
+                    self.expect(CToken::Semicolon)?;
+                    if self.flags.debug {
+                        println!("[DEBUG] Parsed enum:\n{:?}", ewnum);
+                    }
+                    ast.push(ewnum);
+                }
                 _ if self.is_type(self.peek(0)) => {
                     let func = self.parse_func_decl()?;
                     if self.flags.debug {
@@ -615,7 +1750,7 @@ impl<'flags, 'src> Bindgen<'flags> {
         let tkn = match next {
             CToken::Struct => {
                 let strukt = self.parse_struct()?;
-                let CNode::Struct { name, fields } = strukt else {
+                let CNode::Struct { name, fields, packed } = strukt else {
                     unreachable!();
                 };
                 let def_name = self.next();
@@ -628,26 +1763,21 @@ impl<'flags, 'src> Bindgen<'flags> {
                 }
                 CNode::Typedef {
                     name: name.clone(),
-                    ty: Box::new(CNode::Struct { name, fields }),
+                    ty: Box::new(CNode::Struct { name, fields, packed }),
                 }
             }
             CToken::Enum => {
                 let ewnum = self.parse_enum()?;
-                let CNode::Enum { name, values } = ewnum else {
+                let CNode::Enum { values, .. } = ewnum else {
                     unreachable!();
                 };
-                debug_assert!(name.is_empty());
                 let def_name = self.next();
                 let def_name = match def_name {
                     CToken::Identifier(name) => name,
                     _ => return Err(format!("Expected identifier, found {:?}", def_name)),
                 };
-                let ewnum = CNode::Enum { name: def_name.clone(), values };
-                println!("{}: Enums (here: `{}`) are not supported yet and will be ignored", WARN_STR, def_name);
-                CNode::Typedef {
-                    name: def_name,
-                    ty: Box::new(ewnum),
-                }
+                self.aliases.insert(def_name.clone(), CType::Signed(Box::new(CType::Int)));
+                CNode::Enum { name: def_name, values }
             }
             CToken::Identifier(ident) => {
                 if ident == "__builtin_va_list" {
@@ -661,7 +1791,7 @@ impl<'flags, 'src> Bindgen<'flags> {
                 };
                 self.aliases.insert(name.clone(), ty.clone());
                 self.known_types.push(name.clone());
-                CNode::TypeAlias {}
+                CNode::TypeAlias { name, ty }
             }
             typ if self.is_type(&typ) => {
                 let ty = self.parse_type(true)?;
@@ -678,36 +1808,39 @@ impl<'flags, 'src> Bindgen<'flags> {
                     CToken::Identifier(name) => name,
                     _ => return Err(format!("Expected identifier, found {:?}", name)),
                 };
+                if self.at(CToken::Semicolon) {
+                    // `typedef unsigned int name;`
+                    self.aliases.insert(name.clone(), ty.clone());
+                    self.known_types.push(name.clone());
+                    self.expect(CToken::Semicolon)?;
+                    return Ok(CNode::TypeAlias { name, ty });
+                }
                 self.expect(CToken::OpenRound)?;
-                let mut args = Vec::new();
+                let mut params = Vec::new();
                 while !self.at(CToken::CloseRound) {
                     let ty = self.parse_type(true)?;
-                    let name = self.next();
-                    let name = match name {
-                        CToken::Identifier(name) => name,
-                        _ => return Err(format!("Expected identifier, found {:?}", name)),
-                    };
-                    let param = CNode::Field { name, ty };
-                    args.push(param);
-                    if self.eat(CToken::Comma) {
-                        continue;
+                    // Parameter names are optional
+                    if let CToken::Identifier(_) = self.peek(0) {
+                        self.next();
+                    }
+                    if ty != CType::Void {
+                        params.push(ty);
+                    }
+                    if !self.eat(CToken::Comma) {
+                        break;
                     }
                 }
                 self.expect(CToken::CloseRound)?;
-                println!("{}: Function pointers (here: `{}`) are not supported yet and will be ignored", WARN_STR, name);
-                CNode::Typedef {
-                    name: name.clone(),
-                    ty: Box::new(CNode::FuncPtr {
-                        name,
-                        ret: Box::new(CNode::Type(ty)),
-                        args
-                    }),
-                }
+                // `typedef int (*name)(int);` becomes `type name = func(i32) -> i32;`
+                let ty = CType::Function(Box::new(ty), params);
+                self.aliases.insert(name.clone(), ty.clone());
+                self.known_types.push(name.clone());
+                CNode::TypeAlias { name, ty }
             }
             _ => return Err(format!("parse_typedef: Unexpected token {:?}", next)),
         };
         self.expect(CToken::Semicolon)?;
-        if let CNode::Typedef { name, .. } = &tkn {
+        if let CNode::Typedef { name, .. } | CNode::Enum { name, .. } = &tkn {
             self.known_types.push(name.clone());
         }
         Ok(tkn)
@@ -716,11 +1849,19 @@ impl<'flags, 'src> Bindgen<'flags> {
     #[trace_call(always)]
     fn parse_enum(&mut self) -> Result<CNode<'src>, String> {
         self.expect(CToken::Enum)?;
+        let name = match self.peek(0) {
+            CToken::Identifier(name) => name.clone(),
+            _ => String::new(),
+        };
+        if !name.is_empty() {
+            self.next();
+        }
         if !self.at(CToken::OpenCurly) {
             return Err(format!("Expected open curly, found {:?}", self.peek(0)));
         }
         self.expect(CToken::OpenCurly)?;
         let mut values = Vec::new();
+        let mut next_value: i128 = 0;
         while !self.at(CToken::CloseCurly) {
             let value = match self.next() {
                 CToken::Identifier(ident) => ident,
@@ -728,35 +1869,54 @@ impl<'flags, 'src> Bindgen<'flags> {
             };
             if self.eat(CToken::Equals) {
                 let mut expr = Vec::new();
+                let mut depth = 0;
                 loop {
                     let n = self.peek(0);
-                    if n == &CToken::Comma || n == &CToken::CloseCurly {
+                    if depth == 0 && (n == &CToken::Comma || n == &CToken::CloseCurly) || n == &CToken::EOF {
                         break;
                     }
-                    expr.push(self.next());
-                }
-                if expr.len() == 1 {
-                    let n = expr.pop().unwrap();
                     match n {
-                        CToken::Number(n) => values.push(CNode::EnumVariant {
-                            name: value,
-                            value: Some(Box::new(CNode::Number(n)))
-                        }),
-                        _ => return Err(format!("Expected number, found {:?}", n)),
+                        CToken::OpenRound => depth += 1,
+                        CToken::CloseRound => depth -= 1,
+                        _ => {}
                     }
-                } else {
-                    // TODO: Const-Expr support
-                    return Err(format!("Expected single value, found {:?}", expr));
+                    expr.push(Self::token_to_string(&self.next())?);
                 }
-            } else {
-                values.push(CNode::EnumVariant { name: value, value: None });
+                next_value = match self.eval_const_expr(&expr.join(" "))? {
+                    CConst::Int(v, _) => v,
+                    c => Self::const_as_int(c)?.0,
+                };
             }
+            values.push(self.enum_constant(value, next_value)?);
+            next_value += 1;
             if self.eat(CToken::Comma) {
                 continue;
             }
         }
         self.expect(CToken::CloseCurly)?;
-        Ok(CNode::Enum { name: "".to_string(), values })
+        if !name.is_empty() {
+            self.aliases.insert(name.clone(), CType::Signed(Box::new(CType::Int)));
+            self.known_types.push(name.clone());
+        }
+        Ok(CNode::Enum { name, values })
+    }
+
+    // Turns tokens of an enum value back into source, so they can be evaluated like macros
+    #[trace_call(extra)]
+    fn token_to_string(token: &CToken) -> Result<String, String> {
+        Ok(match token {
+            CToken::Identifier(s) | CToken::Number(s) | CToken::Operator(s) => s.clone(),
+            CToken::OpenRound => "(".to_string(),
+            CToken::CloseRound => ")".to_string(),
+            CToken::Asterisk => "*".to_string(),
+            CToken::Char => "char".to_string(),
+            CToken::Int => "int".to_string(),
+            CToken::Long => "long".to_string(),
+            CToken::Short => "short".to_string(),
+            CToken::Signed => "signed".to_string(),
+            CToken::Unsigned => "unsigned".to_string(),
+            t => return Err(format!("Unexpected token {:?} in enum value", t)),
+        })
     }
 
     #[trace_call(always)]
@@ -768,7 +1928,7 @@ impl<'flags, 'src> Bindgen<'flags> {
             _ => return Err(format!("Expected identifier, found {:?}", name)),
         };
         if !self.at(CToken::OpenCurly) {
-            return Ok(CNode::Struct { name, fields: Vec::new() });
+            return Ok(CNode::Struct { name, fields: Vec::new(), packed: false });
         }
         self.expect(CToken::OpenCurly)?;
         let mut fields = Vec::new();
@@ -831,7 +1991,7 @@ impl<'flags, 'src> Bindgen<'flags> {
             }
         }
         self.expect(CToken::CloseCurly)?;
-        Ok(CNode::Struct { name, fields })
+        Ok(CNode::Struct { name, fields, packed: false })
     }
 
     #[trace_call(always)]
@@ -889,4 +2049,343 @@ impl<'flags, 'src> Bindgen<'flags> {
         }
         Ok(typ)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // `Bindgen::new` reads the header from disk, so every test writes its own next to a dummy input file
+    fn with_bindgen(name: &str, header: &str, args: &[&str], test: impl FnOnce(&mut Bindgen)) {
+        let dir = std::env::temp_dir().join(format!("bufo-bindgen-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("main.bufo");
+        let path = dir.join("test.h");
+        std::fs::write(&input, "").unwrap();
+        std::fs::write(&path, header).unwrap();
+        let mut cli = vec!["bufo", input.to_str().unwrap(), "--gen-bind", path.to_str().unwrap()];
+        cli.extend(args);
+        let flags = Flags::parse_from(cli);
+        let mut bindgen = Bindgen::new(&flags);
+        let _ = std::fs::remove_dir_all(&dir);
+        test(&mut bindgen);
+    }
+
+    fn field<'a>(node: &'a CNode) -> (&'a str, &'a CType) {
+        match node {
+            CNode::Field { name, ty } => (name, ty),
+            n => panic!("Expected field, found {:?}", n),
+        }
+    }
+
+    fn signed(ty: CType) -> CType {
+        CType::Signed(Box::new(ty))
+    }
+
+    fn pointer(ty: CType) -> CType {
+        CType::Pointer(Box::new(ty))
+    }
+
+    // Trimmed down output of `clang -Xclang -ast-dump=json -fsyntax-only` for:
+    //   struct point { int x; const char *name; };
+    //   enum color { RED, GREEN = 5, BLUE };
+    //   int draw(struct point *p, const char *label, ...);
+    //   struct handle *open(void);
+    //   long double half(long double);
+    //   int counter;
+    const CLANG_AST: &str = r#"{
+        "kind": "TranslationUnitDecl",
+        "inner": [
+            { "kind": "TypedefDecl", "name": "__int128_t", "isImplicit": true, "type": { "qualType": "__int128" } },
+            {
+                "kind": "RecordDecl", "id": "0x1", "loc": { "file": "test.h", "line": 1 },
+                "name": "point", "tagUsed": "struct", "completeDefinition": true,
+                "inner": [
+                    { "kind": "FieldDecl", "name": "x", "type": { "qualType": "int" } },
+                    { "kind": "FieldDecl", "name": "name", "type": { "qualType": "const char *" } }
+                ]
+            },
+            {
+                "kind": "EnumDecl", "id": "0x2", "name": "color",
+                "inner": [
+                    { "kind": "EnumConstantDecl", "name": "RED", "type": { "qualType": "int" } },
+                    {
+                        "kind": "EnumConstantDecl", "name": "GREEN", "type": { "qualType": "int" },
+                        "inner": [ { "kind": "ConstantExpr", "value": "5", "inner": [ { "kind": "IntegerLiteral", "value": "5" } ] } ]
+                    },
+                    { "kind": "EnumConstantDecl", "name": "BLUE", "type": { "qualType": "int" } }
+                ]
+            },
+            {
+                "kind": "FunctionDecl", "name": "draw", "variadic": true,
+                "type": { "qualType": "int (struct point *, const char *, ...)" },
+                "inner": [
+                    { "kind": "ParmVarDecl", "name": "p", "type": { "qualType": "struct point *" } },
+                    { "kind": "ParmVarDecl", "name": "label", "type": { "qualType": "const char *" } }
+                ]
+            },
+            { "kind": "FunctionDecl", "name": "open", "type": { "qualType": "struct handle *(void)" } },
+            {
+                "kind": "FunctionDecl", "name": "half", "type": { "qualType": "long double (long double)" },
+                "inner": [ { "kind": "ParmVarDecl", "type": { "qualType": "long double" } } ]
+            },
+            { "kind": "VarDecl", "name": "counter", "type": { "qualType": "int" } }
+        ]
+    }"#;
+
+    #[test]
+    fn clang_ast_declarations() {
+        with_bindgen("clang", "", &[], |bindgen| {
+            let CNode::AST(ast) = bindgen.parse_clang_ast(CLANG_AST).unwrap() else {
+                panic!("Expected AST");
+            };
+            // `half` and `counter` are unsupported and skipped, the opaque `handle` is appended at the end
+            assert_eq!(ast.iter().map(Bindgen::node_name).collect::<Vec<_>>(), vec!["point", "color", "draw", "open", "handle"]);
+
+            let CNode::Struct { fields, packed: false, .. } = &ast[0] else {
+                panic!("Expected struct, found {:?}", ast[0]);
+            };
+            assert_eq!(fields.iter().map(field).collect::<Vec<_>>(), vec![
+                ("x", &signed(CType::Int)),
+                ("name", &pointer(CType::Const(Box::new(CType::Char)))),
+            ]);
+
+            let CNode::Enum { values, .. } = &ast[1] else {
+                panic!("Expected enum, found {:?}", ast[1]);
+            };
+            let values = values.iter().map(|v| match v {
+                CNode::Constant { name, value: CConst::Int(value, _) } => (name.as_str(), *value),
+                v => panic!("Expected integer constant, found {:?}", v),
+            }).collect::<Vec<_>>();
+            assert_eq!(values, vec![("RED", 0), ("GREEN", 5), ("BLUE", 6)]);
+            assert_eq!(bindgen.constants.get("BLUE"), Some(&CConst::Int(6, signed(CType::Int))));
+
+            let CNode::FuncDecl { ret, args, vararg: true, .. } = &ast[2] else {
+                panic!("Expected variadic function, found {:?}", ast[2]);
+            };
+            assert!(matches!(ret.as_ref(), CNode::Type(ty) if *ty == signed(CType::Int)));
+            assert_eq!(args.iter().map(field).collect::<Vec<_>>(), vec![
+                ("p", &pointer(CType::Struct("point".to_string()))),
+                ("label", &pointer(CType::Const(Box::new(CType::Char)))),
+            ]);
+
+            let CNode::FuncDecl { ret, args, vararg: false, .. } = &ast[3] else {
+                panic!("Expected function, found {:?}", ast[3]);
+            };
+            assert!(matches!(ret.as_ref(), CNode::Type(ty) if *ty == pointer(CType::Struct("handle".to_string()))));
+            assert!(args.is_empty());
+            assert!(matches!(&ast[4], CNode::Struct { fields, .. } if fields.is_empty()));
+        });
+    }
+
+    #[test]
+    fn clang_ast_unsupported() {
+        with_bindgen("clang-unsupported", "", &[], |bindgen| {
+            assert!(bindgen.parse_clang_ast("{").unwrap_err().contains("Could not parse clang AST"));
+            assert!(bindgen.parse_clang_ast(r#"{ "kind": "FunctionDecl" }"#).unwrap_err().contains("Expected `TranslationUnitDecl`"));
+
+            let mut decls = ClangDecls::default();
+            let var: Value = serde_json::from_str(r#"{ "kind": "VarDecl", "name": "counter", "type": { "qualType": "int" } }"#).unwrap();
+            assert!(bindgen.clang_decl(&var, false, &mut decls).unwrap_err().contains("Global variables (here: `counter`)"));
+            let func: Value = serde_json::from_str(r#"{ "kind": "FunctionDecl", "name": "half", "type": { "qualType": "long double (void)" } }"#).unwrap();
+            assert!(bindgen.clang_decl(&func, false, &mut decls).unwrap_err().contains("Function `half`: Unsupported type `long double`"));
+            assert!(decls.nodes.is_empty());
+        });
+    }
+
+    #[test]
+    fn clang_qual_types() {
+        with_bindgen("qual-types", "", &[], |bindgen| {
+            let parse = |ty: &str| bindgen.parse_qual_type(ty).unwrap();
+            assert_eq!(parse("const char *"), pointer(CType::Const(Box::new(CType::Char))));
+            assert_eq!(parse("char *const"), pointer(CType::Char));
+            assert_eq!(parse("const int **"), pointer(pointer(CType::Const(Box::new(signed(CType::Int))))));
+            assert_eq!(parse("unsigned long long"), CType::Unsigned(Box::new(CType::Long)));
+            assert_eq!(parse("unsigned char"), CType::Unsigned(Box::new(CType::Byte)));
+            assert_eq!(parse("struct point *"), pointer(CType::Struct("point".to_string())));
+            assert_eq!(parse("int [4][2]"), CType::Array(
+                Box::new(CType::Array(Box::new(signed(CType::Int)), CToken::Number("2".to_string()))),
+                CToken::Number("4".to_string()),
+            ));
+            assert_eq!(parse("int (*)(int, const char *)"), CType::Function(
+                Box::new(signed(CType::Int)),
+                vec![signed(CType::Int), pointer(CType::Const(Box::new(CType::Char)))],
+            ));
+            assert_eq!(parse("void (*)(int, ...)"), pointer(CType::Void));
+            assert!(bindgen.parse_qual_type("long double").unwrap_err().contains("Unsupported type"));
+            assert!(bindgen.parse_qual_type("size_t").is_err());
+        });
+    }
+
+    fn func<'src>(name: &str, args: Vec<CNode<'src>>) -> CNode<'src> {
+        CNode::FuncDecl { name: name.to_string(), ret: Box::new(CNode::Type(CType::Void)), args, vararg: false }
+    }
+
+    fn strukt<'src>(name: &str, fields: Vec<CNode<'src>>) -> CNode<'src> {
+        CNode::Struct { name: name.to_string(), fields, packed: false }
+    }
+
+    fn struct_field<'src>(name: &str, ty: &str) -> CNode<'src> {
+        CNode::Field { name: name.to_string(), ty: pointer(CType::Struct(ty.to_string())) }
+    }
+
+    fn declarations<'src>() -> Vec<CNode<'src>> {
+        vec![
+            func("zeta", vec![struct_field("p", "point"), struct_field("b", "blocked_t")]),
+            strukt("unused", Vec::new()),
+            func("internal_helper", vec![struct_field("u", "unused")]),
+            CNode::Constant { name: "B".to_string(), value: CConst::Int(2, signed(CType::Int)) },
+            strukt("point", vec![struct_field("inner", "vec")]),
+            func("type", Vec::new()),
+            strukt("size", Vec::new()),
+            func("alphabet", Vec::new()),
+            strukt("blocked_t", Vec::new()),
+            CNode::TypeAlias { name: "u8".to_string(), ty: CType::Unsigned(Box::new(CType::Byte)) },
+            strukt("vec", Vec::new()),
+            func("alpha", Vec::new()),
+            CNode::Constant { name: "A".to_string(), value: CConst::Int(1, signed(CType::Int)) },
+        ]
+    }
+
+    #[test]
+    fn filter_allow_and_block_lists() {
+        let args = [
+            "--bind-allow-fn", "alpha|zeta|type|internal_.*",
+            "--bind-block-fn", "internal_.*",
+            "--bind-allow-type", "size",
+            "--bind-block-type", "blocked_t",
+        ];
+        with_bindgen("filter", "", &args, |bindgen| {
+            let names = |nodes: Vec<CNode>| nodes.iter().map(Bindgen::node_name).map(str::to_string).collect::<Vec<_>>();
+            // Constants, then types, then functions, each sorted by name.
+            // `point` and `vec` are only kept because `zeta` references them, `unused` only by a blocked function.
+            // `alphabet` isn't matched by the anchored `alpha`, `type` collides with a keyword, `u8` with a builtin.
+            let expected = vec!["A", "B", "point", "size", "vec", "alpha", "zeta"];
+            assert_eq!(names(bindgen.filter_and_sort(declarations())), expected);
+            let mut reversed = declarations();
+            reversed.reverse();
+            assert_eq!(names(bindgen.filter_and_sort(reversed)), expected);
+        });
+        with_bindgen("filter-all", "", &[], |bindgen| {
+            let names = bindgen.filter_and_sort(declarations()).iter().map(Bindgen::node_name).map(str::to_string).collect::<Vec<_>>();
+            assert_eq!(names, vec![
+                "A", "B", "blocked_t", "point", "size", "unused", "vec",
+                "alpha", "alphabet", "internal_helper", "zeta",
+            ]);
+        });
+    }
+
+    #[test]
+    fn rename_keywords() {
+        with_bindgen("rename", "", &[], |bindgen| {
+            assert_eq!(bindgen.rename("type"), "type_");
+            assert_eq!(bindgen.rename("func"), "func_");
+            assert_eq!(bindgen.rename("point"), "point");
+            assert_eq!(bindgen.rename("type_"), "type_");
+            let node = strukt("struct", vec![CNode::Field { name: "type".to_string(), ty: signed(CType::Int) }]);
+            let output = bindgen.ast_to_string(&node);
+            assert!(output.contains("struct struct_ {"), "{}", output);
+            assert!(output.contains("type_: "), "{}", output);
+        });
+        with_bindgen("rename-pattern", "", &["--bind-rename", "c_{}"], |bindgen| {
+            assert_eq!(bindgen.rename("type"), "c_type");
+            assert_eq!(bindgen.rename("type_"), "type_");
+        });
+    }
+
+    fn int(value: i128, signed: bool, bits: u32) -> CConst {
+        CConst::Int(value, int_type(signed, bits))
+    }
+
+    #[test]
+    fn int_literals() {
+        assert_eq!(parse_int_literal("42"), Ok(int(42, true, 32)));
+        assert_eq!(parse_int_literal("3000000000"), Ok(int(3000000000, true, 64)));
+        assert_eq!(parse_int_literal("0xFFFFFFFF"), Ok(int(0xFFFFFFFF, false, 32)));
+        assert_eq!(parse_int_literal("017"), Ok(int(15, true, 32)));
+        assert_eq!(parse_int_literal("0b101"), Ok(int(5, true, 32)));
+        assert_eq!(parse_int_literal("10u"), Ok(int(10, false, 32)));
+        assert_eq!(parse_int_literal("10UL"), Ok(int(10, false, 64)));
+        assert_eq!(parse_int_literal("0x10L"), Ok(int(16, true, 64)));
+        assert_eq!(parse_int_literal("0xFFFFFFFFFFFFFFFF"), Ok(int(u64::MAX as i128, false, 64)));
+        assert!(parse_int_literal("18446744073709551616").unwrap_err().contains("too big"));
+        assert!(parse_int_literal("12z").unwrap_err().contains("not an integer literal"));
+    }
+
+    #[test]
+    fn int_wrap_around() {
+        assert_eq!(wrap_int(256, &int_type(false, 8)), 0);
+        assert_eq!(wrap_int(128, &int_type(true, 8)), -128);
+        assert_eq!(wrap_int(-1, &int_type(false, 32)), u32::MAX as i128);
+        assert_eq!(wrap_int(1 << 63, &int_type(true, 64)), i64::MIN as i128);
+        assert_eq!(wrap_int(-129, &int_type(true, 8)), 127);
+        assert_eq!(fitting_int_type(-1), Some(int_type(true, 32)));
+        assert_eq!(fitting_int_type(u32::MAX as i128), Some(int_type(false, 32)));
+        assert_eq!(fitting_int_type(1 << 64), None);
+    }
+
+    #[test]
+    fn const_expressions() {
+        with_bindgen("const-expr", "", &[], |bindgen| {
+            let mut eval = |expr: &str| bindgen.eval_const_expr(expr);
+            assert_eq!(eval("(1 + 2) * 3"), Ok(int(9, true, 32)));
+            assert_eq!(eval("'a' + 1"), Ok(int(98, true, 32)));
+            assert_eq!(eval("\"bu\" \"fo\""), Ok(CConst::Str("bufo".to_string())));
+            assert_eq!(eval("0x7fffffff + 1"), Ok(int(i32::MIN as i128, true, 32)));
+            assert_eq!(eval("-1 < 0u"), Ok(int(0, true, 32)));
+            assert_eq!(eval("1 << 31"), Ok(int(i32::MIN as i128, true, 32)));
+            assert_eq!(eval("1u << 31"), Ok(int(1 << 31, false, 32)));
+            assert_eq!(eval("-8 >> 1"), Ok(int(-4, true, 32)));
+            assert_eq!(eval("(long)1 << 40"), Ok(int(1 << 40, true, 64)));
+            assert_eq!(eval("(unsigned char)300"), Ok(int(44, false, 8)));
+            assert_eq!(eval("(unsigned)-1"), Ok(int(u32::MAX as i128, false, 32)));
+            assert_eq!(eval("(_Bool)5"), Ok(int(1, false, 8)));
+            assert!(eval("1 << 32").unwrap_err().contains("Shift by 32 is out of range"));
+            assert!(eval("1 >> -1").unwrap_err().contains("out of range"));
+            assert!(eval("1 / 0").unwrap_err().contains("Division by zero"));
+            assert!(eval("5 % (2 - 2)").unwrap_err().contains("Division by zero"));
+            assert!(eval("1.5").unwrap_err().contains("Floating point"));
+            assert!(eval("(1 + 2").unwrap_err().contains("Expected `)`"));
+            assert!(eval("1 2").unwrap_err().contains("Unexpected `2`"));
+        });
+    }
+
+    #[test]
+    fn defines() {
+        let header = r#"
+            #ifndef TEST_H
+            #define TEST_H
+            #define SIZE 16
+            #define MASK (SIZE - 1) // comment
+            #define FLAGS (1u << 3) | \
+                MASK
+            #define MAX(a, b) ((a) > (b) ? (a) : (b))
+            #define NAME "bufo"
+            #define PI 3.14
+            #define CALL foo()
+            #define SELF SELF + 1
+            #define BROKEN (1 / 0)
+            #define EMPTY
+            #define VERSION SIZE
+            #endif
+        "#;
+        with_bindgen("defines", header, &[], |bindgen| {
+            let names = bindgen.collect_defines();
+            assert_eq!(names, vec!["SIZE", "MASK", "FLAGS", "NAME", "PI", "CALL", "SELF", "BROKEN", "VERSION"]);
+            let constants = bindgen.eval_defines(names).into_iter().map(|c| match c {
+                CNode::Constant { name, value } => (name, value),
+                c => panic!("Expected constant, found {:?}", c),
+            }).collect::<Vec<_>>();
+            // Floats, calls, self-references and division by zero are skipped
+            assert_eq!(constants, vec![
+                ("SIZE".to_string(), int(16, true, 32)),
+                ("MASK".to_string(), int(15, true, 32)),
+                ("FLAGS".to_string(), int(15, false, 32)),
+                ("NAME".to_string(), CConst::Str("bufo".to_string())),
+                ("VERSION".to_string(), int(16, true, 32)),
+            ]);
+            assert_eq!(bindgen.resolve_constant("MAX"), Ok(None));
+            assert_eq!(bindgen.resolve_constant("PI"), Ok(None));
+        });
+    }
+}
@@ -0,0 +1,351 @@
+// Programs compiled with `--coverage` count how often each region of the source is entered.
+// The compiler writes a coverage map next to the executable, which describes every counter.
+// At exit, the program adds its counters to a raw profile (see `dumpCoverage` in the prelude).
+// `bufo cov` combines both into a report.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+use tracer::trace_call;
+
+use super::flags::CovFlags;
+
+// "BUFO", must match COVERAGE_MAGIC in std/prelude.bufo
+pub const PROFILE_MAGIC: u64 = 1112884815;
+const MAP_HEADER: &str = "bufo-covmap";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    Function,
+    Then,
+    Else,
+    Loop,
+}
+
+impl RegionKind {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Function => "function",
+            Self::Then => "then",
+            Self::Else => "else",
+            Self::Loop => "loop",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "function" => Some(Self::Function),
+            "then" => Some(Self::Then),
+            "else" => Some(Self::Else),
+            "loop" => Some(Self::Loop),
+            _ => None,
+        }
+    }
+}
+
+/// One counter, `line` is the line of the function or statement that owns the region,
+/// `lines` are the lines of all statements executed when the region is entered
+#[derive(Debug, Clone)]
+pub struct CoverageRegion {
+    pub kind: RegionKind,
+    pub function: String,
+    pub file: String,
+    pub line: usize,
+    pub lines: Vec<usize>,
+}
+
+impl CoverageRegion {
+    fn serialize(&self) -> String {
+        let lines: Vec<_> = self.lines.iter().map(|l| l.to_string()).collect();
+        format!("{}\t{}\t{}\t{}\t{}", self.kind.name(), self.function, self.file, self.line, lines.join(","))
+    }
+
+    fn deserialize(line: &str) -> Option<Self> {
+        let mut parts = line.split('\t');
+        let kind = RegionKind::from_name(parts.next()?)?;
+        let function = parts.next()?.to_string();
+        let file = parts.next()?.to_string();
+        let line = parts.next()?.parse().ok()?;
+        let lines = parts.next()?;
+        let lines = if lines.is_empty() {
+            Vec::new()
+        } else {
+            lines.split(',').map(|l| l.parse().ok()).collect::<Option<Vec<_>>>()?
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { kind, function, file, line, lines })
+    }
+}
+
+/// Identifies a coverage map, so profiles of different builds are never mixed up
+pub fn map_id(regions: &[CoverageRegion]) -> u64 {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for region in regions {
+        for byte in region.serialize().bytes().chain(std::iter::once(b'\n')) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+#[trace_call(always)]
+pub fn write_map(path: &Path, regions: &[CoverageRegion]) -> Result<(), String> {
+    let mut map = format!("{} {}\n", MAP_HEADER, map_id(regions));
+    for region in regions {
+        map.push_str(&region.serialize());
+        map.push('\n');
+    }
+    std::fs::write(path, map).map_err(|e| format!("Could not write coverage map `{}`: {}", path.display(), e))
+}
+
+#[trace_call(always)]
+fn read_map(path: &Path) -> Result<(u64, Vec<CoverageRegion>), String> {
+    let map = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read coverage map `{}`: {}", path.display(), e))?;
+    let mut lines = map.lines();
+    let id = lines.next()
+        .and_then(|header| header.strip_prefix(MAP_HEADER))
+        .and_then(|id| id.trim().parse().ok())
+        .ok_or_else(|| format!("`{}` is not a coverage map.", path.display()))?;
+    let mut regions = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let region = CoverageRegion::deserialize(line)
+            .ok_or_else(|| format!("Coverage map `{}` is malformed in line {}.", path.display(), i + 2))?;
+        regions.push(region);
+    }
+    if map_id(&regions) != id {
+        return Err(format!("Coverage map `{}` is corrupted.", path.display()));
+    }
+    Ok((id, regions))
+}
+
+#[trace_call(always)]
+fn read_profile(path: &Path, id: u64, count: usize) -> Result<Vec<u64>, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("Could not read profile `{}`: {}", path.display(), e))?;
+    let values: Vec<u64> = bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect();
+    if values.len() < 3 || values[0] != PROFILE_MAGIC {
+        return Err(format!("`{}` is not a coverage profile.", path.display()));
+    }
+    if values[1] != id || values[2] != count as u64 || values.len() != count + 3 {
+        return Err(format!("Profile `{}` was not written by the program this coverage map belongs to.", path.display()));
+    }
+    Ok(values[3..].to_vec())
+}
+
+#[derive(Default)]
+struct FunctionCoverage {
+    line: usize,
+    calls: u64,
+    lines: BTreeMap<usize, u64>,
+}
+
+#[derive(Default)]
+struct FileCoverage {
+    lines: BTreeMap<usize, u64>,
+    functions: BTreeMap<String, FunctionCoverage>,
+    // (line, block, branch) -> times taken
+    branches: BTreeMap<(usize, usize, usize), u64>,
+}
+
+fn percent(hit: usize, total: usize) -> f64 {
+    if total == 0 { 100.0 } else { hit as f64 * 100.0 / total as f64 }
+}
+
+fn hit_lines(lines: &BTreeMap<usize, u64>) -> usize {
+    lines.values().filter(|count| **count > 0).count()
+}
+
+fn collect<'a>(regions: &'a [CoverageRegion], counters: &[u64]) -> BTreeMap<&'a str, FileCoverage> {
+    let mut files: BTreeMap<&'a str, FileCoverage> = BTreeMap::new();
+    // Every if statement is one branch block, numbered in order of appearance
+    let mut branch_blocks: HashMap<(&'a str, usize), usize> = HashMap::new();
+    for (region, count) in regions.iter().zip(counters) {
+        let file = files.entry(&region.file).or_default();
+        let function = file.functions.entry(region.function.clone()).or_default();
+        if region.kind == RegionKind::Function {
+            function.line = region.line;
+            function.calls = *count;
+        }
+        for line in &region.lines {
+            // A line may belong to several regions, e.g. `if (c) return;`
+            let entry = function.lines.entry(*line).or_default();
+            *entry = (*entry).max(*count);
+            let entry = file.lines.entry(*line).or_default();
+            *entry = (*entry).max(*count);
+        }
+        let branch = match region.kind {
+            RegionKind::Then => 0,
+            RegionKind::Else => 1,
+            RegionKind::Function | RegionKind::Loop => continue,
+        };
+        let blocks = branch_blocks.len();
+        let block = *branch_blocks.entry((&region.file, region.line)).or_insert(blocks);
+        *file.branches.entry((region.line, block, branch)).or_default() += count;
+    }
+    files
+}
+
+fn text_report(files: &BTreeMap<&str, FileCoverage>) -> String {
+    let mut report = String::new();
+    let (mut total_hit, mut total) = (0, 0);
+    for (name, file) in files {
+        let hit = hit_lines(&file.lines);
+        total_hit += hit;
+        total += file.lines.len();
+        let _ = writeln!(report, "{}: {}/{} lines ({:.2}%)", name, hit, file.lines.len(), percent(hit, file.lines.len()));
+        for (function_name, function) in &file.functions {
+            let hit = hit_lines(&function.lines);
+            let _ = write!(
+                report,
+                "    {}: {}/{} lines ({:.2}%), called {} time{}",
+                function_name, hit, function.lines.len(), percent(hit, function.lines.len()),
+                function.calls, if function.calls == 1 { "" } else { "s" }
+            );
+            let missed: Vec<_> = function.lines.iter()
+                .filter(|(_, count)| **count == 0)
+                .map(|(line, _)| line.to_string())
+                .collect();
+            if !missed.is_empty() && hit != 0 {
+                let _ = write!(report, ", missed lines {}", missed.join(", "));
+            }
+            report.push('\n');
+        }
+    }
+    let _ = writeln!(report, "Total: {}/{} lines ({:.2}%)", total_hit, total, percent(total_hit, total));
+    report
+}
+
+fn lcov_report(files: &BTreeMap<&str, FileCoverage>) -> String {
+    let mut report = String::new();
+    for (name, file) in files {
+        let _ = writeln!(report, "TN:");
+        let _ = writeln!(report, "SF:{}", name);
+        for (function_name, function) in &file.functions {
+            let _ = writeln!(report, "FN:{},{}", function.line, function_name);
+        }
+        for (function_name, function) in &file.functions {
+            let _ = writeln!(report, "FNDA:{},{}", function.calls, function_name);
+        }
+        let _ = writeln!(report, "FNF:{}", file.functions.len());
+        let _ = writeln!(report, "FNH:{}", file.functions.values().filter(|f| f.calls > 0).count());
+        for ((line, block, branch), count) in &file.branches {
+            let _ = writeln!(report, "BRDA:{},{},{},{}", line, block, branch, count);
+        }
+        let _ = writeln!(report, "BRF:{}", file.branches.len());
+        let _ = writeln!(report, "BRH:{}", file.branches.values().filter(|count| **count > 0).count());
+        for (line, count) in &file.lines {
+            let _ = writeln!(report, "DA:{},{}", line, count);
+        }
+        let _ = writeln!(report, "LF:{}", file.lines.len());
+        let _ = writeln!(report, "LH:{}", hit_lines(&file.lines));
+        let _ = writeln!(report, "end_of_record");
+    }
+    report
+}
+
+#[trace_call(always)]
+pub fn report(flags: &CovFlags) -> Result<(), String> {
+    let (id, regions) = read_map(&flags.covmap)?;
+    let counters = read_profile(&flags.profile, id, regions.len())?;
+    let files = collect(&regions, &counters);
+    let report = match flags.format.as_str() {
+        "lcov" => lcov_report(&files),
+        _ => text_report(&files),
+    };
+    match &flags.output {
+        Some(output) => std::fs::write(output, report)
+            .map_err(|e| format!("Could not write coverage report `{}`: {}", output, e)),
+        None => {
+            print!("{}", report);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(kind: RegionKind, line: usize, lines: &[usize]) -> CoverageRegion {
+        CoverageRegion {
+            kind,
+            function: "main".to_string(),
+            file: "main.bufo".to_string(),
+            line,
+            lines: lines.to_vec(),
+        }
+    }
+
+    fn regions() -> Vec<CoverageRegion> {
+        vec![
+            region(RegionKind::Function, 1, &[2, 3]),
+            region(RegionKind::Then, 3, &[4]),
+            region(RegionKind::Else, 3, &[6]),
+            region(RegionKind::Loop, 7, &[]),
+        ]
+    }
+
+    #[test]
+    fn regions_round_trip() {
+        for region in regions() {
+            let parsed = CoverageRegion::deserialize(&region.serialize()).unwrap();
+            assert_eq!(parsed.kind, region.kind);
+            assert_eq!(parsed.line, region.line);
+            assert_eq!(parsed.lines, region.lines);
+        }
+        assert!(CoverageRegion::deserialize("branch\tmain\tmain.bufo\t1\t").is_none());
+        assert!(CoverageRegion::deserialize("then\tmain\tmain.bufo\t1\t2,x").is_none());
+        assert!(CoverageRegion::deserialize("then\tmain\tmain.bufo\t1\t2\textra").is_none());
+    }
+
+    #[test]
+    fn map_and_profile_are_validated() {
+        let dir = std::env::temp_dir().join(format!("bufo-coverage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let map = dir.join("main.covmap");
+        let regions = regions();
+        write_map(&map, &regions).unwrap();
+        let (id, read) = read_map(&map).unwrap();
+        assert_eq!(id, map_id(&regions));
+        assert_eq!(read.len(), regions.len());
+
+        let contents = std::fs::read_to_string(&map).unwrap();
+        std::fs::write(&map, contents.replace("\t7\t", "\t8\t")).unwrap();
+        assert!(read_map(&map).unwrap_err().contains("corrupted"));
+
+        let profile = dir.join("main.profraw");
+        let write_profile = |values: &[u64]| {
+            std::fs::write(&profile, values.iter().flat_map(|v| v.to_ne_bytes()).collect::<Vec<_>>()).unwrap();
+        };
+        write_profile(&[PROFILE_MAGIC, id, 4, 1, 1, 0, 5]);
+        assert_eq!(read_profile(&profile, id, 4).unwrap(), vec![1, 1, 0, 5]);
+        write_profile(&[PROFILE_MAGIC, id + 1, 4, 1, 1, 0, 5]);
+        assert!(read_profile(&profile, id, 4).is_err());
+        write_profile(&[0, id, 4, 1, 1, 0, 5]);
+        assert!(read_profile(&profile, id, 4).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reports_count_lines_and_branches() {
+        let regions = regions();
+        let files = collect(&regions, &[1, 1, 0, 5]);
+        let text = text_report(&files);
+        assert!(text.contains("main.bufo: 3/4 lines (75.00%)"));
+        assert!(text.contains("main: 3/4 lines (75.00%), called 1 time, missed lines 6"));
+        let lcov = lcov_report(&files);
+        assert!(lcov.contains("FNDA:1,main"));
+        assert!(lcov.contains("BRDA:3,0,0,1\nBRDA:3,0,1,0"));
+        assert!(lcov.contains("BRH:1"));
+        assert!(lcov.contains("DA:6,0"));
+        assert!(lcov.contains("LF:4\nLH:3"));
+    }
+}
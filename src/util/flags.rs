@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use clap::Parser;
+use regex::Regex;
 
 use crate::util::opt_flags::OptimizationFlags;
 use crate::compiler::FILE_EXT;
@@ -27,6 +28,28 @@ pub struct Flags {
     pub optimizations: OptimizationFlags,
     #[arg(long, value_parser = valid_header)]
     pub gen_bind: Option<String>,
+    #[arg(long, requires = "gen_bind", value_parser = valid_json)]
+    pub bind_json: Option<String>,
+    #[arg(long, requires = "gen_bind", conflicts_with = "bind_json", default_value = "false")]
+    pub bind_clang: bool,
+    #[arg(long, requires = "gen_bind")]
+    pub bind_output: Option<String>,
+    #[arg(long = "bind-lib", requires = "gen_bind")]
+    pub bind_libs: Vec<String>,
+    #[arg(long = "bind-libpath", requires = "gen_bind")]
+    pub bind_libpaths: Vec<String>,
+    #[arg(long = "bind-linker", requires = "gen_bind")]
+    pub bind_linker_flags: Vec<String>,
+    #[arg(long = "bind-allow-fn", requires = "gen_bind", value_parser = valid_regex)]
+    pub bind_allow_fns: Vec<Regex>,
+    #[arg(long = "bind-block-fn", requires = "gen_bind", value_parser = valid_regex)]
+    pub bind_block_fns: Vec<Regex>,
+    #[arg(long = "bind-allow-type", requires = "gen_bind", value_parser = valid_regex)]
+    pub bind_allow_types: Vec<Regex>,
+    #[arg(long = "bind-block-type", requires = "gen_bind", value_parser = valid_regex)]
+    pub bind_block_types: Vec<Regex>,
+    #[arg(long, default_value = "{}_", value_parser = valid_rename)]
+    pub bind_rename: String,
     #[cfg(not(feature = "old_codegen"))]
     #[arg(long, default_value = "false")]
     pub emit_llvm: bool,
@@ -34,12 +57,60 @@ pub struct Flags {
     pub emit_asm: bool,
     #[arg(long, default_value = "false")]
     pub emit_obj: bool,
+    #[arg(long, default_value = "false")]
+    pub emit_deps: bool,
+    #[arg(long, default_value = "false")]
+    pub overflow_checks: bool,
+    #[arg(long, default_value = "false")]
+    pub coverage: bool,
+    #[arg(long, default_value = "1000000")]
+    pub comptime_steps: usize,
+    #[arg(long, default_value = "64000")]
+    pub comptime_memory: usize,
     #[arg(short='I', long="import")]
     pub imports: Vec<String>,
+    #[arg(long = "cfg", value_parser = valid_cfg)]
+    pub cfgs: Vec<(String, Option<String>)>,
+    #[arg(short='C', value_parser = valid_codegen_option)]
+    pub codegen_options: Vec<CodegenOption>,
+    #[arg(long = "sanitize", value_parser = valid_sanitizer, value_delimiter = ',')]
+    pub sanitizers: Vec<Sanitizer>,
     #[arg(trailing_var_arg=true, use_value_delimiter=false)]
     pub exe_args: Vec<String>,
 }
 
+// `bufo cov`, see util/coverage.rs
+#[derive(Parser, Debug, Clone)]
+#[command(name = "bufo cov")]
+#[command(about = "Turns the profile of a program compiled with `--coverage` into a report")]
+pub struct CovFlags {
+    #[arg(value_parser = valid_covmap, hide_default_value=true)]
+    pub covmap: PathBuf,
+    #[arg(short, long, default_value = "bufo.profraw")]
+    pub profile: PathBuf,
+    #[arg(long, default_value = "text", value_parser = ["text", "lcov"])]
+    pub format: String,
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+impl CovFlags {
+    pub fn parse_flags() -> Self {
+        // Skip `bufo`, so `cov` takes the place of the binary name
+        CovFlags::parse_from(std::env::args().skip(1))
+    }
+}
+
+fn valid_covmap(covmap: &str) -> Result<PathBuf, String> {
+    if !std::path::Path::new(covmap).exists() {
+        return Err(format!("Coverage map `{}` does not exist.", covmap));
+    }
+    if !covmap.ends_with(".covmap") {
+        return Err(format!("Coverage map `{}` does not have the correct extension.", covmap));
+    }
+    Ok(PathBuf::from(covmap))
+}
+
 fn valid_opt(opt: &str) -> Result<OptimizationFlags, String> {
     if !["0", "1", "2", "3", "s"].contains(&opt) {
         return Err(format!("Optimization level `{}` is not supported.", opt));
@@ -57,6 +128,82 @@ fn valid_header(header: &str) -> Result<String, String> {
     Ok(header.to_string())
 }
 
+fn valid_regex(regex: &str) -> Result<Regex, String> {
+    // Anchor the regex, otherwise `--bind-allow-fn=str` would also allow `strlen`
+    Regex::new(&format!("^(?:{})$", regex)).map_err(|e| format!("Invalid regex `{}`: {}", regex, e))
+}
+
+fn valid_rename(pattern: &str) -> Result<String, String> {
+    if !pattern.contains("{}") {
+        return Err(format!("Rename pattern `{}` does not contain `{{}}`.", pattern));
+    }
+    Ok(pattern.to_string())
+}
+
+fn valid_json(json: &str) -> Result<String, String> {
+    if !std::path::Path::new(json).exists() {
+        return Err(format!("Clang AST file `{}` does not exist.", json));
+    }
+    if !json.ends_with(".json") {
+        return Err(format!("Clang AST file `{}` does not have the correct extension.", json));
+    }
+    Ok(json.to_string())
+}
+
+fn valid_cfg(cfg: &str) -> Result<(String, Option<String>), String> {
+    let (name, value) = match cfg.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_string())),
+        None => (cfg, None),
+    };
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("cfg name `{}` is not a valid identifier.", name));
+    }
+    Ok((name.to_string(), value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodegenOption {
+    BoundsChecks(bool),
+}
+
+fn valid_codegen_option(opt: &str) -> Result<CodegenOption, String> {
+    let Some((name, value)) = opt.split_once('=') else {
+        return Err(format!("Codegen option `{}` expects a value, e.g. `{}=off`.", opt, opt));
+    };
+    let enabled = match value {
+        "on" => true,
+        "off" => false,
+        _ => return Err(format!("Codegen option `{}` expects `on` or `off`, found `{}`.", name, value)),
+    };
+    match name {
+        "bounds-checks" => Ok(CodegenOption::BoundsChecks(enabled)),
+        _ => Err(format!("Unknown codegen option `{}`.", name)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sanitizer {
+    Address,
+    Undefined,
+}
+
+impl Sanitizer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Address => "address",
+            Self::Undefined => "undefined",
+        }
+    }
+}
+
+fn valid_sanitizer(sanitizer: &str) -> Result<Sanitizer, String> {
+    match sanitizer {
+        "address" => Ok(Sanitizer::Address),
+        "undefined" => Ok(Sanitizer::Undefined),
+        _ => Err(format!("Sanitizer `{}` is not supported, expected `address` or `undefined`.", sanitizer)),
+    }
+}
+
 fn valid_filepath(filepath: &str) -> Result<PathBuf, String> {
     if !std::path::Path::new(filepath).exists() {
         return Err(format!("File `{}` does not exist.", filepath));
@@ -71,4 +218,22 @@ impl Flags {
     pub fn parse_flags() -> Self {
         Flags::parse()
     }
+
+    pub fn sanitize(&self, sanitizer: Sanitizer) -> bool {
+        self.sanitizers.contains(&sanitizer)
+    }
+
+    pub fn bounds_checks(&self) -> bool {
+        if self.sanitize(Sanitizer::Undefined) {
+            return true;
+        }
+        // Later options override earlier ones
+        let mut enabled = true;
+        for opt in &self.codegen_options {
+            match opt {
+                CodegenOption::BoundsChecks(value) => enabled = *value,
+            }
+        }
+        enabled
+    }
 }
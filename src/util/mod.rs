@@ -1,4 +1,5 @@
 pub mod bindgen;
 pub mod printer;
 pub mod flags;
-pub mod opt_flags;
\ No newline at end of file
+pub mod opt_flags;
+pub mod coverage;
\ No newline at end of file
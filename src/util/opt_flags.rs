@@ -52,6 +52,11 @@ impl From<&str> for OptimizationFlags {
 }
 
 impl OptimizationFlags {
+    /// Unoptimized builds are debug builds
+    pub fn is_debug(&self) -> bool {
+        self.level == OptimizationLevel::None
+    }
+
     pub fn none() -> Self {
         Self {
             level: OptimizationLevel::None,
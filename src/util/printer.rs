@@ -18,12 +18,24 @@ pub trait Printable {
 impl Printable for nodes::FileNode<'_> {
     fn print_ast(&self, indent: usize) {
         println!("{}FileNode", " ".repeat(indent));
+        for assert in &self.asserts {
+            assert.print_ast(indent + INDENT_PER_LEVEL);
+        }
         for ext in &self.externs {
             ext.print_ast(indent + INDENT_PER_LEVEL);
         }
         for strukt in &self.structs {
             strukt.print_ast(indent + INDENT_PER_LEVEL);
         }
+        for interface in &self.interfaces {
+            interface.print_ast(indent + INDENT_PER_LEVEL);
+        }
+        for imp in &self.impls {
+            imp.print_ast(indent + INDENT_PER_LEVEL);
+        }
+        for alias in &self.aliases {
+            alias.print_ast(indent + INDENT_PER_LEVEL);
+        }
         for function in &self.functions {
             function.print_ast(indent + INDENT_PER_LEVEL);
         }
@@ -43,9 +55,21 @@ impl Printable for nodes::ExternNode<'_> {
     }
 }
 
+// ` packed align(N)`, empty for the default layout
+fn layout_modifiers(layout: &nodes::Layout) -> String {
+    let mut modifiers = String::new();
+    if layout.packed {
+        modifiers.push_str(" packed");
+    }
+    if let Some(align) = layout.align {
+        modifiers.push_str(&format!(" align({align})"));
+    }
+    modifiers
+}
+
 impl Printable for nodes::StructNode<'_> {
     fn print_ast(&self, indent: usize) {
-        println!("{}StructNode {}", " ".repeat(indent), self.name);
+        println!("{}{} {}{}", " ".repeat(indent), if self.is_union { "UnionNode" } else { "StructNode" }, self.name, layout_modifiers(&self.layout));
         for field in &self.fields {
             field.print_ast(indent + INDENT_PER_LEVEL);
         }
@@ -55,16 +79,52 @@ impl Printable for nodes::StructNode<'_> {
     }
 }
 
+impl Printable for nodes::InterfaceNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}InterfaceNode {}", " ".repeat(indent), self.name);
+        for method in &self.methods {
+            println!("{}InterfaceMethodNode {}", " ".repeat(indent + INDENT_PER_LEVEL), method.name);
+            println!("{}Return Type", " ".repeat(indent + 2 * INDENT_PER_LEVEL));
+            method.return_type.print_ast(indent + 3 * INDENT_PER_LEVEL);
+
+            println!("{}Parameters", " ".repeat(indent + 2 * INDENT_PER_LEVEL));
+            for parameter in &method.parameters {
+                parameter.print_ast(indent + 3 * INDENT_PER_LEVEL);
+            }
+        }
+    }
+}
+
+impl Printable for nodes::ImplNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}ImplNode {} for {}", " ".repeat(indent), self.interface, self.struct_name);
+    }
+}
+
+impl Printable for nodes::TypeAliasNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}TypeAliasNode {}", " ".repeat(indent), self.name);
+        self.typ.print_ast(indent + INDENT_PER_LEVEL);
+    }
+}
+
 impl Printable for nodes::FieldNode<'_> {
     fn print_ast(&self, indent: usize) {
-        println!("{}FieldNode {}", " ".repeat(indent), self.name);
+        println!("{}FieldNode {}{}", " ".repeat(indent), self.name, layout_modifiers(&self.layout));
         self.type_def.print_ast(indent + INDENT_PER_LEVEL);
+        if let Some(default) = &self.default {
+            println!("{}Default", " ".repeat(indent + INDENT_PER_LEVEL));
+            default.print_ast(indent + INDENT_PER_LEVEL);
+        }
     }
 }
 
 impl Printable for nodes::FunctionNode<'_> {
     fn print_ast(&self, indent: usize) {
         println!("{}FunctionNode {}", " ".repeat(indent), self.name);
+        for type_parameter in &self.type_parameters {
+            println!("{}TypeParameter {}: {}", " ".repeat(indent + INDENT_PER_LEVEL), type_parameter.name, type_parameter.bound);
+        }
         println!("{}Return Type", " ".repeat(indent + INDENT_PER_LEVEL));
         self.return_type.print_ast(indent + 2 * INDENT_PER_LEVEL);
 
@@ -120,6 +180,19 @@ impl Printable for nodes::Statement<'_> {
             nodes::Statement::While(node) => node.print_ast(indent),
             nodes::Statement::Break(node) => node.print_ast(indent),
             nodes::Statement::Continue(node) => node.print_ast(indent),
+            nodes::Statement::ComptimeAssert(node) => node.print_ast(indent),
+            nodes::Statement::Destructure(node) => node.print_ast(indent),
+            nodes::Statement::Defer(node) => node.print_ast(indent),
+        }
+    }
+}
+
+impl Printable for nodes::ComptimeAssertNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}ComptimeAssertNode", " ".repeat(indent));
+        self.condition.print_ast(indent + INDENT_PER_LEVEL);
+        if let Some(message) = self.message {
+            println!("{}Message \"{}\"", " ".repeat(indent + INDENT_PER_LEVEL), message);
         }
     }
 }
@@ -133,6 +206,23 @@ impl Printable for nodes::VarDeclNode<'_> {
     }
 }
 
+impl Printable for nodes::DestructureNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        let names: Vec<_> = self.names.iter().map(|(_, name)| *name).collect();
+        println!("{}DestructureNode ({})", " ".repeat(indent), names.join(", "));
+        self.typ.print_ast(indent + INDENT_PER_LEVEL);
+        println!("{}Mutable {}", " ".repeat(indent + INDENT_PER_LEVEL), self.is_mutable);
+        self.expression.print_ast(indent + INDENT_PER_LEVEL);
+    }
+}
+
+impl Printable for nodes::DeferNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}DeferNode", " ".repeat(indent));
+        self.statement.print_ast(indent + INDENT_PER_LEVEL);
+    }
+}
+
 impl Printable for nodes::IfNode<'_> {
     fn print_ast(&self, indent: usize) {
         println!("{}IfNode", " ".repeat(indent));
@@ -183,6 +273,36 @@ impl Printable for nodes::TypeNode<'_> {
     }
 }
 
+impl Printable for nodes::ReflectionNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}ReflectionNode {}", " ".repeat(indent), self.kind.name());
+        self.reflected.print_ast(indent + INDENT_PER_LEVEL);
+        match &self.kind {
+            nodes::ReflectionKind::FieldName(index) => index.print_ast(indent + INDENT_PER_LEVEL),
+            nodes::ReflectionKind::Offsetof(field) => field.print_ast(indent + INDENT_PER_LEVEL),
+            _ => {}
+        }
+    }
+}
+
+impl Printable for nodes::EmbedNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        let name = if self.length_only { "embedFileLen" } else { "embedFile" };
+        println!("{}EmbedNode {} \"{}\" ({} bytes)", " ".repeat(indent), name, self.path, self.content.len());
+    }
+}
+
+impl Printable for nodes::ArithmeticNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}ArithmeticNode {}", " ".repeat(indent), self.name());
+        self.lhs.print_ast(indent + INDENT_PER_LEVEL);
+        self.rhs.print_ast(indent + INDENT_PER_LEVEL);
+        if let Some(result) = &self.result {
+            result.print_ast(indent + INDENT_PER_LEVEL);
+        }
+    }
+}
+
 impl Printable for nodes::Expression<'_> {
     fn print_ast(&self, indent: usize) {
         match self {
@@ -190,12 +310,40 @@ impl Printable for nodes::Expression<'_> {
             Self::Literal(node) => node.print_ast(indent),
             Self::StructLiteral(node) => node.print_ast(indent),
             Self::ArrayLiteral(node) => node.print_ast(indent),
+            Self::TupleLiteral(node) => node.print_ast(indent),
             Self::Unary(node) => node.print_ast(indent),
             Self::Binary(node) => node.print_ast(indent),
             Self::FunctionCall(node) => node.print_ast(indent),
             Self::Sizeof(node) => node.print_ast(indent),
+            Self::Reflection(node) => node.print_ast(indent),
+            Self::Embed(node) => node.print_ast(indent),
+            Self::Arithmetic(node) => node.print_ast(indent),
             Self::As(_, _) => todo!("Expression::As.print_ast()"),
+            Self::Lambda(node) => node.print_ast(indent),
+        }
+    }
+}
+
+impl Printable for nodes::LambdaNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}LambdaNode", " ".repeat(indent));
+        for capture in &self.captures {
+            let kind = match (capture.is_reference, capture.is_mutable) {
+                (true, true) => "&mut ",
+                (true, false) => "&",
+                _ => "",
+            };
+            println!("{}Capture {}{}", " ".repeat(indent + INDENT_PER_LEVEL), kind, capture.name);
+        }
+        println!("{}Return Type", " ".repeat(indent + INDENT_PER_LEVEL));
+        self.return_type.print_ast(indent + 2 * INDENT_PER_LEVEL);
+
+        println!("{}Parameters", " ".repeat(indent + INDENT_PER_LEVEL));
+        for parameter in &self.parameters {
+            parameter.print_ast(indent + 2 * INDENT_PER_LEVEL);
         }
+
+        self.block.print_ast(indent + INDENT_PER_LEVEL);
     }
 }
 
@@ -221,6 +369,10 @@ impl Printable for nodes::StructLiteralNode<'_> {
             println!("{}Field {}", " ".repeat(indent + INDENT_PER_LEVEL), field.0);
             field.1.print_ast(indent + INDENT_PER_LEVEL);
         }
+        if let Some(base) = &self.base {
+            println!("{}Base", " ".repeat(indent + INDENT_PER_LEVEL));
+            base.print_ast(indent + INDENT_PER_LEVEL);
+        }
     }
 }
 
@@ -234,6 +386,16 @@ impl Printable for nodes::ArrayLiteralNode<'_> {
     }
 }
 
+impl Printable for nodes::TupleLiteralNode<'_> {
+    fn print_ast(&self, indent: usize) {
+        println!("{}ExpressionTupleLiteralNode", " ".repeat(indent));
+        println!("{}Type {}", " ".repeat(indent + INDENT_PER_LEVEL), self.typ);
+        for element in &self.elements {
+            element.print_ast(indent + INDENT_PER_LEVEL);
+        }
+    }
+}
+
 impl Printable for nodes::UnaryNode<'_> {
     fn print_ast(&self, indent: usize) {
         println!("{}ExpressionUnaryNode", " ".repeat(indent));